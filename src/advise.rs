@@ -0,0 +1,114 @@
+//! Access-pattern hints for memory-mapped DAT data.
+//!
+//! These are purely advisory: the OS is free to ignore them, and any failure to apply a hint is
+//! silently ignored. Their only purpose is reducing page-fault stalls when the archive isn't in the
+//! page cache yet.
+
+use std::io;
+use std::ops::Range;
+
+use DatStats;
+
+/// How a region of mapped DAT data is about to be accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    /// The region will be read roughly front-to-back, so aggressive readahead helps.
+    Sequential,
+    /// The region will be read in no particular order, so readahead is wasted IO.
+    Random,
+    /// The region will be read soon, so start paging it in now.
+    WillNeed,
+}
+
+/// Hints that `dat_data` is about to be listed: only the tree is read, so readahead of the (much
+/// larger) data section is disabled and the tree is paged in up front.
+pub fn for_listing(dat_data: &[u8]) -> io::Result<()> {
+    let stats = DatStats::parse(dat_data)?;
+    advise(dat_data, Advice::Random);
+    advise_range(dat_data, stats.tree_range, Advice::WillNeed);
+    Ok(())
+}
+
+/// Hints that `dat_data` is about to be extracted: the data section is read in entry offset
+/// order, so readahead is enabled for it and the tree is paged in up front.
+pub fn for_extraction(dat_data: &[u8]) -> io::Result<()> {
+    let stats = DatStats::parse(dat_data)?;
+    advise_range(dat_data, stats.data_range, Advice::Sequential);
+    advise_range(dat_data, stats.tree_range, Advice::WillNeed);
+    Ok(())
+}
+
+/// Applies `advice` to all of `data`.
+pub fn advise(data: &[u8], advice: Advice) {
+    sys::advise(data, advice)
+}
+
+/// Applies `advice` to `range` within `data`. Out-of-bounds ranges are clamped to `data`.
+pub fn advise_range(data: &[u8], range: Range<usize>, advice: Advice) {
+    let end = range.end.min(data.len());
+    let start = range.start.min(end);
+    advise(&data[start..end], advice)
+}
+
+#[cfg(unix)]
+mod sys {
+    use libc;
+
+    use super::Advice;
+
+    pub fn advise(data: &[u8], advice: Advice) {
+        if data.is_empty() {
+            return;
+        }
+
+        let advice = match advice {
+            Advice::Sequential => libc::MADV_SEQUENTIAL,
+            Advice::Random => libc::MADV_RANDOM,
+            Advice::WillNeed => libc::MADV_WILLNEED,
+        };
+
+        // madvise requires a page-aligned start address
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let addr = data.as_ptr() as usize;
+        let aligned_addr = addr - (addr % page_size);
+        let len = data.len() + (addr - aligned_addr);
+
+        unsafe {
+            libc::madvise(aligned_addr as *mut libc::c_void, len, advice);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::Advice;
+    use winapi::um::memoryapi::{PrefetchVirtualMemory, WIN32_MEMORY_RANGE_ENTRY};
+    use winapi::um::processthreadsapi::GetCurrentProcess;
+
+    pub fn advise(data: &[u8], advice: Advice) {
+        if data.is_empty() {
+            return;
+        }
+
+        // Windows has no equivalent of sequential/random hints for mapped views: only prefetching
+        match advice {
+            Advice::WillNeed => {
+                let mut range = WIN32_MEMORY_RANGE_ENTRY {
+                    VirtualAddress: data.as_ptr() as *mut _,
+                    NumberOfBytes: data.len(),
+                };
+                unsafe {
+                    PrefetchVirtualMemory(GetCurrentProcess(), 1, &mut range, 0);
+                }
+            },
+            Advice::Sequential | Advice::Random => {},
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use super::Advice;
+
+    pub fn advise(_data: &[u8], _advice: Advice) {}
+}
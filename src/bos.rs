@@ -0,0 +1,203 @@
+//! Reading of Fallout Tactics `.bos` archives.
+//!
+//! A `.bos` is a standard ZIP archive, with entries either stored or deflated. The archive is
+//! read via its central directory, which is located through the end of central directory record
+//! at the end of the data. Multi-byte numbers are little-endian.
+
+use std::fmt;
+use std::io;
+use std::io::ErrorKind;
+use byteorder::{LittleEndian, ByteOrder};
+use flate2::Crc;
+use flate2::read::DeflateDecoder;
+
+use errors;
+use errors::Code;
+use {read_chunks, DEFAULT_CHUNK_SIZE};
+
+const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x0605_4b50;
+const END_OF_CENTRAL_DIR_SIZE: usize = 22;
+const MAX_COMMENT_SIZE: usize = 0xffff;
+const CENTRAL_DIR_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const CENTRAL_DIR_HEADER_SIZE: usize = 46;
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const LOCAL_HEADER_SIZE: usize = 30;
+const FLAG_ENCRYPTED: u16 = 0x1;
+
+/// The compression methods of ZIP entries that can be read.
+pub const METHOD_STORED: u16 = 0;
+pub const METHOD_DEFLATED: u16 = 8;
+
+/// A parsed `.bos` archive.
+pub struct Bos<'a> {
+    entries: Vec<BosEntry<'a>>,
+}
+
+/// An entry (file) of a `.bos` archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BosEntry<'a> {
+    /// The entry's path, as stored (`/`-separated).
+    pub name: String,
+    pub method: u16,
+    pub crc32: u32,
+    pub packed_size: usize,
+    pub decompressed_size: usize,
+    /// The entry's raw (possibly deflated) data.
+    pub raw_data: &'a [u8],
+}
+
+impl <'a> Bos<'a> {
+
+    /// Attempts to parse `data` as a `.bos` archive. Directory entries are skipped.
+    pub fn parse(data: &'a [u8]) -> io::Result<Self> {
+        let end_of_central_dir = find_end_of_central_dir(data)?;
+        let num_entries = LittleEndian::read_u16(&data[end_of_central_dir + 10..]) as usize;
+        let central_dir_offset = LittleEndian::read_u32(&data[end_of_central_dir + 16..]) as usize;
+
+        let mut entries = Vec::with_capacity(num_entries);
+        let mut offset = central_dir_offset;
+
+        for _ in 0..num_entries {
+            let header = match data.get(offset..offset + CENTRAL_DIR_HEADER_SIZE) {
+                Some(header) if LittleEndian::read_u32(header) == CENTRAL_DIR_HEADER_SIGNATURE => header,
+                _ => {
+                    let err_msg = format!("invalid central directory header at offset {}", offset);
+                    return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+                }
+            };
+
+            let flags = LittleEndian::read_u16(&header[8..]);
+            let method = LittleEndian::read_u16(&header[10..]);
+            let crc32 = LittleEndian::read_u32(&header[16..]);
+            let packed_size = LittleEndian::read_u32(&header[20..]) as usize;
+            let decompressed_size = LittleEndian::read_u32(&header[24..]) as usize;
+            let name_len = LittleEndian::read_u16(&header[28..]) as usize;
+            let extra_len = LittleEndian::read_u16(&header[30..]) as usize;
+            let comment_len = LittleEndian::read_u16(&header[32..]) as usize;
+            let local_header_offset = LittleEndian::read_u32(&header[42..]) as usize;
+
+            let name_start = offset + CENTRAL_DIR_HEADER_SIZE;
+            let name = match data.get(name_start..name_start + name_len) {
+                Some(name) => String::from_utf8_lossy(name).into_owned(),
+                None => {
+                    let err_msg = format!("central directory header at offset {} has an out of bounds name", offset);
+                    return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+                }
+            };
+            offset = name_start + name_len + extra_len + comment_len;
+
+            if name.ends_with('/') {
+                continue;
+            }
+
+            if flags & FLAG_ENCRYPTED != 0 {
+                let err_msg = format!("{}: encrypted entries are not supported", name);
+                return Err(errors::new(Code::Unsupported, ErrorKind::InvalidData, err_msg));
+            }
+
+            let raw_data = local_data(data, &name, local_header_offset, packed_size)?;
+
+            entries.push(BosEntry { name, method, crc32, packed_size, decompressed_size, raw_data });
+        }
+
+        Ok(Bos { entries })
+    }
+
+    /// Returns the archive's entries, in central directory order.
+    pub fn entries(&self) -> &[BosEntry<'a>] {
+        &self.entries
+    }
+}
+
+impl <'a> BosEntry<'a> {
+
+    /// Returns the components of the entry's path.
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.name.split('/').filter(|component| !component.is_empty())
+    }
+
+    /// Returns the entry's decompressed content, checking it against the entry's CRC-32.
+    pub fn decompress(&self) -> io::Result<Vec<u8>> {
+        let mut content = Vec::with_capacity(self.decompressed_size);
+        self.for_each_chunk(DEFAULT_CHUNK_SIZE, |chunk| {
+            content.extend_from_slice(chunk);
+            Ok(())
+        })?;
+        Ok(content)
+    }
+
+    /// Passes the entry's decompressed content to `f` in chunks of `chunk_size` bytes (see
+    /// `fo2dat::for_each_chunk`), then checks it against the entry's CRC-32. A mismatch is only
+    /// detected once every chunk has been passed on.
+    pub fn for_each_chunk<F>(&self, chunk_size: usize, mut f: F) -> io::Result<usize>
+        where F: FnMut(&[u8]) -> io::Result<()>
+    {
+        let mut crc = Crc::new();
+        let mut checked = |chunk: &[u8]| {
+            crc.update(chunk);
+            f(chunk)
+        };
+
+        let len = match self.method {
+            METHOD_STORED => read_chunks(self.raw_data, chunk_size, &mut checked)?,
+            METHOD_DEFLATED => read_chunks(DeflateDecoder::new(self.raw_data), chunk_size, &mut checked)?,
+            method => {
+                let err_msg = format!("{}: unsupported compression method ({})", self.name, method);
+                return Err(errors::new(Code::Unsupported, ErrorKind::InvalidData, err_msg));
+            }
+        };
+
+        if crc.sum() != self.crc32 {
+            let err_msg = format!("{}: CRC-32 mismatch (expected {:08x}, got {:08x})", self.name, self.crc32, crc.sum());
+            return Err(errors::new(Code::CrcMismatch, ErrorKind::InvalidData, err_msg));
+        }
+
+        Ok(len)
+    }
+}
+
+impl <'a> fmt::Display for BosEntry<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// Returns the offset of the end of central directory record, which is followed by a comment of
+/// up to 64 KiB.
+fn find_end_of_central_dir(data: &[u8]) -> io::Result<usize> {
+    if data.len() >= END_OF_CENTRAL_DIR_SIZE {
+        let last_start = data.len() - END_OF_CENTRAL_DIR_SIZE;
+        let first_start = last_start.saturating_sub(MAX_COMMENT_SIZE);
+
+        for start in (first_start..=last_start).rev() {
+            if LittleEndian::read_u32(&data[start..]) == END_OF_CENTRAL_DIR_SIGNATURE {
+                return Ok(start);
+            }
+        }
+    }
+
+    Err(errors::new(Code::Format, ErrorKind::InvalidData, "data is not a .bos (ZIP) archive: no end of central directory record"))
+}
+
+/// Returns the data of the entry whose local header is at `offset`.
+fn local_data<'a>(data: &'a [u8], name: &str, offset: usize, packed_size: usize) -> io::Result<&'a [u8]> {
+    let header = match data.get(offset..offset + LOCAL_HEADER_SIZE) {
+        Some(header) if LittleEndian::read_u32(header) == LOCAL_HEADER_SIGNATURE => header,
+        _ => {
+            let err_msg = format!("{}: invalid local header at offset {}", name, offset);
+            return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+        }
+    };
+
+    let name_len = LittleEndian::read_u16(&header[26..]) as usize;
+    let extra_len = LittleEndian::read_u16(&header[28..]) as usize;
+    let data_start = offset + LOCAL_HEADER_SIZE + name_len + extra_len;
+
+    match data.get(data_start..data_start + packed_size) {
+        Some(entry_data) => Ok(entry_data),
+        None => {
+            let err_msg = format!("{}: data range ({}-{}) is out of bounds", name, data_start, data_start + packed_size);
+            Err(errors::new(Code::DataOutOfBounds, ErrorKind::InvalidData, err_msg))
+        }
+    }
+}
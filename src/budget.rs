@@ -0,0 +1,84 @@
+//! A limit on the memory that workers hold at once (`--max-memory`), for running in containers
+//! and on small machines.
+//!
+//! Workers reserve the memory they're about to use (e.g. an entry's decompression and write
+//! buffers) before using it, and wait while the budget's spent. Memory that's mapped rather than
+//! allocated (e.g. a memory-mapped archive, which the OS pages in and out as it likes) doesn't
+//! count against it.
+
+use std::sync::{Condvar, Mutex};
+
+/// A number of bytes of memory shared between workers.
+pub struct MemoryBudget {
+    limit: usize,
+    used: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Memory reserved from a `MemoryBudget`, which is released when dropped.
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: usize,
+    /// The bytes reserved by this reservation and those it was made within (see
+    /// `Reservation::reserve`).
+    held: usize,
+}
+
+impl MemoryBudget {
+
+    /// Creates a budget of `limit` bytes, none of which are reserved.
+    pub fn new(limit: usize) -> Self {
+        MemoryBudget { limit, used: Mutex::new(0), released: Condvar::new() }
+    }
+
+    /// Returns the number of bytes in the budget.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the number of bytes that are reserved.
+    pub fn used(&self) -> usize {
+        *self.used.lock().unwrap()
+    }
+
+    /// Blocks until `bytes` of the budget are free, then reserves them. A reservation larger than
+    /// the whole budget waits for all of it, so that work which needs more than the budget still
+    /// gets done, alone.
+    pub fn reserve(&self, bytes: usize) -> Reservation<'_> {
+        self.reserve_within(0, bytes)
+    }
+
+    /// Reserves `bytes` for work that already holds `held` bytes, waiting for at most the whole
+    /// budget between them.
+    fn reserve_within(&self, held: usize, bytes: usize) -> Reservation<'_> {
+        let bytes = held.saturating_add(bytes).min(self.limit).saturating_sub(held);
+        let mut used = self.used.lock().unwrap();
+        while self.limit - *used < bytes {
+            used = self.released.wait(used).unwrap();
+        }
+        *used += bytes;
+        Reservation { budget: self, bytes, held: held + bytes }
+    }
+}
+
+impl <'a> Reservation<'a> {
+    /// Returns the number of bytes reserved.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Blocks until `bytes` more of the budget are free, then reserves them, for work that holds
+    /// this reservation while it does more (e.g. an archive nested in a nested archive). As with
+    /// `MemoryBudget::reserve`, the reservations together wait for at most the whole budget, so
+    /// the work never waits for memory that it holds itself.
+    pub fn reserve(&self, bytes: usize) -> Reservation<'a> {
+        self.budget.reserve_within(self.held, bytes)
+    }
+}
+
+impl <'a> Drop for Reservation<'a> {
+    fn drop(&mut self) {
+        *self.budget.used.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
@@ -0,0 +1,171 @@
+//! A size-bounded cache of entries' decompressed content, for frontends that read the same
+//! entries again and again (as the engine does when it scans directories).
+//!
+//! Content is keyed by its entry's normalized path (see `names`), and the least recently used is
+//! evicted once the cache holds more than its capacity. It's shared between threads: content is
+//! handed out as `Arc`s, so evicting it doesn't invalidate readers that still hold it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+
+use names;
+use Dat;
+
+/// A cache of decompressed content, holding at most `capacity` bytes of it.
+pub struct ContentCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    slots: HashMap<String, Slot>,
+    /// The key of each slot, by when it was last used.
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+    size: usize,
+    stats: CacheStats,
+}
+
+struct Slot {
+    content: Arc<Vec<u8>>,
+    last_used: u64,
+}
+
+/// How a cache has been used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of lookups that found content in the cache.
+    pub hits: u64,
+    /// The number of lookups that didn't.
+    pub misses: u64,
+    /// The number of entries' content that was evicted to make room.
+    pub evictions: u64,
+}
+
+impl ContentCache {
+
+    /// Creates an empty cache that holds at most `capacity` bytes of content. Content larger
+    /// than that is never cached.
+    pub fn new(capacity: usize) -> Self {
+        ContentCache {
+            capacity,
+            inner: Mutex::new(Inner {
+                slots: HashMap::new(),
+                recency: BTreeMap::new(),
+                clock: 0,
+                size: 0,
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    /// Returns the decompressed content of the entry at `path` in `dat`, from the cache if it's
+    /// there, or else decompressing it and caching it. If the archive has duplicates of the path,
+    /// it's the first one's content (as with `Dat::get`).
+    pub fn content(&self, dat: &Dat, path: &str) -> io::Result<Arc<Vec<u8>>> {
+        self.get_or_insert_with(path, || {
+            let tree_entry = dat.get(path).ok_or_else(|| {
+                Error::new(ErrorKind::NotFound, format!("{}: no such entry", path))
+            })?;
+            ::decompress_entry(tree_entry, dat.data(tree_entry)?)
+        })
+    }
+
+    /// Returns the content cached for `path`, or else the content `load` returns, which is cached.
+    /// The lock isn't held while loading, so two threads may load the same content at once.
+    pub fn get_or_insert_with<F>(&self, path: &str, load: F) -> io::Result<Arc<Vec<u8>>>
+        where F: FnOnce() -> io::Result<Vec<u8>> {
+        let key = names::normalize(path);
+        if let Some(content) = self.lookup(&key) {
+            return Ok(content);
+        }
+
+        let content = Arc::new(load()?);
+        self.store(key, content.clone());
+        Ok(content)
+    }
+
+    /// Returns the content cached for `path`, if any.
+    pub fn get(&self, path: &str) -> Option<Arc<Vec<u8>>> {
+        self.lookup(&names::normalize(path))
+    }
+
+    /// Caches `content` for `path`, replacing any that's cached for it.
+    pub fn insert(&self, path: &str, content: Vec<u8>) {
+        self.store(names::normalize(path), Arc::new(content));
+    }
+
+    /// Removes all content from the cache.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.slots.clear();
+        inner.recency.clear();
+        inner.size = 0;
+    }
+
+    /// Returns the number of bytes of content in the cache.
+    pub fn size(&self) -> usize {
+        self.inner.lock().unwrap().size
+    }
+
+    /// Returns the maximum number of bytes of content that the cache holds.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns how the cache has been used so far.
+    pub fn stats(&self) -> CacheStats {
+        self.inner.lock().unwrap().stats
+    }
+
+    fn lookup(&self, key: &str) -> Option<Arc<Vec<u8>>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let now = inner.clock;
+
+        let (content, last_used) = match inner.slots.get_mut(key) {
+            Some(slot) => {
+                let last_used = slot.last_used;
+                slot.last_used = now;
+                (slot.content.clone(), last_used)
+            },
+            None => {
+                inner.stats.misses += 1;
+                return None;
+            },
+        };
+
+        let key = inner.recency.remove(&last_used).unwrap();
+        inner.recency.insert(now, key);
+        inner.stats.hits += 1;
+        Some(content)
+    }
+
+    fn store(&self, key: String, content: Arc<Vec<u8>>) {
+        if content.len() > self.capacity {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(replaced) = inner.slots.remove(&key) {
+            inner.recency.remove(&replaced.last_used);
+            inner.size -= replaced.content.len();
+        }
+
+        while inner.size + content.len() > self.capacity {
+            let (_, evicted_key) = inner.recency.pop_first().unwrap();
+            let evicted = inner.slots.remove(&evicted_key).unwrap();
+            inner.size -= evicted.content.len();
+            inner.stats.evictions += 1;
+        }
+
+        inner.clock += 1;
+        let now = inner.clock;
+        inner.size += content.len();
+        inner.recency.insert(now, key.clone());
+        inner.slots.insert(key, Slot { content, last_used: now });
+    }
+}
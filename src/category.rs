@@ -0,0 +1,85 @@
+//! Categories of Fallout 2 content, as patterns over where the game keeps it.
+//!
+//! Each category matches its top-level directory (e.g. `sound`, which holds `sound/sfx`,
+//! `sound/music` and `sound/speech`) and files of its types wherever they are, as mods don't
+//! always follow the game's layout.
+//!
+//! Localized content is under a directory named after its language (e.g. `text/german`), which
+//! `language_patterns` matches.
+
+use std::io;
+use std::io::Error;
+use std::io::ErrorKind;
+
+use glob::{MatchOptions, Pattern};
+
+/// A category of content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    /// Sound effects, music and speech.
+    Audio,
+    /// Sprites, interface art, palettes and splash screens.
+    Art,
+    /// Message files and other text, in every language.
+    Text,
+    /// Compiled (and source) scripts.
+    Scripts,
+    /// Maps and their global variables.
+    Maps,
+    /// Prototypes of items, critters, scenery, walls, tiles and misc objects.
+    Protos,
+}
+
+/// The names of every category, as `from_name` accepts them.
+pub const NAMES: &[&str] = &["audio", "art", "text", "scripts", "maps", "protos"];
+
+impl Category {
+
+    /// Parses a category's name (e.g. `audio`).
+    pub fn from_name(name: &str) -> io::Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "audio" => Ok(Category::Audio),
+            "art" => Ok(Category::Art),
+            "text" => Ok(Category::Text),
+            "scripts" => Ok(Category::Scripts),
+            "maps" => Ok(Category::Maps),
+            "protos" => Ok(Category::Protos),
+            _ => {
+                let err_msg = format!("{}: unknown category (known: {})", name, NAMES.join(", "));
+                Err(Error::new(ErrorKind::InvalidInput, err_msg))
+            }
+        }
+    }
+
+    /// Returns the patterns (see `glob`) of the paths in the category.
+    pub fn globs(self) -> &'static [&'static str] {
+        match self {
+            Category::Audio => &["sound", "*.acm", "*.lip"],
+            Category::Art => &["art", "*.frm", "*.fr[0-5]", "*.rix", "*.pal"],
+            Category::Text => &["text", "*.msg", "*.txt"],
+            Category::Scripts => &["scripts", "*.int", "*.ssl"],
+            Category::Maps => &["maps", "*.map", "*.gam"],
+            Category::Protos => &["proto", "*.pro"],
+        }
+    }
+
+    /// Returns the category's patterns, compiled. They're anchored, and their wildcards match
+    /// `/`, so that e.g. `*.acm` matches ACMs in any directory.
+    pub fn patterns(self) -> Vec<Pattern> {
+        let options = MatchOptions { anchored: true, wildcards_match_slash: true };
+        self.globs().iter().map(|glob| Pattern::new(glob, options)).collect()
+    }
+}
+
+/// Returns the patterns of the paths of content localized into `language` (e.g. `german`): its
+/// text (`text/<language>`), art (`art/<language>`) and speech (`sound/speech/<language>`).
+pub fn language_patterns(language: &str) -> io::Result<Vec<Pattern>> {
+    let is_name = !language.is_empty() && language.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !is_name {
+        let err_msg = format!("{}: invalid language (must be a directory name, e.g. german)", language);
+        return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+    }
+
+    let options = MatchOptions { anchored: true, wildcards_match_slash: true };
+    Ok(["text", "art", "sound/speech"].iter().map(|dir| Pattern::new(&format!("{}/{}", dir, language), options)).collect())
+}
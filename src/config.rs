@@ -0,0 +1,185 @@
+//! The load order of an installation's archives, from its `fallout2.cfg` (and sfall's `ddraw.ini`).
+//!
+//! The engine looks files up in every database it opens, most recently opened first, and in a
+//! database's patches directory before its archive. It opens, in order:
+//!
+//! 1. `master_dat`, with `master_patches` (`[system]` in `fallout2.cfg`);
+//! 2. `critter_dat`, with `critter_patches`;
+//! 3. `patch000.dat` to `patch999.dat`, those that exist.
+//!
+//! sfall's extra patches (`PatchFile0`, `PatchFile1`, ... in `[ExtraPatches]` of `ddraw.ini`) take
+//! precedence over all of them, `PatchFile0` first. Paths are relative to the game's directory
+//! (the one `fallout2.cfg` is in), and archives and directories that don't exist are skipped, as
+//! the engine skips them.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use errors;
+
+/// The number of `patchNNN.dat` archives the engine looks for.
+const NUM_NUMBERED_PATCHES: usize = 1000;
+
+/// The most `PatchFileN` entries of `ddraw.ini` that are read.
+const MAX_EXTRA_PATCHES: usize = 1000;
+
+/// A source of game files: an archive, or a directory of loose files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layer {
+    Dat(PathBuf),
+    Dir(PathBuf),
+}
+
+impl Layer {
+    /// Returns the layer's path.
+    pub fn path(&self) -> &Path {
+        match *self {
+            Layer::Dat(ref path) | Layer::Dir(ref path) => path,
+        }
+    }
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Layer::Dat(ref path) => write!(f, "{}", path.display()),
+            Layer::Dir(ref path) => write!(f, "{}/", path.display()),
+        }
+    }
+}
+
+/// An INI file's settings, as `fallout2.cfg` and `ddraw.ini` are written.
+#[derive(Debug, Clone, Default)]
+pub struct Ini {
+    /// Each setting's section, key and value. Later settings override earlier ones.
+    settings: Vec<(String, String, String)>,
+}
+
+impl Ini {
+
+    /// Parses `text`: `[section]` headers and `key=value` lines, with `;` and `#` comments.
+    pub fn parse(text: &str) -> Self {
+        let mut settings = Vec::new();
+        let mut section = String::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_ascii_lowercase();
+            } else if let Some(i) = line.find('=') {
+                // a trailing comment isn't part of the value
+                let value = line[i + 1..].split(';').next().unwrap().trim();
+                settings.push((section.clone(), line[..i].trim().to_ascii_lowercase(), value.to_string()));
+            }
+        }
+        Ini { settings }
+    }
+
+    /// Returns the (lowercase) names of the sections that have settings, in the order they first
+    /// appear.
+    pub fn sections(&self) -> Vec<&str> {
+        let mut sections: Vec<&str> = Vec::new();
+        for (section, _, _) in &self.settings {
+            if !sections.contains(&section.as_str()) {
+                sections.push(section);
+            }
+        }
+        sections
+    }
+
+    /// Returns the value of `key` in `section` (both compared case-insensitively), if it's set.
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.settings.iter().rev()
+            .find(|&(s, k, _)| s.eq_ignore_ascii_case(section) && k.eq_ignore_ascii_case(key))
+            .map(|(_, _, value)| value.as_str())
+    }
+}
+
+/// Returns the layers of the installation that `fallout2.cfg` at `cfg_path` configures, and
+/// `ddraw.ini` at `ddraw_path` (if it's given) adds to, highest priority first.
+pub fn load_order(cfg_path: &Path, ddraw_path: Option<&Path>) -> io::Result<Vec<Layer>> {
+    let cfg = read_ini(cfg_path)?;
+    let game_dir = cfg_path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut opened = Vec::new();
+    for &(dat_key, dat_default, patches_key) in &[("master_dat", "master.dat", "master_patches"), ("critter_dat", "critter.dat", "critter_patches")] {
+        let dat = cfg.get("system", dat_key).unwrap_or(dat_default);
+        let patches = cfg.get("system", patches_key).unwrap_or("data");
+        opened.push(vec![Layer::Dat(resolve(game_dir, dat)), Layer::Dir(resolve(game_dir, patches))]);
+    }
+    for i in 0..NUM_NUMBERED_PATCHES {
+        opened.push(vec![Layer::Dat(resolve(game_dir, &format!("patch{:03}.dat", i)))]);
+    }
+
+    // the most recently opened database is looked in first, and its directory before its archive
+    let mut layers: Vec<Layer> = opened.into_iter().rev().flat_map(|database| database.into_iter().rev()).collect();
+
+    if let Some(ddraw_path) = ddraw_path {
+        let ddraw = read_ini(ddraw_path)?;
+        let extra_patches = (0..MAX_EXTRA_PATCHES)
+            .filter_map(|i| ddraw.get("ExtraPatches", &format!("PatchFile{}", i)))
+            .filter(|patch| !patch.is_empty())
+            .map(|patch| {
+                let path = resolve(game_dir, patch);
+                if path.is_dir() { Layer::Dir(path) } else { Layer::Dat(path) }
+            });
+        layers.splice(0..0, extra_patches.collect::<Vec<_>>());
+    }
+
+    // several databases may share a patches directory, which only needs looking in once
+    let mut seen = Vec::new();
+    layers.retain(|layer| {
+        let exists = match *layer {
+            Layer::Dat(ref path) => path.is_file(),
+            Layer::Dir(ref path) => path.is_dir(),
+        };
+        let is_new = !seen.contains(layer);
+        seen.push(layer.clone());
+        exists && is_new
+    });
+
+    Ok(layers)
+}
+
+fn read_ini(path: &Path) -> io::Result<Ini> {
+    let bytes = fs::read(path).map_err(|e| errors::with_context(e, path.display()))?;
+    Ok(Ini::parse(&String::from_utf8_lossy(&bytes)))
+}
+
+/// Resolves `path`, as written in a config file (with `\` or `/` separators), against `game_dir`.
+fn resolve(game_dir: &Path, path: &str) -> PathBuf {
+    let mut resolved = if Path::new(path).is_absolute() { PathBuf::new() } else { game_dir.to_path_buf() };
+    for component in path.split(['\\', '/']).filter(|component| !component.is_empty() && *component != ".") {
+        resolved.push(component);
+    }
+    resolved
+}
+
+/// Finds the file at `path` (relative to `dir`, and matched case-insensitively, as the engine's
+/// lookups are on Windows) under `dir`.
+pub fn find_in_dir(dir: &Path, path: &str) -> io::Result<Option<PathBuf>> {
+    let mut found = dir.to_path_buf();
+    for component in path.split(['\\', '/']).filter(|component| !component.is_empty()) {
+        let exact = found.join(component);
+        if exact.exists() {
+            found = exact;
+            continue;
+        }
+        let matching = match fs::read_dir(&found) {
+            Ok(children) => children.filter_map(Result::ok)
+                .find(|child| child.file_name().to_string_lossy().eq_ignore_ascii_case(component)),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        match matching {
+            Some(child) => found = child.path(),
+            None => return Ok(None),
+        }
+    }
+    Ok(if found.is_file() { Some(found) } else { None })
+}
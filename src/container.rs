@@ -0,0 +1,150 @@
+//! Archives distributed inside of other files: ZIP files and gzip wrappers.
+//!
+//! A path to an archive in a ZIP file names the ZIP file and the member, separated by a `!` (e.g.
+//! `patch.zip!master.dat`). A gzipped archive (e.g. `master.dat.gz`) is named by its own path.
+//! Either is decompressed into memory to be read.
+
+use std::io;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::path::Path;
+
+use byteorder::{LittleEndian, ByteOrder};
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+
+use errors;
+use errors::Code;
+
+const EOCD_MAGIC: u32 = 0x0605_4b50;
+const EOCD_LEN: usize = 22;
+const CENTRAL_MAGIC: u32 = 0x0201_4b50;
+const CENTRAL_LEN: usize = 46;
+const LOCAL_MAGIC: u32 = 0x0403_4b50;
+const LOCAL_LEN: usize = 30;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// Splits `path` into the file that's read and, if it's in a ZIP file, the member's name. A path
+/// that exists as a file isn't split, so that archives can have a `!` in their names.
+pub fn split_path(path: &str) -> (&str, Option<&str>) {
+    if Path::new(path).exists() {
+        return (path, None);
+    }
+
+    for (i, _) in path.match_indices('!') {
+        let (file, member) = (&path[..i], &path[i + 1..]);
+        if !member.is_empty() && Path::new(file).is_file() {
+            return (file, Some(member));
+        }
+    }
+
+    (path, None)
+}
+
+/// Returns whether the archive at `path` is inside of another file, and so can't be written to in
+/// place.
+pub fn is_wrapped(path: &str) -> bool {
+    let (file, member) = split_path(path);
+    member.is_some() || is_gzip_path(file)
+}
+
+/// Returns whether `path` names a gzip file (by its `.gz` extension: an archive's data can start
+/// with anything, including gzip's magic).
+pub fn is_gzip_path(path: &str) -> bool {
+    path.to_ascii_lowercase().ends_with(".gz")
+}
+
+/// Decompresses gzip `data`, which may have several members.
+pub fn gunzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    MultiGzDecoder::new(data).read_to_end(&mut decompressed).map_err(|e| {
+        errors::new(Code::Format, ErrorKind::InvalidData, format!("invalid gzip data: {}", e))
+    })?;
+    Ok(decompressed)
+}
+
+/// Returns the content of the member of ZIP file `zip` named `name`. Names are compared with `\`
+/// and `/` alike, and ignoring ASCII case. Only stored and deflated members are supported, and not
+/// ZIP64 files.
+pub fn zip_member(zip: &[u8], name: &str) -> io::Result<Vec<u8>> {
+    let eocd = find_eocd(zip)?;
+    let num_entries = LittleEndian::read_u16(&zip[eocd + 10..]) as usize;
+    let dir_offset = LittleEndian::read_u32(&zip[eocd + 16..]) as usize;
+    if num_entries == 0xffff || dir_offset == 0xffff_ffff {
+        return Err(invalid("ZIP64 files aren't supported"));
+    }
+
+    let wanted = normalize(name);
+    let mut pos = dir_offset;
+    for _ in 0..num_entries {
+        let header = zip.get(pos..pos + CENTRAL_LEN).ok_or_else(|| invalid("truncated central directory"))?;
+        if LittleEndian::read_u32(header) != CENTRAL_MAGIC {
+            return Err(invalid("bad central directory entry"));
+        }
+        let method = LittleEndian::read_u16(&header[10..]);
+        let compressed_size = LittleEndian::read_u32(&header[20..]) as usize;
+        let size = LittleEndian::read_u32(&header[24..]) as usize;
+        let name_len = LittleEndian::read_u16(&header[28..]) as usize;
+        let extra_len = LittleEndian::read_u16(&header[30..]) as usize;
+        let comment_len = LittleEndian::read_u16(&header[32..]) as usize;
+        let local_offset = LittleEndian::read_u32(&header[42..]) as usize;
+
+        let entry_name = zip.get(pos + CENTRAL_LEN..pos + CENTRAL_LEN + name_len).ok_or_else(|| invalid("truncated central directory"))?;
+        pos += CENTRAL_LEN + name_len + extra_len + comment_len;
+
+        if normalize(&String::from_utf8_lossy(entry_name)) != wanted {
+            continue;
+        }
+
+        let data = member_data(zip, local_offset, compressed_size)?;
+        return match method {
+            STORED => Ok(data.to_vec()),
+            DEFLATED => {
+                let mut content = Vec::with_capacity(size);
+                DeflateDecoder::new(data).read_to_end(&mut content).map_err(|e| {
+                    invalid(&format!("{}: {}", name, e))
+                })?;
+                Ok(content)
+            },
+            _ => Err(invalid(&format!("{}: unsupported compression method {}", name, method))),
+        };
+    }
+
+    Err(Error::new(ErrorKind::NotFound, format!("{}: not in the ZIP file", name)))
+}
+
+/// Returns the offset of the end of central directory record, which is at the end of the file
+/// but for a comment of up to 64 KiB.
+fn find_eocd(zip: &[u8]) -> io::Result<usize> {
+    if zip.len() < EOCD_LEN {
+        return Err(invalid("too small to be a ZIP file"));
+    }
+
+    let earliest = zip.len().saturating_sub(EOCD_LEN + 0xffff);
+    (earliest..zip.len() - EOCD_LEN + 1).rev()
+        .find(|&i| LittleEndian::read_u32(&zip[i..]) == EOCD_MAGIC)
+        .ok_or_else(|| invalid("no end of central directory (not a ZIP file?)"))
+}
+
+/// Returns a member's (possibly compressed) data, which follows its local header.
+fn member_data(zip: &[u8], local_offset: usize, compressed_size: usize) -> io::Result<&[u8]> {
+    let header = zip.get(local_offset..local_offset + LOCAL_LEN).ok_or_else(|| invalid("truncated local header"))?;
+    if LittleEndian::read_u32(header) != LOCAL_MAGIC {
+        return Err(invalid("bad local header"));
+    }
+    let name_len = LittleEndian::read_u16(&header[26..]) as usize;
+    let extra_len = LittleEndian::read_u16(&header[28..]) as usize;
+
+    let start = local_offset + LOCAL_LEN + name_len + extra_len;
+    zip.get(start..start + compressed_size).ok_or_else(|| invalid("truncated member data"))
+}
+
+fn normalize(name: &str) -> String {
+    name.replace('\\', "/").to_ascii_lowercase()
+}
+
+fn invalid(msg: &str) -> Error {
+    errors::new(Code::Format, ErrorKind::InvalidData, format!("invalid ZIP file: {}", msg))
+}
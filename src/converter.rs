@@ -0,0 +1,233 @@
+//! External converters: commands that extraction pipes entries of a type through (`--convert
+//! SRC=DEST`), for formats that fo2dat can't convert itself.
+//!
+//! They're configured in fo2dat's config file (see `config_path`), an INI file with a
+//! `[convert.SRC]` section for each type of entry, e.g.:
+//!
+//! ```ini
+//! [convert.acm]
+//! to = wav
+//! command = acm2wav {in} {out}
+//! jobs = 2
+//! ```
+//!
+//! `command` is run by the shell (`sh -c`, or `cmd /C` on Windows), with `{in}` replaced by the
+//! path of a temporary file holding the entry's content and `{out}` by the path it writes the
+//! converted file to. Without `{in}`, the content is piped to the command's stdin instead and,
+//! without `{out}`, the converted file is read from its stdout. The temporary files are named
+//! after the extensions (e.g. `in.acm` and `out.wav`), for commands that go by them, in a
+//! directory of their own that only the user can access (see `temp`), which is removed once the
+//! command is done. `jobs`, if it's set, limits how many instances of the command run at
+//! once (by default, there are as many as extraction has workers).
+//!
+//! As in `fallout2.cfg`, `;` starts a comment, even part way through a value.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use config::Ini;
+use errors;
+use errors::Code;
+use temp::TempDir;
+
+/// The environment variable that gives the path of fo2dat's config file, overriding the default.
+pub const CONFIG_ENV: &str = "FO2DAT_CONFIG";
+
+/// The prefix of the names of sections that configure converters.
+const SECTION_PREFIX: &str = "convert.";
+
+/// A command that converts entries with one extension into files with another.
+pub struct ExternalConverter {
+    source_extension: String,
+    target_extension: String,
+    command: String,
+    /// The number of instances of the command that may start, if it's limited.
+    jobs: Option<Mutex<usize>>,
+    finished: Condvar,
+}
+
+impl ExternalConverter {
+
+    /// Creates a converter of entries with `source_extension` into files with `target_extension`
+    /// by `command`, of which at most `jobs` instances (if given) run at once.
+    pub fn new(source_extension: &str, target_extension: &str, command: &str, jobs: Option<usize>) -> Self {
+        ExternalConverter {
+            source_extension: source_extension.to_ascii_lowercase(),
+            target_extension: target_extension.to_ascii_lowercase(),
+            command: command.to_string(),
+            jobs: jobs.map(Mutex::new),
+            finished: Condvar::new(),
+        }
+    }
+
+    /// Returns the (lowercase) extension of entries the converter applies to.
+    pub fn source_extension(&self) -> &str {
+        &self.source_extension
+    }
+
+    /// Returns the extension given to converted files.
+    pub fn target_extension(&self) -> &str {
+        &self.target_extension
+    }
+
+    /// Runs the command on `content`, an entry's content, returning the converted file's content.
+    pub fn convert(&self, content: &[u8]) -> io::Result<Vec<u8>> {
+        let _job = self.start_job();
+
+        let uses_in = self.command.contains("{in}");
+        let uses_out = self.command.contains("{out}");
+        let temp_dir = TempDir::create()?;
+        let input_name = format!("in.{}", self.source_extension);
+        let input_path = temp_dir.path().join(&input_name);
+        let output_path = temp_dir.path().join(format!("out.{}", self.target_extension));
+
+        if uses_in {
+            let mut input_file = temp_dir.create_file(&input_name)?;
+            input_file.write_all(content).map_err(|e| errors::with_context(e, input_path.display()))?;
+        }
+        let command = self.command.replace("{in}", &quote(&input_path)).replace("{out}", &quote(&output_path));
+
+        let mut child = shell(&command)
+            .stdin(if uses_in { Stdio::null() } else { Stdio::piped() })
+            .stdout(if uses_out { Stdio::null() } else { Stdio::piped() })
+            .spawn()
+            .map_err(|e| errors::with_context(e, format!("cannot run `{}`", command)))?;
+
+        // the content is written while the output is read, as the command may not read all of
+        // its input before its output fills the pipe
+        let written = child.stdin.take().map(|mut stdin| {
+            let content = content.to_vec();
+            thread::spawn(move || {
+                // a command that exits without reading its input is judged by its exit status
+                match stdin.write_all(&content) {
+                    Err(ref e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+                    result => result,
+                }
+            })
+        });
+        let finished = child.wait_with_output().map_err(|e| errors::with_context(e, format!("`{}`", command)))?;
+        if let Some(written) = written {
+            written.join().expect("writing a command's input shouldn't panic")?;
+        }
+
+        if !finished.status.success() {
+            let err_msg = format!("`{}` failed ({})", command, finished.status);
+            return Err(errors::new(Code::Convert, ErrorKind::Other, err_msg));
+        }
+
+        if uses_out {
+            fs::read(&output_path).map_err(|e| match e.kind() {
+                ErrorKind::NotFound => errors::new(Code::Convert, ErrorKind::Other, format!("`{}` wrote no {}", command, output_path.display())),
+                _ => errors::with_context(e, output_path.display()),
+            })
+        } else {
+            Ok(finished.stdout)
+        }
+    }
+
+    /// Waits until another instance of the command may start, if they're limited, returning a
+    /// guard that lets the next one start when dropped.
+    fn start_job(&self) -> Option<Job<'_>> {
+        let jobs = self.jobs.as_ref()?;
+        let mut available = jobs.lock().unwrap();
+        while *available == 0 {
+            available = self.finished.wait(available).unwrap();
+        }
+        *available -= 1;
+        Some(Job { converter: self })
+    }
+}
+
+/// A running instance of a converter's command, which is limited by its `jobs`.
+struct Job<'a> {
+    converter: &'a ExternalConverter,
+}
+
+impl <'a> Drop for Job<'a> {
+    fn drop(&mut self) {
+        if let Some(ref jobs) = self.converter.jobs {
+            *jobs.lock().unwrap() += 1;
+            self.converter.finished.notify_one();
+        }
+    }
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+/// Quotes `path` for the shell.
+#[cfg(windows)]
+fn quote(path: &Path) -> String {
+    format!("\"{}\"", path.display())
+}
+
+/// Quotes `path` for the shell.
+#[cfg(not(windows))]
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', "'\\''"))
+}
+
+/// Returns the path of fo2dat's config file: `$FO2DAT_CONFIG` if it's set, or else `fo2dat.ini` in
+/// the user's config directory (`$XDG_CONFIG_HOME/fo2dat` or `~/.config/fo2dat`, or
+/// `%APPDATA%\fo2dat` on Windows), if it's known.
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(CONFIG_ENV) {
+        return Some(PathBuf::from(path));
+    }
+
+    let config_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    config_dir.map(|config_dir| config_dir.join("fo2dat").join("fo2dat.ini"))
+}
+
+/// Reads the converters configured in the config file at `path`. A config file that doesn't exist
+/// configures none.
+pub fn load(path: &Path) -> io::Result<Vec<ExternalConverter>> {
+    let text = match fs::read(path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(errors::with_context(e, path.display())),
+    };
+    let ini = Ini::parse(&text);
+
+    let invalid = |section: &str, problem: &str| {
+        errors::new(Code::InvalidArg, ErrorKind::InvalidData, format!("{}: [{}]: {}", path.display(), section, problem))
+    };
+
+    let mut converters = Vec::new();
+    for section in ini.sections().into_iter().filter(|section| section.starts_with(SECTION_PREFIX)) {
+        let source_extension = &section[SECTION_PREFIX.len()..];
+        let target_extension = ini.get(section, "to").filter(|to| !to.is_empty()).ok_or_else(|| invalid(section, "missing 'to' (the extension of converted files)"))?;
+        let command = ini.get(section, "command").filter(|command| !command.is_empty()).ok_or_else(|| invalid(section, "missing 'command'"))?;
+        let jobs = match ini.get(section, "jobs") {
+            Some(jobs_str) => match jobs_str.parse::<usize>() {
+                Ok(jobs) if jobs > 0 => Some(jobs),
+                _ => return Err(invalid(section, &format!("{}: invalid 'jobs' (must be a positive number)", jobs_str))),
+            },
+            None => None,
+        };
+        converters.push(ExternalConverter::new(source_extension, target_extension, command, jobs));
+    }
+
+    Ok(converters)
+}
@@ -0,0 +1,612 @@
+use std::cmp::Ordering;
+use std::io;
+use std::slice;
+use std::str;
+use std::sync::Arc;
+
+use {DatStats, TreeEntryRef, TreeEntryRefs, get_data};
+use names::normalize;
+use extension::Extension;
+use index;
+use index::{CachedEntry, CachedIndex, CachedPath};
+
+/// A parsed DAT2 archive with an index over its entries.
+///
+/// Parsing walks the tree once and builds an index ordered by (normalized) directory, so that
+/// grouping or filtering entries by directory doesn't require re-scanning and string-matching the
+/// whole tree. Directory arguments are compared case-insensitively and may use either `/` or `\`.
+///
+/// Cloning a `Dat` is cheap: clones share the index. A `Dat` only reads the data it borrows, so it
+/// can be read from many threads at once (see `SharedDat` for one that owns its data).
+#[derive(Clone)]
+pub struct Dat<'a> {
+    stats: DatStats,
+    data_section: &'a [u8],
+    tree_data: &'a [u8],
+    index: Arc<Index<'a>>,
+}
+
+struct Index<'a> {
+    /// The tree entries, in tree order.
+    entries: Vec<TreeEntryRef<'a>>,
+    /// The entries by path, ordered by directory.
+    by_path: Vec<IndexEntry>,
+}
+
+/// An entry's normalized (lowercase, `/`-separated) path, split into directory and filename.
+struct IndexEntry {
+    path: String,
+    dir_len: usize,
+    entry: usize,
+}
+
+impl IndexEntry {
+    fn new(entry: &TreeEntryRef, i: usize) -> Self {
+        let path = normalize(entry.name);
+        let dir_len = path.rfind('/').unwrap_or(0);
+        IndexEntry { path, dir_len, entry: i }
+    }
+
+    fn dir(&self) -> &str {
+        &self.path[..self.dir_len]
+    }
+
+    fn file_name(&self) -> &str {
+        if self.dir_len == 0 {
+            &self.path
+        } else {
+            &self.path[self.dir_len+1..]
+        }
+    }
+}
+
+impl <'a> Dat<'a> {
+
+    /// Parses `dat_data` and indexes all of its tree entries.
+    pub fn parse(dat_data: &'a [u8]) -> io::Result<Self> {
+        let stats = DatStats::parse(dat_data)?;
+
+        let entries: io::Result<Vec<TreeEntryRef>> =
+            TreeEntryRefs::new(&dat_data[stats.tree_range.clone()], stats.is_dat64, stats.num_files).collect();
+        let entries = entries?;
+
+        let mut by_path: Vec<IndexEntry> = entries.iter().enumerate().map(|(i, entry)| IndexEntry::new(entry, i)).collect();
+
+        // stable, so duplicate paths stay in tree order
+        by_path.sort_by(cmp_paths);
+
+        Ok(Dat {
+            data_section: &dat_data[stats.data_range.clone()],
+            tree_data: &dat_data[stats.tree_range.clone()],
+            stats,
+            index: Arc::new(Index { entries, by_path }),
+        })
+    }
+
+    /// Parses `dat_data` with `cached`, an index of it (e.g. from a sidecar, see `index`), rather
+    /// than indexing its tree entries. Returns `None` if `cached` isn't an index of `dat_data`'s
+    /// tree (e.g. the archive has changed since it was written), or isn't a sound index (e.g. its
+    /// paths aren't its entries' names, or aren't in order).
+    pub fn from_index(dat_data: &'a [u8], cached: CachedIndex) -> io::Result<Option<Self>> {
+        let stats = DatStats::parse(dat_data)?;
+        let tree_data = &dat_data[stats.tree_range.clone()];
+        if index::key(tree_data, &stats) != cached.key || cached.paths.len() != cached.entries.len() {
+            return Ok(None);
+        }
+
+        // the key vouches for the tree, but not for the sidecar, which is checked as it's read
+        let mut entries = Vec::with_capacity(cached.entries.len());
+        for entry in &cached.entries {
+            let name_data = entry.name_offset.checked_add(entry.name_len).and_then(|end| tree_data.get(entry.name_offset..end));
+            let name = match name_data.map(str::from_utf8) {
+                Some(Ok(name)) => name,
+                _ => return Ok(None),
+            };
+            entries.push(TreeEntryRef {
+                name,
+                is_compressed: entry.is_compressed,
+                decompressed_size: entry.decompressed_size,
+                packed_size: entry.packed_size,
+                offset: entry.offset,
+            });
+        }
+
+        // lookups rely on every entry having one path, which is its normalized name, split at its
+        // last `/`, and on the paths being in order, so the sidecar's paths are checked for that
+        let mut has_path = vec![false; entries.len()];
+        let mut by_path: Vec<IndexEntry> = Vec::with_capacity(entries.len());
+        for CachedPath { path, dir_len, entry } in cached.paths {
+            let is_sound = has_path.get(entry) == Some(&false) &&
+                dir_len == path.rfind('/').unwrap_or(0) &&
+                path == normalize(entries[entry].name);
+            if !is_sound {
+                return Ok(None);
+            }
+            has_path[entry] = true;
+
+            let path = IndexEntry { path, dir_len, entry };
+            // duplicate paths are in tree order, as sorting leaves them
+            if by_path.last().is_some_and(|previous| cmp_paths(previous, &path).then(previous.entry.cmp(&path.entry)) != Ordering::Less) {
+                return Ok(None);
+            }
+            by_path.push(path);
+        }
+
+        Ok(Some(Dat {
+            data_section: &dat_data[stats.data_range.clone()],
+            tree_data,
+            stats,
+            index: Arc::new(Index { entries, by_path }),
+        }))
+    }
+
+    /// Returns the archive's index, as it's cached in a sidecar (see `index`).
+    pub fn index(&self) -> CachedIndex {
+        let tree_start = self.tree_data.as_ptr() as usize;
+        let entries = self.index.entries.iter().map(|entry| CachedEntry {
+            name_offset: entry.name.as_ptr() as usize - tree_start,
+            name_len: entry.name.len(),
+            is_compressed: entry.is_compressed,
+            decompressed_size: entry.decompressed_size,
+            packed_size: entry.packed_size,
+            offset: entry.offset,
+        }).collect();
+
+        CachedIndex {
+            key: index::key(self.tree_data, &self.stats),
+            entries,
+            paths: self.index.by_path.iter().map(|e| CachedPath { path: e.path.clone(), dir_len: e.dir_len, entry: e.entry }).collect(),
+        }
+    }
+
+    /// Returns the archive-level metadata of the archive.
+    pub fn stats(&self) -> &DatStats {
+        &self.stats
+    }
+
+    /// Returns all tree entries, in the order they appear in the tree.
+    pub fn tree_entries(&self) -> &[TreeEntryRef<'a>] {
+        &self.index.entries
+    }
+
+    /// Returns the number of entries in the archive.
+    pub fn len(&self) -> usize {
+        self.index.entries.len()
+    }
+
+    /// Returns `true` if the archive contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.index.entries.is_empty()
+    }
+
+    /// Returns the raw (possibly compressed) data of `entry`.
+    pub fn data(&self, entry: &TreeEntryRef) -> io::Result<&'a [u8]> {
+        get_data(self.data_section, entry.name, entry.offset, entry.packed_size)
+    }
+
+    /// Returns the archive's extension, if it has one.
+    pub fn extension(&self) -> io::Result<Option<Extension<'a>>> {
+        Extension::parse(self.data_section)
+    }
+
+    /// Returns the CRC-32 of each entry's decompressed content, in tree order, if the archive's
+    /// extension has a CRC table.
+    pub fn crc_table(&self) -> io::Result<Option<Vec<u32>>> {
+        match self.extension()? {
+            Some(extension) => extension.crc_table(self.index.entries.len()),
+            None => Ok(None),
+        }
+    }
+
+    /// Looks up an entry by path. If the archive contains duplicates of the path, the first one in
+    /// the tree is returned.
+    pub fn get(&self, path: &str) -> Option<&TreeEntryRef<'a>> {
+        self.index_of(path).map(|i| &self.index.entries[i])
+    }
+
+    /// Looks up the index (position in the tree, i.e. in `tree_entries()`) of an entry by path.
+    /// If the archive contains duplicates of the path, the index of the first one is returned.
+    pub fn index_of(&self, path: &str) -> Option<usize> {
+        self.indexes_of(path).next()
+    }
+
+    /// Returns an iterator over the indexes of every entry with `path`, in tree order. Archives
+    /// from some packers contain the same path several times.
+    pub fn indexes_of(&self, path: &str) -> impl Iterator<Item = usize> + '_ {
+        let path = normalize(path);
+        let dir_len = path.rfind('/').unwrap_or(0);
+        let key = IndexEntry { path, dir_len, entry: 0 };
+
+        let start = self.index.by_path.partition_point(|e| {
+            cmp_dirs(e.dir(), key.dir()).then_with(|| e.file_name().cmp(key.file_name())) == Ordering::Less
+        });
+
+        self.index.by_path[start..].iter()
+            .take_while(move |e| e.path == key.path)
+            .map(|e| e.entry)
+    }
+
+    /// Returns an iterator over every directory that directly contains at least one entry, in
+    /// sorted order, each paired with the entries it directly contains. The archive root is
+    /// emitted as `""`.
+    pub fn dirs(&self) -> Dirs<'_, 'a> {
+        Dirs { dat: self, remaining: &self.index.by_path }
+    }
+
+    /// Returns the archive's root directory, for navigating its entries as a tree of directories.
+    pub fn root(&self) -> Dir<'_, 'a> {
+        Dir { dat: self, path: "", index_entries: &self.index.by_path }
+    }
+
+    /// Returns the directory at `path`, if any entry is under it.
+    pub fn dir(&self, path: &str) -> Option<Dir<'_, 'a>> {
+        self.root().subdir(path)
+    }
+
+    /// Returns an iterator over all entries under `dir` (recursively), ordered by directory.
+    pub fn entries_in(&self, dir: &str) -> EntriesIn<'_, 'a> {
+        let dir = normalize(dir);
+
+        let index_entries = if dir.is_empty() {
+            &self.index.by_path[..]
+        } else {
+            let start = self.index.by_path.partition_point(|e| cmp_dirs(e.dir(), &dir) == Ordering::Less);
+            let len = self.index.by_path[start..].partition_point(|e| is_within(e.dir(), &dir));
+            &self.index.by_path[start..start+len]
+        };
+
+        EntriesIn { dat: self, index_entries: index_entries.iter() }
+    }
+}
+
+/// A `Dat` that shares ownership of its archive's data (e.g. a `Vec<u8>` or a memory map), so that
+/// it can be handed to threads that outlive whoever parsed it (e.g. a server's connections).
+///
+/// Cloning a `SharedDat` is cheap: clones share the data and the index.
+#[derive(Clone)]
+pub struct SharedDat {
+    // borrows from `data`, so it's declared (and so dropped) first
+    dat: Dat<'static>,
+    data: Arc<dyn StableBytes>,
+}
+
+/// Bytes that a `SharedDat` can own: ones that `as_ref` always returns the same slice of, which
+/// stays where it is, unchanged, until they're dropped.
+///
+/// # Safety
+///
+/// `SharedDat` keeps the slice that `as_ref` first returns for as long as it owns the bytes, so an
+/// implementation must not return a different one (e.g. from a buffer that's reallocated through
+/// a shared reference), nor let the slice's contents change.
+pub unsafe trait StableBytes: AsRef<[u8]> + Send + Sync + 'static {}
+
+unsafe impl StableBytes for Vec<u8> {}
+unsafe impl StableBytes for Box<[u8]> {}
+unsafe impl StableBytes for &'static [u8] {}
+#[cfg(feature = "memmap")]
+unsafe impl StableBytes for memmap::Mmap {}
+
+impl SharedDat {
+
+    /// Parses the archive `data`, which the `SharedDat` takes ownership of, and indexes all of its
+    /// tree entries.
+    pub fn parse<D: StableBytes>(data: D) -> io::Result<Self> {
+        let data: Arc<dyn StableBytes> = Arc::new(data);
+
+        // the bytes live (and, as they're `StableBytes`, stay where they are) for as long as
+        // `data` does, which outlives `dat`, and `dat` only lends them out for as long as the
+        // `SharedDat` is borrowed
+        let bytes: &'static [u8] = unsafe { &*((*data).as_ref() as *const [u8]) };
+        let dat = Dat::parse(bytes)?;
+
+        Ok(SharedDat { dat, data })
+    }
+
+    /// Returns the parsed archive.
+    pub fn dat(&self) -> &Dat<'_> {
+        &self.dat
+    }
+
+    /// Returns the archive's data.
+    pub fn data(&self) -> &[u8] {
+        (*self.data).as_ref()
+    }
+}
+
+// `Dat`s are read from many threads at once (e.g. by extraction's workers and a server's
+// connections), which this stops compiling if they stop being safe for
+#[allow(dead_code)]
+fn assert_thread_safe() {
+    fn is_thread_safe<T: Send + Sync + Clone>() {}
+    is_thread_safe::<Dat<'static>>();
+    is_thread_safe::<SharedDat>();
+}
+
+/// An iterator over directories and the entries they directly contain.
+pub struct Dirs<'d, 'a: 'd> {
+    dat: &'d Dat<'a>,
+    remaining: &'d [IndexEntry],
+}
+
+impl <'d, 'a> Iterator for Dirs<'d, 'a> {
+    type Item = (&'d str, EntriesIn<'d, 'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let dir = self.remaining.first()?.dir();
+        let len = self.remaining.iter().take_while(|e| e.dir() == dir).count();
+        let (group, rest) = self.remaining.split_at(len);
+        self.remaining = rest;
+
+        Some((dir, EntriesIn { dat: self.dat, index_entries: group.iter() }))
+    }
+}
+
+/// An iterator over entries in (or under) a directory.
+pub struct EntriesIn<'d, 'a: 'd> {
+    dat: &'d Dat<'a>,
+    index_entries: slice::Iter<'d, IndexEntry>,
+}
+
+impl <'d, 'a> Iterator for EntriesIn<'d, 'a> {
+    type Item = &'d TreeEntryRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index_entry = self.index_entries.next()?;
+        Some(&self.dat.index.entries[index_entry.entry])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.index_entries.size_hint()
+    }
+}
+
+/// A directory of an archive, which is implied by the paths of the entries under it.
+///
+/// Paths and names are normalized (lowercase, `/`-separated), and the root's path and name are
+/// `""`. Directories are views of the archive's index, so navigating them doesn't allocate.
+#[derive(Clone, Copy)]
+pub struct Dir<'d, 'a: 'd> {
+    dat: &'d Dat<'a>,
+    path: &'d str,
+    index_entries: &'d [IndexEntry],
+}
+
+impl <'d, 'a> Dir<'d, 'a> {
+
+    /// Returns the directory's path (e.g. `art/critters`).
+    pub fn path(&self) -> &'d str {
+        self.path
+    }
+
+    /// Returns the directory's name, the final component of its path (e.g. `critters`).
+    pub fn name(&self) -> &'d str {
+        self.path.rsplit('/').next().unwrap()
+    }
+
+    /// Returns `true` if this is the archive's root directory.
+    pub fn is_root(&self) -> bool {
+        self.path.is_empty()
+    }
+
+    /// Returns an iterator over the entries directly in the directory, sorted by filename.
+    pub fn files(&self) -> EntriesIn<'d, 'a> {
+        // a directory's own entries sort before its subdirectories'
+        let len = self.index_entries.iter().take_while(|e| e.dir() == self.path).count();
+        EntriesIn { dat: self.dat, index_entries: self.index_entries[..len].iter() }
+    }
+
+    /// Returns an iterator over the directory's immediate subdirectories, sorted by name.
+    pub fn children(&self) -> Subdirs<'d, 'a> {
+        let files_len = self.index_entries.iter().take_while(|e| e.dir() == self.path).count();
+        Subdirs { dat: self.dat, parent_len: self.path.len(), remaining: &self.index_entries[files_len..] }
+    }
+
+    /// Returns the subdirectory at `path`, relative to the directory (e.g. `art` or
+    /// `art/critters` from the root), if any entry is under it.
+    pub fn subdir(&self, path: &str) -> Option<Dir<'d, 'a>> {
+        let relative = normalize(path);
+        if relative.is_empty() {
+            return Some(*self);
+        }
+        let dir = if self.is_root() { relative } else { format!("{}/{}", self.path, relative) };
+
+        let start = self.index_entries.partition_point(|e| cmp_dirs(e.dir(), &dir) == Ordering::Less);
+        let len = self.index_entries[start..].partition_point(|e| is_within(e.dir(), &dir));
+        let first = self.index_entries.get(start).filter(|_| len > 0)?;
+
+        Some(Dir { dat: self.dat, path: &first.path[..dir.len()], index_entries: &self.index_entries[start..start+len] })
+    }
+
+    /// Returns an iterator over all entries under the directory (recursively), ordered by
+    /// directory.
+    pub fn entries(&self) -> EntriesIn<'d, 'a> {
+        EntriesIn { dat: self.dat, index_entries: self.index_entries.iter() }
+    }
+
+    /// Returns the number of entries under the directory (recursively).
+    pub fn len(&self) -> usize {
+        self.index_entries.len()
+    }
+
+    /// Returns `true` if the directory has no entries under it, which only the root of an empty
+    /// archive doesn't.
+    pub fn is_empty(&self) -> bool {
+        self.index_entries.is_empty()
+    }
+}
+
+/// An iterator over a directory's immediate subdirectories.
+pub struct Subdirs<'d, 'a: 'd> {
+    dat: &'d Dat<'a>,
+    parent_len: usize,
+    remaining: &'d [IndexEntry],
+}
+
+impl <'d, 'a> Iterator for Subdirs<'d, 'a> {
+    type Item = Dir<'d, 'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = self.remaining.first()?;
+
+        // the subdirectory is the parent's path and the next component of the entry's directory
+        let name_start = if self.parent_len == 0 { 0 } else { self.parent_len + 1 };
+        let name_end = first.dir()[name_start..].find('/').map_or(first.dir_len, |i| name_start + i);
+        let path = &first.path[..name_end];
+
+        let len = self.remaining.partition_point(|e| is_within(e.dir(), path));
+        let (group, rest) = self.remaining.split_at(len);
+        self.remaining = rest;
+
+        Some(Dir { dat: self.dat, path, index_entries: group })
+    }
+}
+
+/// Orders directories component-wise, which keeps every directory's subdirectories contiguous
+/// (e.g. `a/b/c` sorts between `a/b` and `a/b-c`).
+/// Orders index entries by directory, then by filename.
+fn cmp_paths(a: &IndexEntry, b: &IndexEntry) -> Ordering {
+    cmp_dirs(a.dir(), b.dir()).then_with(|| a.file_name().cmp(b.file_name()))
+}
+
+fn cmp_dirs(a: &str, b: &str) -> Ordering {
+    a.split('/').cmp(b.split('/'))
+}
+
+/// Returns `true` if `dir` is `ancestor` or a subdirectory of it.
+fn is_within(dir: &str, ancestor: &str) -> bool {
+    dir.starts_with(ancestor) && (dir.len() == ancestor.len() || dir.as_bytes()[ancestor.len()] == b'/')
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use byteorder::{ByteOrder, LittleEndian};
+    use errors::{self, Code};
+    use testutil;
+    use writer::{NewEntry, Source, WriteOptions};
+    use Method;
+
+    #[test]
+    fn shared_dat_is_read_from_many_threads() {
+        let entries = testutil::sample_entries(100, 3);
+        let shared = SharedDat::parse(testutil::build(&entries, &WriteOptions::default()).unwrap()).unwrap();
+        let expected: Vec<Vec<u8>> = {
+            let dat = shared.dat();
+            dat.tree_entries().iter().map(|tree_entry| ::decompress_entry(tree_entry, dat.data(tree_entry).unwrap()).unwrap()).collect()
+        };
+
+        // each thread gets its own clone, and outlives the scope that parsed it
+        let threads: Vec<_> = (0..8).map(|n| {
+            let shared = shared.clone();
+            let expected = expected.clone();
+            thread::spawn(move || {
+                let dat = shared.dat();
+                for (i, tree_entry) in dat.tree_entries().iter().enumerate().skip(n).step_by(3) {
+                    assert_eq!(::decompress_entry(tree_entry, dat.data(tree_entry).unwrap()).unwrap(), expected[i]);
+                    assert_eq!(dat.index_of(tree_entry.name), Some(i));
+                }
+                shared.data().len()
+            })
+        }).collect();
+        drop(shared);
+
+        let lens: Vec<usize> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        assert!(lens.windows(2).all(|pair| pair[0] == pair[1]));
+    }
+
+    #[test]
+    fn stored_entries_that_look_compressed_are_read_as_stored() {
+        // "XG" is a valid zlib header, though not one the writer stores content under, so the
+        // archive is patched to have such an entry (as the original archives do)
+        let content = b"XGstored content that isn't zlib data".to_vec();
+        let mut new_entry = testutil::entry("text/readme.txt", b"XXstored content that isn't zlib data");
+        new_entry.compress = Some(false);
+        let options = WriteOptions { crc_table: false, ..WriteOptions::default() };
+        let mut data = testutil::build(&[new_entry], &options).unwrap();
+        let at = data.windows(content.len()).position(|window| window[..2] == *b"XX" && window[2..] == content[2..]).unwrap();
+        data[at + 1] = b'G';
+
+        let dat = Dat::parse(&data).unwrap();
+        let tree_entry = &dat.tree_entries()[0];
+        let packed = dat.data(tree_entry).unwrap();
+        assert_eq!(Method::of(packed), Method::Zlib);
+        assert_eq!(Method::of_entry(tree_entry, packed), Method::Stored);
+        assert_eq!(::decompress_entry(tree_entry, packed).unwrap(), content);
+
+        // rewritten from its packed data (as recompress does), it's compressed instead
+        let repacked = NewEntry {
+            name: tree_entry.name.to_string(),
+            source: Source::Packed { data: packed.to_vec(), method: Method::of_entry(tree_entry, packed), decompressed_size: tree_entry.decompressed_size },
+            compress: None,
+        };
+        let rewritten = testutil::build(&[repacked], &options).unwrap();
+        let dat = Dat::parse(&rewritten).unwrap();
+        let tree_entry = &dat.tree_entries()[0];
+        let packed = dat.data(tree_entry).unwrap();
+        assert!(tree_entry.is_compressed);
+        assert_eq!(Method::of_entry(tree_entry, packed), Method::Zlib);
+        assert_eq!(::decompress_entry(tree_entry, packed).unwrap(), content);
+    }
+
+    #[test]
+    fn dat64_entries_whose_data_ends_past_u64_max_are_out_of_bounds() {
+        let options = WriteOptions { dat64: true, ..WriteOptions::default() };
+        let mut data = testutil::build(&[testutil::entry("readme.txt", b"some content")], &options).unwrap();
+
+        // the entry's footer (its flag, decompressed size, packed size and offset) follows its name
+        let name_at = data.windows(10).rposition(|window| window == b"readme.txt").unwrap();
+        let offset_at = name_at + 10 + 1 + 2 * 8;
+        LittleEndian::write_u64(&mut data[offset_at..offset_at + 8], u64::MAX);
+
+        let dat = Dat::parse(&data).unwrap();
+        let tree_entry = &dat.tree_entries()[0];
+        assert_eq!(tree_entry.offset as u64, u64::MAX);
+        let err = dat.data(tree_entry).unwrap_err();
+        assert_eq!(errors::code(&err), Code::DataOutOfBounds);
+    }
+
+    #[test]
+    fn dats_are_parsed_with_their_index() {
+        let data = testutil::build(&testutil::sample_entries(30, 7), &WriteOptions::default()).unwrap();
+        let dat = Dat::parse(&data).unwrap();
+
+        let cached = Dat::from_index(&data, dat.index()).unwrap().unwrap();
+        assert_eq!(cached.tree_entries(), dat.tree_entries());
+        for tree_entry in dat.tree_entries() {
+            assert_eq!(cached.index_of(tree_entry.name), dat.index_of(tree_entry.name));
+        }
+    }
+
+    #[test]
+    fn unsound_indexes_are_rejected() {
+        let data = testutil::build(&testutil::sample_entries(30, 7), &WriteOptions::default()).unwrap();
+        let index = Dat::parse(&data).unwrap().index();
+
+        // of another tree
+        let mut stale = index.clone();
+        stale.key = stale.key.wrapping_add(1);
+        assert!(Dat::from_index(&data, stale).unwrap().is_none());
+
+        // with its paths out of order
+        let mut unordered = index.clone();
+        unordered.paths.swap(0, 1);
+        assert!(Dat::from_index(&data, unordered).unwrap().is_none());
+
+        // with a path of an entry that isn't there
+        let mut out_of_range = index.clone();
+        out_of_range.paths[0].entry = index.entries.len();
+        assert!(Dat::from_index(&data, out_of_range).unwrap().is_none());
+
+        // with a path of another entry
+        let mut misattributed = index.clone();
+        misattributed.paths[0].entry = misattributed.paths[1].entry;
+        assert!(Dat::from_index(&data, misattributed).unwrap().is_none());
+
+        // with a name out of bounds of the tree
+        let mut out_of_bounds = index;
+        out_of_bounds.entries[0].name_offset = usize::MAX;
+        assert!(Dat::from_index(&data, out_of_bounds).unwrap().is_none());
+    }
+}
@@ -0,0 +1,276 @@
+//! Binary deltas between two versions of some content, in the manner of bsdiff (Colin Percival's
+//! "Naive differences of executable code").
+//!
+//! The new version is built from runs of the old one, added to bytewise (so data that has only
+//! shifted, or numbers that have only changed a little, differ by mostly zeros), and runs of extra
+//! bytes that the old version has nothing like:
+//!
+//! ```text
+//! delta   := control*
+//! control := diff_len (u64) | diff (diff_len bytes) | extra_len (u64) | extra (extra_len bytes) | seek (i64)
+//! ```
+//!
+//! Each control adds `diff` bytewise (wrapping) to the old content at the old position, which
+//! moves past it, appends `extra`, then moves the old position by `seek`. Multi-byte numbers are
+//! little-endian. Deltas aren't compressed themselves: they're meant to be compressed with
+//! whatever they're stored in, which their runs of zeros compress well in.
+
+use std::io;
+use std::io::ErrorKind;
+use std::mem;
+use byteorder::{LittleEndian, ByteOrder, WriteBytesExt};
+
+use errors;
+use errors::Code;
+
+/// The length of match that's long enough to be taken if it's any better than the current one.
+const LONG_MATCH_LEN: isize = 4096;
+
+/// Returns a delta that turns `old` into `new`.
+///
+/// Finding it takes memory for sorting the suffixes of `old` (sixteen times its size), and time
+/// that grows a little faster than linearly in the sizes of both.
+pub fn diff(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let suffixes = suffix_array(old);
+    let mut delta = Vec::new();
+
+    // bsdiff's scan: look for the longest match of the new content at each position, and emit a
+    // control whenever it's a better one than continuing the current alignment would give
+    let (old_len, new_len) = (old.len() as isize, new.len() as isize);
+    let (mut scan, mut pos, mut len) = (0isize, 0isize, 0isize);
+    let (mut last_scan, mut last_pos, mut last_offset) = (0isize, 0isize, 0isize);
+    let same = |old_pos: isize, new_pos: isize| old_pos >= 0 && old_pos < old_len && old[old_pos as usize] == new[new_pos as usize];
+
+    while scan < new_len {
+        let mut old_score = 0;
+        scan += len;
+        let mut scored_to = scan;
+
+        while scan < new_len {
+            let (match_pos, match_len) = longest_match(&suffixes, old, &new[scan as usize..]);
+            pos = match_pos as isize;
+            len = match_len as isize;
+
+            while scored_to < scan + len {
+                if same(scored_to + last_offset, scored_to) {
+                    old_score += 1;
+                }
+                scored_to += 1;
+            }
+
+            // unlike bsdiff, a long match that's any better is taken at once: stepping through it
+            // a byte at a time, searching at each, takes time quadratic in its length
+            if (len == old_score && len != 0) || len > old_score + 8 || (len >= LONG_MATCH_LEN && len > old_score) {
+                break;
+            }
+
+            if same(scan + last_offset, scan) {
+                old_score -= 1;
+            }
+            scan += 1;
+        }
+
+        if len == old_score && scan != new_len {
+            continue;
+        }
+
+        // how far the last match extends forwards, and the next one backwards, while they
+        // match at least half of their bytes
+        let mut len_forwards = 0;
+        let (mut score, mut best) = (0, 0);
+        let mut i = 0;
+        while last_scan + i < scan && last_pos + i < old_len {
+            if same(last_pos + i, last_scan + i) {
+                score += 1;
+            }
+            i += 1;
+            if score * 2 - i > best * 2 - len_forwards {
+                best = score;
+                len_forwards = i;
+            }
+        }
+
+        let mut len_backwards = 0;
+        if scan < new_len {
+            let (mut score, mut best) = (0, 0);
+            let mut i = 1;
+            while scan >= last_scan + i && pos >= i {
+                if same(pos - i, scan - i) {
+                    score += 1;
+                }
+                if score * 2 - i > best * 2 - len_backwards {
+                    best = score;
+                    len_backwards = i;
+                }
+                i += 1;
+            }
+        }
+
+        // where the two extensions overlap, split them where the most bytes match
+        if last_scan + len_forwards > scan - len_backwards {
+            let overlap = (last_scan + len_forwards) - (scan - len_backwards);
+            let (mut score, mut best, mut len_split) = (0, 0, 0);
+            for i in 0..overlap {
+                if same(last_pos + len_forwards - overlap + i, last_scan + len_forwards - overlap + i) {
+                    score += 1;
+                }
+                if same(pos - len_backwards + i, scan - len_backwards + i) {
+                    score -= 1;
+                }
+                if score > best {
+                    best = score;
+                    len_split = i + 1;
+                }
+            }
+            len_forwards += len_split - overlap;
+            len_backwards -= len_split;
+        }
+
+        let diff_start = (last_pos as usize, last_scan as usize);
+        let extra = &new[(last_scan + len_forwards) as usize..(scan - len_backwards) as usize];
+        let seek = (pos - len_backwards) - (last_pos + len_forwards);
+
+        // writing to a Vec can't fail
+        delta.write_u64::<LittleEndian>(len_forwards as u64).unwrap();
+        delta.extend((0..len_forwards as usize).map(|i| new[diff_start.1 + i].wrapping_sub(old[diff_start.0 + i])));
+        delta.write_u64::<LittleEndian>(extra.len() as u64).unwrap();
+        delta.extend_from_slice(extra);
+        delta.write_i64::<LittleEndian>(seek as i64).unwrap();
+
+        last_scan = scan - len_backwards;
+        last_pos = pos - len_backwards;
+        last_offset = pos - scan;
+    }
+
+    delta
+}
+
+/// Returns the content that applying `delta` to `old` gives.
+pub fn apply(old: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let mut remaining = delta;
+    let mut new = Vec::new();
+    let mut old_pos = 0i64;
+
+    while !remaining.is_empty() {
+        let diff = take_run(&mut remaining)?;
+        let old_run = if old_pos < 0 { None } else { old.get(old_pos as usize..).and_then(|old_run| old_run.get(..diff.len())) };
+        match old_run {
+            Some(old_run) => new.extend(old_run.iter().zip(diff).map(|(&o, &d)| o.wrapping_add(d))),
+            None => return Err(malformed("adds to bytes outside of the old content")),
+        }
+
+        new.extend_from_slice(take_run(&mut remaining)?);
+
+        let seek = take_u64(&mut remaining)? as i64;
+        old_pos = old_pos.saturating_add(diff.len() as i64).saturating_add(seek);
+    }
+
+    Ok(new)
+}
+
+fn take_u64(remaining: &mut &[u8]) -> io::Result<u64> {
+    if remaining.len() < 8 {
+        return Err(malformed("ends part way through a control"));
+    }
+    let value = LittleEndian::read_u64(remaining);
+    *remaining = &remaining[8..];
+    Ok(value)
+}
+
+/// Takes a run's length, and then the run itself, from the start of `remaining`.
+fn take_run<'a>(remaining: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let len = take_u64(remaining)?;
+    if (remaining.len() as u64) < len {
+        return Err(malformed("ends part way through a run"));
+    }
+    let (run, rest) = remaining.split_at(len as usize);
+    *remaining = rest;
+    Ok(run)
+}
+
+fn malformed(problem: &str) -> io::Error {
+    errors::new(Code::Format, ErrorKind::InvalidData, format!("malformed delta: {}", problem))
+}
+
+/// Returns the start of each suffix of `data` (including the empty one, at its end), in
+/// lexicographic order, found by prefix doubling with radix sorts.
+fn suffix_array(data: &[u8]) -> Vec<u32> {
+    let n = data.len();
+
+    // suffixes ranked by their first `k` bytes, in which the empty suffix ranks lowest
+    let mut rank: Vec<u32> = data.iter().map(|&b| b as u32 + 1).chain(Some(0)).collect();
+    let mut suffixes: Vec<u32> = (0..=n as u32).collect();
+    suffixes.sort_unstable_by_key(|&i| rank[i as usize]);
+    let mut by_second = vec![0u32; n + 1];
+    let mut next_rank = vec![0u32; n + 1];
+    let mut counts = Vec::new();
+    let mut num_ranks = 257;
+    let mut k = 1;
+
+    while k <= n {
+        // ordered by the rank of the `k` bytes after their first `k` (suffixes that don't have
+        // them first), then stably by the rank of their first `k`
+        let mut j = 0;
+        for i in n + 1 - k..=n {
+            by_second[j] = i as u32;
+            j += 1;
+        }
+        for &i in &suffixes {
+            if i as usize >= k {
+                by_second[j] = i - k as u32;
+                j += 1;
+            }
+        }
+
+        counts.clear();
+        counts.resize(num_ranks + 1, 0usize);
+        for &i in &by_second {
+            counts[rank[i as usize] as usize + 1] += 1;
+        }
+        for r in 1..counts.len() {
+            counts[r] += counts[r - 1];
+        }
+        for &i in &by_second {
+            let r = rank[i as usize] as usize;
+            suffixes[counts[r]] = i;
+            counts[r] += 1;
+        }
+
+        let key = |i: u32| (rank[i as usize], rank.get(i as usize + k).cloned().unwrap_or(0));
+        next_rank[suffixes[0] as usize] = 0;
+        for j in 1..=n {
+            next_rank[suffixes[j] as usize] = next_rank[suffixes[j - 1] as usize] + (key(suffixes[j - 1]) != key(suffixes[j])) as u32;
+        }
+        mem::swap(&mut rank, &mut next_rank);
+
+        // every suffix has its own rank once they're all told apart
+        num_ranks = rank[suffixes[n] as usize] as usize + 1;
+        if num_ranks == n + 1 {
+            break;
+        }
+        k *= 2;
+    }
+
+    suffixes
+}
+
+/// Returns the position in `old` and length of the longest match of the start of `new`, going
+/// by `old`'s suffix array.
+fn longest_match(suffixes: &[u32], old: &[u8], new: &[u8]) -> (usize, usize) {
+    let (mut lo, mut hi) = (0, suffixes.len() - 1);
+    while hi - lo >= 2 {
+        let mid = lo + (hi - lo) / 2;
+        // unlike bsdiff's, the comparison orders a prefix first, so that a run (e.g. of zeros)
+        // finds the longest match of it rather than the shortest
+        if old[suffixes[mid] as usize..] < *new {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+
+    let match_len = |start: usize| old[start..].iter().zip(new).take_while(|&(a, b)| a == b).count();
+    let (lo, hi) = (suffixes[lo] as usize, suffixes[hi] as usize);
+    let (lo_len, hi_len) = (match_len(lo), match_len(hi));
+    if lo_len > hi_len { (lo, lo_len) } else { (hi, hi_len) }
+}
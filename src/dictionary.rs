@@ -0,0 +1,292 @@
+//! A preset zlib dictionary for small text entries (e.g. dialog MSG files), which, as zlib only
+//! finds repeats within what it's already seen, otherwise compress poorly: most of a short
+//! message file is boilerplate (`{100}{}{`) and common words that it has no earlier copy of.
+//!
+//! Entries compressed with it are standard zlib streams, but with the `FDICT` flag set in their
+//! header, followed by the dictionary's Adler-32 checksum (its "DICTID"), which is how fo2dat
+//! recognizes them. The engine (and any tool that doesn't know the dictionary) can't decompress
+//! them, so they're only written when asked for (`--dictionary`), for archives that only fo2dat
+//! (or another tool that has the dictionary) reads, e.g. patch archives that are unpacked before
+//! the game sees them.
+//!
+//! The dictionary can't change without breaking archives written with it; a different one would
+//! have a different DICTID, so its entries wouldn't be mistaken for this one's.
+
+#[cfg(feature = "compression")]
+use std::io;
+#[cfg(feature = "compression")]
+use std::io::{Chain, Cursor, ErrorKind, Read};
+use byteorder::{BigEndian, ByteOrder};
+#[cfg(feature = "compression")]
+use byteorder::WriteBytesExt;
+#[cfg(feature = "compression")]
+use flate2::{Compress, Compression, FlushCompress, Status};
+#[cfg(feature = "compression")]
+use flate2::read::DeflateDecoder;
+
+#[cfg(feature = "compression")]
+use errors;
+#[cfg(feature = "compression")]
+use errors::Code;
+use sniff;
+
+/// The dictionary: the boilerplate of the game's MSG files, and phrases common in its dialog and
+/// other text. As zlib matches nearer repeats more cheaply, the most common are last.
+pub const DICTIONARY: &[u8] = b"\
+# Message file\r\n# Dialog\r\n# Description\r\n# Floats\r\n# Random\r\n\
+Vault 13 Vault City New Reno Broken Hills San Francisco Navarro Arroyo Klamath the Den Modoc \
+Gecko Redding Sierra Army Depot Enclave Brotherhood of Steel NCR Khans Slags Shi Hubologists \
+the Chosen One the Vault Dweller the Elder the Temple of Trials the Garden of Eden Creation Kit \
+GECK caps Geiger counter Pip-Boy Jet RadAway Rad-X Stimpak Super Stimpak Nuka-Cola \
+ammo armor weapon gun knife spear rifle pistol shotgun SMG minigun plasma laser grenade \
+deathclaw radscorpion molerat brahmin gecko ghoul super mutant raider slaver caravan \
+strength perception endurance charisma intelligence agility luck skill perk karma \
+experience points hit points action points Armor Class damage critical \
+[Done] [Leave] [Continue] [More] [Next] [Back] [Barter] [Trade] [Attack] [Exit] \
+I don't know. I don't think so. I'm not sure. I'm looking for What can you tell me about \
+Who are you? Where am I? What is this place? What do you want? Can you help me? \
+Tell me about Do you know anything about I need your help. I have a few questions. \
+Never mind. Nothing. Maybe later. Not right now. That's all. Forget it. Goodbye. \
+Thank you. Thanks. Sure. Okay. Yes. No. Hello. Hi. Hey. Well, Look, Listen, \
+Get out of here! Leave me alone. Don't waste my time. What do you think you're doing? \
+You're not welcome here. Come back when you have something to say. \
+You look like you could use Be careful out there. Watch your back. \
+You see a man You see a woman You see a young man You see a young woman You see an old man \
+You see an old woman You see a guard You see a child You see a merchant You see a \
+wearing a carrying a He looks She looks They look This is a You see nothing special. \
+That does nothing. Nothing happens. You can't do that. That won't work. You don't have \
+You gain experience points. You have been killed. was hit for points of damage. \
+and the of the to the in the on the for the with the from the at the is a is the \
+it is it's that's there's there is there are you are you're you have you can you want \
+do you have you know I am I'm I have I've I will I'll I can I can't I want I need \
+of your to you for you with you about the about it about that \
+}{}{You see }{}{You \r\n{100}{}{}\r\n{101}{}{}\r\n{102}{}{}\r\n{103}{}{}\r\n{104}{}{}\r\n\
+{105}{}{}\r\n{106}{}{}\r\n{107}{}{}\r\n{108}{}{}\r\n{109}{}{}\r\n{110}{}{}\r\n{200}{}{}\r\n\
+.}\r\n?}\r\n!}\r\n}\r\n{1}{}{";
+
+/// The largest entries that the dictionary's worth trying on: it's only in zlib's window until
+/// that much more has been compressed, and larger entries have repeats of their own.
+pub const MAX_ENTRY_SIZE: usize = 32 * 1024;
+
+#[cfg(feature = "compression")]
+/// The zlib header of data compressed with a preset dictionary, at the best level. Only the
+/// `FDICT` bit (`0x20` of the second byte) matters: readers ignore the level.
+const HEADER: [u8; 2] = [0x78, 0xf9];
+
+/// The size of the zlib header with a DICTID.
+const HEADER_SIZE: usize = 6;
+
+/// The size of the zlib trailer (the content's Adler-32 checksum).
+const TRAILER_SIZE: usize = 4;
+
+/// Returns the dictionary's Adler-32 checksum, which identifies it in compressed data.
+pub fn id() -> u32 {
+    let mut checksum = Adler32::new();
+    checksum.update(DICTIONARY);
+    checksum.sum()
+}
+
+/// Returns true if the dictionary's worth trying on `content`, an entry's content: if it's small
+/// and looks like text.
+pub fn suits(content: &[u8]) -> bool {
+    content.len() <= MAX_ENTRY_SIZE && sniff::sniff(content).is_text()
+}
+
+/// Returns true if `data` appears to be zlib compressed with the dictionary.
+pub fn is_dictionary_compressed(data: &[u8]) -> bool {
+    const CM_DEFLATE: u8 = 8;
+    const MAX_CINFO: u8 = 7;
+    const FDICT: u8 = 0x20;
+
+    if data.len() < HEADER_SIZE + TRAILER_SIZE {
+        return false;
+    }
+
+    let (cmf, flg) = (data[0], data[1]);
+    cmf & 0x0f == CM_DEFLATE &&
+        cmf >> 4 <= MAX_CINFO &&
+        flg & FDICT != 0 &&
+        (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0 &&
+        BigEndian::read_u32(&data[2..HEADER_SIZE]) == id()
+}
+
+#[cfg(feature = "compression")]
+/// Compresses `content` at zlib `level` (0-9) with the dictionary.
+pub fn compress(content: &[u8], level: u32) -> io::Result<Vec<u8>> {
+    // zlib's own way of presetting a dictionary isn't available with every backend, so the
+    // compressor is primed by compressing the dictionary (and flushing, which byte-aligns the
+    // output without forgetting what it's seen), then everything after that is kept
+    let mut compressor = Compress::new(Compression::new(level), false);
+    let mut deflated = Vec::new();
+    deflate(&mut compressor, DICTIONARY, &mut deflated, FlushCompress::Sync)?;
+    let primed_len = deflated.len();
+    deflate(&mut compressor, content, &mut deflated, FlushCompress::Finish)?;
+
+    let mut checksum = Adler32::new();
+    checksum.update(content);
+
+    let mut compressed = Vec::with_capacity(HEADER_SIZE + deflated.len() - primed_len + TRAILER_SIZE);
+    compressed.extend_from_slice(&HEADER);
+    compressed.write_u32::<BigEndian>(id())?;
+    compressed.extend_from_slice(&deflated[primed_len..]);
+    compressed.write_u32::<BigEndian>(checksum.sum())?;
+    Ok(compressed)
+}
+
+#[cfg(feature = "compression")]
+/// Deflates all of `input` into `out`, flushing with `flush`.
+fn deflate(compressor: &mut Compress, input: &[u8], out: &mut Vec<u8>, flush: FlushCompress) -> io::Result<()> {
+    let mut consumed = 0;
+    loop {
+        out.reserve(input.len() - consumed + 1024);
+        let total_in = compressor.total_in();
+        let status = compressor.compress_vec(&input[consumed..], out, flush).map_err(io::Error::other)?;
+        consumed += (compressor.total_in() - total_in) as usize;
+
+        // a flush is complete once the compressor stops filling the space it's given
+        let is_flushed = match flush {
+            FlushCompress::Finish => status == Status::StreamEnd,
+            _ => consumed == input.len() && out.len() < out.capacity(),
+        };
+        if is_flushed {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+/// Returns the content of `data`, which is compressed with the dictionary.
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    reader(data).read_to_end(&mut content)?;
+    Ok(content)
+}
+
+#[cfg(feature = "compression")]
+/// Returns a reader of the content of `data`, which is compressed with the dictionary, that
+/// decompresses it as it's read.
+pub fn reader(data: &[u8]) -> Reader<'_> {
+    // the inflater is primed with the dictionary, as stored deflate blocks, whose output is
+    // skipped; the compressed data starts byte-aligned (see `compress`), so it follows on
+    let mut primer = Vec::with_capacity(DICTIONARY.len() + 5 * (DICTIONARY.len() / 0xffff + 1));
+    for block in DICTIONARY.chunks(0xffff) {
+        // not final, and stored (the rest of the byte is padding)
+        primer.push(0);
+        primer.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        primer.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        primer.extend_from_slice(block);
+    }
+
+    let body_end = data.len().saturating_sub(TRAILER_SIZE).max(HEADER_SIZE.min(data.len()));
+    let body = &data[HEADER_SIZE.min(data.len())..body_end];
+    let expected = data.get(body_end..).filter(|trailer| trailer.len() == TRAILER_SIZE).map(BigEndian::read_u32);
+
+    Reader {
+        inflater: DeflateDecoder::new(Cursor::new(primer).chain(body)),
+        to_skip: DICTIONARY.len(),
+        checksum: Adler32::new(),
+        expected,
+    }
+}
+
+#[cfg(feature = "compression")]
+/// A reader of the content of data compressed with the dictionary (see `reader`), which checks it
+/// against the data's checksum once it's all read.
+pub struct Reader<'a> {
+    inflater: DeflateDecoder<Chain<Cursor<Vec<u8>>, &'a [u8]>>,
+    /// The number of bytes of the dictionary's own output still to skip.
+    to_skip: usize,
+    checksum: Adler32,
+    expected: Option<u32>,
+}
+
+#[cfg(feature = "compression")]
+impl <'a> Read for Reader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut skipped = [0; 4096];
+        while self.to_skip > 0 {
+            let len = self.to_skip.min(skipped.len());
+            match self.inflater.read(&mut skipped[..len]).map_err(invalid)? {
+                0 => return Err(invalid("ends part way through the dictionary")),
+                n => self.to_skip -= n,
+            }
+        }
+
+        let n = self.inflater.read(buf).map_err(invalid)?;
+        self.checksum.update(&buf[..n]);
+        if n == 0 && !buf.is_empty() && self.expected != Some(self.checksum.sum()) {
+            return Err(invalid("Adler-32 mismatch"));
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "compression")]
+fn invalid<E: ToString>(e: E) -> io::Error {
+    errors::new(Code::Decompress, ErrorKind::InvalidData, format!("corrupt dictionary-compressed data: {}", e.to_string()))
+}
+
+/// A running Adler-32 checksum, as zlib streams end with.
+struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    fn new() -> Self {
+        Adler32 { a: 1, b: 0 }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        const MOD: u32 = 65521;
+        // the most bytes that can be summed before the sums could overflow
+        const MAX_RUN: usize = 5552;
+
+        for run in data.chunks(MAX_RUN) {
+            for &byte in run {
+                self.a += u32::from(byte);
+                self.b += self.a;
+            }
+            self.a %= MOD;
+            self.b %= MOD;
+        }
+    }
+
+    fn sum(&self) -> u32 {
+        self.b << 16 | self.a
+    }
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::*;
+    use testutil;
+    use writer::{self, WriteOptions};
+    use {Dat, Method};
+
+    const MSG: &[u8] = b"{100}{}{You see a wooden door.}\r\n{101}{}{The door is locked.}\r\n{102}{}{You see nothing special.}\r\n";
+
+    #[test]
+    fn content_round_trips() {
+        let data = compress(MSG, writer::BEST_LEVEL).unwrap();
+        assert!(is_dictionary_compressed(&data));
+        assert_eq!(Method::of(&data), Method::ZlibDictionary);
+        assert_eq!(::decompress(&data).unwrap(), MSG);
+
+        let mut content = Vec::new();
+        ::method_reader(&data, Method::ZlibDictionary).read_to_end(&mut content).unwrap();
+        assert_eq!(content, MSG);
+    }
+
+    #[test]
+    fn archives_written_with_the_dictionary_round_trip() {
+        let options = WriteOptions { dictionary: true, ..WriteOptions::default() };
+        let data = testutil::build(&[testutil::entry("text/english/dialog/door.msg", MSG)], &options).unwrap();
+
+        let dat = Dat::parse(&data).unwrap();
+        let tree_entry = &dat.tree_entries()[0];
+        let packed = dat.data(tree_entry).unwrap();
+        assert_eq!(Method::of_entry(tree_entry, packed), Method::ZlibDictionary);
+        assert_eq!(::decompress_entry(tree_entry, packed).unwrap(), MSG);
+    }
+}
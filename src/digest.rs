@@ -0,0 +1,625 @@
+//! Cryptographic (SHA-256, BLAKE3) and non-cryptographic (CRC-32, XXH64) digests of data.
+//!
+//! XXH64 is much faster than CRC-32 with far fewer collisions, and BLAKE3 is much faster than
+//! SHA-256 on large data. They're implemented here, as SHA-256 is, rather than taken from crates,
+//! so they cost the build no dependencies.
+
+use std::fmt::Write;
+use std::io;
+use std::io::Error;
+use std::io::ErrorKind;
+use flate2::Crc;
+
+/// A digest algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    /// CRC-32 (as used by zlib and ZIP), which is fast but only detects accidental changes.
+    Crc32,
+    /// XXH64 (with seed 0), which is fast but only detects accidental changes.
+    Xxh64,
+    /// BLAKE3, with a 256-bit digest.
+    Blake3,
+}
+
+/// The names of the supported algorithms, as `Algorithm::from_name` accepts them.
+pub const NAMES: &[&str] = &["sha256", "crc32", "xxh64", "blake3"];
+
+impl Algorithm {
+
+    /// Parses an algorithm's name (e.g. `sha256`, `crc32`).
+    pub fn from_name(name: &str) -> io::Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha256" | "sha-256" => Ok(Algorithm::Sha256),
+            "crc32" | "crc-32" => Ok(Algorithm::Crc32),
+            "xxh64" | "xxhash64" => Ok(Algorithm::Xxh64),
+            "blake3" => Ok(Algorithm::Blake3),
+            _ => {
+                let err_msg = format!("{}: unsupported digest algorithm (supported: {})", name, NAMES.join(", "));
+                Err(Error::new(ErrorKind::InvalidInput, err_msg))
+            }
+        }
+    }
+
+    /// Returns the algorithm's (lowercase) name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Crc32 => "crc32",
+            Algorithm::Xxh64 => "xxh64",
+            Algorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Returns `true` if finding two inputs with the same digest is infeasible, so that data with
+    /// the same digest can be taken to be identical without comparing it.
+    pub fn is_collision_resistant(self) -> bool {
+        match self {
+            Algorithm::Sha256 => true,
+            Algorithm::Crc32 => false,
+            Algorithm::Xxh64 => false,
+            Algorithm::Blake3 => true,
+        }
+    }
+
+    /// Returns a hasher that computes a digest with the algorithm.
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Crc32 => Hasher::Crc32(Crc::new()),
+            Algorithm::Xxh64 => Hasher::Xxh64(Xxh64::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Blake3::new()),
+        }
+    }
+
+    /// Returns the digest of `data`.
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finish()
+    }
+}
+
+/// An in-progress digest computation.
+pub enum Hasher {
+    Sha256(Sha256),
+    Crc32(Crc),
+    Xxh64(Xxh64),
+    Blake3(Blake3),
+}
+
+impl Hasher {
+
+    /// Feeds `data` into the digest.
+    pub fn update(&mut self, data: &[u8]) {
+        match *self {
+            Hasher::Sha256(ref mut sha256) => sha256.update(data),
+            Hasher::Crc32(ref mut crc) => crc.update(data),
+            Hasher::Xxh64(ref mut xxh64) => xxh64.update(data),
+            Hasher::Blake3(ref mut blake3) => blake3.update(data),
+        }
+    }
+
+    /// Returns the digest of all the data fed in. A CRC-32 or XXH64 is returned big-endian, which
+    /// is how they're conventionally written.
+    pub fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(sha256) => sha256.finish().to_vec(),
+            Hasher::Crc32(crc) => crc.sum().to_be_bytes().to_vec(),
+            Hasher::Xxh64(xxh64) => xxh64.finish().to_be_bytes().to_vec(),
+            Hasher::Blake3(blake3) => blake3.finish().to_vec(),
+        }
+    }
+}
+
+/// Formats `digest` as lowercase hex.
+pub fn to_hex(digest: &[u8]) -> String {
+    let mut hex = String::with_capacity(digest.len() * 2);
+    for b in digest {
+        write!(hex, "{:02x}", b).unwrap();
+    }
+    hex
+}
+
+const SHA256_BLOCK_SIZE: usize = 64;
+
+const SHA256_INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const SHA256_ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// A SHA-256 (FIPS 180-4) computation.
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    /// Data that doesn't yet fill a block.
+    pending: Vec<u8>,
+    len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Sha256::new()
+    }
+}
+
+impl Sha256 {
+
+    pub fn new() -> Self {
+        Sha256 { state: SHA256_INITIAL_STATE, pending: Vec::with_capacity(SHA256_BLOCK_SIZE), len: 0 }
+    }
+
+    /// Feeds `data` into the digest.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+
+        if !self.pending.is_empty() {
+            let len = (SHA256_BLOCK_SIZE - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..len]);
+            data = &data[len..];
+
+            if self.pending.len() < SHA256_BLOCK_SIZE {
+                return;
+            }
+            compress(&mut self.state, &self.pending);
+            self.pending.clear();
+        }
+
+        let mut blocks = data.chunks_exact(SHA256_BLOCK_SIZE);
+        for block in &mut blocks {
+            compress(&mut self.state, block);
+        }
+        self.pending.extend_from_slice(blocks.remainder());
+    }
+
+    /// Returns the digest of all the data fed in.
+    pub fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.len * 8;
+
+        // pad with a 1 bit, then zeroes up to the length field that ends the last block
+        let padded_len = (self.pending.len() + 1 + 8).div_ceil(SHA256_BLOCK_SIZE) * SHA256_BLOCK_SIZE;
+        let mut padding = vec![0; padded_len - self.pending.len()];
+        padding[0] = 0x80;
+        let len_field_start = padding.len() - 8;
+        padding[len_field_start..].copy_from_slice(&bit_len.to_be_bytes());
+        self.update(&padding);
+
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(&self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+/// Processes one 64-byte block, updating `state`.
+fn compress(state: &mut [u32; 8], block: &[u8]) {
+    let mut w = [0u32; 64];
+    for (i, word) in block.chunks(4).enumerate() {
+        w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..64 {
+        let s0 = w[i-15].rotate_right(7) ^ w[i-15].rotate_right(18) ^ (w[i-15] >> 3);
+        let s1 = w[i-2].rotate_right(17) ^ w[i-2].rotate_right(19) ^ (w[i-2] >> 10);
+        w[i] = w[i-16].wrapping_add(s0).wrapping_add(w[i-7]).wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+
+    for (&k, &w) in SHA256_ROUND_CONSTANTS.iter().zip(&w) {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let temp1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(k).wrapping_add(w);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let temp2 = s0.wrapping_add(maj);
+
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(temp1);
+        d = c;
+        c = b;
+        b = a;
+        a = temp1.wrapping_add(temp2);
+    }
+
+    for (word, value) in state.iter_mut().zip(&[a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(*value);
+    }
+}
+
+const XXH64_PRIME_1: u64 = 0x9E3779B185EBCA87;
+const XXH64_PRIME_2: u64 = 0xC2B2AE3D27D4EB4F;
+const XXH64_PRIME_3: u64 = 0x165667B19E3779F9;
+const XXH64_PRIME_4: u64 = 0x85EBCA77C2B2AE63;
+const XXH64_PRIME_5: u64 = 0x27D4EB2F165667C5;
+const XXH64_STRIPE_SIZE: usize = 32;
+
+/// An XXH64 computation, with seed 0.
+#[derive(Clone, Default)]
+pub struct Xxh64 {
+    /// The four lanes' accumulators, once a whole stripe has been fed in.
+    lanes: Option<[u64; 4]>,
+    /// Data that doesn't yet fill a stripe.
+    pending: Vec<u8>,
+    len: u64,
+}
+
+impl Xxh64 {
+
+    pub fn new() -> Self {
+        Xxh64 { lanes: None, pending: Vec::with_capacity(XXH64_STRIPE_SIZE), len: 0 }
+    }
+
+    /// Feeds `data` into the digest.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+
+        if !self.pending.is_empty() {
+            let len = (XXH64_STRIPE_SIZE - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..len]);
+            data = &data[len..];
+
+            if self.pending.len() < XXH64_STRIPE_SIZE {
+                return;
+            }
+            let stripe = std::mem::take(&mut self.pending);
+            self.consume(&stripe);
+        }
+
+        let mut stripes = data.chunks_exact(XXH64_STRIPE_SIZE);
+        for stripe in &mut stripes {
+            self.consume(stripe);
+        }
+        self.pending.extend_from_slice(stripes.remainder());
+    }
+
+    /// Returns the digest of all the data fed in.
+    pub fn finish(self) -> u64 {
+        let mut h = match self.lanes {
+            Some(lanes) => {
+                let mut h = lanes[0].rotate_left(1)
+                    .wrapping_add(lanes[1].rotate_left(7))
+                    .wrapping_add(lanes[2].rotate_left(12))
+                    .wrapping_add(lanes[3].rotate_left(18));
+                for &lane in &lanes {
+                    h = (h ^ xxh64_round(0, lane)).wrapping_mul(XXH64_PRIME_1).wrapping_add(XXH64_PRIME_4);
+                }
+                h
+            },
+            None => XXH64_PRIME_5,
+        };
+        h = h.wrapping_add(self.len);
+
+        let mut words = self.pending.chunks_exact(8);
+        for word in &mut words {
+            h ^= xxh64_round(0, read_u64_le(word));
+            h = h.rotate_left(27).wrapping_mul(XXH64_PRIME_1).wrapping_add(XXH64_PRIME_4);
+        }
+        let mut rest = words.remainder();
+        if rest.len() >= 4 {
+            h ^= u64::from(u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]])).wrapping_mul(XXH64_PRIME_1);
+            h = h.rotate_left(23).wrapping_mul(XXH64_PRIME_2).wrapping_add(XXH64_PRIME_3);
+            rest = &rest[4..];
+        }
+        for &b in rest {
+            h ^= u64::from(b).wrapping_mul(XXH64_PRIME_5);
+            h = h.rotate_left(11).wrapping_mul(XXH64_PRIME_1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(XXH64_PRIME_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(XXH64_PRIME_3);
+        h ^ (h >> 32)
+    }
+
+    /// Processes one 32-byte stripe.
+    fn consume(&mut self, stripe: &[u8]) {
+        let lanes = self.lanes.get_or_insert([
+            XXH64_PRIME_1.wrapping_add(XXH64_PRIME_2),
+            XXH64_PRIME_2,
+            0,
+            0u64.wrapping_sub(XXH64_PRIME_1),
+        ]);
+        for (lane, word) in lanes.iter_mut().zip(stripe.chunks_exact(8)) {
+            *lane = xxh64_round(*lane, read_u64_le(word));
+        }
+    }
+}
+
+fn xxh64_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(XXH64_PRIME_2)).rotate_left(31).wrapping_mul(XXH64_PRIME_1)
+}
+
+fn read_u64_le(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(word)
+}
+
+const BLAKE3_BLOCK_LEN: usize = 64;
+const BLAKE3_CHUNK_LEN: usize = 1024;
+const BLAKE3_CHUNK_START: u32 = 1 << 0;
+const BLAKE3_CHUNK_END: u32 = 1 << 1;
+const BLAKE3_PARENT: u32 = 1 << 2;
+const BLAKE3_ROOT: u32 = 1 << 3;
+const BLAKE3_MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+/// A BLAKE3 computation (unkeyed, with a 32-byte digest).
+///
+/// Input is split into 1 KiB chunks, which are the leaves of a binary tree whose nodes are
+/// merged as soon as both of their children are known, so only a stack of one chaining value
+/// per level of the tree is kept.
+#[derive(Clone)]
+pub struct Blake3 {
+    chunk: Blake3Chunk,
+    /// The chaining values of the completed subtrees, largest first.
+    cv_stack: Vec<[u32; 8]>,
+}
+
+impl Default for Blake3 {
+    fn default() -> Self {
+        Blake3::new()
+    }
+}
+
+impl Blake3 {
+
+    pub fn new() -> Self {
+        Blake3 { chunk: Blake3Chunk::new(0), cv_stack: Vec::new() }
+    }
+
+    /// Feeds `data` into the digest.
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // a full chunk is only completed once more input arrives, as the last chunk is
+            // finished differently (as the root, if it's the only one)
+            if self.chunk.len() == BLAKE3_CHUNK_LEN {
+                let chunk_cv = self.chunk.output().chaining_value();
+                let total_chunks = self.chunk.counter + 1;
+                self.push_chunk_cv(chunk_cv, total_chunks);
+                self.chunk = Blake3Chunk::new(total_chunks);
+            }
+
+            let len = (BLAKE3_CHUNK_LEN - self.chunk.len()).min(data.len());
+            self.chunk.update(&data[..len]);
+            data = &data[len..];
+        }
+    }
+
+    /// Returns the digest of all the data fed in.
+    pub fn finish(self) -> [u8; 32] {
+        let mut output = self.chunk.output();
+        for cv in self.cv_stack.iter().rev() {
+            output = blake3_parent_output(cv, &output.chaining_value());
+        }
+
+        let words = output.compress(BLAKE3_ROOT);
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(&words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+
+    /// Pushes the chaining value of a completed chunk, first merging every subtree that it
+    /// completes: one for each trailing zero bit of the number of chunks so far.
+    fn push_chunk_cv(&mut self, mut cv: [u32; 8], mut total_chunks: u64) {
+        while total_chunks & 1 == 0 {
+            let left = self.cv_stack.pop().expect("a completed subtree has a left child");
+            cv = blake3_parent_output(&left, &cv).chaining_value();
+            total_chunks >>= 1;
+        }
+        self.cv_stack.push(cv);
+    }
+}
+
+/// The state of the chunk being fed in.
+#[derive(Clone)]
+struct Blake3Chunk {
+    cv: [u32; 8],
+    counter: u64,
+    block: [u8; BLAKE3_BLOCK_LEN],
+    block_len: usize,
+    blocks_compressed: usize,
+}
+
+impl Blake3Chunk {
+    fn new(counter: u64) -> Self {
+        Blake3Chunk { cv: SHA256_INITIAL_STATE, counter, block: [0; BLAKE3_BLOCK_LEN], block_len: 0, blocks_compressed: 0 }
+    }
+
+    fn len(&self) -> usize {
+        self.blocks_compressed * BLAKE3_BLOCK_LEN + self.block_len
+    }
+
+    fn start_flag(&self) -> u32 {
+        if self.blocks_compressed == 0 { BLAKE3_CHUNK_START } else { 0 }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // as with chunks, a full block is only compressed once more input arrives
+            if self.block_len == BLAKE3_BLOCK_LEN {
+                let words = blake3_compress(&self.cv, &block_words(&self.block), self.counter, BLAKE3_BLOCK_LEN as u32, self.start_flag());
+                self.cv.copy_from_slice(&words[..8]);
+                self.blocks_compressed += 1;
+                self.block = [0; BLAKE3_BLOCK_LEN];
+                self.block_len = 0;
+            }
+
+            let len = (BLAKE3_BLOCK_LEN - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + len].copy_from_slice(&data[..len]);
+            self.block_len += len;
+            data = &data[len..];
+        }
+    }
+
+    fn output(&self) -> Blake3Output {
+        Blake3Output {
+            cv: self.cv,
+            block_words: block_words(&self.block),
+            counter: self.counter,
+            block_len: self.block_len as u32,
+            flags: self.start_flag() | BLAKE3_CHUNK_END,
+        }
+    }
+}
+
+/// The inputs of a node's last compression, which is finished as a chaining value or, for the
+/// root, as the digest.
+struct Blake3Output {
+    cv: [u32; 8],
+    block_words: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Blake3Output {
+    fn compress(&self, extra_flags: u32) -> [u32; 16] {
+        blake3_compress(&self.cv, &self.block_words, self.counter, self.block_len, self.flags | extra_flags)
+    }
+
+    fn chaining_value(&self) -> [u32; 8] {
+        let mut cv = [0; 8];
+        cv.copy_from_slice(&self.compress(0)[..8]);
+        cv
+    }
+}
+
+fn blake3_parent_output(left: &[u32; 8], right: &[u32; 8]) -> Blake3Output {
+    let mut block_words = [0; 16];
+    block_words[..8].copy_from_slice(left);
+    block_words[8..].copy_from_slice(right);
+    Blake3Output { cv: SHA256_INITIAL_STATE, block_words, counter: 0, block_len: BLAKE3_BLOCK_LEN as u32, flags: BLAKE3_PARENT }
+}
+
+fn block_words(block: &[u8; BLAKE3_BLOCK_LEN]) -> [u32; 16] {
+    let mut words = [0; 16];
+    for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    words
+}
+
+/// BLAKE3's compression function. Its IV is SHA-256's initial state.
+fn blake3_compress(cv: &[u32; 8], block_words: &[u32; 16], counter: u64, block_len: u32, flags: u32) -> [u32; 16] {
+    let mut state = [
+        cv[0], cv[1], cv[2], cv[3], cv[4], cv[5], cv[6], cv[7],
+        SHA256_INITIAL_STATE[0], SHA256_INITIAL_STATE[1], SHA256_INITIAL_STATE[2], SHA256_INITIAL_STATE[3],
+        counter as u32, (counter >> 32) as u32, block_len, flags,
+    ];
+    let mut m = *block_words;
+
+    for round in 0..7 {
+        blake3_g(&mut state, 0, 4, 8, 12, m[0], m[1]);
+        blake3_g(&mut state, 1, 5, 9, 13, m[2], m[3]);
+        blake3_g(&mut state, 2, 6, 10, 14, m[4], m[5]);
+        blake3_g(&mut state, 3, 7, 11, 15, m[6], m[7]);
+        blake3_g(&mut state, 0, 5, 10, 15, m[8], m[9]);
+        blake3_g(&mut state, 1, 6, 11, 12, m[10], m[11]);
+        blake3_g(&mut state, 2, 7, 8, 13, m[12], m[13]);
+        blake3_g(&mut state, 3, 4, 9, 14, m[14], m[15]);
+
+        if round < 6 {
+            let mut permuted = [0; 16];
+            for (word, &i) in permuted.iter_mut().zip(&BLAKE3_MSG_PERMUTATION) {
+                *word = m[i];
+            }
+            m = permuted;
+        }
+    }
+
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= cv[i];
+    }
+    state
+}
+
+/// BLAKE3's quarter-round, which mixes `mx` and `my` into the columns or diagonals `a`-`d`.
+fn blake3_g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(mx);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(my);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The input of BLAKE3's official test vectors: `len` bytes cycling through 0 to 250.
+    fn blake3_input(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Returns the hex digest of `data`, fed in at once, and checks that feeding it in pieces of
+    /// sizes that straddle blocks, stripes and chunks gives the same digest.
+    fn hex_digest(algorithm: Algorithm, data: &[u8]) -> String {
+        let digest = to_hex(&algorithm.digest(data));
+        for &piece_len in &[1, 7, 31, 63, 64, 65, 1000, 1025] {
+            let mut hasher = algorithm.hasher();
+            for piece in data.chunks(piece_len) {
+                hasher.update(piece);
+            }
+            assert_eq!(to_hex(&hasher.finish()), digest, "{} fed in {} byte pieces", algorithm.name(), piece_len);
+        }
+        digest
+    }
+
+    #[test]
+    fn sha256_known_answers() {
+        assert_eq!(hex_digest(Algorithm::Sha256, b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex_digest(Algorithm::Sha256, b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        // padding it takes a second block
+        assert_eq!(hex_digest(Algorithm::Sha256, b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+                   "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+        assert_eq!(hex_digest(Algorithm::Sha256, &vec![b'a'; 1_000_000]), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn blake3_known_answers() {
+        assert_eq!(hex_digest(Algorithm::Blake3, &blake3_input(0)), "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        assert_eq!(hex_digest(Algorithm::Blake3, &blake3_input(1)), "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213");
+        assert_eq!(hex_digest(Algorithm::Blake3, &blake3_input(64)), "4eed7141ea4a5cd4b788606bd23f46e212af9cacebacdc7d1f4c6dc7f2511b98");
+        // one whole chunk, then more than one, which are merged in a tree
+        assert_eq!(hex_digest(Algorithm::Blake3, &blake3_input(1024)), "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7");
+        assert_eq!(hex_digest(Algorithm::Blake3, &blake3_input(1025)), "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444");
+        assert_eq!(hex_digest(Algorithm::Blake3, &blake3_input(2048)), "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a");
+        assert_eq!(hex_digest(Algorithm::Blake3, &blake3_input(3072)), "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2");
+        assert_eq!(hex_digest(Algorithm::Blake3, &blake3_input(31744)), "62b6960e1a44bcc1eb1a611a8d6235b6b4b78f32e7abc4fb4c6cdcce94895c47");
+    }
+
+    #[test]
+    fn xxh64_known_answers() {
+        assert_eq!(hex_digest(Algorithm::Xxh64, b""), "ef46db3751d8e999");
+        assert_eq!(hex_digest(Algorithm::Xxh64, b"a"), "d24ec4f1a98c6e5b");
+        assert_eq!(hex_digest(Algorithm::Xxh64, b"abc"), "44bc2cf5ad770999");
+        // longer than a stripe
+        assert_eq!(hex_digest(Algorithm::Xxh64, b"Nobody inspects the spammish repetition"), "fbcea83c8a378bf1");
+    }
+
+    #[test]
+    fn crc32_known_answers() {
+        assert_eq!(hex_digest(Algorithm::Crc32, b""), "00000000");
+        assert_eq!(hex_digest(Algorithm::Crc32, b"123456789"), "cbf43926");
+    }
+}
@@ -0,0 +1,379 @@
+//! Diagnosis of broken archives: what's wrong with them, and what might fix it.
+//!
+//! `diagnose` runs every structural check that parsing and `verify` run, but rather than stopping
+//! at the first failure, it recognizes the ways archives commonly get broken and says what to do
+//! about them:
+//!
+//! - truncation (e.g. an interrupted download), which loses the tree at the end of the archive;
+//! - data appended after the footer (e.g. padding from a tool or a transfer);
+//! - a `tree_size` that's off by the size of the field itself, as some tools write it;
+//! - a footer written big-endian, by tools that confuse DAT2 with Fallout 1's (big-endian) DAT1;
+//! - Fallout 1 archives and savegames, which are also named `.DAT`;
+//! - a `num_files` that disagrees with the tree;
+//! - entries whose data is out of bounds, overlaps or is corrupt, and flags that misdescribe it.
+
+use std::fmt;
+use std::ops::Range;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use flate2::Crc;
+use rayon::prelude::*;
+
+use layout;
+use layout::Kind;
+use save;
+use {Anomaly, Dat, DatStats, Method, TreeEntryRefs, DAT64_MAGIC};
+
+/// How many of the entries that share a problem are named in its diagnosis.
+const MAX_EXAMPLES: usize = 3;
+
+/// How far before the end of an archive its footer is looked for, if it isn't at the end.
+const MAX_TRAILING_LEN: usize = 64 * 1024;
+
+/// How serious a problem is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The archive is (at least partly) unreadable, by the engine or by fo2dat.
+    Error,
+    /// The archive is readable, but isn't as a well-behaved packer would write it.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+/// A problem with an archive, and what might fix it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub severity: Severity,
+    pub problem: String,
+    pub fix: Option<String>,
+}
+
+impl Diagnosis {
+    fn error(problem: String, fix: String) -> Self {
+        Diagnosis { severity: Severity::Error, problem, fix: Some(fix) }
+    }
+
+    fn warning(problem: String, fix: String) -> Self {
+        Diagnosis { severity: Severity::Warning, problem, fix: Some(fix) }
+    }
+}
+
+/// Returns the problems with `dat_data`, an archive, most fundamental first. `dat_path` is the
+/// archive's path, as it's given in suggested commands. An archive without problems has none.
+pub fn diagnose(dat_data: &[u8], dat_path: &str) -> Vec<Diagnosis> {
+    let mut diagnoses = Vec::new();
+
+    if save::is_save(dat_data) {
+        diagnoses.push(Diagnosis::error(
+            "it's a Fallout 2 savegame (SAVE.DAT), not an archive".to_string(),
+            format!("read its header with `fo2dat save-info -f {}`", dat_path)));
+        return diagnoses;
+    }
+    if DatStats::parse(dat_data).is_err() && is_dat1(dat_data) {
+        diagnoses.push(Diagnosis::error(
+            "it looks like a Fallout 1 (DAT1) archive, which fo2dat doesn't read: its header is a big-endian directory count".to_string(),
+            "extract it with a Fallout 1 tool (e.g. undat), and pack the files with `fo2dat -c` if a DAT2 archive is wanted".to_string()));
+        return diagnoses;
+    }
+
+    let dat_data = match diagnose_footer(dat_data, dat_path, &mut diagnoses) {
+        Some(dat_data) => dat_data,
+        None => return diagnoses,
+    };
+    if !diagnose_tree(dat_data, &mut diagnoses) {
+        return diagnoses;
+    }
+
+    let dat = match Dat::parse(dat_data) {
+        Ok(dat) => dat,
+        Err(e) => {
+            diagnoses.push(Diagnosis::error(e.to_string(), copy_again()));
+            return diagnoses;
+        },
+    };
+    diagnose_layout(&dat, dat_path, &mut diagnoses);
+    diagnose_entries(&dat, dat_path, &mut diagnoses);
+
+    diagnoses
+}
+
+/// Returns `true` if `data` starts as a Fallout 1 archive does: a big-endian directory count,
+/// followed by a big-endian `0x0A` or `0x5E` and a zero.
+fn is_dat1(data: &[u8]) -> bool {
+    data.len() >= 16 &&
+        (1..=0xFFFF).contains(&BigEndian::read_u32(data)) &&
+        [0x0A, 0x5E].contains(&BigEndian::read_u32(&data[4..])) &&
+        BigEndian::read_u32(&data[8..]) == 0
+}
+
+/// Checks the `file_size` field. Returns the archive's data, without any trailing data, if the
+/// rest of it can be diagnosed.
+fn diagnose_footer<'a>(dat_data: &'a [u8], dat_path: &str, diagnoses: &mut Vec<Diagnosis>) -> Option<&'a [u8]> {
+    let field_bytes = field_bytes(dat_data);
+    let body_len = body_len(dat_data);
+    let min_size = 3 * field_bytes;
+
+    if body_len < min_size {
+        diagnoses.push(Diagnosis::error(
+            format!("it's only {} bytes long: too small to fit an archive's footer ({} bytes)", dat_data.len(), min_size),
+            copy_again()));
+        return None;
+    }
+
+    let field = &dat_data[body_len - field_bytes..body_len];
+    let file_size = read_field(field, field_bytes, false);
+    if file_size == dat_data.len() as u64 {
+        return Some(dat_data);
+    }
+
+    if read_field(field, field_bytes, true) == dat_data.len() as u64 {
+        diagnoses.push(Diagnosis::error(
+            "its footer's fields are big-endian, as Fallout 1's DAT1 archives are, but DAT2 archives are little-endian".to_string(),
+            "it was written by a tool that confused the formats: repack its files with that tool's DAT2 mode, or with `fo2dat -c`".to_string()));
+        return None;
+    }
+
+    if let Some(end) = find_footer_end(dat_data) {
+        diagnoses.push(Diagnosis::error(
+            format!("it has {} bytes of data after its footer (which ends at offset {}), so its footer isn't where readers look for it",
+                    dat_data.len() - end, end),
+            format!("cut off the trailing data, e.g. with `truncate -s {} {}`", end, dat_path)));
+        return Some(&dat_data[..end]);
+    }
+
+    // a truncated archive's last bytes are whatever was there, so file_size is only a hint
+    let problem = if file_size > dat_data.len() as u64 {
+        format!("its file_size field says it's {} bytes, but it's {}: it's probably truncated",
+                file_size, dat_data.len())
+    } else {
+        format!("its file_size field says it's {} bytes, but it's {}: it's probably truncated, or not an archive",
+                file_size, dat_data.len())
+    };
+    diagnoses.push(Diagnosis::error(problem, format!("{} (its tree, which lists its entries, is at its end, so none of them can be found)", copy_again())));
+    None
+}
+
+/// Returns the end of an archive that's followed by trailing data in `dat_data`: the first offset,
+/// looking back from its end, where a `file_size` field states the data up to it is an archive.
+fn find_footer_end(dat_data: &[u8]) -> Option<usize> {
+    let min_end = dat_data.len().saturating_sub(MAX_TRAILING_LEN).max(12);
+    (min_end..dat_data.len()).rev().find(|&end| {
+        LittleEndian::read_u32(&dat_data[end - 4..end]) as usize == end && DatStats::parse(&dat_data[..end]).is_ok()
+    })
+}
+
+/// Checks the `tree_size` and `num_files` fields, and that the tree parses. Returns `true` if the
+/// tree is sound enough for the archive's entries to be checked.
+fn diagnose_tree(dat_data: &[u8], diagnoses: &mut Vec<Diagnosis>) -> bool {
+    let field_bytes = field_bytes(dat_data);
+    let body_len = body_len(dat_data);
+    let tree_size_offset = body_len - 2 * field_bytes;
+    let tree_size = read_field(&dat_data[tree_size_offset..], field_bytes, false);
+
+    let stated = parse_tree(dat_data, tree_size);
+    if let Some(Ok(ref tree)) = stated {
+        if tree.num_files == tree.num_entries {
+            return true;
+        }
+    }
+
+    // some tools don't count the field itself in tree_size, and some count it twice
+    let off_by_one_field = [tree_size + field_bytes as u64, tree_size.wrapping_sub(field_bytes as u64)].iter().cloned().find(|&candidate| {
+        match parse_tree(dat_data, candidate) {
+            Some(Ok(ref tree)) => tree.num_files == tree.num_entries,
+            _ => false,
+        }
+    });
+    if let Some(correct) = off_by_one_field {
+        diagnoses.push(Diagnosis::error(
+            format!("its tree_size field is off by {}: it says {}, but the tree (with the field itself) is {} bytes, as some tools miscount it",
+                    field_bytes, tree_size, correct),
+            format!("set the {}-byte little-endian tree_size field at offset {} to {}", field_bytes, tree_size_offset, correct)));
+        return false;
+    }
+
+    let problem = match stated {
+        Some(Ok(tree)) => {
+            diagnoses.push(Diagnosis::error(
+                format!("its num_files field says it has {} entries, but its tree has {}, and the engine only reads num_files of them",
+                        tree.num_files, tree.num_entries),
+                format!("set the {}-byte little-endian num_files field at offset {} to {}",
+                        field_bytes, tree.num_files_offset, tree.num_entries)));
+            return true;
+        },
+        Some(Err(e)) => format!("its tree is corrupt: {}", e),
+        _ => format!("its tree_size field ({}) is larger than the archive", tree_size),
+    };
+    diagnoses.push(Diagnosis::error(problem, copy_again()));
+    false
+}
+
+/// The tree of an archive, as parsed with a given `tree_size`.
+struct Tree {
+    num_files: u64,
+    num_files_offset: usize,
+    num_entries: u64,
+}
+
+/// Parses the tree of `dat_data`, taking its `tree_size` field to be `tree_size`. Returns `None` if
+/// that doesn't fit in the archive, or the error of the first tree entry that doesn't parse.
+fn parse_tree(dat_data: &[u8], tree_size: u64) -> Option<Result<Tree, ::std::io::Error>> {
+    let field_bytes = field_bytes(dat_data);
+    let tree_end = body_len(dat_data) - 2 * field_bytes;
+    let tree_range: Range<usize> = match tree_size.checked_sub(field_bytes as u64) {
+        Some(len) if len as usize + field_bytes <= tree_end => (tree_end - len as usize)..tree_end,
+        _ => return None,
+    };
+
+    let num_files_offset = tree_range.start - field_bytes;
+    let num_files = read_field(&dat_data[num_files_offset..], field_bytes, false);
+    let mut num_entries = 0;
+    for tree_entry in TreeEntryRefs::new(&dat_data[tree_range], field_bytes == 8, num_files as usize) {
+        if let Err(e) = tree_entry {
+            return Some(Err(e));
+        }
+        num_entries += 1;
+    }
+
+    Some(Ok(Tree { num_files, num_files_offset, num_entries }))
+}
+
+/// Checks how entries' data is laid out in the data section.
+fn diagnose_layout(dat: &Dat, dat_path: &str, diagnoses: &mut Vec<Diagnosis>) {
+    let regions = layout::layout(dat);
+    let mut out_of_bounds = Vec::new();
+    let mut overlap_bytes = 0;
+    let mut gap_bytes = 0;
+    for region in &regions {
+        match region.kind {
+            Kind::OutOfBounds(ref entries) => out_of_bounds.extend(entries.iter().cloned()),
+            Kind::Overlap(_) => overlap_bytes += region.range.len(),
+            Kind::Gap => gap_bytes += region.range.len(),
+            Kind::Entry(_) | Kind::Shared(_) | Kind::Extension => {},
+        }
+    }
+    out_of_bounds.sort_unstable();
+    out_of_bounds.dedup();
+
+    if !out_of_bounds.is_empty() {
+        let names: Vec<String> = out_of_bounds.iter().map(|&i| dat.tree_entries()[i].to_string()).collect();
+        diagnoses.push(Diagnosis::error(
+            format!("{} entries' data extends past the end of the data section ({}): it was probably truncated, and its tree written again",
+                    names.len(), examples(&names)),
+            format!("{}, or extract the other entries with `fo2dat -xf {} --exclude PATTERN`", copy_again(), dat_path)));
+    }
+    if overlap_bytes > 0 {
+        diagnoses.push(Diagnosis::warning(
+            format!("{} bytes of its data section are used by several entries whose data only partly coincides (see `fo2dat layout --problems-only`)", overlap_bytes),
+            recompress(dat_path)));
+    }
+    if gap_bytes > 0 {
+        diagnoses.push(Diagnosis::warning(
+            format!("{} bytes of its data section aren't used by any entry (see `fo2dat layout --problems-only`)", gap_bytes),
+            recompress(dat_path)));
+    }
+}
+
+/// Checks each entry's data: that it decompresses, to its stated size and CRC-32 (if the archive
+/// has a CRC table), and that its flags describe it.
+fn diagnose_entries(dat: &Dat, dat_path: &str, diagnoses: &mut Vec<Diagnosis>) {
+    let crcs = match dat.crc_table() {
+        Ok(crcs) => crcs,
+        Err(e) => {
+            diagnoses.push(Diagnosis::warning(format!("its extension's CRC table is unreadable: {}", e), recompress(dat_path)));
+            None
+        },
+    };
+
+    let checked: Vec<CheckedEntry> = dat.tree_entries().par_iter().enumerate().map(|(i, tree_entry)| {
+        let entry_data = match dat.data(tree_entry) {
+            Ok(entry_data) => entry_data,
+            // out of bounds, which is diagnosed with the layout
+            Err(_) => return CheckedEntry { is_sound: true, anomalies: Vec::new() },
+        };
+
+        let mut crc = Crc::new();
+        let is_sound = match ::for_each_chunk_with(entry_data, Method::of_entry(tree_entry, entry_data), ::DEFAULT_CHUNK_SIZE, |chunk| {
+            crc.update(chunk);
+            Ok(())
+        }) {
+            Ok(len) => len == tree_entry.decompressed_size && crcs.as_ref().map_or(true, |crcs| crcs[i] == crc.sum()),
+            Err(_) => false,
+        };
+
+        CheckedEntry { is_sound, anomalies: tree_entry.anomalies(entry_data) }
+    }).collect();
+
+    let names_where = |f: &dyn Fn(&CheckedEntry) -> bool| -> Vec<String> {
+        dat.tree_entries().iter().zip(&checked).filter(|&(_, checked)| f(checked)).map(|(tree_entry, _)| tree_entry.to_string()).collect()
+    };
+
+    let corrupt = names_where(&|checked| !checked.is_sound);
+    if !corrupt.is_empty() {
+        diagnoses.push(Diagnosis::error(
+            format!("{} entries are corrupt: their data doesn't decompress, or not to their stated size{} ({})",
+                    corrupt.len(), if crcs.is_some() { " and CRC-32" } else { "" }, examples(&corrupt)),
+            format!("`fo2dat verify -f {}` lists them: extract the others with `fo2dat -xf {} --exclude PATTERN`, and get the corrupt ones from another copy",
+                    dat_path, dat_path)));
+    }
+
+    for &anomaly in &[Anomaly::FlaggedCompressed, Anomaly::FlaggedStored, Anomaly::SizeMismatch] {
+        let names = names_where(&|checked| checked.anomalies.contains(&anomaly));
+        if !names.is_empty() {
+            diagnoses.push(Diagnosis::warning(
+                format!("{} entries are misdescribed by their tree entries: each is {} ({})", names.len(), anomaly.description(), examples(&names)),
+                format!("the engine and extraction go by the data (see --strict-flags), but other tools may not: {}", recompress(dat_path))));
+        }
+    }
+}
+
+/// What checking an entry's data found.
+struct CheckedEntry {
+    /// Whether it decompresses to its stated size and CRC-32.
+    is_sound: bool,
+    anomalies: Vec<Anomaly>,
+}
+
+/// Returns the size of the archive's fields: 8 bytes for DAT64 archives, otherwise 4.
+fn field_bytes(dat_data: &[u8]) -> usize {
+    if dat_data.ends_with(DAT64_MAGIC) { 8 } else { 4 }
+}
+
+/// Returns the length of the archive's data without the DAT64 magic number, if it has one.
+fn body_len(dat_data: &[u8]) -> usize {
+    if dat_data.ends_with(DAT64_MAGIC) { dat_data.len() - DAT64_MAGIC.len() } else { dat_data.len() }
+}
+
+fn read_field(field: &[u8], field_bytes: usize, big_endian: bool) -> u64 {
+    match (field_bytes, big_endian) {
+        (8, false) => LittleEndian::read_u64(field),
+        (8, true) => BigEndian::read_u64(field),
+        (_, false) => LittleEndian::read_u32(field) as u64,
+        (_, true) => BigEndian::read_u32(field) as u64,
+    }
+}
+
+/// Returns the first few of `names`, and how many more there are.
+fn examples(names: &[String]) -> String {
+    let mut examples = names.iter().take(MAX_EXAMPLES).cloned().collect::<Vec<_>>().join(", ");
+    if names.len() > MAX_EXAMPLES {
+        examples.push_str(&format!(", and {} more", names.len() - MAX_EXAMPLES));
+    }
+    examples
+}
+
+fn copy_again() -> String {
+    "copy or download it again from its source".to_string()
+}
+
+fn recompress(dat_path: &str) -> String {
+    format!("`fo2dat recompress -f {} -o FIXED.dat` rewrites it without the problem", dat_path)
+}
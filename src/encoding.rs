@@ -0,0 +1,130 @@
+//! Decoding and encoding of the single-byte text encodings used by Fallout's text files.
+
+use std::io;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::str;
+
+use errors;
+use errors::Code;
+
+/// A text encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8. Invalid sequences are decoded as U+FFFD.
+    Utf8,
+    /// Windows-1252 (Western European), which the English, French, German, etc. releases use.
+    Windows1252,
+    /// CP866 (DOS Cyrillic), which Russian localizations use.
+    Cp866,
+}
+
+/// The characters of Windows-1252's `0x80..=0x9f` range, which (unlike the rest of the upper
+/// half) don't match ISO-8859-1. Unassigned bytes are mapped to their C1 control equivalent.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+/// The characters of CP866's upper half (`0x80..=0xff`).
+const CP866_HIGH: [char; 128] = [
+    '\u{410}', '\u{411}', '\u{412}', '\u{413}', '\u{414}', '\u{415}', '\u{416}', '\u{417}',
+    '\u{418}', '\u{419}', '\u{41a}', '\u{41b}', '\u{41c}', '\u{41d}', '\u{41e}', '\u{41f}',
+    '\u{420}', '\u{421}', '\u{422}', '\u{423}', '\u{424}', '\u{425}', '\u{426}', '\u{427}',
+    '\u{428}', '\u{429}', '\u{42a}', '\u{42b}', '\u{42c}', '\u{42d}', '\u{42e}', '\u{42f}',
+    '\u{430}', '\u{431}', '\u{432}', '\u{433}', '\u{434}', '\u{435}', '\u{436}', '\u{437}',
+    '\u{438}', '\u{439}', '\u{43a}', '\u{43b}', '\u{43c}', '\u{43d}', '\u{43e}', '\u{43f}',
+    '\u{2591}', '\u{2592}', '\u{2593}', '\u{2502}', '\u{2524}', '\u{2561}', '\u{2562}', '\u{2556}',
+    '\u{2555}', '\u{2563}', '\u{2551}', '\u{2557}', '\u{255d}', '\u{255c}', '\u{255b}', '\u{2510}',
+    '\u{2514}', '\u{2534}', '\u{252c}', '\u{251c}', '\u{2500}', '\u{253c}', '\u{255e}', '\u{255f}',
+    '\u{255a}', '\u{2554}', '\u{2569}', '\u{2566}', '\u{2560}', '\u{2550}', '\u{256c}', '\u{2567}',
+    '\u{2568}', '\u{2564}', '\u{2565}', '\u{2559}', '\u{2558}', '\u{2552}', '\u{2553}', '\u{256b}',
+    '\u{256a}', '\u{2518}', '\u{250c}', '\u{2588}', '\u{2584}', '\u{258c}', '\u{2590}', '\u{2580}',
+    '\u{440}', '\u{441}', '\u{442}', '\u{443}', '\u{444}', '\u{445}', '\u{446}', '\u{447}',
+    '\u{448}', '\u{449}', '\u{44a}', '\u{44b}', '\u{44c}', '\u{44d}', '\u{44e}', '\u{44f}',
+    '\u{401}', '\u{451}', '\u{404}', '\u{454}', '\u{407}', '\u{457}', '\u{40e}', '\u{45e}',
+    '\u{b0}', '\u{2219}', '\u{b7}', '\u{221a}', '\u{2116}', '\u{a4}', '\u{25a0}', '\u{a0}',
+];
+
+impl Encoding {
+
+    /// Parses an encoding's name (e.g. `cp1252`, `windows-1252`, `cp866`, `utf-8`).
+    pub fn from_name(name: &str) -> io::Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "cp1252" | "windows-1252" | "windows1252" => Ok(Encoding::Windows1252),
+            "cp866" | "ibm866" => Ok(Encoding::Cp866),
+            _ => {
+                let err_msg = format!("{}: unsupported encoding (supported: cp1252, cp866, utf-8)", name);
+                Err(Error::new(ErrorKind::InvalidInput, err_msg))
+            }
+        }
+    }
+
+    /// Decodes `data` into a string.
+    pub fn decode(self, data: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(data).into_owned(),
+            Encoding::Windows1252 => data.iter().map(|&b| decode_windows_1252(b)).collect(),
+            Encoding::Cp866 => data.iter().map(|&b| decode_cp866(b)).collect(),
+        }
+    }
+
+    /// Decodes `data` into a string, failing if it isn't valid in the encoding (which only UTF-8
+    /// data can be: every byte means something in the others).
+    pub fn decode_exact(self, data: &[u8]) -> io::Result<String> {
+        match self {
+            Encoding::Utf8 => str::from_utf8(data).map(String::from).map_err(|e| {
+                let err_msg = format!("isn't valid UTF-8 (at byte {})", e.valid_up_to());
+                errors::new(Code::Convert, ErrorKind::InvalidData, err_msg)
+            }),
+            _ => Ok(self.decode(data)),
+        }
+    }
+
+    /// Encodes `text`, failing if it has a character that the encoding doesn't have.
+    pub fn encode(self, text: &str) -> io::Result<Vec<u8>> {
+        let high = match self {
+            Encoding::Utf8 => return Ok(text.as_bytes().to_vec()),
+            Encoding::Windows1252 => &WINDOWS_1252_HIGH[..],
+            Encoding::Cp866 => &CP866_HIGH[..],
+        };
+
+        text.chars().map(|c| {
+            let is_latin1 = self == Encoding::Windows1252 && ('\u{a0}'..='\u{ff}').contains(&c);
+            if c.is_ascii() || is_latin1 {
+                Ok(c as u8)
+            } else if let Some(i) = high.iter().position(|&high_c| high_c == c) {
+                Ok(0x80 + i as u8)
+            } else {
+                let err_msg = format!("{:?} (U+{:04X}) can't be encoded in {}", c, c as u32, self.name());
+                Err(errors::new(Code::Convert, ErrorKind::InvalidData, err_msg))
+            }
+        }).collect()
+    }
+
+    /// Returns the encoding's name, as `from_name` parses it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Windows1252 => "cp1252",
+            Encoding::Cp866 => "cp866",
+        }
+    }
+}
+
+fn decode_windows_1252(b: u8) -> char {
+    match b {
+        0x80..=0x9f => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+        _ => b as char,
+    }
+}
+
+fn decode_cp866(b: u8) -> char {
+    match b {
+        0x80..=0xff => CP866_HIGH[(b - 0x80) as usize],
+        _ => b as char,
+    }
+}
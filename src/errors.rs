@@ -0,0 +1,171 @@
+//! Stable identifiers of errors, for wrappers and tests to tell errors apart by.
+//!
+//! fo2dat's errors are `io::Error`s, whose messages are for people and may be reworded. Each error
+//! also has a `Code` (e.g. `E_SIZE_MISMATCH`), which won't change: errors that fo2dat raises carry
+//! one, and any other error (e.g. from the OS) has the code of its `io::ErrorKind`. The CLI prints
+//! an error's code in brackets after its name (`fo2dat: [E_TREE_OOB] ...`).
+
+use std::error;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+/// An error's stable identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Code {
+    /// The data is a savegame (`SAVE.DAT`), not an archive.
+    Savegame,
+    /// The data is too small to be an archive.
+    TooSmall,
+    /// The archive's `file_size` field doesn't match its size.
+    FileSize,
+    /// The archive's tree (or its file count) doesn't fit in it.
+    TreeOutOfBounds,
+    /// A tree entry is malformed.
+    TreeEntry,
+    /// An entry's name is invalid, or may not be used (e.g. it's absolute, or isn't unique).
+    BadName,
+    /// An entry's data is out of bounds of the data section.
+    DataOutOfBounds,
+    /// An entry's data doesn't decompress.
+    Decompress,
+    /// An entry's data decompresses to a size other than its stated one.
+    SizeMismatch,
+    /// An entry's content doesn't match its CRC-32.
+    CrcMismatch,
+    /// An entry's compression flag misdescribes its data.
+    FlagMismatch,
+    /// Entries are corrupt (e.g. as `verify` found).
+    Corrupt,
+    /// fo2dat's extension (see `extension`) is malformed.
+    Extension,
+    /// An archive would be too large for its format.
+    TooLarge,
+    /// An entry (or other file) of another format, e.g. an FRM or a spec, is malformed.
+    Format,
+    /// An external converter (see `converter`) failed.
+    Convert,
+    /// A patch (see `patch`) is applied to another archive than the one it was made for.
+    BaseMismatch,
+    /// Applying a patch didn't rebuild the archive it was made for.
+    ResultMismatch,
+    /// A server answered unexpectedly.
+    Http,
+    /// A path resolves to outside of a sandbox.
+    Sandbox,
+    /// No such entry or file (`ErrorKind::NotFound`).
+    NotFound,
+    /// Permission denied (`ErrorKind::PermissionDenied`).
+    Permission,
+    /// A file exists already (`ErrorKind::AlreadyExists`).
+    Exists,
+    /// An invalid argument (`ErrorKind::InvalidInput`).
+    InvalidArg,
+    /// Invalid data (`ErrorKind::InvalidData`).
+    InvalidData,
+    /// Unsupported (`ErrorKind::Unsupported`).
+    Unsupported,
+    /// Data ended early (`ErrorKind::UnexpectedEof`).
+    UnexpectedEof,
+    /// Any other error.
+    Io,
+}
+
+impl Code {
+    /// Returns the code as it's printed (e.g. `E_SIZE_MISMATCH`).
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Code::Savegame => "E_SAVEGAME",
+            Code::TooSmall => "E_TOO_SMALL",
+            Code::FileSize => "E_FILE_SIZE",
+            Code::TreeOutOfBounds => "E_TREE_OOB",
+            Code::TreeEntry => "E_TREE_ENTRY",
+            Code::BadName => "E_BAD_NAME",
+            Code::DataOutOfBounds => "E_DATA_OOB",
+            Code::Decompress => "E_DECOMPRESS",
+            Code::SizeMismatch => "E_SIZE_MISMATCH",
+            Code::CrcMismatch => "E_CRC_MISMATCH",
+            Code::FlagMismatch => "E_FLAG_MISMATCH",
+            Code::Corrupt => "E_CORRUPT",
+            Code::Extension => "E_EXTENSION",
+            Code::TooLarge => "E_TOO_LARGE",
+            Code::Format => "E_FORMAT",
+            Code::Convert => "E_CONVERT",
+            Code::BaseMismatch => "E_BASE_MISMATCH",
+            Code::ResultMismatch => "E_RESULT_MISMATCH",
+            Code::Http => "E_HTTP",
+            Code::Sandbox => "E_SANDBOX",
+            Code::NotFound => "E_NOT_FOUND",
+            Code::Permission => "E_PERMISSION",
+            Code::Exists => "E_EXISTS",
+            Code::InvalidArg => "E_INVALID_ARG",
+            Code::InvalidData => "E_INVALID_DATA",
+            Code::Unsupported => "E_UNSUPPORTED",
+            Code::UnexpectedEof => "E_UNEXPECTED_EOF",
+            Code::Io => "E_IO",
+        }
+    }
+
+    /// Returns the code of errors of `kind` that don't carry one.
+    pub fn of_kind(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::NotFound => Code::NotFound,
+            ErrorKind::PermissionDenied => Code::Permission,
+            ErrorKind::AlreadyExists => Code::Exists,
+            ErrorKind::InvalidInput => Code::InvalidArg,
+            ErrorKind::InvalidData => Code::InvalidData,
+            ErrorKind::Unsupported => Code::Unsupported,
+            ErrorKind::UnexpectedEof => Code::UnexpectedEof,
+            _ => Code::Io,
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The payload of an `io::Error` that carries a code. It displays as its message alone.
+#[derive(Debug)]
+struct CodedError {
+    code: Code,
+    message: String,
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::Error for CodedError {}
+
+/// Returns an error of `kind` with `message`, that carries `code`.
+pub fn new<M: Into<String>>(code: Code, kind: ErrorKind, message: M) -> Error {
+    Error::new(kind, CodedError { code, message: message.into() })
+}
+
+/// Returns the code of `e`: the one it carries, or else its kind's.
+pub fn code(e: &Error) -> Code {
+    match e.get_ref().and_then(|inner| inner.downcast_ref::<CodedError>()) {
+        Some(coded) => coded.code,
+        None => Code::of_kind(e.kind()),
+    }
+}
+
+/// Returns `e` with `context` (e.g. the path of the file it's about) before its message, keeping
+/// its kind and code.
+pub fn with_context<C: fmt::Display>(e: Error, context: C) -> Error {
+    new(code(&e), e.kind(), format!("{}: {}", context, e))
+}
+
+/// Returns `e` as an error that carries `code`, unless it carries one already.
+pub fn or_code(e: Error, code: Code) -> Error {
+    if e.get_ref().is_some_and(|inner| inner.is::<CodedError>()) {
+        e
+    } else {
+        let message = e.to_string();
+        new(code, e.kind(), message)
+    }
+}
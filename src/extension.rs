@@ -0,0 +1,166 @@
+//! Reading and writing of fo2dat's archive extension: extra metadata stored in the otherwise
+//! unused space at the end of an archive's data section.
+//!
+//! The engine only ever reads the data section at the offsets given by tree entries, so bytes
+//! after the last entry's data are ignored by it (and by other tools). The extension uses that
+//! space, which keeps archives that have one readable by everything that reads plain DAT2. It's
+//! located through a trailer that ends the data section:
+//!
+//! ```text
+//! extension := block* | size (u32, of the whole extension) | "FO2DATX1"
+//! block     := tag (4 bytes) | len (u32) | payload (len bytes)
+//! ```
+//!
+//! Unknown blocks are skipped, so blocks can be added without breaking older readers. The blocks
+//! currently written are:
+//!
+//! - `CRC1`: the CRC-32 of each tree entry's decompressed content (u32, in tree order)
+//! - `TOOL`: the tool (and its version) that created the archive, as UTF-8 (e.g. `fo2dat 0.0.10`)
+//! - `NOTE`: a freetext comment, as UTF-8
+//!
+//! Multi-byte numbers are little-endian.
+//!
+//! A tool that rewrites the tree without knowing about the extension may leave a stale one, so
+//! readers should check a table against the tree before trusting it.
+
+use std::borrow::Cow;
+use std::io;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::ops::Range;
+use byteorder::{LittleEndian, ByteOrder, WriteBytesExt};
+
+use errors;
+use errors::Code;
+
+/// The magic number that ends the extension.
+pub const MAGIC: &[u8; 8] = b"FO2DATX1";
+
+/// The tag of the block holding each entry's CRC-32.
+pub const CRC_TABLE_TAG: [u8; 4] = *b"CRC1";
+
+/// The tag of the block naming the tool that created the archive.
+pub const TOOL_TAG: [u8; 4] = *b"TOOL";
+
+/// The tag of the block holding a freetext comment.
+pub const COMMENT_TAG: [u8; 4] = *b"NOTE";
+
+const TRAILER_SIZE: usize = 4 + MAGIC.len();
+const BLOCK_HEADER_SIZE: usize = 4 + 4;
+
+/// An extension block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block<'a> {
+    pub tag: [u8; 4],
+    pub payload: &'a [u8],
+}
+
+/// The extension of an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension<'a> {
+    /// The byte range of the extension in the data section.
+    pub range: Range<usize>,
+    pub blocks: Vec<Block<'a>>,
+}
+
+impl <'a> Extension<'a> {
+
+    /// Parses the extension at the end of `data_section` (an archive's `data`), if it has one.
+    pub fn parse(data_section: &'a [u8]) -> io::Result<Option<Self>> {
+        if data_section.len() < TRAILER_SIZE || !data_section.ends_with(MAGIC) {
+            return Ok(None);
+        }
+
+        let trailer_start = data_section.len() - TRAILER_SIZE;
+        let size = LittleEndian::read_u32(&data_section[trailer_start..]) as usize;
+
+        if size < TRAILER_SIZE || size > data_section.len() {
+            let err_msg = format!("extension size ({}) is out of bounds of the data section ({} bytes)", size, data_section.len());
+            return Err(errors::new(Code::Extension, ErrorKind::InvalidData, err_msg));
+        }
+
+        let start = data_section.len() - size;
+        let mut remaining = &data_section[start..trailer_start];
+        let mut blocks = Vec::new();
+
+        while !remaining.is_empty() {
+            if remaining.len() < BLOCK_HEADER_SIZE {
+                return Err(errors::new(Code::Extension, ErrorKind::InvalidData, "extension ends with a truncated block header"));
+            }
+
+            let mut tag = [0; 4];
+            tag.copy_from_slice(&remaining[..4]);
+            let len = LittleEndian::read_u32(&remaining[4..]) as usize;
+
+            let payload = match remaining[BLOCK_HEADER_SIZE..].get(..len) {
+                Some(payload) => payload,
+                None => {
+                    let err_msg = format!("extension block {} ({} bytes) is out of bounds", String::from_utf8_lossy(&tag), len);
+                    return Err(errors::new(Code::Extension, ErrorKind::InvalidData, err_msg));
+                }
+            };
+
+            blocks.push(Block { tag, payload });
+            remaining = &remaining[BLOCK_HEADER_SIZE + len..];
+        }
+
+        Ok(Some(Extension { range: start..data_section.len(), blocks }))
+    }
+
+    /// Returns the payload of the first block tagged `tag`, if there is one.
+    pub fn block(&self, tag: [u8; 4]) -> Option<&'a [u8]> {
+        self.blocks.iter().find(|block| block.tag == tag).map(|block| block.payload)
+    }
+
+    /// Returns the CRC-32 of each tree entry, in tree order, if the extension has a CRC table.
+    /// `num_entries` is the number of entries in the tree, which a current table matches.
+    pub fn crc_table(&self, num_entries: usize) -> io::Result<Option<Vec<u32>>> {
+        let payload = match self.block(CRC_TABLE_TAG) {
+            Some(payload) => payload,
+            None => return Ok(None),
+        };
+
+        if payload.len() != num_entries * 4 {
+            let err_msg = format!("CRC table has {} bytes, which doesn't match the {} entries in the tree (was the archive modified by another tool?)",
+                                  payload.len(), num_entries);
+            return Err(errors::new(Code::Extension, ErrorKind::InvalidData, err_msg));
+        }
+
+        Ok(Some(payload.chunks(4).map(LittleEndian::read_u32).collect()))
+    }
+
+    /// Returns the tool that created the archive, if the extension names it. Invalid UTF-8 is
+    /// replaced, rather than failing.
+    pub fn tool(&self) -> Option<Cow<'a, str>> {
+        self.block(TOOL_TAG).map(String::from_utf8_lossy)
+    }
+
+    /// Returns the archive's comment, if the extension has one.
+    pub fn comment(&self) -> Option<Cow<'a, str>> {
+        self.block(COMMENT_TAG).map(String::from_utf8_lossy)
+    }
+}
+
+/// Writes an extension holding `blocks` to `out`, returning its size.
+pub fn write<W: Write>(out: &mut W, blocks: &[Block]) -> io::Result<usize> {
+    let mut size = TRAILER_SIZE;
+
+    for block in blocks {
+        out.write_all(&block.tag)?;
+        out.write_u32::<LittleEndian>(block.payload.len() as u32)?;
+        out.write_all(block.payload)?;
+        size += BLOCK_HEADER_SIZE + block.payload.len();
+    }
+
+    out.write_u32::<LittleEndian>(size as u32)?;
+    out.write_all(MAGIC)?;
+
+    Ok(size)
+}
+
+/// Returns a `CRC1` block's payload for entries with the given CRC-32s.
+pub fn crc_table_payload(crcs: &[u32]) -> Vec<u8> {
+    let mut payload = vec![0; crcs.len() * 4];
+    LittleEndian::write_u32_into(crcs, &mut payload);
+    payload
+}
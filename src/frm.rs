@@ -0,0 +1,245 @@
+//! Parsing of Fallout FRM sprites.
+//!
+//! FRMs hold one or more frames of 8-bit paletted pixels, optionally for each of the six
+//! directions a critter/object can face. All multi-byte numbers are big-endian.
+
+use std::io;
+use std::io::ErrorKind;
+use byteorder::{BigEndian, ByteOrder};
+
+use errors;
+use errors::Code;
+
+/// The number of directions an FRM can hold frames for.
+pub const NUM_DIRECTIONS: usize = 6;
+
+/// The palette index FRMs use for transparent pixels.
+pub const TRANSPARENT_INDEX: u8 = 0;
+
+const HEADER_SIZE: usize = 0x3e;
+const FRAME_HEADER_SIZE: usize = 12;
+
+/// A parsed FRM, borrowing its pixel data from the underlying FRM data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frm<'a> {
+    pub version: u32,
+    pub fps: u16,
+    pub action_frame: u16,
+    pub frames_per_direction: u16,
+    pub shift_x: [i16; NUM_DIRECTIONS],
+    pub shift_y: [i16; NUM_DIRECTIONS],
+    /// The frames of each direction held by the FRM. Most non-critter FRMs only hold one
+    /// direction.
+    pub directions: Vec<Vec<Frame<'a>>>,
+}
+
+/// A single frame of an FRM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub offset_x: i16,
+    pub offset_y: i16,
+    /// Row-major palette indices, `width * height` long.
+    pub pixels: &'a [u8],
+}
+
+/// An image made by laying out every frame of an FRM, as returned by `Frm::sprite_sheet`.
+pub struct SpriteSheet {
+    pub width: usize,
+    pub height: usize,
+    /// Row-major palette indices, `width * height` long.
+    pub pixels: Vec<u8>,
+}
+
+impl <'a> Frm<'a> {
+
+    /// Attempts to parse `data` as an FRM.
+    pub fn parse(data: &'a [u8]) -> io::Result<Self> {
+        if data.len() < HEADER_SIZE {
+            let err_msg = format!("FRM data ({} bytes) is too small to fit an FRM header", data.len());
+            return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+        }
+
+        let version = BigEndian::read_u32(&data[0x0..]);
+        let fps = BigEndian::read_u16(&data[0x4..]);
+        let action_frame = BigEndian::read_u16(&data[0x6..]);
+        let frames_per_direction = BigEndian::read_u16(&data[0x8..]);
+
+        let mut shift_x = [0; NUM_DIRECTIONS];
+        let mut shift_y = [0; NUM_DIRECTIONS];
+        let mut direction_offsets = [0; NUM_DIRECTIONS];
+        for i in 0..NUM_DIRECTIONS {
+            shift_x[i] = BigEndian::read_i16(&data[0xa + 2*i..]);
+            shift_y[i] = BigEndian::read_i16(&data[0x16 + 2*i..]);
+            direction_offsets[i] = BigEndian::read_u32(&data[0x22 + 4*i..]) as usize;
+        }
+
+        // FRMs that only hold one direction leave the other directions' offsets zeroed
+        let num_directions = if direction_offsets[1..].iter().all(|&offset| offset == 0) {
+            1
+        } else {
+            NUM_DIRECTIONS
+        };
+
+        let frame_area = &data[HEADER_SIZE..];
+        let mut directions = Vec::with_capacity(num_directions);
+
+        for &direction_offset in &direction_offsets[..num_directions] {
+            let mut offset = direction_offset;
+            let mut frames = Vec::with_capacity(frames_per_direction as usize);
+
+            for _ in 0..frames_per_direction {
+                let (frame, frame_size) = Frame::parse(frame_area.get(offset..).unwrap_or(&[]))?;
+                frames.push(frame);
+                offset += frame_size;
+            }
+
+            directions.push(frames);
+        }
+
+        Ok(Frm {
+            version,
+            fps,
+            action_frame,
+            frames_per_direction,
+            shift_x,
+            shift_y,
+            directions,
+        })
+    }
+
+    /// Lays out every frame of the FRM on one image: a row per direction and a column per frame.
+    /// Each cell is as large as the largest frame, and frames are anchored at the bottom-center of
+    /// their cell, which is how the engine anchors them on screen.
+    pub fn sprite_sheet(&self) -> SpriteSheet {
+        let frames = || self.directions.iter().flat_map(|frames| frames.iter());
+        let cell_width = frames().map(|frame| frame.width).max().unwrap_or(0);
+        let cell_height = frames().map(|frame| frame.height).max().unwrap_or(0);
+        let num_columns = self.directions.iter().map(|frames| frames.len()).max().unwrap_or(0);
+
+        let width = cell_width * num_columns;
+        let height = cell_height * self.directions.len();
+        let mut pixels = vec![0; width * height];
+
+        for (row, frames) in self.directions.iter().enumerate() {
+            for (column, frame) in frames.iter().enumerate() {
+                let left = column * cell_width + (cell_width - frame.width) / 2;
+                let top = row * cell_height + (cell_height - frame.height);
+
+                if frame.width == 0 {
+                    continue;
+                }
+
+                for (y, frame_row) in frame.pixels.chunks(frame.width).enumerate() {
+                    let start = (top + y) * width + left;
+                    pixels[start..start+frame.width].copy_from_slice(frame_row);
+                }
+            }
+        }
+
+        SpriteSheet { width, height, pixels }
+    }
+}
+
+impl <'a> Frame<'a> {
+
+    /// Attempts to parse `data` as a frame. Returns the frame, along with the number of bytes read
+    /// to parse it.
+    fn parse(data: &'a [u8]) -> io::Result<(Self, usize)> {
+        if data.len() < FRAME_HEADER_SIZE {
+            let err_msg = "remaining FRM data is too small to fit a frame header";
+            return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+        }
+
+        let width = BigEndian::read_u16(&data[0x0..]) as usize;
+        let height = BigEndian::read_u16(&data[0x2..]) as usize;
+        let size = BigEndian::read_u32(&data[0x4..]) as usize;
+        let offset_x = BigEndian::read_i16(&data[0x8..]);
+        let offset_y = BigEndian::read_i16(&data[0xa..]);
+
+        if size != width * height {
+            let err_msg = format!("frame size ({}) doesn't match its dimensions ({}x{})", size, width, height);
+            return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+        }
+
+        match data.get(FRAME_HEADER_SIZE..FRAME_HEADER_SIZE+size) {
+            Some(pixels) => {
+                let frame = Frame { width, height, offset_x, offset_y, pixels };
+                Ok((frame, FRAME_HEADER_SIZE + size))
+            },
+            None => {
+                let err_msg = "remaining FRM data is too small to fit the frame's pixels";
+                Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "image")]
+impl <'a> Frm<'a> {
+
+    /// Writes the FRM's sprite sheet as a PNG, coloring it with `palette`. Palette index `0` is
+    /// written as transparent. Fails if the FRM has no frames, or a frame without pixels (of zero
+    /// width or height), which a PNG can't hold.
+    pub fn write_png<W: ::std::io::Write>(&self, out: &mut W, palette: &::pal::Palette) -> io::Result<()> {
+        if self.directions.iter().all(|frames| frames.is_empty()) {
+            return Err(errors::new(Code::Format, ErrorKind::InvalidData, "FRM has no frames to write as a PNG"));
+        }
+        for (direction, frames) in self.directions.iter().enumerate() {
+            if let Some(i) = frames.iter().position(|frame| frame.width == 0 || frame.height == 0) {
+                let frame = &frames[i];
+                let err_msg = format!("frame {} of direction {} is {}x{}, which can't be written as a PNG", i, direction, frame.width, frame.height);
+                return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+            }
+        }
+
+        let sheet = self.sprite_sheet();
+        ::png::write_indexed(out, sheet.width, sheet.height, &sheet.pixels, palette, Some(TRANSPARENT_INDEX))
+    }
+}
+
+#[cfg(all(test, feature = "image"))]
+mod tests {
+    use super::*;
+    use pal::{Palette, NUM_COLORS};
+
+    /// Returns a one-direction FRM of frames with the given dimensions.
+    fn frm_data(frames: &[(u16, u16)]) -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE];
+        data[0x3] = 4;
+        data[0x9] = frames.len() as u8;
+        for &(width, height) in frames {
+            let size = width as usize * height as usize;
+            data.extend_from_slice(&width.to_be_bytes());
+            data.extend_from_slice(&height.to_be_bytes());
+            data.extend_from_slice(&(size as u32).to_be_bytes());
+            data.extend_from_slice(&[0; 4]);
+            data.extend(vec![1; size]);
+        }
+        data
+    }
+
+    fn png_of(frames: &[(u16, u16)]) -> io::Result<Vec<u8>> {
+        let palette = Palette { colors: [[0; 3]; NUM_COLORS] };
+        let data = frm_data(frames);
+        let mut png = Vec::new();
+        Frm::parse(&data)?.write_png(&mut png, &palette)?;
+        Ok(png)
+    }
+
+    #[test]
+    fn writes_png_of_frames() {
+        let png = png_of(&[(3, 2), (1, 4)]).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+        // the sheet is two 3x4 cells side by side
+        assert_eq!(&png[16..24], &[0, 0, 0, 6, 0, 0, 0, 4]);
+    }
+
+    #[test]
+    fn rejects_empty_frames() {
+        for frames in &[&[(0, 0)][..], &[(3, 2), (0, 5)][..], &[(3, 0)][..], &[][..]] {
+            assert_eq!(errors::code(&png_of(frames).unwrap_err()), Code::Format, "{:?}", frames);
+        }
+    }
+}
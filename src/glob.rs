@@ -0,0 +1,243 @@
+//! Matching of entry paths against shell-style wildcard patterns.
+//!
+//! Patterns support `*` (any run of characters), `?` (any one character) and bracket expressions
+//! (`[abc]`, `[a-z]`, `[!abc]`). Patterns and paths are normalized as entry names are (see
+//! `names`), so matching is case-insensitive and treats `\` and `/` alike.
+//!
+//! Which parts of a path a pattern binds to mirrors tar:
+//!
+//! - An anchored pattern must match from the start of a path, while an unanchored one may match
+//!   starting at any component (so `*.frm` and `critters/*.frm` both match `art/critters/a.frm`).
+//! - A pattern that matches a directory also matches everything under it.
+//! - Wildcards may or may not match `/`. If they can't, `art/*.frm` only matches FRMs directly in
+//!   `art/`.
+
+use names::normalize;
+
+/// Options controlling how a pattern binds to paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchOptions {
+    pub anchored: bool,
+    pub wildcards_match_slash: bool,
+}
+
+/// A compiled wildcard pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+    options: MatchOptions,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    AnyChar,
+    AnyRun,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl Pattern {
+
+    /// Compiles `pattern`. A `[` without a closing `]` is matched literally, and trailing
+    /// separators are ignored.
+    pub fn new(pattern: &str, options: MatchOptions) -> Self {
+        let chars: Vec<char> = normalize(pattern).chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '*' => {
+                    if tokens.last() != Some(&Token::AnyRun) {
+                        tokens.push(Token::AnyRun);
+                    }
+                },
+                '?' => tokens.push(Token::AnyChar),
+                '[' => {
+                    if let Some((class, len)) = parse_class(&chars[i..]) {
+                        tokens.push(class);
+                        i += len;
+                        continue;
+                    }
+                    tokens.push(Token::Char('['));
+                },
+                c => tokens.push(Token::Char(c)),
+            }
+            i += 1;
+        }
+
+        Pattern { tokens, options }
+    }
+
+    /// Returns `true` if the pattern matches `path` (an entry's path, with either separator).
+    pub fn matches(&self, path: &str) -> bool {
+        let path: Vec<char> = normalize(path).chars().collect();
+
+        let component_starts = (0..path.len()).filter(|&i| i == 0 || path[i-1] == '/');
+        let component_ends: Vec<usize> = (1..=path.len()).filter(|&i| i == path.len() || path[i] == '/').collect();
+
+        for start in component_starts {
+            if self.options.anchored && start > 0 {
+                break;
+            }
+            for &end in component_ends.iter().filter(|&&end| end > start) {
+                if self.matches_from(&self.tokens, &path[start..end]) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Returns `true` if `tokens` match the whole of `path`.
+    ///
+    /// Matching is left to right, and a mismatch backtracks to the last `*`, which takes one more
+    /// character. Earlier `*`s never need to be retried (whatever they'd take, the last one could
+    /// take instead), so this takes at most the product of the lengths of `tokens` and `path`.
+    fn matches_from(&self, tokens: &[Token], path: &[char]) -> bool {
+        let matches_wildcard = |c: char| c != '/' || self.options.wildcards_match_slash;
+
+        let mut t = 0;
+        let mut p = 0;
+        // the token after the last `*`, and where in the path matching it resumes
+        let mut last_run: Option<(usize, usize)> = None;
+
+        while p < path.len() {
+            let matched = match tokens.get(t) {
+                Some(&Token::AnyRun) => {
+                    t += 1;
+                    last_run = Some((t, p));
+                    continue;
+                },
+                Some(&Token::Char(expected)) => path[p] == expected,
+                Some(&Token::AnyChar) => matches_wildcard(path[p]),
+                Some(&Token::Class { negated, ref ranges }) => {
+                    let in_class = ranges.iter().any(|&(low, high)| low <= path[p] && path[p] <= high);
+                    matches_wildcard(path[p]) && in_class != negated
+                },
+                None => false,
+            };
+
+            if matched {
+                t += 1;
+                p += 1;
+                continue;
+            }
+
+            match last_run {
+                Some((run_t, run_p)) if matches_wildcard(path[run_p]) => {
+                    last_run = Some((run_t, run_p + 1));
+                    t = run_t;
+                    p = run_p + 1;
+                },
+                // and if the last `*` can't take a `/`, no earlier one can either
+                _ => return false,
+            }
+        }
+
+        tokens[t..].iter().all(|token| *token == Token::AnyRun)
+    }
+}
+
+/// A set of include and exclude patterns that selects entries.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Filter {
+    /// If non-empty, only paths matching at least one of these are selected.
+    pub includes: Vec<Pattern>,
+    /// Paths matching any of these aren't selected, even if they match an include.
+    pub excludes: Vec<Pattern>,
+    /// If non-empty, only paths that also match at least one of these (e.g. a `Category`'s
+    /// patterns) are selected.
+    pub categories: Vec<Pattern>,
+    /// If non-empty, only paths that also match at least one of these (e.g. a language's
+    /// patterns) are selected.
+    pub languages: Vec<Pattern>,
+}
+
+impl Filter {
+
+    /// Returns `true` if the filter has no patterns, and so selects everything.
+    pub fn is_empty(&self) -> bool {
+        self.includes.is_empty() && self.excludes.is_empty() && self.categories.is_empty() && self.languages.is_empty()
+    }
+
+    /// Returns `true` if `path` is selected by the filter.
+    pub fn is_selected(&self, path: &str) -> bool {
+        let is_included = self.includes.is_empty() || self.includes.iter().any(|pattern| pattern.matches(path));
+        let is_categorized = self.categories.is_empty() || self.categories.iter().any(|pattern| pattern.matches(path));
+        let is_localized = self.languages.is_empty() || self.languages.iter().any(|pattern| pattern.matches(path));
+        is_included && is_categorized && is_localized && !self.excludes.iter().any(|pattern| pattern.matches(path))
+    }
+}
+
+/// Parses the bracket expression at the start of `chars`, returning it and its length.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 1;
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        first = false;
+
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some('-'), Some(&high)) if high != ']' => {
+                ranges.push((c, high));
+                i += 3;
+            },
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str, anchored: bool, wildcards_match_slash: bool) -> bool {
+        Pattern::new(pattern, MatchOptions { anchored, wildcards_match_slash }).matches(path)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.frm", "art/critters/hmjmpsaa.frm", false, false));
+        assert!(matches("critters/hm?mps*.FRM", "ART\\CRITTERS\\HMJMPSAA.FRM", false, false));
+        assert!(matches("art/*/[a-h]*.frm", "art/critters/hmjmpsaa.frm", true, false));
+        assert!(!matches("art/*/[!a-h]*.frm", "art/critters/hmjmpsaa.frm", true, false));
+        assert!(!matches("critters/*.frm", "art/critters/hmjmpsaa.frm", true, false));
+        assert!(!matches("*a*b", "aaa", false, false));
+        assert!(matches("a*b*c", "abxbxc", false, false));
+        assert!(matches("art", "art/critters/hmjmpsaa.frm", true, false));
+    }
+
+    #[test]
+    fn wildcards_and_slashes() {
+        assert!(!matches("art/*.frm", "art/critters/hmjmpsaa.frm", true, false));
+        assert!(matches("art/*.frm", "art/critters/hmjmpsaa.frm", true, true));
+        assert!(!matches("art?critters", "art/critters", true, false));
+        assert!(matches("art?critters", "art/critters", true, true));
+        assert!(!matches("a*x*z", "ab/xz", true, false));
+        assert!(matches("a*x*z", "ab/xz", true, true));
+    }
+
+    #[test]
+    fn stars_dont_backtrack_exponentially() {
+        // which, tried recursively, takes exponential time
+        let path = "a".repeat(200);
+        let pattern = format!("{}b", "*a".repeat(20));
+        assert!(!matches(&pattern, &path, true, true));
+        assert!(matches(&pattern[..pattern.len() - 1], &path, true, true));
+    }
+}
@@ -0,0 +1,367 @@
+//! Files left out of archives created from a directory: those that `.datignore` files exclude,
+//! and optionally version control's files (`--exclude-vcs`).
+//!
+//! A `.datignore` file has the syntax of a `.gitignore`, and applies to the directory it's in and
+//! everything under it, with a subdirectory's rules taking precedence over its parents':
+//!
+//! - Blank lines, and lines starting with `#`, are skipped. `\` escapes the next character (e.g.
+//!   `\#` or `\!` at the start of a pattern, or a trailing space).
+//! - `*` matches any run of characters except `/`, `?` any one of them, and `[...]` one of a set.
+//!   `**/` matches any number of directories, and a trailing `/**` everything inside of one.
+//! - A pattern with a `/` at its start or in its middle matches paths relative to the directory
+//!   of the `.datignore`; any other matches a file or directory of that name at any depth.
+//! - A pattern ending in `/` only matches directories.
+//! - A pattern starting with `!` includes what earlier patterns excluded. The last pattern that
+//!   matches a path decides, but nothing under an excluded directory can be included, as the
+//!   directory isn't walked.
+//!
+//! Unlike in git, patterns are matched ignoring ASCII case, as entry names are (see `names`).
+//! `.datignore` files are never packed themselves.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use errors;
+
+/// The name of the files that list what to leave out of an archive.
+pub const IGNORE_FILE: &str = ".datignore";
+
+/// The names of the files and directories of version control systems, which `--exclude-vcs`
+/// leaves out (as tar's does).
+pub const VCS_NAMES: &[&str] = &[
+    ".git", ".gitignore", ".gitattributes", ".gitmodules",
+    ".svn", ".hg", ".hgignore", ".hgtags", ".bzr", ".bzrignore", ".bzrtags",
+    "CVS", ".cvsignore", "RCS", "SCCS", "_darcs", ".arch-ids", "{arch}",
+];
+
+/// The rules that decide which files under a directory are left out, as a walk of the directory
+/// gathers them.
+#[derive(Debug, Clone, Default)]
+pub struct Ignores {
+    rules: Vec<Rule>,
+    exclude_vcs: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    /// The components of the path of the directory of the `.datignore` the rule is from.
+    base: Vec<String>,
+    tokens: Vec<Token>,
+    is_negated: bool,
+    is_dir_only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    /// `?`
+    AnyChar,
+    /// `*`
+    AnyRun,
+    /// `**/`: any number of directories, including none.
+    AnyDirs,
+    /// A trailing `**`: anything at all.
+    AnyPath,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl Ignores {
+
+    /// Creates rules that leave out no file but `.datignore` files and, if `exclude_vcs`, version
+    /// control's files.
+    pub fn new(exclude_vcs: bool) -> Self {
+        Ignores { rules: Vec::new(), exclude_vcs }
+    }
+
+    /// Returns the rules that apply to the file or directory at `components`, relative to `root`:
+    /// those of the `.datignore` files of the directories on the way to it (a directory's own is
+    /// left for `enter`).
+    pub fn load(root: &Path, components: &[String], exclude_vcs: bool) -> io::Result<Self> {
+        let mut ignores = Ignores::new(exclude_vcs);
+        if components.is_empty() {
+            return Ok(ignores);
+        }
+
+        let mut dir = root.to_path_buf();
+        ignores.add_file(&dir, &[])?;
+        for i in 1..components.len() {
+            dir.push(&components[i - 1]);
+            ignores.add_file(&dir, &components[..i])?;
+        }
+        Ok(ignores)
+    }
+
+    /// Returns the rules that apply under `dir`, whose path relative to the root is
+    /// `dir_components`: these, with those of its `.datignore`, if it has one.
+    pub fn enter(&self, dir: &Path, dir_components: &[String]) -> io::Result<Cow<'_, Self>> {
+        if !dir.join(IGNORE_FILE).is_file() {
+            return Ok(Cow::Borrowed(self));
+        }
+        let mut ignores = self.clone();
+        ignores.add_file(dir, dir_components)?;
+        Ok(Cow::Owned(ignores))
+    }
+
+    fn add_file(&mut self, dir: &Path, dir_components: &[String]) -> io::Result<()> {
+        let path = dir.join(IGNORE_FILE);
+        match fs::read(&path) {
+            Ok(bytes) => {
+                self.add(&String::from_utf8_lossy(&bytes), dir_components);
+                Ok(())
+            },
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(errors::with_context(e, path.display())),
+        }
+    }
+
+    /// Adds the rules in `text`, a `.datignore`'s content, for the directory at `dir_components`.
+    pub fn add(&mut self, text: &str, dir_components: &[String]) {
+        self.rules.extend(text.lines().filter_map(|line| Rule::parse(line, dir_components)));
+    }
+
+    /// Returns `true` if the file or directory at `components` (relative to the root) is left out.
+    pub fn is_ignored(&self, components: &[String], is_dir: bool) -> bool {
+        let name = match components.last() {
+            Some(name) => name,
+            None => return false,
+        };
+        if name == IGNORE_FILE || (self.exclude_vcs && VCS_NAMES.contains(&name.as_str())) {
+            return true;
+        }
+
+        self.rules.iter().rev()
+            .find(|rule| rule.matches(components, is_dir))
+            .is_some_and(|rule| !rule.is_negated)
+    }
+}
+
+impl Rule {
+
+    /// Parses a line of a `.datignore` in the directory at `base`, returning `None` if it has no
+    /// pattern (e.g. it's a comment).
+    fn parse(line: &str, base: &[String]) -> Option<Self> {
+        let mut pattern = line.trim_end_matches('\r');
+        if pattern.starts_with('#') {
+            return None;
+        }
+
+        // trailing spaces are trimmed, unless they're escaped
+        while pattern.ends_with(' ') && !pattern.ends_with("\\ ") {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+
+        let is_negated = pattern.starts_with('!');
+        if is_negated {
+            pattern = &pattern[1..];
+        }
+        let is_dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        if pattern.is_empty() {
+            return None;
+        }
+
+        let is_anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/');
+        let mut tokens = if is_anchored { Vec::new() } else { vec![Token::AnyDirs] };
+        tokens.extend(tokenize(pattern));
+
+        Some(Rule { base: base.to_vec(), tokens, is_negated, is_dir_only })
+    }
+
+    fn matches(&self, components: &[String], is_dir: bool) -> bool {
+        if (self.is_dir_only && !is_dir) || components.len() <= self.base.len() {
+            return false;
+        }
+        let is_under_base = self.base.iter().zip(components).all(|(base, component)| base.eq_ignore_ascii_case(component));
+        if !is_under_base {
+            return false;
+        }
+
+        let path: Vec<char> = components[self.base.len()..].join("/").chars().map(|c| c.to_ascii_lowercase()).collect();
+        matches(&self.tokens, &path)
+    }
+}
+
+fn tokenize(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                tokens.push(Token::Char(chars[i + 1].to_ascii_lowercase()));
+                i += 2;
+                continue;
+            },
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let is_component_start = i == 0 || chars[i - 1] == '/';
+                match chars.get(i + 2) {
+                    Some('/') if is_component_start => {
+                        tokens.push(Token::AnyDirs);
+                        i += 3;
+                    },
+                    None if is_component_start => {
+                        tokens.push(Token::AnyPath);
+                        i += 2;
+                    },
+                    // elsewhere, `**` is just two `*`s
+                    _ => {
+                        tokens.push(Token::AnyRun);
+                        i += 2;
+                    },
+                }
+                continue;
+            },
+            '*' => {
+                if tokens.last() != Some(&Token::AnyRun) {
+                    tokens.push(Token::AnyRun);
+                }
+            },
+            '?' => tokens.push(Token::AnyChar),
+            '[' => {
+                if let Some((class, len)) = parse_class(&chars[i..]) {
+                    tokens.push(class);
+                    i += len;
+                    continue;
+                }
+                tokens.push(Token::Char('['));
+            },
+            c => tokens.push(Token::Char(c.to_ascii_lowercase())),
+        }
+        i += 1;
+    }
+
+    tokens
+}
+
+/// Parses the bracket expression at the start of `chars`, returning it and its length.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 1;
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+
+    loop {
+        let c = chars.get(i)?.to_ascii_lowercase();
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        first = false;
+
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some('-'), Some(&high)) if high != ']' => {
+                ranges.push((c, high.to_ascii_lowercase()));
+                i += 3;
+            },
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            },
+        }
+    }
+}
+
+/// Returns `true` if `tokens` match all of `path`.
+fn matches(tokens: &[Token], path: &[char]) -> bool {
+    let (token, remaining_tokens) = match tokens.split_first() {
+        Some(split) => split,
+        None => return path.is_empty(),
+    };
+
+    match *token {
+        Token::AnyRun => {
+            for len in 0..=path.len() {
+                if matches(remaining_tokens, &path[len..]) {
+                    return true;
+                }
+                if len < path.len() && path[len] == '/' {
+                    return false;
+                }
+            }
+            false
+        },
+        Token::AnyDirs => {
+            matches(remaining_tokens, path) ||
+                (0..path.len()).any(|i| path[i] == '/' && matches(remaining_tokens, &path[i + 1..]))
+        },
+        Token::AnyPath => true,
+        _ if path.is_empty() => false,
+        Token::Char(expected) => path[0] == expected && matches(remaining_tokens, &path[1..]),
+        Token::AnyChar => path[0] != '/' && matches(remaining_tokens, &path[1..]),
+        Token::Class { negated, ref ranges } => {
+            let in_class = ranges.iter().any(|&(low, high)| low <= path[0] && path[0] <= high);
+            path[0] != '/' && in_class != negated && matches(remaining_tokens, &path[1..])
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn components(path: &str) -> Vec<String> {
+        path.split('/').map(String::from).collect()
+    }
+
+    fn ignored(rules: &str, path: &str, is_dir: bool) -> bool {
+        let mut ignores = Ignores::new(false);
+        ignores.add(rules, &[]);
+        ignores.is_ignored(&components(path), is_dir)
+    }
+
+    #[test]
+    fn negation() {
+        assert!(ignored("*.msg\n!keep.msg", "text/drop.msg", false));
+        assert!(!ignored("*.msg\n!keep.msg", "text/keep.msg", false));
+        // the last pattern that matches decides
+        assert!(ignored("!keep.msg\n*.msg", "text/keep.msg", false));
+        assert!(ignored("\\!keep.msg", "!keep.msg", false));
+        assert!(!ignored("\\!keep.msg", "keep.msg", false));
+    }
+
+    #[test]
+    fn directory_only_patterns() {
+        assert!(ignored("build/", "build", true));
+        assert!(ignored("build/", "src/build", true));
+        assert!(!ignored("build/", "build", false));
+        assert!(ignored("build", "build", false));
+    }
+
+    #[test]
+    fn anchoring() {
+        assert!(ignored("*.bak", "art/critters/a.bak", false));
+        assert!(ignored("/notes.txt", "notes.txt", false));
+        assert!(!ignored("/notes.txt", "text/notes.txt", false));
+        assert!(ignored("text/*.txt", "text/notes.txt", false));
+        assert!(!ignored("text/*.txt", "data/text/notes.txt", false));
+        assert!(!ignored("text/*.txt", "text/english/notes.txt", false));
+        assert!(ignored("**/english/*.txt", "data/text/english/notes.txt", false));
+        assert!(ignored("text/**", "text/english/notes.txt", false));
+        assert!(ignored("*.FRM", "art/A.frm", false));
+    }
+
+    #[test]
+    fn rules_apply_under_their_directory() {
+        let mut ignores = Ignores::new(false);
+        ignores.add("*.tmp\n/local.txt", &components("mods"));
+        assert!(ignores.is_ignored(&components("mods/a/b.tmp"), false));
+        assert!(ignores.is_ignored(&components("MODS/local.txt"), false));
+        assert!(!ignores.is_ignored(&components("b.tmp"), false));
+        assert!(!ignores.is_ignored(&components("mods/a/local.txt"), false));
+    }
+
+    #[test]
+    fn ignore_files_and_vcs_files() {
+        assert!(Ignores::new(false).is_ignored(&components("art/.datignore"), false));
+        assert!(!Ignores::new(false).is_ignored(&components(".git"), true));
+        assert!(Ignores::new(true).is_ignored(&components("mods/.git"), true));
+    }
+}
@@ -0,0 +1,315 @@
+//! Index sidecars: a `Dat`'s index, cached in a file next to its archive (`ARCHIVE.idx`), so that
+//! commands that look entries up in a large archive needn't parse its whole tree, and normalize
+//! and sort its paths, first.
+//!
+//! A sidecar is keyed by a CRC-32 of the archive's tree and footer, so it's only used for the
+//! archive it was written for: once the archive's entries change, it's stale, and is rebuilt
+//! rather than read. (A CRC-32 is much cheaper than parsing the tree, which a cryptographic
+//! digest isn't, and only has to catch accidental changes.) The data of entries can change
+//! without making it stale, as it doesn't cover their content. The sidecar's own content is
+//! covered by a CRC-32 of it, so that a damaged sidecar is rebuilt too, rather than believed.
+//!
+//! The sidecar's layout (all fields little-endian):
+//!
+//! - `FO2DATIX`
+//! - the version (u32, currently 2)
+//! - the key (u32)
+//! - the CRC-32 of the rest of the sidecar, from the number of entries on (u32)
+//! - the number of entries (u64)
+//! - each entry, in tree order: the offset of its name in the tree (u64), the name's length
+//!   (u32), its compressed flag (u8), and its decompressed size, packed size and offset (u64 each)
+//! - each entry's normalized path, in path order: the entry's index in the tree (u32), and the
+//!   length of the path's directory and of the whole path (u32 each)
+//! - the paths themselves (UTF-8), in the same order, one after another
+
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+#[cfg(feature = "compression")]
+use flate2::Crc;
+#[cfg(not(feature = "compression"))]
+use self::crc::Crc;
+
+use DatStats;
+use errors;
+
+/// The extension of sidecars, which is added to their archive's file name.
+pub const EXTENSION: &str = "idx";
+
+const MAGIC: &[u8; 8] = b"FO2DATIX";
+
+const VERSION: u32 = 2;
+
+const HEADER_SIZE: usize = 8 + 4 + 4 + 4 + 8;
+
+/// The offset of the number of entries, which the checksum covers the sidecar from.
+const CHECKED_START: usize = 8 + 4 + 4 + 4;
+
+const ENTRY_SIZE: usize = 8 + 4 + 1 + 3 * 8;
+
+const PATH_SIZE: usize = 3 * 4;
+
+/// Identifies the tree that an index is of.
+pub type Key = u32;
+
+/// A `Dat`'s index, as it's cached in a sidecar (see `Dat::from_index` and `Dat::index`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedIndex {
+    pub key: Key,
+    /// The tree entries, in tree order.
+    pub entries: Vec<CachedEntry>,
+    /// The entries' normalized paths, in order.
+    pub paths: Vec<CachedPath>,
+}
+
+/// A tree entry, with its name given by where it is in the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CachedEntry {
+    pub name_offset: usize,
+    pub name_len: usize,
+    pub is_compressed: bool,
+    pub decompressed_size: usize,
+    pub packed_size: usize,
+    pub offset: usize,
+}
+
+/// An entry's normalized path (see `names::normalize`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedPath {
+    pub path: String,
+    /// The length of the path's directory (up to its last `/`).
+    pub dir_len: usize,
+    /// The entry's index in the tree.
+    pub entry: usize,
+}
+
+/// Returns the path of the sidecar of the archive at `archive_path`.
+pub fn sidecar_path(archive_path: &Path) -> PathBuf {
+    let mut path = archive_path.as_os_str().to_owned();
+    path.push(".");
+    path.push(EXTENSION);
+    PathBuf::from(path)
+}
+
+/// Returns the key of an archive whose tree (section) is `tree_data`: a CRC-32 of it, and of the
+/// footer and other fields that `stats` has of the archive.
+pub fn key(tree_data: &[u8], stats: &DatStats) -> Key {
+    let mut crc = Crc::new();
+    crc.update(tree_data);
+
+    let mut fields = [0; 8 + 8 + 1];
+    LittleEndian::write_u64(&mut fields[0..8], stats.num_files as u64);
+    LittleEndian::write_u64(&mut fields[8..16], stats.file_size as u64);
+    fields[16] = stats.is_dat64 as u8;
+    crc.update(&fields);
+
+    crc.sum()
+}
+
+impl CachedIndex {
+
+    /// Reads the sidecar at `path`. Returns `None` if there's none, or if it isn't one that this
+    /// version of fo2dat wrote (e.g. it's truncated), which is then rebuilt like a stale one.
+    pub fn read(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(Self::decode(&bytes)),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(errors::with_context(e, path.display())),
+        }
+    }
+
+    /// Writes the index to a sidecar at `path`, replacing any that's there. It's written under a
+    /// temporary name and renamed into place, so that a concurrent reader never sees part of it.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut part_path = path.as_os_str().to_owned();
+        part_path.push(".part");
+        let part_path = PathBuf::from(part_path);
+
+        fs::write(&part_path, self.encode())
+            .and_then(|_| fs::rename(&part_path, path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&part_path);
+                errors::with_context(e, path.display())
+            })
+    }
+
+    /// Returns the index's sidecar content.
+    pub fn encode(&self) -> Vec<u8> {
+        let paths_len: usize = self.paths.iter().map(|path| path.path.len()).sum();
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.entries.len() * ENTRY_SIZE + self.paths.len() * PATH_SIZE + paths_len);
+        bytes.extend_from_slice(MAGIC);
+        bytes.write_u32::<LittleEndian>(VERSION).unwrap();
+        bytes.write_u32::<LittleEndian>(self.key).unwrap();
+        // the checksum, which is filled in once the rest is written
+        bytes.write_u32::<LittleEndian>(0).unwrap();
+        bytes.write_u64::<LittleEndian>(self.entries.len() as u64).unwrap();
+
+        for entry in &self.entries {
+            bytes.write_u64::<LittleEndian>(entry.name_offset as u64).unwrap();
+            bytes.write_u32::<LittleEndian>(entry.name_len as u32).unwrap();
+            bytes.push(entry.is_compressed as u8);
+            bytes.write_u64::<LittleEndian>(entry.decompressed_size as u64).unwrap();
+            bytes.write_u64::<LittleEndian>(entry.packed_size as u64).unwrap();
+            bytes.write_u64::<LittleEndian>(entry.offset as u64).unwrap();
+        }
+        for path in &self.paths {
+            bytes.write_u32::<LittleEndian>(path.entry as u32).unwrap();
+            bytes.write_u32::<LittleEndian>(path.dir_len as u32).unwrap();
+            bytes.write_u32::<LittleEndian>(path.path.len() as u32).unwrap();
+        }
+        for path in &self.paths {
+            bytes.extend_from_slice(path.path.as_bytes());
+        }
+
+        let checksum = checksum(&bytes[CHECKED_START..]);
+        LittleEndian::write_u32(&mut bytes[CHECKED_START - 4..CHECKED_START], checksum);
+        bytes
+    }
+
+    /// Parses sidecar content, returning `None` if it isn't valid.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE || &bytes[..8] != MAGIC || LittleEndian::read_u32(&bytes[8..12]) != VERSION {
+            return None;
+        }
+
+        if LittleEndian::read_u32(&bytes[16..20]) != checksum(&bytes[CHECKED_START..]) {
+            return None;
+        }
+
+        let key = LittleEndian::read_u32(&bytes[12..16]);
+        let num_entries = LittleEndian::read_u64(&bytes[20..28]) as usize;
+
+        let body = &bytes[HEADER_SIZE..];
+        let paths_start = num_entries.checked_mul(ENTRY_SIZE + PATH_SIZE).filter(|&len| len <= body.len())?;
+        let (entries_data, path_data) = body[..paths_start].split_at(num_entries * ENTRY_SIZE);
+        let mut path_bytes = &body[paths_start..];
+
+        let entries = entries_data.chunks(ENTRY_SIZE).map(|entry| CachedEntry {
+            name_offset: LittleEndian::read_u64(&entry[0..8]) as usize,
+            name_len: LittleEndian::read_u32(&entry[8..12]) as usize,
+            is_compressed: entry[12] != 0,
+            decompressed_size: LittleEndian::read_u64(&entry[13..21]) as usize,
+            packed_size: LittleEndian::read_u64(&entry[21..29]) as usize,
+            offset: LittleEndian::read_u64(&entry[29..37]) as usize,
+        }).collect();
+
+        let mut paths = Vec::with_capacity(num_entries);
+        for path in path_data.chunks(PATH_SIZE) {
+            let dir_len = LittleEndian::read_u32(&path[4..8]) as usize;
+            let len = LittleEndian::read_u32(&path[8..12]) as usize;
+            if len > path_bytes.len() || dir_len > len {
+                return None;
+            }
+            let (path_str, rest) = path_bytes.split_at(len);
+            path_bytes = rest;
+            paths.push(CachedPath {
+                path: String::from_utf8(path_str.to_vec()).ok()?,
+                dir_len,
+                entry: LittleEndian::read_u32(&path[0..4]) as usize,
+            });
+        }
+        if !path_bytes.is_empty() {
+            return None;
+        }
+
+        Some(CachedIndex { key, entries, paths })
+    }
+}
+
+/// Returns the CRC-32 of `bytes`, part of a sidecar.
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(bytes);
+    crc.sum()
+}
+
+/// A CRC-32, as zlib's (and so flate2's) computes it, for builds without the `compression`
+/// feature, whose keys must be the same as other builds' for them to share sidecars.
+#[cfg(not(feature = "compression"))]
+mod crc {
+    const TABLE: [u32; 256] = table();
+
+    const fn table() -> [u32; 256] {
+        const POLYNOMIAL: u32 = 0xedb8_8320;
+
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    }
+
+    pub struct Crc {
+        crc: u32,
+    }
+
+    impl Crc {
+        pub fn new() -> Self {
+            Crc { crc: 0 }
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            let mut crc = !self.crc;
+            for &byte in data {
+                crc = TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8);
+            }
+            self.crc = !crc;
+        }
+
+        pub fn sum(&self) -> u32 {
+            self.crc
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_index() -> CachedIndex {
+        CachedIndex {
+            key: 0x1234_5678,
+            entries: vec![
+                CachedEntry { name_offset: 4, name_len: 11, is_compressed: true, decompressed_size: 100, packed_size: 60, offset: 0 },
+                CachedEntry { name_offset: 32, name_len: 5, is_compressed: false, decompressed_size: 7, packed_size: 7, offset: 60 },
+            ],
+            paths: vec![
+                CachedPath { path: "a.txt".to_string(), dir_len: 0, entry: 1 },
+                CachedPath { path: "art/b.frm".to_string(), dir_len: 3, entry: 0 },
+            ],
+        }
+    }
+
+    #[test]
+    fn indexes_round_trip() {
+        let index = sample_index();
+        assert_eq!(CachedIndex::decode(&index.encode()), Some(index));
+
+        let empty = CachedIndex { key: 0, entries: Vec::new(), paths: Vec::new() };
+        assert_eq!(CachedIndex::decode(&empty.encode()), Some(empty));
+    }
+
+    #[test]
+    fn damaged_indexes_are_rejected() {
+        let bytes = sample_index().encode();
+        // every byte from the checksum on, which the checksum (or the header's checks) covers
+        for i in CHECKED_START - 4..bytes.len() {
+            let mut damaged = bytes.clone();
+            damaged[i] ^= 0x01;
+            assert_eq!(CachedIndex::decode(&damaged), None, "byte {} flipped", i);
+        }
+
+        assert_eq!(CachedIndex::decode(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(CachedIndex::decode(&bytes[..HEADER_SIZE - 1]), None);
+    }
+}
@@ -0,0 +1,273 @@
+//! Minimal helpers for writing and parsing JSON.
+
+use std::io;
+use std::io::Write;
+
+use errors;
+use errors::Code;
+
+/// The version of the schema of fo2dat's JSON output, which every JSON document it writes (and
+/// every line of output that's a stream of JSON objects, one per line) declares as its `schema`
+/// member.
+///
+/// Within a version, output only changes compatibly: members may be added (so readers should
+/// ignore those they don't know), but none is removed or renamed, or changes type or meaning. Any
+/// other change makes a new version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Writes the start of a JSON object that declares the schema version (`{"schema":1`), to which
+/// the caller appends the object's other members, each after a `,`, then the closing `}`.
+pub fn begin_document<W: Write>(out: &mut W) -> io::Result<()> {
+    write!(out, "{{\"schema\":{}", SCHEMA_VERSION)
+}
+
+/// Writes `s` as a quoted, escaped JSON string.
+pub fn write_str<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+/// Writes `items` as a JSON array, using `write_item` to write each item.
+pub fn write_array<W, T, I, F>(out: &mut W, items: I, mut write_item: F) -> io::Result<()>
+    where W: Write, I: IntoIterator<Item = T>, F: FnMut(&mut W, T) -> io::Result<()>
+{
+    out.write_all(b"[")?;
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        write_item(out, item)?;
+    }
+    out.write_all(b"]")
+}
+
+/// Writes `s` as a JSON string, or `null` if it's `None`.
+pub fn write_opt_str<W: Write>(out: &mut W, s: Option<&str>) -> io::Result<()> {
+    match s {
+        Some(s) => write_str(out, s),
+        None => out.write_all(b"null"),
+    }
+}
+
+/// A parsed JSON value. Objects keep their members in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Returns the member of an object named `name`, or `None` if it has none (or isn't an object).
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref members) => members.iter().find(|member| member.0 == name).map(|member| &member.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match *self {
+            Value::Array(ref values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Parses `text` as a single JSON value.
+pub fn parse(text: &str) -> io::Result<Value> {
+    let mut parser = Parser { chars: text.chars().collect(), pos: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("trailing characters after the value"));
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn value(&mut self) -> io::Result<Value> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Value::String),
+            Some('t') => self.literal("true", Value::Bool(true)),
+            Some('f') => self.literal("false", Value::Bool(false)),
+            Some('n') => self.literal("null", Value::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(self.error(&format!("unexpected {:?}", c))),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self) -> io::Result<Value> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(members)),
+                _ => return Err(self.error("expected ',' or '}' in object")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> io::Result<Value> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(values)),
+                _ => return Err(self.error("expected ',' or ']' in array")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => {
+                        let unit = self.hex4()?;
+                        // a surrogate pair encodes a character outside of the BMP
+                        let c = if (0xd800..0xdc00).contains(&unit) && self.peek() == Some('\\') {
+                            self.pos += 1;
+                            self.expect('u')?;
+                            let low = self.hex4()?;
+                            std::char::from_u32(0x10000 + ((unit - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff))
+                        } else {
+                            std::char::from_u32(unit)
+                        };
+                        s.push(c.ok_or_else(|| self.error("invalid \\u escape"))?);
+                    },
+                    _ => return Err(self.error("invalid escape in string")),
+                },
+                Some(c) => s.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> io::Result<u32> {
+        let digits: String = (0..4).filter_map(|_| self.next()).collect();
+        u32::from_str_radix(&digits, 16).map_err(|_| self.error("invalid \\u escape"))
+    }
+
+    fn number(&mut self) -> io::Result<Value> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' || c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let number: String = self.chars[start..self.pos].iter().collect();
+        number.parse::<f64>().map(Value::Number).map_err(|_| self.error(&format!("invalid number {}", number)))
+    }
+
+    fn literal(&mut self, literal: &str, value: Value) -> io::Result<Value> {
+        for expected in literal.chars() {
+            if self.next() != Some(expected) {
+                return Err(self.error(&format!("expected {}", literal)));
+            }
+        }
+        Ok(value)
+    }
+
+    fn expect(&mut self, expected: char) -> io::Result<()> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(self.error(&format!("expected '{}'", expected))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn error(&self, msg: &str) -> io::Error {
+        errors::new(Code::Format, io::ErrorKind::InvalidData, format!("invalid JSON at character {}: {}", self.pos, msg))
+    }
+}
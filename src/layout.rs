@@ -0,0 +1,231 @@
+//! Mapping of how an archive's data section is used by its entries.
+//!
+//! Packers are expected to lay entries' data out back to back, with each byte of the data section
+//! belonging to exactly one entry (or, for deduplicated entries, to several entries that share the
+//! same bytes). Buggy packers leave unused gaps, or entries whose data partly overlaps another's,
+//! which the engine tolerates but which break tools that rewrite archives.
+//!
+//! `locate` maps an offset anywhere in an archive back to what it belongs to, e.g. to correlate a
+//! hex dump of a corrupt archive with its entries.
+
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use Dat;
+
+/// A run of the data section whose bytes are all used the same way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub range: Range<usize>,
+    pub kind: Kind,
+}
+
+/// How the bytes of a `Region` are used. Entries are given as their indexes in the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// Data of one entry.
+    Entry(usize),
+    /// Data of several entries that all have exactly the same data, as deduplicated entries do.
+    Shared(Vec<usize>),
+    /// Data of several entries whose data only partly coincides.
+    Overlap(Vec<usize>),
+    /// fo2dat's extension (see `extension`).
+    Extension,
+    /// Bytes that no entry uses.
+    Gap,
+    /// Bytes past the end of the data section, which the data of these entries extends into.
+    OutOfBounds(Vec<usize>),
+}
+
+impl Kind {
+    /// Returns `true` if the region is one that a well-behaved packer wouldn't produce.
+    pub fn is_problem(&self) -> bool {
+        match *self {
+            Kind::Overlap(_) | Kind::Gap | Kind::OutOfBounds(_) => true,
+            Kind::Entry(_) | Kind::Shared(_) | Kind::Extension => false,
+        }
+    }
+}
+
+/// A structural part of an archive file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    /// The data section, which holds entries' data.
+    Data,
+    /// The `num_files` field.
+    NumFiles,
+    /// A field of the record of the tree entry at this index.
+    TreeEntry(usize, TreeEntryField),
+    /// The `tree_size` field.
+    TreeSize,
+    /// The `file_size` field.
+    FileSize,
+    /// The magic number that ends a DAT64 archive.
+    Dat64Magic,
+}
+
+/// A field of a tree entry's record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeEntryField {
+    NameLen,
+    Name,
+    IsCompressed,
+    DecompressedSize,
+    PackedSize,
+    Offset,
+}
+
+impl TreeEntryField {
+    pub fn name(self) -> &'static str {
+        match self {
+            TreeEntryField::NameLen => "name length",
+            TreeEntryField::Name => "name",
+            TreeEntryField::IsCompressed => "compressed flag",
+            TreeEntryField::DecompressedSize => "decompressed size",
+            TreeEntryField::PackedSize => "packed size",
+            TreeEntryField::Offset => "offset",
+        }
+    }
+}
+
+/// What an offset into an archive file belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub part: Part,
+    /// The byte range of the part (for a tree entry, of its whole record).
+    pub range: Range<usize>,
+    /// The region of the data section's layout (see `layout`) that the offset is in, if any. Past
+    /// the data section, this is an `OutOfBounds` region if entries' data extends there.
+    pub region: Option<Region>,
+}
+
+/// Returns what `offset` into `dat`'s archive file belongs to, or `None` if it's past its end.
+pub fn locate(dat: &Dat, offset: usize) -> Option<Location> {
+    let stats = dat.stats();
+    let field_bytes = if stats.is_dat64 { 8 } else { 4 };
+
+    let region = layout(dat).into_iter().find(|region| region.range.start <= offset && offset < region.range.end);
+
+    let mut parts = vec![
+        (Part::Data, stats.data_range.clone()),
+        (Part::NumFiles, stats.data_range.end..stats.tree_range.start),
+    ];
+
+    // records are laid out back to back, in tree order
+    let mut record_start = stats.tree_range.start;
+    for (i, tree_entry) in dat.tree_entries().iter().enumerate() {
+        let name_start = record_start + 4;
+        let flag_start = name_start + tree_entry.name.len();
+        let record_end = flag_start + 1 + 3 * field_bytes;
+        if record_start <= offset && offset < record_end {
+            let field = if offset < name_start {
+                TreeEntryField::NameLen
+            } else if offset < flag_start {
+                TreeEntryField::Name
+            } else if offset == flag_start {
+                TreeEntryField::IsCompressed
+            } else {
+                match (offset - flag_start - 1) / field_bytes {
+                    0 => TreeEntryField::DecompressedSize,
+                    1 => TreeEntryField::PackedSize,
+                    _ => TreeEntryField::Offset,
+                }
+            };
+            parts.push((Part::TreeEntry(i, field), record_start..record_end));
+        }
+        record_start = record_end;
+    }
+
+    let tree_size_start = stats.tree_range.end;
+    let file_size_start = tree_size_start + field_bytes;
+    let file_size_end = file_size_start + field_bytes;
+    parts.push((Part::TreeSize, tree_size_start..file_size_start));
+    parts.push((Part::FileSize, file_size_start..file_size_end));
+    if stats.is_dat64 {
+        parts.push((Part::Dat64Magic, file_size_end..stats.file_size));
+    }
+
+    parts.into_iter()
+        .find(|(_, range)| range.start <= offset && offset < range.end)
+        .map(|(part, range)| Location { part, range, region })
+}
+
+/// Returns the regions of `dat`'s data section (and beyond it, if entries extend past its end),
+/// in order of offset. Entries with no data don't occupy any region.
+pub fn layout(dat: &Dat) -> Vec<Region> {
+    let data_len = dat.stats().data_range.len();
+    // a malformed extension is treated as ordinary (and so probably unused) bytes
+    let extension_range = dat.extension().ok().and_then(|extension| extension).map(|extension| extension.range);
+
+    // at each offset, entries' data ends (false sorts first) before other entries' data starts
+    let mut events: Vec<(usize, bool, usize)> = Vec::new();
+    for (i, tree_entry) in dat.tree_entries().iter().enumerate() {
+        if tree_entry.packed_size > 0 {
+            events.push((tree_entry.offset, true, i));
+            events.push((tree_entry.offset.saturating_add(tree_entry.packed_size), false, i));
+        }
+    }
+    events.sort();
+
+    let mut regions: Vec<Region> = Vec::new();
+    let mut active: BTreeSet<usize> = BTreeSet::new();
+    let mut start = 0;
+
+    // regions are also split where the data section and the extension start, which entries
+    // don't necessarily start or end at
+    let mut boundaries = vec![data_len];
+    boundaries.extend(extension_range.as_ref().map(|extension_range| extension_range.start));
+
+    let push = |regions: &mut Vec<Region>, range: Range<usize>, active: &BTreeSet<usize>| {
+        let mut start = range.start;
+        for &boundary in boundaries.iter().filter(|&&boundary| range.start < boundary && boundary < range.end) {
+            push_region(regions, start..boundary, active, dat, data_len, &extension_range);
+            start = boundary;
+        }
+        if start < range.end {
+            push_region(regions, start..range.end, active, dat, data_len, &extension_range);
+        }
+    };
+
+    for (offset, is_start, i) in events {
+        push(&mut regions, start..offset, &active);
+        start = offset;
+        if is_start {
+            active.insert(i);
+        } else {
+            active.remove(&i);
+        }
+    }
+    push(&mut regions, start..data_len.max(start), &active);
+
+    regions
+}
+
+/// Pushes the region `range`, whose bytes are used by the `active` entries, onto `regions`,
+/// merging it into the last region if they're used the same way.
+fn push_region(regions: &mut Vec<Region>, range: Range<usize>, active: &BTreeSet<usize>, dat: &Dat, data_len: usize, extension_range: &Option<Range<usize>>) {
+    let entries: Vec<usize> = active.iter().cloned().collect();
+
+    let kind = if range.start >= data_len {
+        Kind::OutOfBounds(entries)
+    } else if entries.is_empty() {
+        match *extension_range {
+            Some(ref extension_range) if extension_range.start <= range.start && range.end <= extension_range.end => Kind::Extension,
+            _ => Kind::Gap,
+        }
+    } else if entries.len() == 1 {
+        Kind::Entry(entries[0])
+    } else {
+        let tree_entries = dat.tree_entries();
+        let first = &tree_entries[entries[0]];
+        let is_shared = entries.iter().all(|&i| {
+            tree_entries[i].offset == first.offset && tree_entries[i].packed_size == first.packed_size
+        });
+        if is_shared { Kind::Shared(entries) } else { Kind::Overlap(entries) }
+    };
+
+    match regions.last_mut() {
+        Some(last) if last.kind == kind && last.range.end == range.start => last.range.end = range.end,
+        _ => regions.push(Region { range, kind }),
+    }
+}
@@ -1,73 +1,205 @@
 extern crate byteorder;
+#[cfg(feature = "compression")]
 extern crate flate2;
+#[cfg(feature = "compression")]
+extern crate rayon;
+#[cfg(feature = "memmap")]
+extern crate memmap;
+#[cfg(unix)]
+extern crate libc;
+#[cfg(windows)]
+extern crate winapi;
+
+pub mod advise;
+#[cfg(feature = "compression")]
+pub mod bos;
+pub mod budget;
+#[cfg(feature = "compression")]
+pub mod cache;
+pub mod category;
+pub mod config;
+#[cfg(feature = "compression")]
+pub mod container;
+pub mod converter;
+mod dat;
+pub mod delta;
+pub mod dictionary;
+#[cfg(feature = "compression")]
+pub mod digest;
+#[cfg(feature = "compression")]
+pub mod doctor;
+pub mod encoding;
+pub mod errors;
+pub mod extension;
+pub mod frm;
+pub mod glob;
+pub mod ignore;
+pub mod index;
+pub mod json;
+pub mod layout;
+pub mod limits;
+#[cfg(feature = "compression")]
+pub mod lst;
+pub mod map;
+pub mod msg;
+pub mod names;
+pub mod pal;
+#[cfg(feature = "compression")]
+pub mod patch;
+pub mod priority;
+pub mod pro;
+pub mod profile;
+pub mod remote;
+pub mod rix;
+pub mod sandbox;
+pub mod save;
+#[cfg(feature = "compression")]
+pub mod serve;
+pub mod sniff;
+pub mod spec;
+pub mod temp;
+pub mod template;
+pub mod text;
+#[cfg(feature = "compression")]
+pub mod writer;
+#[cfg(feature = "image")]
+pub mod png;
+#[cfg(any(feature = "testutil", all(test, feature = "compression")))]
+pub mod testutil;
+
+pub use dat::{Dat, Dir, Dirs, EntriesIn, SharedDat, StableBytes, Subdirs};
 
 use std::io;
+use std::io::Read;
 use byteorder::{LittleEndian, ByteOrder};
 use std::str;
 use std::path::PathBuf;
-use std::io::Error;
 use std::io::ErrorKind;
 use std::ops::Range;
+use std::fmt;
+
+use errors::Code;
+#[cfg(feature = "compression")]
+use profile::Stage;
+#[cfg(feature = "compression")]
+use sniff::Kind;
+
+const TREE_ENTRY_PATH_SEPARATOR: char = '\\';
+
+/// The magic number that ends a DAT64 archive: fo2dat's variant of DAT2 for archives over 4 GiB,
+/// whose `num_files`, `tree_size` and `file_size` fields, and tree entries' sizes and offsets,
+/// are u64 rather than u32. The engine can't load DAT64 archives.
+pub const DAT64_MAGIC: &[u8; 8] = b"FO2DAT64";
+
+/// Which characters separate the components of entries' stored names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Separators {
+    /// `\`, as the engine and the game's own archives use.
+    Backslash,
+    /// Either `\` or `/`, for archives from packers that stored names with `/` separators.
+    Any,
+    /// Neither: the stored name is a single component.
+    Literal,
+}
 
-#[allow(dead_code)]
-struct DatTopLevelStructure {
-    data: Range<usize>,
-    num_files: usize,
-    tree: Range<usize>,
-    file_size: usize,
+impl Separators {
+    fn is_separator(self) -> fn(char) -> bool {
+        match self {
+            Separators::Backslash => |c| c == TREE_ENTRY_PATH_SEPARATOR,
+            Separators::Any => |c| c == TREE_ENTRY_PATH_SEPARATOR || c == '/',
+            Separators::Literal => |_| false,
+        }
+    }
 }
 
-impl DatTopLevelStructure {
+/// Archive-level metadata, as parsed from the top-level structure (`dat_file`) of DAT2 data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatStats {
+    /// Byte range of the `data` section.
+    pub data_range: Range<usize>,
+    /// The number of files, as stated by the `num_files` field.
+    pub num_files: usize,
+    /// Byte range of the `tree_entries` section.
+    pub tree_range: Range<usize>,
+    /// The size of the archive, as stated by the `file_size` field.
+    pub file_size: usize,
+    /// Whether the archive is a DAT64 archive (see `DAT64_MAGIC`), rather than a DAT2 one.
+    pub is_dat64: bool,
+}
+
+/// Returns the archive-level metadata of the supplied DAT2 data.
+pub fn stats(dat_data: &[u8]) -> io::Result<DatStats> {
+    DatStats::parse(dat_data)
+}
+
+impl DatStats {
 
     fn parse(dat_data: &[u8]) -> io::Result<Self> {
-        const NUM_FILES_BYTES: usize = 4;
-        const TREE_SIZE_BYTES: usize = 4;
-        const FILE_SIZE_BYTES: usize = 4;
-        const NUM_FOOTER_BYTES: usize = TREE_SIZE_BYTES + FILE_SIZE_BYTES;
-        const MIN_SIZE: usize = NUM_FILES_BYTES + NUM_FOOTER_BYTES;
+        // a common mix-up, because savegames are also named .DAT
+        if save::is_save(dat_data) {
+            let err_msg = "data is a Fallout 2 savegame (SAVE.DAT), not a DAT2 archive";
+            return Err(errors::new(Code::Savegame, ErrorKind::InvalidData, err_msg));
+        }
+
+        // DAT64 archives have the same structure, with wider fields, followed by their magic number
+        let (dat_data, field_bytes) = match dat_data.len().checked_sub(DAT64_MAGIC.len()) {
+            Some(magic_start) if dat_data.ends_with(DAT64_MAGIC) => (&dat_data[..magic_start], 8),
+            _ => (dat_data, 4),
+        };
+        let is_dat64 = field_bytes == 8;
+        let read_field = |field_data: &[u8]| -> usize {
+            if is_dat64 { LittleEndian::read_u64(field_data) as usize } else { LittleEndian::read_u32(field_data) as usize }
+        };
+
+        let num_files_bytes = field_bytes;
+        let tree_size_bytes = field_bytes;
+        let file_size_bytes = field_bytes;
+        let num_footer_bytes = tree_size_bytes + file_size_bytes;
+        let min_size = num_files_bytes + num_footer_bytes;
 
         let len = dat_data.len();
+        // the file_size field counts the magic number, which isn't in `dat_data`
+        let file_len = if is_dat64 { len + DAT64_MAGIC.len() } else { len };
 
-        if len < MIN_SIZE {
-            let err_msg = format!("is too small: must be at least 8 bytes long");
-            return Err(Error::new(ErrorKind::InvalidData, err_msg));
+        if len < min_size {
+            let err_msg = format!("is too small: must be at least {} bytes long", min_size);
+            return Err(errors::new(Code::TooSmall, ErrorKind::InvalidData, err_msg));
         }
 
-        let file_size =
-            LittleEndian::read_u32(&dat_data[len-FILE_SIZE_BYTES..]) as usize;
+        let file_size = read_field(&dat_data[len-file_size_bytes..]);
 
-        if file_size != len {
-            let err_msg = format!("size of data ({}) doesn't match size from the dat_file size field ({})", len, file_size);
-            return Err(Error::new(ErrorKind::InvalidData, err_msg));
+        if file_size != file_len {
+            let err_msg = format!("size of data ({}) doesn't match size from the dat_file size field ({})", file_len, file_size);
+            return Err(errors::new(Code::FileSize, ErrorKind::InvalidData, err_msg));
         }
 
-        let tree_end = len - NUM_FOOTER_BYTES;
+        let tree_end = len - num_footer_bytes;
 
-        let tree_size =
-            LittleEndian::read_u32(&dat_data[tree_end..][..TREE_SIZE_BYTES]) as usize - TREE_SIZE_BYTES;
-
-        if tree_size > tree_end {
-            let err_msg = format!("size of data ({}) is too small to fit tree entries", len);
-            return Err(Error::new(ErrorKind::InvalidData, err_msg));
-        }
+        let tree_size = match read_field(&dat_data[tree_end..][..tree_size_bytes]).checked_sub(tree_size_bytes) {
+            Some(tree_size) if tree_size <= tree_end => tree_size,
+            _ => {
+                let err_msg = format!("size of data ({}) is too small to fit tree entries", file_len);
+                return Err(errors::new(Code::TreeOutOfBounds, ErrorKind::InvalidData, err_msg));
+            }
+        };
 
         let tree_start = tree_end - tree_size;
 
-        if tree_start < NUM_FILES_BYTES {
-            let err_msg = format!("size of data ({}) is too small to fit the file count", len);
-            return Err(Error::new(ErrorKind::InvalidData, err_msg));
+        if tree_start < num_files_bytes {
+            let err_msg = format!("size of data ({}) is too small to fit the file count", file_len);
+            return Err(errors::new(Code::TreeOutOfBounds, ErrorKind::InvalidData, err_msg));
         }
 
-        let num_files_start = tree_start - NUM_FILES_BYTES;
+        let num_files_start = tree_start - num_files_bytes;
 
-        let num_files =
-            LittleEndian::read_u32(&dat_data[num_files_start..][..NUM_FILES_BYTES]) as usize;
+        let num_files = read_field(&dat_data[num_files_start..][..num_files_bytes]);
 
-        Ok(DatTopLevelStructure {
-            data: (0..num_files_start),
+        Ok(DatStats {
+            data_range: (0..num_files_start),
             num_files,
-            tree: (tree_start..tree_end),
+            tree_range: (tree_start..tree_end),
             file_size,
+            is_dat64,
         })
     }
 }
@@ -75,23 +207,136 @@ impl DatTopLevelStructure {
 /// Returns an iterator that emits tree entries found in the supplied DAT2 data.
 ///
 /// The iterator will emit an `Err` if the data is invalid, followed by halting.
-pub fn iter_tree(dat_data: &[u8]) -> io::Result<TreeEntries> {
-    let top_level_structure = DatTopLevelStructure::parse(&dat_data)?;
-    Ok(TreeEntries {
-        tree_data: &dat_data[top_level_structure.tree],
-        offset: 0,
-    })
+pub fn iter_tree(dat_data: &[u8]) -> io::Result<TreeEntries<'_>> {
+    let stats = DatStats::parse(dat_data)?;
+    Ok(TreeEntries::new(&dat_data[stats.tree_range], stats.is_dat64, stats.num_files))
 }
 
 /// An iterator that emits `TreeEntry`s parsed from the tree section of DAT data.
 pub struct TreeEntries<'a> {
-    tree_data: &'a [u8],
-    offset: usize,
+    refs: TreeEntryRefs<'a>,
+}
+
+impl <'a> TreeEntries<'a> {
+    fn new(tree_data: &'a [u8], is_dat64: bool, num_files: usize) -> Self {
+        TreeEntries { refs: TreeEntryRefs::new(tree_data, is_dat64, num_files) }
+    }
+
+    /// Returns the number of entries the archive states it has (see `TreeEntryRefs::total`).
+    pub fn total(&self) -> usize {
+        self.refs.total()
+    }
+
+    /// Returns an iterator that emits each entry along with its index and the total (see
+    /// `WithProgress`).
+    pub fn with_progress(self) -> WithProgress<Self> {
+        let total = self.total();
+        WithProgress::new(self, total)
+    }
 }
 
 impl <'a> Iterator for TreeEntries<'a> {
     type Item = io::Result<TreeEntry>;
 
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.refs.next()?.map(TreeEntry::from))
+    }
+}
+
+/// A tree entry, as parsed from the `tree_entires` section of the input DAT file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TreeEntry {
+    pub path: PathBuf,
+    pub is_compressed: bool,
+    pub decompressed_size: usize,
+    pub packed_size: usize,
+    pub offset: usize,
+}
+
+impl TreeEntry {
+
+    /// Returns an iterator over the components of the entry's path.
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        // always valid UTF-8, because paths are built from validated names
+        self.path.iter().map(|component| component.to_str().unwrap())
+    }
+
+    /// Returns the final component of the entry's path (e.g. `HAPOWRAA.FRM`).
+    pub fn file_name(&self) -> Option<&str> {
+        self.path.file_name().map(|file_name| file_name.to_str().unwrap())
+    }
+
+    /// Returns the extension of the entry's filename, without the leading `.` (e.g. `FRM`).
+    pub fn extension(&self) -> Option<&str> {
+        self.file_name().and_then(extension)
+    }
+
+    /// Returns the entry's path with `/` separators (e.g. `ART/CRITTERS/HAPOWRAA.FRM`),
+    /// optionally lowercased.
+    pub fn normalized_path(&self, lowercase: bool) -> String {
+        names::join(self.components(), '/', lowercase)
+    }
+}
+
+impl fmt::Display for TreeEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.normalized_path(false))
+    }
+}
+
+impl <'a> From<TreeEntryRef<'a>> for TreeEntry {
+    fn from(entry: TreeEntryRef<'a>) -> Self {
+        TreeEntry {
+            path: entry.path(),
+            is_compressed: entry.is_compressed,
+            decompressed_size: entry.decompressed_size,
+            packed_size: entry.packed_size,
+            offset: entry.offset,
+        }
+    }
+}
+
+/// Returns an iterator that emits borrowed tree entries found in the supplied DAT2 data.
+///
+/// Unlike `iter_tree`, this performs no allocation per entry, which makes it the better choice
+/// for counting, filtering, or indexing large archives.
+///
+/// The iterator will emit an `Err` if the data is invalid, followed by halting.
+pub fn iter_tree_refs(dat_data: &[u8]) -> io::Result<TreeEntryRefs<'_>> {
+    let stats = DatStats::parse(dat_data)?;
+    Ok(TreeEntryRefs::new(&dat_data[stats.tree_range], stats.is_dat64, stats.num_files))
+}
+
+/// An iterator that emits `TreeEntryRef`s parsed from the tree section of DAT data.
+pub struct TreeEntryRefs<'a> {
+    tree_data: &'a [u8],
+    offset: usize,
+    is_dat64: bool,
+    num_files: usize,
+}
+
+impl <'a> TreeEntryRefs<'a> {
+    fn new(tree_data: &'a [u8], is_dat64: bool, num_files: usize) -> Self {
+        TreeEntryRefs { tree_data, offset: 0, is_dat64, num_files }
+    }
+
+    /// Returns the number of entries the archive states it has (its `num_files` field), which is
+    /// known without parsing the tree. A malformed archive's tree may have more or fewer.
+    pub fn total(&self) -> usize {
+        self.num_files
+    }
+
+    /// Returns an iterator that emits each entry along with its index and the total (see
+    /// `WithProgress`).
+    pub fn with_progress(self) -> WithProgress<Self> {
+        let total = self.total();
+        WithProgress::new(self, total)
+    }
+}
+
+impl <'a> Iterator for TreeEntryRefs<'a> {
+    type Item = io::Result<TreeEntryRef<'a>>;
+
     fn next(&mut self) -> Option<Self::Item> {
         if self.offset >= self.tree_data.len() {
             return None
@@ -99,137 +344,653 @@ impl <'a> Iterator for TreeEntries<'a> {
 
         let tree_data = &self.tree_data[self.offset..];
 
-        match TreeEntry::parse(tree_data) {
+        match TreeEntryRef::parse(tree_data, self.is_dat64) {
             Ok((entry, entry_size)) => {
                 self.offset += entry_size;
                 Some(Ok(entry))
             },
             Err(e) => {
                 // halts iteration
-                self.offset += std::usize::MAX;
+                self.offset = usize::MAX;
                 Some(Err(e))
             }
         }
     }
 }
 
-/// A tree entry, as parsed from the `tree_entires` section of the input DAT file.
-pub struct TreeEntry {
-    pub path: PathBuf,
+/// A tree entry that borrows its name from the underlying DAT data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TreeEntryRef<'a> {
+    /// The filename exactly as stored in the DAT (i.e. with `\`-separated components).
+    pub name: &'a str,
     pub is_compressed: bool,
     pub decompressed_size: usize,
     pub packed_size: usize,
     pub offset: usize,
 }
 
-impl TreeEntry {
+impl <'a> TreeEntryRef<'a> {
 
-    /// Attempts to parse `data` as a tree entry. Returns the data as a `TreeEntry`, along with the
-    /// number of bytes read to parse the returned `TreeEntry`.
-    fn parse(data: &[u8]) -> io::Result<(Self, usize)> {
+    /// Attempts to parse `data` as a tree entry (of a DAT64 archive, if `is_dat64`). Returns the
+    /// data as a `TreeEntryRef`, along with the number of bytes read to parse the returned
+    /// `TreeEntryRef`.
+    fn parse(data: &'a [u8], is_dat64: bool) -> io::Result<(Self, usize)> {
         const TREE_ENTRY_HEADER_SIZE: usize = 4;
-        const TREE_ENTRY_FOOTER_SIZE: usize = 13;
-        const TREE_ENTRY_MIN_SIZE: usize = TREE_ENTRY_HEADER_SIZE + TREE_ENTRY_FOOTER_SIZE;
-        const TREE_ENTRY_PATH_SEPARATOR: char = '\\';
 
-        if data.len() < TREE_ENTRY_MIN_SIZE {
-            let err_msg = format!("remaining tree data is too small to actually fit a tree entry");
-            return Err(Error::new(ErrorKind::InvalidData, err_msg));
+        // the compression flag, followed by the decompressed size, packed size and offset
+        let field_bytes = if is_dat64 { 8 } else { 4 };
+        let tree_entry_footer_size = 1 + 3 * field_bytes;
+        let tree_entry_min_size = TREE_ENTRY_HEADER_SIZE + tree_entry_footer_size;
+
+        if data.len() < tree_entry_min_size {
+            let err_msg = "remaining tree data is too small to actually fit a tree entry";
+            return Err(errors::new(Code::TreeEntry, ErrorKind::InvalidData, err_msg));
         }
 
         let filename_len =
             LittleEndian::read_u32(&data[0..TREE_ENTRY_HEADER_SIZE]) as usize;
 
-        let total_len = TREE_ENTRY_HEADER_SIZE + filename_len + TREE_ENTRY_FOOTER_SIZE;
+        let total_len = TREE_ENTRY_HEADER_SIZE + filename_len + tree_entry_footer_size;
 
         if data.len() < total_len {
             let err_kind = ErrorKind::InvalidData;
-            let err_msg = format!("not enough space remaining in tree data to accommodate a filename + relevant footers");
-            let err = Error::new(err_kind, err_msg);
+            let err_msg = "not enough space remaining in tree data to accommodate a filename + relevant footers";
+            let err = errors::new(Code::TreeEntry, err_kind, err_msg);
             return Err(err);
         }
 
-        let filename = match  str::from_utf8(&data[TREE_ENTRY_HEADER_SIZE..TREE_ENTRY_HEADER_SIZE+filename_len]) {
-            Ok(s) => {
-                let mut filename = PathBuf::new();
-                for el in s.split(TREE_ENTRY_PATH_SEPARATOR) {
-                    filename.push(el);
-                }
-                Ok(filename)
-            },
+        let name = match str::from_utf8(&data[TREE_ENTRY_HEADER_SIZE..TREE_ENTRY_HEADER_SIZE+filename_len]) {
+            Ok(s) => Ok(s),
             Err(_) => {
-                let err_msg = format!("cannot decode filename as ASCII");
-                Err(Error::new(ErrorKind::InvalidData, err_msg))
+                let err_msg = "cannot decode filename as ASCII";
+                Err(errors::new(Code::BadName, ErrorKind::InvalidData, err_msg))
             }
         }?;
 
         let footer_start = TREE_ENTRY_HEADER_SIZE + filename_len;
-        let footer_end = footer_start + TREE_ENTRY_FOOTER_SIZE;
+        let footer_end = footer_start + tree_entry_footer_size;
         let footer_data = &data[footer_start..footer_end];
+        let read_field = |i: usize| -> usize {
+            let field_data = &footer_data[1 + i * field_bytes..];
+            if is_dat64 { LittleEndian::read_u64(field_data) as usize } else { LittleEndian::read_u32(field_data) as usize }
+        };
 
-        let tree_entry = TreeEntry {
-            path: filename,
-            is_compressed: data[0] > 0,
-            decompressed_size: LittleEndian::read_u32(&footer_data[1..5]) as usize,
-            packed_size: LittleEndian::read_u32(&footer_data[5..9]) as usize,
-            offset: LittleEndian::read_u32(&footer_data[9..13]) as usize,
+        let tree_entry = TreeEntryRef {
+            name,
+            is_compressed: footer_data[0] > 0,
+            decompressed_size: read_field(0),
+            packed_size: read_field(1),
+            offset: read_field(2),
         };
 
         Ok((tree_entry, total_len))
     }
+
+    /// Returns an iterator over the components of the entry's stored name.
+    pub fn components(&self) -> str::Split<'a, char> {
+        self.name.split(TREE_ENTRY_PATH_SEPARATOR)
+    }
+
+    /// Returns an iterator over the components of the entry's stored name, as delimited by
+    /// `separators`.
+    pub fn components_with(&self, separators: Separators) -> str::Split<'a, fn(char) -> bool> {
+        self.name.split(separators.is_separator())
+    }
+
+    /// Builds the entry's stored name into a native path.
+    pub fn path(&self) -> PathBuf {
+        self.components().collect()
+    }
+
+    /// Returns the final component of the entry's name (e.g. `HAPOWRAA.FRM`).
+    pub fn file_name(&self) -> Option<&'a str> {
+        self.name.rsplit(TREE_ENTRY_PATH_SEPARATOR).next()
+    }
+
+    /// Returns the extension of the entry's filename, without the leading `.` (e.g. `FRM`).
+    pub fn extension(&self) -> Option<&'a str> {
+        self.file_name().and_then(extension)
+    }
+
+    /// Returns the entry's name with `/` separators (e.g. `ART/CRITTERS/HAPOWRAA.FRM`),
+    /// optionally lowercased.
+    pub fn normalized_path(&self, lowercase: bool) -> String {
+        names::join(self.components(), '/', lowercase)
+    }
+
+    /// Returns the ways in which the entry misdescribes its raw data, `entry_data`.
+    pub fn anomalies(&self, entry_data: &[u8]) -> Vec<Anomaly> {
+        let mut anomalies = Vec::new();
+        let method = Method::of_entry(self, entry_data);
+
+        match method {
+            Method::Zlib | Method::ZlibDictionary if !self.is_compressed => anomalies.push(Anomaly::FlaggedStored),
+            Method::Stored if self.is_compressed => anomalies.push(Anomaly::FlaggedCompressed),
+            _ => {},
+        }
+
+        if method == Method::Stored && self.packed_size != self.decompressed_size {
+            anomalies.push(Anomaly::SizeMismatch);
+        }
+
+        anomalies
+    }
+}
+
+impl <'a> fmt::Display for TreeEntryRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.normalized_path(false))
+    }
+}
+
+/// Returns the extension of `file_name`. Like `Path::extension`, a leading `.` doesn't count.
+fn extension(file_name: &str) -> Option<&str> {
+    match file_name.rfind('.') {
+        Some(0) | None => None,
+        Some(i) => Some(&file_name[i+1..]),
+    }
+}
+
+/// Returns an iterator that emits each entry found in the supplied DAT2 data, in tree order.
+///
+/// Each `Entry` has its tree entry's metadata, and reads its data only when asked to, so this
+/// parses the archive once whether a consumer needs metadata, data or both.
+///
+/// The iterator will emit an `Err` if the tree is invalid, followed by halting.
+pub fn entries(dat_data: &[u8]) -> io::Result<Entries<'_>> {
+    let stats = DatStats::parse(dat_data)?;
+    Ok(Entries {
+        data_section: &dat_data[stats.data_range],
+        refs: TreeEntryRefs::new(&dat_data[stats.tree_range], stats.is_dat64, stats.num_files),
+    })
+}
+
+/// An iterator that emits `Entry`s parsed from DAT data.
+pub struct Entries<'a> {
+    data_section: &'a [u8],
+    refs: TreeEntryRefs<'a>,
+}
+
+impl <'a> Entries<'a> {
+
+    /// Returns the number of entries the archive states it has (see `TreeEntryRefs::total`).
+    pub fn total(&self) -> usize {
+        self.refs.total()
+    }
+
+    /// Returns an iterator that emits each entry along with its index and the total (see
+    /// `WithProgress`).
+    pub fn with_progress(self) -> WithProgress<Self> {
+        let total = self.total();
+        WithProgress::new(self, total)
+    }
+}
+
+impl <'a> Iterator for Entries<'a> {
+    type Item = io::Result<Entry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data_section = self.data_section;
+        Some(self.refs.next()?.map(|tree_entry| Entry { tree_entry, data_section }))
+    }
+}
+
+/// An iterator that emits each entry of another as `(index, total, entry)`, from which progress
+/// can be reported (e.g. as `(index + 1) / total`) without counting the entries first.
+///
+/// `total` is the number of entries the archive states it has. It's raised as needed to stay
+/// above `index` if the tree turns out to have more, so a fraction of it never exceeds one.
+pub struct WithProgress<I> {
+    inner: I,
+    index: usize,
+    total: usize,
+}
+
+impl <I> WithProgress<I> {
+    fn new(inner: I, total: usize) -> Self {
+        WithProgress { inner, index: 0, total }
+    }
+}
+
+impl <I, T> Iterator for WithProgress<I> where I: Iterator<Item = io::Result<T>> {
+    type Item = io::Result<(usize, usize, T)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.inner.next()?;
+        let index = self.index;
+        self.index += 1;
+        self.total = self.total.max(self.index);
+        Some(entry.map(|entry| (index, self.total, entry)))
+    }
+}
+
+/// An entry of a DAT: its tree entry's metadata (which it dereferences to), and its data.
+///
+/// The data's range isn't checked until it's read, so an entry with an out-of-bounds range only
+/// fails when its data is asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry<'a> {
+    tree_entry: TreeEntryRef<'a>,
+    data_section: &'a [u8],
+}
+
+impl <'a> Entry<'a> {
+
+    /// Returns the entry's tree entry.
+    pub fn tree_entry(&self) -> &TreeEntryRef<'a> {
+        &self.tree_entry
+    }
+
+    /// Returns the entry's raw (possibly compressed) data.
+    pub fn packed_data(&self) -> io::Result<&'a [u8]> {
+        get_data(self.data_section, self.tree_entry.name, self.tree_entry.offset, self.tree_entry.packed_size)
+    }
+
+    /// Returns how the entry's data is stored (see `Method::of_entry`).
+    pub fn method(&self) -> io::Result<Method> {
+        Ok(Method::of_entry(&self.tree_entry, self.packed_data()?))
+    }
+
+    #[cfg(feature = "compression")]
+    /// Returns the entry's decompressed content.
+    pub fn content(&self) -> io::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        self.reader()?.read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    #[cfg(feature = "compression")]
+    /// Returns a reader of the entry's decompressed content (see `method_reader`).
+    pub fn reader(&self) -> io::Result<EntryReader<'a>> {
+        let entry_data = self.packed_data()?;
+        Ok(method_reader(entry_data, Method::of_entry(&self.tree_entry, entry_data)))
+    }
+
+    #[cfg(feature = "compression")]
+    /// Returns what the entry's content looks like (see `sniff`), decompressing only as much of
+    /// it as that takes.
+    pub fn sniff(&self) -> io::Result<Kind> {
+        sniff::sniff_reader(self.reader()?)
+    }
+
+    #[cfg(feature = "compression")]
+    /// Returns true if the entry's content looks like text (see `sniff`).
+    pub fn looks_like_text(&self) -> io::Result<bool> {
+        Ok(self.sniff()?.is_text())
+    }
+}
+
+impl <'a> std::ops::Deref for Entry<'a> {
+    type Target = TreeEntryRef<'a>;
+
+    fn deref(&self) -> &TreeEntryRef<'a> {
+        &self.tree_entry
+    }
+}
+
+impl <'a> fmt::Display for Entry<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.tree_entry.fmt(f)
+    }
+}
+
+/// Returns an iterator that emits each tree entry found in the supplied DAT2 data along with the
+/// raw (possibly compressed) data it describes.
+#[deprecated(note = "use `entries`, whose `Entry`s read their data when asked to")]
+pub fn iter_entries(dat_data: &[u8]) -> io::Result<impl Iterator<Item = io::Result<(TreeEntry, &[u8])>>> {
+    Ok(entries(dat_data)?.map(|entry| {
+        let entry = entry?;
+        Ok((TreeEntry::from(entry.tree_entry), entry.packed_data()?))
+    }))
 }
 
 /// Returns an iterator that emits raw data entries found in the supplied DAT2 data.
-pub fn iter_data(dat_data: &[u8]) -> io::Result<DataEntries> {
-    let top_level_structure = DatTopLevelStructure::parse(dat_data)?;
+#[deprecated(note = "use `entries`, whose `Entry`s have both metadata and data")]
+pub fn iter_data(dat_data: &[u8]) -> io::Result<DataEntries<'_>> {
     Ok(DataEntries {
-        data_section: &dat_data[top_level_structure.data],
-        tree_entries: iter_tree(dat_data)?,
+        entries: entries(dat_data)?,
     })
 }
 
 pub struct DataEntries<'a> {
-    data_section: &'a [u8],
-    tree_entries: TreeEntries<'a>,
+    entries: Entries<'a>,
 }
 
 impl <'a> Iterator for DataEntries<'a> {
     type Item = io::Result<DataEntry<'a>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.tree_entries.next()? {
-            Ok(tree_entry) => {
-                Some(get_data(self.data_section, tree_entry))
-            },
-            Err(e) => {
-                Some(Err(e))
-            },
-        }
+        let data_entry = self.entries.next()?.and_then(|entry| {
+            Ok(DataEntry {
+                path: entry.path(),
+                raw_data: entry.packed_data()?,
+                decompressed_size: entry.decompressed_size,
+            })
+        });
+
+        Some(data_entry)
     }
 }
 
 /// A data entry (effectively, a file), as parsed from the DAT.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DataEntry<'a> {
     pub path: PathBuf,
     pub raw_data: &'a [u8],
     pub decompressed_size: usize,
 }
 
-fn get_data<'a>(data_section_data: &'a [u8], entry: TreeEntry) -> io::Result<DataEntry<'a>> {
-    let data_start = entry.offset;
-    let data_end = data_start + entry.packed_size;
+impl <'a> fmt::Display for DataEntry<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let components = self.path.iter().map(|component| component.to_str().unwrap());
+        f.write_str(&names::join(components, '/', false))
+    }
+}
+
+#[cfg(feature = "compression")]
+/// Returns the decompressed content of an entry's raw data, going by the data alone (see
+/// `Method::of`). Prefer `decompress_entry` for an archive's entries, whose stored content may
+/// look compressed.
+pub fn decompress(entry_data: &[u8]) -> io::Result<Vec<u8>> {
+    decompress_with(entry_data, Method::of(entry_data))
+}
+
+#[cfg(feature = "compression")]
+/// Returns the decompressed content of `entry_data`, the raw data of `tree_entry`, which is
+/// stored as `Method::of_entry` determines.
+pub fn decompress_entry(tree_entry: &TreeEntryRef, entry_data: &[u8]) -> io::Result<Vec<u8>> {
+    decompress_with(entry_data, Method::of_entry(tree_entry, entry_data))
+}
+
+#[cfg(feature = "compression")]
+/// Returns the decompressed content of an entry's raw data, which is stored with `method`.
+pub fn decompress_with(entry_data: &[u8], method: Method) -> io::Result<Vec<u8>> {
+    match method {
+        Method::Zlib => {
+            let mut decompressed = Vec::new();
+            profile::time(Stage::Decompress, || flate2::read::ZlibDecoder::new(entry_data).read_to_end(&mut decompressed)).map_err(|e| errors::or_code(e, Code::Decompress))?;
+            Ok(decompressed)
+        },
+        Method::ZlibDictionary => profile::time(Stage::Decompress, || dictionary::decompress(entry_data)),
+        Method::Stored => Ok(entry_data.to_vec()),
+    }
+}
+
+/// The size of the chunks that entries' content is streamed in, unless told otherwise.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+#[cfg(feature = "compression")]
+/// Returns a reader of the decompressed content of an entry's raw data, which decompresses it as
+/// it's read. As with `decompress`, this goes by the data alone: for an archive's entries, prefer
+/// `method_reader` with `Method::of_entry`.
+pub fn entry_reader(entry_data: &[u8]) -> EntryReader<'_> {
+    method_reader(entry_data, Method::of(entry_data))
+}
+
+#[cfg(feature = "compression")]
+/// Returns a reader of the decompressed content of an entry's raw data, which is stored with
+/// `method` (e.g. as `Method::of_entry` determined it).
+pub fn method_reader(entry_data: &[u8], method: Method) -> EntryReader<'_> {
+    match method {
+        Method::Zlib => EntryReader { inner: ReaderInner::Zlib(flate2::read::ZlibDecoder::new(entry_data)) },
+        Method::ZlibDictionary => EntryReader { inner: ReaderInner::Dictionary(dictionary::reader(entry_data)) },
+        Method::Stored => EntryReader { inner: ReaderInner::Stored(entry_data) },
+    }
+}
+
+#[cfg(feature = "compression")]
+/// Returns a reader of the content of an entry's raw data that is raw deflate data (see
+/// `is_raw_deflate`), which inflates it as it's read.
+pub fn raw_deflate_reader(entry_data: &[u8]) -> EntryReader<'_> {
+    EntryReader { inner: ReaderInner::Deflate(flate2::read::DeflateDecoder::new(entry_data)) }
+}
+
+#[cfg(feature = "compression")]
+/// A reader of an entry's decompressed content (see `entry_reader`).
+pub struct EntryReader<'a> {
+    inner: ReaderInner<'a>,
+}
 
-    match data_section_data.get(data_start..data_end) {
+#[cfg(feature = "compression")]
+enum ReaderInner<'a> {
+    Zlib(flate2::read::ZlibDecoder<&'a [u8]>),
+    Deflate(flate2::read::DeflateDecoder<&'a [u8]>),
+    Dictionary(dictionary::Reader<'a>),
+    Stored(&'a [u8]),
+}
+
+#[cfg(feature = "compression")]
+impl <'a> Read for EntryReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner {
+            ReaderInner::Zlib(ref mut decoder) => profile::time(Stage::Decompress, || decoder.read(buf)).map_err(|e| errors::or_code(e, Code::Decompress)),
+            ReaderInner::Deflate(ref mut decoder) => profile::time(Stage::Decompress, || decoder.read(buf)).map_err(|e| errors::or_code(e, Code::Decompress)),
+            ReaderInner::Dictionary(ref mut reader) => profile::time(Stage::Decompress, || reader.read(buf)),
+            ReaderInner::Stored(ref mut data) => data.read(buf),
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+/// Passes the decompressed content of an entry's raw data to `f` in chunks of `chunk_size` bytes
+/// (the last may be smaller), returning the content's size.
+///
+/// Unlike `decompress`, this only ever holds one chunk of the content in memory, so it suits
+/// entries of any size (e.g. movies). Stored content is passed straight from `entry_data`. As with
+/// `decompress`, this goes by the data alone: for an archive's entries, prefer
+/// `for_each_chunk_with` and `Method::of_entry`.
+pub fn for_each_chunk<F>(entry_data: &[u8], chunk_size: usize, f: F) -> io::Result<usize>
+    where F: FnMut(&[u8]) -> io::Result<()>
+{
+    for_each_chunk_with(entry_data, Method::of(entry_data), chunk_size, f)
+}
+
+#[cfg(feature = "compression")]
+/// As `for_each_chunk`, for an entry's raw data that is stored with `method` (e.g. as
+/// `Method::of_entry` determined it).
+pub fn for_each_chunk_with<F>(entry_data: &[u8], method: Method, chunk_size: usize, mut f: F) -> io::Result<usize>
+    where F: FnMut(&[u8]) -> io::Result<()>
+{
+    if method != Method::Stored {
+        read_chunks(method_reader(entry_data, method), chunk_size, f)
+    } else {
+        for chunk in entry_data.chunks(chunk_size.max(1)) {
+            f(chunk)?;
+        }
+        Ok(entry_data.len())
+    }
+}
+
+/// Passes everything read from `reader` to `f` in chunks of `chunk_size` bytes (the last may be
+/// smaller), returning the number of bytes read.
+pub fn read_chunks<R: Read, F>(mut reader: R, chunk_size: usize, mut f: F) -> io::Result<usize>
+    where F: FnMut(&[u8]) -> io::Result<()>
+{
+    let mut chunk = vec![0; chunk_size.max(1)];
+    let mut total = 0;
+
+    loop {
+        // decoders return less than asked for, so chunks are filled before they're passed on
+        let mut len = 0;
+        while len < chunk.len() {
+            match reader.read(&mut chunk[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+
+        if len == 0 {
+            return Ok(total);
+        }
+        f(&chunk[..len])?;
+        total += len;
+
+        if len < chunk.len() {
+            return Ok(total);
+        }
+    }
+}
+
+/// The zlib header that the original packer wrote (deflate, with a 32K window, at its best
+/// compression).
+const ORIGINAL_ZLIB_HEADER: [u8; 2] = [0x78, 0xda];
+
+/// Returns true if `data` appears to be zlib compressed.
+///
+/// Entries' `is_compressed` flags aren't reliable, so the data's zlib header is checked instead.
+/// Any valid header is accepted, not just the `78 da` that the original packer wrote: packers
+/// that compress at other levels write e.g. `78 01` or `78 9c`.
+///
+/// One in 31 pairs of bytes is a valid header, so stored content may start with one (e.g. text
+/// starting with `XG`). `Method::of_entry` tells such content apart, given its entry.
+pub fn is_zlib_compressed(data: &[u8]) -> bool {
+    // the low 4 bits of CMF are the method (8 is deflate), and the high 4 bits the log2 of the
+    // window size, minus 8 (at most 7, for 32K)
+    const CM_DEFLATE: u8 = 8;
+    const MAX_CINFO: u8 = 7;
+    // set if a preset dictionary, which the engine doesn't have (see `dictionary`), is needed to
+    // decompress the data
+    const FDICT: u8 = 0x20;
+
+    if data.len() <= 2 {
+        return false;
+    }
+
+    let (cmf, flg) = (data[0], data[1]);
+    cmf & 0x0f == CM_DEFLATE &&
+        cmf >> 4 <= MAX_CINFO &&
+        flg & FDICT == 0 &&
+        (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0
+}
+
+#[cfg(feature = "compression")]
+/// Returns true if `entry_data` is raw deflate data (a zlib stream without its header and
+/// checksum, as some third-party packers write) that inflates to exactly `decompressed_size`
+/// bytes.
+///
+/// Unlike `is_zlib_compressed`, this has to inflate the data, so it's only worth trying on entries
+/// that are flagged as compressed but whose data has no zlib header.
+pub fn is_raw_deflate(entry_data: &[u8], decompressed_size: usize) -> bool {
+    // reading one byte more than expected is enough to tell that there's too much
+    let mut reader = raw_deflate_reader(entry_data).take(decompressed_size as u64 + 1);
+    match io::copy(&mut reader, &mut io::sink()) {
+        Ok(len) => len == decompressed_size as u64,
+        Err(_) => false,
+    }
+}
+
+#[cfg(feature = "compression")]
+/// Returns true if `entry_data` is a zlib stream that inflates to exactly `decompressed_size` bytes.
+fn inflates_to(entry_data: &[u8], decompressed_size: usize) -> bool {
+    // reading one byte more than expected is enough to tell that there's too much
+    let mut reader = flate2::read::ZlibDecoder::new(entry_data).take(decompressed_size as u64 + 1);
+    match io::copy(&mut reader, &mut io::sink()) {
+        Ok(len) => len == decompressed_size as u64,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+/// Without the `compression` feature, data can't be inflated, so only the original packer's
+/// header is taken to be zlib's.
+fn inflates_to(_entry_data: &[u8], _decompressed_size: usize) -> bool {
+    false
+}
+
+/// How an entry's data is stored, as determined from the data itself (and, by `of_entry`, its
+/// entry).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Zlib,
+    /// Zlib with fo2dat's preset dictionary (see `dictionary`), which the engine can't read.
+    ZlibDictionary,
+    Stored,
+}
+
+impl Method {
+    /// Returns the method of `entry_data`, an entry's raw data.
+    pub fn of(entry_data: &[u8]) -> Self {
+        if is_zlib_compressed(entry_data) {
+            Method::Zlib
+        } else if dictionary::is_dictionary_compressed(entry_data) {
+            Method::ZlibDictionary
+        } else {
+            Method::Stored
+        }
+    }
+
+    /// Returns the method of `entry_data`, the raw data of `tree_entry`.
+    ///
+    /// This is `of(entry_data)`, unless the entry is flagged as stored and its data has a zlib
+    /// header other than the original packer's `78 da`, which stored content may start with by
+    /// chance (see `is_zlib_compressed`). Such data is only taken to be zlib if it inflates to
+    /// exactly the entry's decompressed size, which means inflating it.
+    pub fn of_entry(tree_entry: &TreeEntryRef, entry_data: &[u8]) -> Self {
+        let method = Method::of(entry_data);
+        let is_ambiguous = method == Method::Zlib && !tree_entry.is_compressed && !entry_data.starts_with(&ORIGINAL_ZLIB_HEADER);
+        if is_ambiguous && !inflates_to(entry_data, tree_entry.decompressed_size) {
+            Method::Stored
+        } else {
+            method
+        }
+    }
+
+    /// Returns the method's name, as listings show it (e.g. `zlib`).
+    pub fn name(self) -> &'static str {
+        match self {
+            Method::Zlib => "zlib",
+            Method::ZlibDictionary => "zlib-dict",
+            Method::Stored => "stored",
+        }
+    }
+}
+
+/// A way in which a tree entry misdescribes its data. The engine ignores these (it goes by the
+/// data), but they're real artifacts of buggy packers, and may trip up other tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anomaly {
+    /// The entry is flagged as compressed, but its data isn't zlib.
+    FlaggedCompressed,
+    /// The entry is flagged as stored, but its data is zlib.
+    FlaggedStored,
+    /// The data is stored, but the entry's packed and decompressed sizes differ.
+    SizeMismatch,
+}
+
+impl Anomaly {
+    /// Returns the anomaly's name, as listings show it (e.g. `flagged-compressed`).
+    pub fn name(self) -> &'static str {
+        match self {
+            Anomaly::FlaggedCompressed => "flagged-compressed",
+            Anomaly::FlaggedStored => "flagged-stored",
+            Anomaly::SizeMismatch => "size-mismatch",
+        }
+    }
+
+    /// Returns a description of the anomaly, for messages about an entry.
+    pub fn description(self) -> &'static str {
+        match self {
+            Anomaly::FlaggedCompressed => "flagged as compressed, but its data isn't zlib",
+            Anomaly::FlaggedStored => "flagged as stored, but its data is zlib",
+            Anomaly::SizeMismatch => "stored, but its packed and decompressed sizes differ",
+        }
+    }
+}
+
+fn get_data<'a>(data_section_data: &'a [u8], name: &str, offset: usize, packed_size: usize) -> io::Result<&'a [u8]> {
+    let data_start = offset;
+    // a DAT64 entry's offset and size can each be near `u64::MAX`, so their sum can overflow
+    let data_end = data_start.checked_add(packed_size);
+
+    match data_end.and_then(|data_end| data_section_data.get(data_start..data_end)) {
         Some(entry_data) => {
-            Ok(DataEntry {
-                path: entry.path,
-                raw_data: entry_data,
-                decompressed_size: entry.decompressed_size,
-            })
+            Ok(entry_data)
         },
         None => {
-            let err_msg = format!("{}: data range ({}-{}) is out of bounds", entry.path.to_str().unwrap(), data_start, data_end);
-            Err(Error::new(ErrorKind::InvalidData, err_msg))
+            let err_msg = format!("{}: data range ({}-{}) is out of bounds", name, data_start, data_start as u128 + packed_size as u128);
+            Err(errors::new(Code::DataOutOfBounds, ErrorKind::InvalidData, err_msg))
         }
     }
 }
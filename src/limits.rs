@@ -0,0 +1,32 @@
+//! Queries of the OS's per-process resource limits.
+
+/// Returns the number of files the process may have open at once, if it's limited.
+///
+/// This counts every open file descriptor (including stdio and memory-mapped archives), so callers
+/// should leave headroom below it.
+pub fn max_open_files() -> Option<usize> {
+    sys::max_open_files()
+}
+
+#[cfg(unix)]
+mod sys {
+    use libc;
+
+    pub fn max_open_files() -> Option<usize> {
+        let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 || limit.rlim_cur == libc::RLIM_INFINITY {
+            None
+        } else {
+            Some(limit.rlim_cur as usize)
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    // Windows limits handles per process only by available memory
+    pub fn max_open_files() -> Option<usize> {
+        None
+    }
+}
@@ -0,0 +1,123 @@
+//! Parsing and cross-checking of Fallout LST index files.
+//!
+//! An LST lists the files of its directory, one per line, and the engine refers to those files
+//! by line number (e.g. an FID or PID encodes an index into an LST). Lines may carry extra
+//! comma-separated fields or a `;` comment after the file name:
+//!
+//! ```text
+//! knife.frm
+//! 00000002.pro   ; Knife
+//! hapowr,11,1
+//! ```
+//!
+//! Critter art LSTs list base names (`hapowr`) rather than files, which the engine combines with
+//! animation codes (`hapowraa.frm`, `hapowrab.frm`, ...).
+
+use std::collections::HashSet;
+use std::io;
+
+use {Dat, TreeEntryRef, decompress_entry};
+
+/// The extensions of art files, which are expected to be listed in their directory's LST.
+const ART_EXTENSIONS: [&str; 7] = ["frm", "fr0", "fr1", "fr2", "fr3", "fr4", "fr5"];
+
+/// A file name referenced by an LST that doesn't exist in the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingReference<'a> {
+    pub lst: TreeEntryRef<'a>,
+    /// The (1-based) line of the LST the reference is on.
+    pub line: usize,
+    pub name: String,
+}
+
+/// The result of cross-checking an archive's LSTs against its entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validation<'a> {
+    pub num_lsts: usize,
+    /// References to files that don't exist, ordered by LST directory.
+    pub missing: Vec<MissingReference<'a>>,
+    /// Art entries in a directory with an LST that none of the directory's LSTs reference.
+    pub unreferenced: Vec<TreeEntryRef<'a>>,
+}
+
+/// Returns the name referenced by each line of an LST, or `""` for lines that don't reference
+/// anything (blank lines, comments or counts).
+pub fn names(content: &str) -> impl Iterator<Item = &str> {
+    content.lines().map(|line| {
+        let name = line.split(';').next().unwrap_or("");
+        let name = name.split(',').next().unwrap_or("").trim();
+        let name = name.split_whitespace().next().unwrap_or("");
+        if name.bytes().all(|b| b.is_ascii_digit()) { "" } else { name }
+    })
+}
+
+/// Checks that every file referenced by an LST in `dat` exists in `dat`, and finds art that isn't
+/// referenced by its directory's LST.
+pub fn validate<'a>(dat: &Dat<'a>) -> io::Result<Validation<'a>> {
+    let mut validation = Validation { num_lsts: 0, missing: Vec::new(), unreferenced: Vec::new() };
+
+    for (dir, dir_entries) in dat.dirs() {
+        let dir_entries: Vec<&TreeEntryRef<'a>> = dir_entries.collect();
+        let file_names: HashSet<String> = dir_entries.iter()
+            .filter_map(|entry| entry.file_name())
+            .map(str::to_ascii_lowercase)
+            .collect();
+
+        let lst_entries = dir_entries.iter()
+            .filter(|entry| entry.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("lst")));
+
+        let mut referenced_files = HashSet::new();
+        let mut referenced_base_names = Vec::new();
+
+        for lst_entry in lst_entries {
+            validation.num_lsts += 1;
+
+            let content = decompress_entry(lst_entry, dat.data(lst_entry)?)?;
+            let content = String::from_utf8_lossy(&content);
+
+            for (i, name) in names(&content).enumerate() {
+                if name.is_empty() {
+                    continue;
+                }
+                let name_lower = name.to_ascii_lowercase();
+
+                let exists = if name.contains('.') {
+                    let exists = if name.contains(['/', '\\']) {
+                        dat.get(&format!("{}/{}", dir, name)).is_some()
+                    } else {
+                        file_names.contains(&name_lower)
+                    };
+                    referenced_files.insert(name_lower);
+                    exists
+                } else {
+                    let exists = file_names.iter().any(|file_name| file_name.starts_with(&name_lower));
+                    referenced_base_names.push(name_lower);
+                    exists
+                };
+
+                if !exists {
+                    validation.missing.push(MissingReference { lst: **lst_entry, line: i + 1, name: name.to_string() });
+                }
+            }
+        }
+
+        let is_art_dir = dir == "art" || dir.starts_with("art/");
+        if !is_art_dir || (referenced_files.is_empty() && referenced_base_names.is_empty()) {
+            continue;
+        }
+
+        for entry in &dir_entries {
+            let is_art = entry.extension().is_some_and(|ext| ART_EXTENSIONS.iter().any(|art_ext| ext.eq_ignore_ascii_case(art_ext)));
+            let file_name = entry.file_name().unwrap_or("").to_ascii_lowercase();
+
+            let is_referenced = referenced_files.contains(&file_name) ||
+                referenced_base_names.iter().any(|base_name| file_name.starts_with(base_name.as_str()));
+
+            if is_art && !is_referenced {
+                validation.unreferenced.push(**entry);
+            }
+        }
+    }
+
+    Ok(validation)
+}
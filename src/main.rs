@@ -5,54 +5,611 @@ extern crate flate2;
 extern crate rayon;
 
 use clap::App;
+use clap::AppSettings;
 use clap::Arg;
+use clap::SubCommand;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
 use std::io;
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
+use std::net::TcpListener;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::io::ErrorKind;
-use memmap::Mmap;
+use std::ops::Range;
+use memmap::{Mmap, MmapMut};
 use std::fs::File;
-use flate2::read::ZlibDecoder;
+use flate2::Crc;
 use std::io::Error;
 use std::io::Write;
-use fo2dat::TreeEntry;
+use std::io::BufWriter;
+use fo2dat::Dat;
+use fo2dat::{Entry, Separators, TreeEntryRef};
+use fo2dat::{decompress_entry, decompress_with};
+use fo2dat::{Anomaly, Method};
+use fo2dat::advise;
+use fo2dat::advise::Advice;
+use fo2dat::bos::Bos;
+use fo2dat::budget::{MemoryBudget, Reservation};
+use fo2dat::category::{self, Category};
+use fo2dat::config::{self, Layer};
+use fo2dat::container;
+use fo2dat::converter::{self, ExternalConverter};
+#[cfg(feature = "image")]
+use fo2dat::frm::Frm;
+use fo2dat::digest;
+use fo2dat::digest::{Algorithm, Hasher};
+use fo2dat::doctor::{self, Severity};
+use fo2dat::encoding::Encoding;
+use fo2dat::errors::{self, Code};
+use fo2dat::extension::Extension;
+use fo2dat::glob::{Filter, MatchOptions, Pattern};
+use fo2dat::ignore::Ignores;
+use fo2dat::index::{self, CachedIndex};
+use fo2dat::json;
+use fo2dat::layout;
+use fo2dat::layout::{Kind, Part, Region};
+use fo2dat::limits;
+use fo2dat::lst;
+use fo2dat::map::{MapInfo, SCRIPT_TYPE_NAMES};
+use fo2dat::msg::Msg;
+use fo2dat::names;
+use fo2dat::pal::{Palette, Scaling};
+use fo2dat::patch;
+use fo2dat::remote::{self, RemoteFile};
+use fo2dat::priority;
+use fo2dat::profile::{self, Stage, Timed};
+use fo2dat::pro::Pro;
+#[cfg(feature = "image")]
+use fo2dat::rix::Rix;
+use fo2dat::sandbox::Sandbox;
+use fo2dat::save::{self, SaveHeader};
+use fo2dat::sniff;
+use fo2dat::serve::{self, Published, Snapshot};
+use fo2dat::spec::Spec;
+use fo2dat::temp::TempDir;
+use fo2dat::template::Template;
+use fo2dat::text::{self, LineEnding, Transcoding};
+use fo2dat::writer;
+use fo2dat::writer::{EntryOrder, NewEntry, Source, WriteOptions};
 use rayon::prelude::*;
 
 
 const APP_NAME: &str = "fo2dat";
+const DEFAULT_WRITE_BUFFER_SIZE: usize = 64 * 1024;
+/// Open files that extraction leaves for everything else (stdio, the archive, etc.) when deriving
+/// `--max-open-files` from the process's limit.
+const RESERVED_OPEN_FILES: usize = 16;
+const SUPPORTED_CONVERSIONS: &str = "frm=png, rix=png, msg=json, msg=po, msg=txt";
+const OUTPUT_FORMAT_HELP: &str = "print each entry as FORMAT, in which {path}, {index}, {size} (decompressed), {packed} (packed size), {ratio} (packed size / size), {offset}, {compressed} (the entry's flag: yes or no), {method}, {anomalies} and {crc} (see --show-method and --show-crc) are replaced with the entry's, {{ and }} are braces, and \\t and \\n are a tab and a newline";
 
 enum CliAction {
+    /// Create an archive from the given files and directories.
+    Create(Vec<String>),
+    /// Create the archive described by the spec at the given path (see `fo2dat::spec`).
+    CreateFromSpec(String),
     Extract,
     List,
+    /// List the immediate subdirectories and files of the directory at the given path, with their
+    /// sizes if `long`.
+    Ls { dir: String, long: bool },
+    /// Print the tree of subdirectories and files under the directory at the given path, at
+    /// most `depth` levels deep.
+    Tree { dir: String, depth: Option<usize> },
+    /// Summarize the given MAP entries, or all of them if none are given.
+    MapInfo(Vec<String>),
+    /// Dump the given PRO entries as JSON, or all of them if none are given.
+    ProDump(Vec<String>),
+    /// Check that the files referenced by LSTs exist, and report art that no LST references.
+    LstCheck,
+    /// Summarize a savegame's SAVE.DAT.
+    SaveInfo,
+    /// Print the archive's metadata, including the creator and comment recorded in its extension.
+    Info,
+    /// Write (or rewrite) the archive's index sidecar.
+    Index,
+    /// Print the metadata of the given entries.
+    Stat(Vec<String>),
+    /// Print a hex dump of the given entries' (decompressed, unless `raw`) data.
+    Hexdump { paths: Vec<String>, raw: bool },
+    /// Check that every entry decompresses to its stated size and, if the archive has a CRC
+    /// table, to its stated CRC-32.
+    Verify,
+    /// Print the digest of the archive file and, if `logical`, of its logical content.
+    Checksum { algorithm: Algorithm, logical: bool },
+    /// Compare the files in a directory with the entries they were extracted from.
+    VerifyDir,
+    /// Print how a directory differs from the archive (`diff-dir`).
+    DiffDir { dir: String },
+    /// Run every structural check on the archive, and diagnose what's wrong with it (if anything)
+    /// and what might fix it.
+    Doctor,
+    /// Print the number of entries that `filter` selects and, if `sizes`, their total sizes and, if
+    /// `histogram`, histograms of their compression ratios and sizes.
+    Count { filter: Filter, sizes: bool, histogram: bool },
+    /// Exit with 0 if the archive has entries at all of `paths`, or 1 if it doesn't, printing nothing.
+    Exists { paths: Vec<String>, case_sensitive: bool },
+    /// Print the entries whose content is identical to each of the given files, and exit with 1
+    /// if any of them matches none.
+    Which { files: Vec<String>, algorithm: Algorithm },
+    /// Write the decompressed content of the entries at `paths` and those `filter` selects to
+    /// stdout, one after the other, in tree order.
+    Cat { paths: Vec<String>, filter: Filter },
+    /// Open the decompressed content of the entry at the given path (or `--entry-index`) with
+    /// `program`, or else the viewer configured for its type, or else the pager.
+    View { path: Option<String>, program: Option<String> },
+    /// Print how the data section is used, region by region, or only its problem regions.
+    Layout { problems_only: bool },
+    /// Print what each of the given offsets into the archive belongs to.
+    Offset(Vec<usize>),
+    /// Estimate the size of the archive's data if it were recompressed at `level`, from at most
+    /// `sample` entries' data (or all of it).
+    Estimate { level: u32, sample: Option<usize> },
+    /// Rewrite the archive with its entries recompressed at `level`, to `output` if it's given
+    /// (otherwise, in place).
+    Recompress { level: u32, packing: Packing, dictionary: bool, output: Option<String> },
+    /// Write the archive's spec (see `fo2dat::spec`) to `output`, or to stdout if it isn't given.
+    ExportSpec { output: Option<String> },
+    /// Serve an archive of the files in the directory over HTTP at `listen` (see `fo2dat::serve`),
+    /// rebuilding it whenever they change, as seen by polling every `poll_interval`.
+    DevServer { listen: String, poll_interval: Duration, cache_size: usize },
+    /// Print the load order of the installation that `fallout2.cfg` (the file) and, if it's given,
+    /// `ddraw.ini` configure or, for each of `paths`, the layer it's loaded from.
+    LoadOrder { ddraw: Option<String>, paths: Vec<String> },
+    /// Make the archive match the directory at `dir`, adding, updating and deleting entries, or
+    /// if `reverse`, make the directory match the archive. If `dry_run`, only print the changes.
+    Sync { dir: String, reverse: bool, dry_run: bool },
+    /// Write a patch (see `fo2dat::patch`) that turns the archive into the archive at `new`, to
+    /// `output`.
+    PatchCreate { new: String, output: String },
+    /// Apply the patch at `patch` to the archive, writing the archive it rebuilds to `output` if
+    /// it's given (otherwise, in place).
+    PatchApply { patch: String, output: Option<String> },
+}
+
+/// The format of the archive being listed or extracted (`--format`).
+#[derive(Clone, Copy, PartialEq)]
+enum ArchiveFormat {
+    Dat2,
+    /// Fallout Tactics `.bos` archives.
+    Tactics,
 }
 
 struct CliArgs {
     action: CliAction,
     file: String,
+    /// Every archive that `-f` names, of which `file` is the one being acted on: list, extract and
+    /// verify take several, and act on each in turn.
+    files: Vec<String>,
+    format: ArchiveFormat,
     ch_dir: String,
     verbose: bool,
+    dedup: bool,
+    dedup_algorithm: Algorithm,
+    sort_entries: EntryOrder,
+    strict_names: bool,
+    strict_flags: bool,
+    raw_deflate: bool,
+    absolute_names: bool,
+    follow_symlinks: bool,
+    sandbox: bool,
+    read_separators: Separators,
+    write_separator: char,
+    crc_table: bool,
+    dat64: bool,
+    /// Whether creating leaves out version control's files (`--exclude-vcs`).
+    exclude_vcs: bool,
+    /// The time that files must have been modified after to be packed (`--newer-mtime`), if any.
+    newer_mtime: Option<SystemTime>,
+    /// Whether packing a directory includes the files and directories under it whose names start
+    /// with `.` (`--dotfiles`).
+    dotfiles: bool,
+    symlinks: Symlinks,
+    empty_dirs: EmptyDirs,
+    /// Whether created archives compress small text entries with the preset dictionary
+    /// (`--dictionary`).
+    dictionary: bool,
+    /// The creator recorded in created archives (`--record-tool`), if any.
+    tool: Option<String>,
+    /// The comment recorded in created archives (`--comment`), if any.
+    comment: Option<String>,
+    filter: Filter,
+    /// Entries selected by their index in the tree (`--entry-index`).
+    entry_indexes: Vec<usize>,
+    /// Which occurrence of each duplicated path is selected (`--occurrence`), if not all of them.
+    occurrence: Option<usize>,
+    show_index: bool,
+    show_crc: bool,
+    show_method: bool,
+    /// Whether sizes in summaries are printed like `1.4M` rather than in bytes (`-h`).
+    human_readable: bool,
+    /// Whether output (and progress) is printed as JSON (`--json`), rather than as text.
+    json: bool,
+    /// How each listed entry is printed (`--output-format`), if not the default.
+    output_format: Option<Template<Field>>,
+    dirs_only: bool,
+    /// Whether listing and extraction descend into entries that are archives themselves.
+    recursive: bool,
+    /// The line endings that text assets are extracted with, if they're converted.
+    text_eol: Option<LineEnding>,
+    /// The conversion of text assets' content as they're extracted (and, reversed, as they're
+    /// packed), if they're transcoded.
+    text_encoding: Option<Transcoding>,
+    /// The most entries to list (`--limit`), if it's limited.
+    limit: Option<usize>,
+    /// How many of the largest entries to list (`--largest`), if only those are listed.
+    largest: Option<usize>,
+    write_buffer_size: usize,
+    /// The size of the chunks that entries are decompressed and written in.
+    chunk_size: usize,
+    /// The most output files extraction may have open at once, if it's limited.
+    max_open_files: Option<usize>,
+    checkpoint: Option<Checkpoint>,
+    /// Whether to print where the operation's time went (`--profile`).
+    profile: bool,
+    /// The most bytes per second extraction may write, if it's limited.
+    throttle: Option<f64>,
+    /// The most memory that workers may hold at once (`--max-memory`), if it's bounded.
+    max_memory: Option<usize>,
+    low_priority: bool,
+    /// The niceness to run at (`--nice`), if any.
+    nice: Option<u8>,
+    /// Whether extracted files are written under a temporary name and renamed into place.
+    atomic: bool,
+    /// Where the manifest of extracted files is written (`--write-manifest`), if anywhere.
+    manifest: Option<String>,
+    /// The digest algorithm of the manifest (`--manifest-algo`).
+    manifest_algorithm: Algorithm,
+    /// The permissions of extracted files and of the directories created for them (before the
+    /// umask is applied), if they're not the defaults.
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+    conversions: Vec<Conversion>,
+    keep_original: bool,
+    flatten: bool,
+    on_collision: Collision,
+    palette: Option<String>,
+    palette_scaling: Scaling,
+    msg_encoding: Encoding,
+}
+
+/// How often extraction reports its progress (`--checkpoint`).
+#[derive(Clone, Copy, PartialEq)]
+enum Checkpoint {
+    /// After every N entries.
+    Entries(usize),
+    /// After the first entry that finishes at least this long after the last report.
+    Interval(Duration),
+}
+
+impl Checkpoint {
+    /// Parses `N` (entries) or `Ns` (seconds).
+    fn parse(checkpoint_str: &str) -> io::Result<Self> {
+        let (digits, is_seconds) = match checkpoint_str.strip_suffix('s') {
+            Some(digits) => (digits, true),
+            None => (checkpoint_str, false),
+        };
+
+        match digits.parse::<u64>() {
+            Ok(n) if n > 0 && is_seconds => Ok(Checkpoint::Interval(Duration::from_secs(n))),
+            Ok(n) if n > 0 => Ok(Checkpoint::Entries(n as usize)),
+            _ => {
+                let err_msg = format!("{}: invalid checkpoint: must be a number of entries (e.g. 1000) or seconds (e.g. 30s)", checkpoint_str);
+                Err(Error::new(ErrorKind::InvalidInput, err_msg))
+            }
+        }
+    }
+}
+
+/// What packing a directory does with a symbolic link under it (`--symlinks`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Symlinks {
+    /// Packs what it links to, unless that's a directory it's in (which would never end).
+    Follow,
+    Skip,
+    Error,
+}
+
+/// What packing a directory does with an empty directory under it, which an archive can't store
+/// (`--empty-dirs`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EmptyDirs {
+    Skip,
+    Warn,
+    Error,
+}
+
+/// Which entries `recompress` compresses.
+#[derive(Clone, Copy, PartialEq)]
+enum Packing {
+    /// Those that compressing makes smaller, as archives are created.
+    Auto,
+    /// None (`--store-all`).
+    StoreAll,
+    /// All (`--compress-all`).
+    CompressAll,
+}
+
+/// A placeholder of `--output-format`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Path,
+    Index,
+    Size,
+    PackedSize,
+    Ratio,
+    Offset,
+    Compressed,
+    Method,
+    Anomalies,
+    Crc,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "path" => Some(Field::Path),
+            "index" => Some(Field::Index),
+            "size" => Some(Field::Size),
+            "packed" => Some(Field::PackedSize),
+            "ratio" => Some(Field::Ratio),
+            "offset" => Some(Field::Offset),
+            "compressed" => Some(Field::Compressed),
+            "method" => Some(Field::Method),
+            "anomalies" => Some(Field::Anomalies),
+            "crc" => Some(Field::Crc),
+            _ => None,
+        }
+    }
+}
+
+/// What `--flatten` does when several entries have the same file name (`--on-collision`).
+#[derive(Clone, Copy, PartialEq)]
+enum Collision {
+    /// Fail before extracting anything.
+    Error,
+    /// Keep the first entry (in tree order).
+    Skip,
+    /// Keep the last entry (in tree order).
+    Overwrite,
+    /// Keep all entries, giving later ones a `~N` suffix.
+    Rename,
+}
+
+/// A conversion applied to matching entries during extraction (`--convert SRC=DEST`).
+#[derive(Clone)]
+enum Conversion {
+    #[cfg(feature = "image")]
+    FrmToPng,
+    #[cfg(feature = "image")]
+    RixToPng,
+    MsgToJson,
+    MsgToPo,
+    MsgToText,
+    /// A conversion by a command, configured in fo2dat's config file (see `converter`).
+    External(Arc<ExternalConverter>),
+}
+
+impl Conversion {
+    /// Parses `conversion_str`, which may name one of the `external` converters, which take
+    /// precedence over fo2dat's own.
+    fn parse(conversion_str: &str, external: &[Arc<ExternalConverter>]) -> io::Result<Self> {
+        let configured = external.iter().find(|converter| {
+            conversion_str.eq_ignore_ascii_case(&format!("{}={}", converter.source_extension(), converter.target_extension()))
+        });
+        if let Some(converter) = configured {
+            return Ok(Conversion::External(converter.clone()));
+        }
+
+        match conversion_str.to_ascii_lowercase().as_str() {
+            #[cfg(feature = "image")]
+            "frm=png" => Ok(Conversion::FrmToPng),
+            #[cfg(feature = "image")]
+            "rix=png" => Ok(Conversion::RixToPng),
+            "msg=json" => Ok(Conversion::MsgToJson),
+            "msg=po" => Ok(Conversion::MsgToPo),
+            "msg=txt" => Ok(Conversion::MsgToText),
+            #[cfg(not(feature = "image"))]
+            "frm=png" | "rix=png" => {
+                let err_msg = format!("{}: this build of {} has no image support", conversion_str, APP_NAME);
+                Err(Error::new(ErrorKind::InvalidInput, err_msg))
+            },
+            _ => {
+                let configured = external.iter().map(|converter| format!(", {}={}", converter.source_extension(), converter.target_extension())).collect::<String>();
+                let err_msg = format!("{}: unsupported conversion (supported: {}{})", conversion_str, SUPPORTED_CONVERSIONS, configured);
+                Err(Error::new(ErrorKind::InvalidInput, err_msg))
+            },
+        }
+    }
+
+    /// Returns the (lowercase) extension of entries the conversion applies to.
+    fn source_extension(&self) -> &str {
+        match *self {
+            #[cfg(feature = "image")]
+            Conversion::FrmToPng => "frm",
+            #[cfg(feature = "image")]
+            Conversion::RixToPng => "rix",
+            Conversion::MsgToJson | Conversion::MsgToPo | Conversion::MsgToText => "msg",
+            Conversion::External(ref converter) => converter.source_extension(),
+        }
+    }
+
+    /// Returns the extension given to converted files.
+    fn target_extension(&self) -> &str {
+        match *self {
+            #[cfg(feature = "image")]
+            Conversion::FrmToPng | Conversion::RixToPng => "png",
+            Conversion::MsgToJson => "json",
+            Conversion::MsgToPo => "po",
+            Conversion::MsgToText => "txt",
+            Conversion::External(ref converter) => converter.target_extension(),
+        }
+    }
+
+    /// Returns true if the conversion needs a palette from outside of the entry being converted.
+    fn needs_palette(&self) -> bool {
+        match *self {
+            #[cfg(feature = "image")]
+            Conversion::FrmToPng => true,
+            #[cfg(feature = "image")]
+            Conversion::RixToPng => false,
+            Conversion::MsgToJson | Conversion::MsgToPo | Conversion::MsgToText | Conversion::External(_) => false,
+        }
+    }
 }
 
 impl CliArgs {
     fn parse() -> io::Result<Self> {
         let matches = App::new(APP_NAME)
             .about("A Fallout 2 DAT archive utility")
+            .arg(Arg::with_name("create")
+                .short("c")
+                .long("create")
+                .help("create a DAT2 archive from files and directories")
+                .takes_value(false))
+            .arg(Arg::with_name("no-dedup")
+                .long("--no-dedup")
+                .help("when creating, store every file's data separately, even if its content is identical to another file's"))
+            .arg(Arg::with_name("dedup-algo")
+                .long("--dedup-algo")
+                .value_name("ALGORITHM")
+                .help("when creating, the digest algorithm that files with identical content are found by: 'sha256', 'crc32', 'xxh64' or 'blake3'. Files whose crc32 or xxh64 digests match are compared to confirm that they're identical, while sha256 and blake3 digests are trusted")
+                .default_value("crc32"))
+            .arg(Arg::with_name("sort-entries")
+                .long("--sort-entries")
+                .value_name("ORDER")
+                .help("when creating, the order of entries in the archive: 'path' (case-insensitively), 'none' (as given, with directories in the order the filesystem lists them) or 'size' (smallest first)")
+                .possible_values(&["path", "none", "size"])
+                .default_value("path"))
+            .arg(Arg::with_name("from-spec")
+                .long("--from-spec")
+                .value_name("SPEC")
+                .help("when creating, rebuild the archive described by SPEC (see export-spec), with its paths, order and compression, from the files in the directory (-C), rather than from the given paths")
+                .takes_value(true))
+            .arg(Arg::with_name("exclude-vcs")
+                .long("--exclude-vcs")
+                .help("when creating (or syncing) from a directory, leave out version control's files and directories (e.g. .git, .gitignore and .svn), as well as what .datignore files (in gitignore's syntax) exclude, which is always left out"))
+            .arg(newer_mtime_arg())
+            .arg(Arg::with_name("dotfiles")
+                .long("--dotfiles")
+                .value_name("POLICY")
+                .help("when creating (or syncing) from a directory, whether files and directories under it whose names start with '.' are packed: 'include' or 'exclude'. Paths given explicitly are packed either way")
+                .possible_values(&["include", "exclude"])
+                .default_value("include"))
+            .arg(Arg::with_name("symlinks")
+                .long("--symlinks")
+                .value_name("POLICY")
+                .help("when creating (or syncing) from a directory, what to do with symbolic links under it: 'follow' (pack what they link to, skipping links to a directory they're in, with a warning), 'skip' or 'error'")
+                .possible_values(&["follow", "skip", "error"])
+                .default_value("follow"))
+            .arg(Arg::with_name("empty-dirs")
+                .long("--empty-dirs")
+                .value_name("POLICY")
+                .help("when creating (or syncing) from a directory, what to do with directories under it that nothing is packed from, which an archive can't store (it only stores files, under their paths): 'skip', 'warn' or 'error'")
+                .possible_values(&["skip", "warn", "error"])
+                .default_value("skip"))
+            .arg(Arg::with_name("strict-names")
+                .long("--strict-names")
+                .help("when creating, fail on paths the engine may be unable to load (e.g. too long or non-ASCII), rather than warning about them"))
+            .arg(Arg::with_name("strict-flags")
+                .long("--strict-flags")
+                .help("when extracting, fail on entries whose compressed flag disagrees with their data (as some third-party packers write), rather than warning about them and going by the data, as the engine does"))
+            .arg(Arg::with_name("raw-deflate")
+                .long("--raw-deflate")
+                .help("when extracting, inflate entries that are flagged as compressed but whose data has no zlib header as raw deflate data (as some third-party packers write), if it inflates to their stated size"))
+            .arg(Arg::with_name("absolute-names")
+                .long("--absolute-names")
+                .help("when extracting, extract entries whose stored path is absolute (e.g. '\\art\\a.frm' or 'C:\\art\\a.frm') into the output directory, as if it were relative, rather than refusing to"))
+            .arg(Arg::with_name("follow-symlinks")
+                .long("--follow-symlinks")
+                .help("when extracting, write through symbolic links in the output directory (e.g. a directory 'art' that links elsewhere), which may lead outside of it, rather than refusing to"))
+            .arg(Arg::with_name("sandbox")
+                .long("--sandbox")
+                .help("when extracting, create every file and directory through a handle on the output directory that can't resolve paths to outside of it (openat2's RESOLVE_BENEATH), so that no entry path, symbolic link or concurrent rename can make extraction write elsewhere (Linux 5.6 or later)"))
+            .arg(Arg::with_name("write-separator")
+                .long("--write-separator")
+                .value_name("SEPARATOR")
+                .help("when creating, the separator stored between the components of entry paths: 'backslash' (as the engine expects) or 'slash'")
+                .possible_values(&["backslash", "slash"])
+                .default_value("backslash"))
+            .arg(Arg::with_name("crc")
+                .long("--crc")
+                .help("when creating, store every entry's CRC-32 in otherwise unused space of the archive (which the engine ignores), for 'verify' and --show-crc"))
+            .arg(Arg::with_name("dat64")
+                .long("--dat64")
+                .help("when creating, write a DAT64 archive: fo2dat's variant of DAT2 with 64-bit sizes and offsets, which may be larger than 4 GiB, but WHICH THE GAME CAN'T LOAD"))
+            .arg(Arg::with_name("dictionary")
+                .long("--dictionary")
+                .help("when creating, compress small text entries (e.g. dialog MSG files) with fo2dat's preset dictionary where that makes them smaller, flagging them as such in their zlib header, which makes dialog-heavy archives smaller, but WHICH THE GAME CAN'T LOAD (only fo2dat can read them, e.g. to extract them or recompress them without it)"))
+            .arg(Arg::with_name("record-tool")
+                .long("--record-tool")
+                .help("when creating, record fo2dat and its version as the archive's creator, in otherwise unused space of the archive (which the engine ignores), for 'info'"))
+            .arg(Arg::with_name("comment")
+                .long("--comment")
+                .value_name("TEXT")
+                .help("when creating, record TEXT (e.g. which pipeline or build produced the archive) as the archive's comment, in otherwise unused space of the archive, for 'info'")
+                .takes_value(true))
+            .arg(Arg::with_name("paths")
+                .value_name("PATH")
+                .help("when creating, a file or directory (relative to -C) to add to the archive")
+                .multiple(true))
             .arg(Arg::with_name("extract")
                 .short("x")
                 .long("extract")
                 .help("extract files from a DAT2 archive")
                 .takes_value(false))
-            .arg(Arg::with_name("file")
-                .short("f")
-                .long("--file")
-                .value_name("DAT2_FILE")
-                .help("use file")
-                .takes_value(true))
+            .arg(file_arg("DAT2_FILE"))
             .arg(Arg::with_name("list")
                 .short("t")
                 .long("list")
                 .help("list the contents of a DAT2 archive"))
+            .arg(Arg::with_name("read-separators")
+                .long("--read-separators")
+                .value_name("SEPARATORS")
+                .help("when listing or extracting a DAT2 archive, what separates the components of stored entry paths: 'backslash' (as the engine expects), 'any' (backslash or slash) or 'literal' (nothing, so each path is one file name)")
+                .possible_values(&["backslash", "any", "literal"])
+                .default_value("backslash"))
+            .arg(Arg::with_name("show-index")
+                .long("--show-index")
+                .help("prefix each listed entry with its index in the tree (#0, #1, ...)"))
+            .arg(Arg::with_name("dirs-only")
+                .long("--dirs-only")
+                .help("list the directories that entries are in (including their ancestors) rather than the entries, each prefixed with the number of entries under it"))
+            .arg(Arg::with_name("recursive")
+                .long("--recursive")
+                .help("when listing or extracting, descend into entries that are archives themselves (.dat entries that are DAT2 archives, e.g. bundled mod packages): their entries are listed under the archive's path (e.g. mods/pack.dat/art/a.frm), and extracted into a directory at that path, in place of the archive. Selecting and renaming entries (e.g. --include, --occurrence, --flatten) and --convert apply to the outer archive's entries only. Nested archives are extracted once the rest are, one at a time, within --max-memory")
+                .conflicts_with_all(&["dirs-only", "show-index", "show-crc", "show-method", "largest", "json", "output-format"]))
+            .arg(Arg::with_name("limit")
+                .long("--limit")
+                .value_name("N")
+                .help("list at most the first N selected entries")
+                .takes_value(true)
+                .conflicts_with_all(&["largest", "dirs-only"]))
+            .arg(Arg::with_name("largest")
+                .long("--largest")
+                .value_name("N")
+                .help("list only the N largest selected entries (by decompressed size), largest first")
+                .takes_value(true)
+                .conflicts_with("dirs-only"))
+            .arg(Arg::with_name("show-crc")
+                .long("--show-crc")
+                .help("prefix each listed entry with its CRC-32, if the archive was created with --crc (otherwise, with dashes)"))
+            .arg(Arg::with_name("show-method")
+                .long("--show-method")
+                .help("prefix each listed entry with how its data is stored ('zlib' or 'stored', going by the data rather than the entry's flag) and any anomalies: 'flagged-compressed' or 'flagged-stored' (the flag disagrees with the data) and 'size-mismatch' (stored data whose sizes differ), or '-'"))
+            .arg(output_format_arg())
+            .arg(json_arg())
+            .arg(entry_index_arg())
+            .arg(occurrence_arg())
+            .arg(Arg::with_name("format")
+                .long("--format")
+                .value_name("FORMAT")
+                .help("the format of the archive: 'dat2' (Fallout 2) or 'tactics' (Fallout Tactics .bos)")
+                .possible_values(&["dat2", "tactics"])
+                .default_value("dat2"))
             .arg(Arg::with_name("directory")
                 .short("-C")
                 .long("--directory")
@@ -62,27 +619,708 @@ impl CliArgs {
                  .short("-v")
                  .long("--verbose")
                  .help("verbosely list files processed"))
+            .arg(Arg::with_name("include")
+                 .long("--include")
+                 .value_name("GLOB")
+                 .help("only list or extract entries matching GLOB (anchored by default)")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("only")
+                 .long("--only")
+                 .value_name("CATEGORY")
+                 .help("only list or extract entries of CATEGORY: audio (sound/), art (art/), text (text/), scripts (scripts/), maps (maps/) or protos (proto/), including files of the category's types elsewhere (e.g. *.acm for audio). With --include, entries must match both")
+                 .possible_values(category::NAMES)
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("language")
+                 .long("--language")
+                 .value_name("LANGUAGE")
+                 .help("only list or extract content localized into LANGUAGE (e.g. german): its text (text/LANGUAGE), art (art/LANGUAGE) and speech (sound/speech/LANGUAGE). With --include or --only, entries must match those too")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("exclude")
+                 .long("--exclude")
+                 .value_name("GLOB")
+                 .help("don't list or extract entries matching GLOB (unanchored by default)")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("anchored")
+                 .long("--anchored")
+                 .help("patterns must match from the start of an entry's path")
+                 .overrides_with("no-anchored"))
+            .arg(Arg::with_name("no-anchored")
+                 .long("--no-anchored")
+                 .help("patterns may match starting at any path component")
+                 .overrides_with("anchored"))
+            .arg(Arg::with_name("wildcards-match-slash")
+                 .long("--wildcards-match-slash")
+                 .help("wildcards in patterns match '/' (the default)")
+                 .overrides_with("no-wildcards-match-slash"))
+            .arg(Arg::with_name("no-wildcards-match-slash")
+                 .long("--no-wildcards-match-slash")
+                 .help("wildcards in patterns don't match '/'")
+                 .overrides_with("wildcards-match-slash"))
+            .arg(Arg::with_name("write-buffer-size")
+                 .long("--write-buffer-size")
+                 .value_name("SIZE")
+                 .help("size of the buffer used for each extracted file (e.g. 64K, 1M)")
+                 .takes_value(true))
+            .arg(Arg::with_name("chunk-size")
+                 .long("--chunk-size")
+                 .value_name("SIZE")
+                 .help("when extracting, decompress and write entries in chunks of SIZE (e.g. 64K, 1M), so that no more than a chunk of each is held in memory, however large it is (except for entries being converted)")
+                 .takes_value(true))
+            .arg(Arg::with_name("convert")
+                 .long("--convert")
+                 .value_name("SRC=DEST")
+                 .help("convert matching entries while extracting (frm=png, rix=png, msg=json, msg=po, msg=txt, or any conversion configured as a [convert.SRC] section of fo2dat's config file, $FO2DAT_CONFIG or fo2dat/fo2dat.ini in the user's config directory)")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("keep-original")
+                 .long("--keep-original")
+                 .help("also extract the original of each converted entry"))
+            .arg(Arg::with_name("flatten")
+                 .long("--flatten")
+                 .help("extract entries directly into the output directory, without their directories"))
+            .arg(Arg::with_name("on-collision")
+                 .long("--on-collision")
+                 .value_name("POLICY")
+                 .help("what --flatten does with entries that have the same file name: 'error', 'skip' (keep the first), 'overwrite' (keep the last) or 'rename'")
+                 .possible_values(&["error", "skip", "overwrite", "rename"])
+                 .default_value("error"))
+            .arg(checkpoint_arg())
+            .arg(Arg::with_name("profile")
+                 .long("--profile")
+                 .help("print how long the operation spent in each stage (parsing, reading, decompressing, converting, compressing, writing and flushing to disk), and how busy each thread was, on stderr once it's done, to tell whether it's CPU- or IO-bound"))
+            .arg(Arg::with_name("throttle")
+                 .long("--throttle")
+                 .value_name("MIB_PER_SEC")
+                 .help("when extracting, write at most MIB_PER_SEC MiB per second (e.g. 20 or 0.5), so that the extraction doesn't starve other programs of disk bandwidth")
+                 .takes_value(true))
+            .arg(low_priority_arg())
+            .arg(nice_arg())
+            .arg(max_memory_arg())
+            .arg(Arg::with_name("no-atomic")
+                 .long("--no-atomic")
+                 .help("when extracting, write files in place, rather than as FILE.part renamed to FILE once complete (which leaves no partially-written files if extraction is interrupted, but is slow on some filesystems)"))
+            .arg(Arg::with_name("write-manifest")
+                 .long("--write-manifest")
+                 .value_name("FILE")
+                 .help("when extracting, write a JSON manifest to FILE listing every extracted file's path (relative to the output directory), size and digest (see --manifest-algo), with the entry and archive it came from, once extraction completes")
+                 .takes_value(true))
+            .arg(Arg::with_name("manifest-algo")
+                 .long("--manifest-algo")
+                 .value_name("ALGORITHM")
+                 .help("the digest algorithm of --write-manifest: 'sha256', 'crc32', 'xxh64' or 'blake3'")
+                 .default_value("sha256"))
+            .arg(Arg::with_name("mode")
+                 .long("--mode")
+                 .value_name("OCTAL")
+                 .help("when extracting, create files with permissions OCTAL (e.g. 644), less the umask (unix only)")
+                 .takes_value(true))
+            .arg(Arg::with_name("dir-mode")
+                 .long("--dir-mode")
+                 .value_name("OCTAL")
+                 .help("when extracting, create directories with permissions OCTAL (e.g. 755), less the umask (unix only)")
+                 .takes_value(true))
+            .arg(Arg::with_name("max-open-files")
+                 .long("--max-open-files")
+                 .value_name("N")
+                 .help("when extracting, have at most N output files open at once (default: as many as the process's open file limit leaves room for)")
+                 .takes_value(true))
+            .arg(Arg::with_name("palette")
+                 .long("--palette")
+                 .value_name("PAL_FILE")
+                 .help("color converted images with PAL_FILE, rather than the archive's color.pal or a RIX's own palette")
+                 .takes_value(true))
+            .arg(Arg::with_name("palette-scaling")
+                 .long("--palette-scaling")
+                 .value_name("SCALING")
+                 .help("how 6-bit palette colors become 8-bit: 'engine' (x4, as the game does) or 'full' (0-255)")
+                 .possible_values(&["engine", "full"])
+                 .default_value("engine"))
+            .arg(Arg::with_name("text-eol")
+                 .long("--text-eol")
+                 .value_name("EOL")
+                 .help("when extracting, convert the line endings of text assets (MSG, TXT, SSL, H, LST, GAM, CFG and INI entries that look like text) to 'lf' or 'crlf' (the game's)")
+                 .possible_values(&["lf", "crlf"])
+                 .takes_value(true))
+            .arg(Arg::with_name("text-encoding")
+                 .long("--text-encoding")
+                 .value_name("FROM:TO")
+                 .help("transcode the content of text assets from FROM to TO (cp1252, cp866, utf-8) when extracting, e.g. cp866:utf-8, and from TO back to FROM when creating")
+                 .takes_value(true))
+            .arg(Arg::with_name("msg-encoding")
+                 .long("--msg-encoding")
+                 .value_name("ENCODING")
+                 .help("the encoding of MSG files being converted (cp1252, cp866, utf-8)")
+                 .default_value("cp1252"))
+            .subcommand(SubCommand::with_name("map-info")
+                 .about("summarize the MAP entries of a DAT2 archive")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(Arg::with_name("maps")
+                      .value_name("MAP")
+                      .help("path of a MAP entry to summarize (default: all MAP entries)")
+                      .multiple(true)))
+            .subcommand(SubCommand::with_name("pro-dump")
+                 .about("dump the PRO entries of a DAT2 archive as JSON")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(Arg::with_name("pros")
+                      .value_name("PRO")
+                      .help("path of a PRO entry to dump (default: all PRO entries)")
+                      .multiple(true)))
+            .subcommand(SubCommand::with_name("lst-check")
+                 .about("check that every file referenced by the LSTs of a DAT2 archive exists, and report unreferenced art")
+                 .arg(file_arg("DAT2_FILE")))
+            .subcommand(SubCommand::with_name("info")
+                 .about("print the metadata of a DAT2 archive: its format, sizes, and the creator and comment recorded in it (see --record-tool and --comment)")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(human_readable_arg())
+                 .arg(json_arg()))
+            .subcommand(SubCommand::with_name("index")
+                 .about("write an index sidecar (DAT2_FILE.idx) for a DAT2 archive, which commands that look its entries up (e.g. ls, tree, stat, cat) then read, rather than parsing and sorting its whole tree. Worth it for archives with many entries. Once the archive's entries change, the sidecar is rebuilt by the next command that reads it; deleting it stops its use")
+                 .arg(file_arg("DAT2_FILE")))
+            .subcommand(SubCommand::with_name("ls")
+                 .about("list the subdirectories (with a trailing '/') and files directly in a directory of a DAT2 archive, or in its root")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(human_readable_arg())
+                 .arg(Arg::with_name("long")
+                      .short("l")
+                      .long("--long")
+                      .help("also print each file's size and packed size, and each subdirectory's totals"))
+                 .arg(Arg::with_name("dir")
+                      .value_name("DIR")
+                      .help("path of the directory ('/' or '\\'-separated)")))
+            .subcommand(SubCommand::with_name("tree")
+                 .about("print the subdirectories and files under a directory of a DAT2 archive, or under its root, as a tree")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(Arg::with_name("level")
+                      .short("L")
+                      .long("--level")
+                      .value_name("DEPTH")
+                      .help("descend at most DEPTH directories deep")
+                      .takes_value(true))
+                 .arg(Arg::with_name("dir")
+                      .value_name("DIR")
+                      .help("path of the directory ('/' or '\\'-separated)")))
+            .subcommand(SubCommand::with_name("stat")
+                 .about("print the metadata of entries of a DAT2 archive")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(entry_index_arg())
+                 .arg(occurrence_arg())
+                 .arg(human_readable_arg())
+                 .arg(output_format_arg())
+                 .arg(json_arg())
+                 .arg(Arg::with_name("paths")
+                      .value_name("PATH")
+                      .help("path of an entry")
+                      .multiple(true)))
+            .subcommand(SubCommand::with_name("hexdump")
+                 .about("print a hex dump of the (decompressed) data of entries of a DAT2 archive")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(entry_index_arg())
+                 .arg(occurrence_arg())
+                 .arg(Arg::with_name("raw")
+                      .long("--raw")
+                      .help("dump the data as stored in the archive, without decompressing it"))
+                 .arg(Arg::with_name("paths")
+                      .value_name("PATH")
+                      .help("path of an entry")
+                      .multiple(true)))
+            .subcommand(SubCommand::with_name("verify")
+                 .about("check that every entry of a DAT2 archive decompresses to its stated size and, if the archive was created with --crc, its CRC-32")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(checkpoint_arg())
+                 .arg(json_arg())
+                 .arg(low_priority_arg())
+                 .arg(nice_arg()))
+            .subcommand(SubCommand::with_name("doctor")
+                 .about("diagnose what's wrong with a broken DAT2 archive (truncation, trailing data, a miscounted tree_size or num_files, a big-endian footer, a Fallout 1 archive or a savegame, and entries that are out of bounds, overlapping, corrupt or misflagged), and suggest how to fix it")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(json_arg()))
+            .subcommand(SubCommand::with_name("verify-dir")
+                 .about("compare a directory that a DAT2 archive was extracted to with the archive, reporting missing, extra and mismatched files")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(Arg::with_name("directory")
+                      .short("-C")
+                      .long("--directory")
+                      .value_name("DIR")
+                      .help("the directory the archive was extracted to (default: the current directory)")
+                      .takes_value(true)))
+            .subcommand(SubCommand::with_name("diff-dir")
+                 .about("print how the files in a directory differ from a DAT2 archive's entries, before syncing the archive to it (see sync) or creating one from it: '+ PATH' for a file that only the directory has, '- PATH' for an entry that only the archive has and 'M PATH' for a file whose content differs from its entry's; exits with 1 if they differ. Files are found as creating finds them (e.g. honoring .datignore)")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(Arg::with_name("dir")
+                      .value_name("DIR")
+                      .help("the directory")
+                      .required(true)))
+            .subcommand(SubCommand::with_name("checksum")
+                 .about("print a digest of a DAT2 archive")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(Arg::with_name("algo")
+                      .long("--algo")
+                      .value_name("ALGORITHM")
+                      .help("the digest algorithm: 'sha256', 'crc32', 'xxh64' or 'blake3'")
+                      .default_value("sha256"))
+                 .arg(Arg::with_name("logical")
+                      .long("--logical")
+                      .help("also print a digest of the archive's logical content (its sorted paths and decompressed data), which is the same for archives with the same files however they were packed")))
+            .subcommand(SubCommand::with_name("count")
+                 .about("print the number of entries of a DAT2 archive (or of those matching --glob)")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(human_readable_arg())
+                 .arg(Arg::with_name("glob")
+                      .long("--glob")
+                      .value_name("GLOB")
+                      .help("only count entries matching GLOB (anchored, as with --include)")
+                      .takes_value(true)
+                      .multiple(true)
+                      .number_of_values(1))
+                 .arg(Arg::with_name("sizes")
+                      .long("--sizes")
+                      .help("also print the entries' total decompressed and packed sizes, in bytes (tab-separated)"))
+                 .arg(Arg::with_name("histogram")
+                      .long("--histogram")
+                      .help("also print histograms of the entries' compression ratios (packed / decompressed size, leaving out empty entries) and of their decompressed sizes, giving each bucket's number of entries and total decompressed and packed sizes, and a bar of its packed size")))
+            .subcommand(SubCommand::with_name("cat")
+                 .about("write the decompressed content of entries of a DAT2 archive to stdout, one after the other in tree order")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(entry_index_arg())
+                 .arg(occurrence_arg())
+                 .arg(Arg::with_name("glob")
+                      .long("--glob")
+                      .value_name("GLOB")
+                      .help("also write entries matching GLOB (anchored, as with --include)")
+                      .takes_value(true)
+                      .multiple(true)
+                      .number_of_values(1))
+                 .arg(Arg::with_name("paths")
+                      .value_name("PATH")
+                      .help("path of an entry")
+                      .multiple(true)))
+            .subcommand(SubCommand::with_name("view")
+                 .about("open the decompressed content of an entry of a DAT2 archive in a viewer: the program in $FO2DAT_VIEWER_<EXT> for its type (e.g. FO2DAT_VIEWER_FRM), or else $PAGER, or else less (more on Windows). It's written to a temporary file that only the user can read, which is removed once the viewer exits")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(entry_index_arg())
+                 .arg(occurrence_arg())
+                 .arg(Arg::with_name("with")
+                      .long("--with")
+                      .value_name("PROGRAM")
+                      .help("open the entry with PROGRAM (which may include arguments, e.g. 'less -R') rather than its viewer")
+                      .takes_value(true))
+                 .arg(Arg::with_name("path")
+                      .value_name("PATH")
+                      .help("path of the entry")))
+            .subcommand(SubCommand::with_name("layout")
+                 .about("print the data section of a DAT2 archive as a map of byte ranges, each with the entry whose data it is, or flagged as a gap (unused) or an overlap (the data of entries that partly coincide)")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(human_readable_arg())
+                 .arg(Arg::with_name("problems-only")
+                      .long("--problems-only")
+                      .help("only print gaps, overlaps and ranges past the end of the data section")))
+            .subcommand(SubCommand::with_name("estimate")
+                 .about("estimate how much smaller (or larger) a DAT2 archive's data would be if it were recompressed, by recompressing a sample of its entries")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(human_readable_arg())
+                 .arg(level_arg())
+                 .arg(Arg::with_name("sample")
+                      .long("--sample")
+                      .value_name("N")
+                      .help("recompress N entries, spread evenly through the archive, rather than all of them")
+                      .takes_value(true)))
+            .subcommand(SubCommand::with_name("recompress")
+                 .about("rewrite a DAT2 archive with its entries recompressed, keeping their paths and order, and print its size before and after")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(human_readable_arg())
+                 .arg(level_arg())
+                 .arg(Arg::with_name("store-all")
+                      .long("--store-all")
+                      .help("store every entry uncompressed, which some engine forks load faster (except for any entry whose content starts like zlib data, which must be compressed to be read back correctly)")
+                      .conflicts_with("compress-all"))
+                 .arg(Arg::with_name("compress-all")
+                      .long("--compress-all")
+                      .help("compress every entry, even those that compressing makes larger (by default, they're stored)"))
+                 .arg(Arg::with_name("dictionary")
+                      .long("--dictionary")
+                      .help("compress small text entries with fo2dat's preset dictionary where that makes them smaller (see --dictionary when creating), which the game can't load. Without it, entries that were compressed with the dictionary are recompressed without it, so the game can load the archive again")
+                      .conflicts_with("store-all"))
+                 .arg(Arg::with_name("output")
+                      .short("o")
+                      .long("--output")
+                      .value_name("DAT2_FILE")
+                      .help("write the recompressed archive to DAT2_FILE, rather than replacing the original (which is only replaced once the new archive is complete)")
+                      .takes_value(true))
+                 .arg(low_priority_arg())
+                 .arg(nice_arg())
+                 .arg(max_memory_arg()))
+            .subcommand(SubCommand::with_name("offset")
+                 .about("print what each of the given offsets into a DAT2 archive belongs to: which entries' data (see layout), which field of which tree entry, or which other part of the archive")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(Arg::with_name("offsets")
+                      .value_name("OFFSET")
+                      .help("offset from the start of the archive file, in bytes (decimal, or hex with 0x)")
+                      .required(true)
+                      .multiple(true)))
+            .subcommand(SubCommand::with_name("export-spec")
+                 .about("print the spec of a DAT2 archive (its entries' paths, order and compression) as JSON, from which create --from-spec rebuilds it")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(Arg::with_name("output")
+                      .short("o")
+                      .long("--output")
+                      .value_name("FILE")
+                      .help("write the spec to FILE, rather than to stdout")
+                      .takes_value(true)))
+            .subcommand(SubCommand::with_name("exists")
+                 .about("print nothing, and exit with 0 if a DAT2 archive has entries at all of the given paths, or 1 if it doesn't")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(Arg::with_name("case-sensitive")
+                      .long("--case-sensitive")
+                      .help("match paths' case exactly, rather than case-insensitively as the engine does"))
+                 .arg(Arg::with_name("paths")
+                      .value_name("PATH")
+                      .help("path of an entry ('/' or '\\'-separated)")
+                      .required(true)
+                      .multiple(true)))
+            .subcommand(SubCommand::with_name("which")
+                 .about("print the entries of a DAT2 archive whose decompressed content is identical to each of the given files (e.g. to find where a stray asset came from), and exit with 1 if any file matches none")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(Arg::with_name("algo")
+                      .long("--algo")
+                      .value_name("ALGORITHM")
+                      .help("the digest algorithm that contents are compared by: 'sha256', 'crc32', 'xxh64' or 'blake3'")
+                      .default_value("sha256"))
+                 .arg(Arg::with_name("files")
+                      .value_name("FILE")
+                      .help("file to look for")
+                      .required(true)
+                      .multiple(true)))
+            .subcommand(SubCommand::with_name("dev-server")
+                 .about("serve an archive of the files in a directory (as -c would create it, but stored uncompressed and held in memory) over HTTP, rebuilding it whenever they change. The archive is served as /<name of DAT2_FILE>, with range requests supported, and its entries' content under /files/ (a directory's gives its subdirectories and files); nothing is written to DAT2_FILE")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(Arg::with_name("directory")
+                      .short("-C")
+                      .long("--directory")
+                      .value_name("DIR")
+                      .help("the directory of files to serve (default: the current directory)")
+                      .takes_value(true))
+                 .arg(Arg::with_name("listen")
+                      .long("--listen")
+                      .value_name("ADDRESS")
+                      .help("the address and port to listen on")
+                      .default_value("127.0.0.1:8000"))
+                 .arg(Arg::with_name("poll-interval")
+                      .long("--poll-interval")
+                      .value_name("MILLISECONDS")
+                      .help("how often to check the directory for changes")
+                      .default_value("500"))
+                 .arg(Arg::with_name("cache-size")
+                      .long("--cache-size")
+                      .value_name("SIZE")
+                      .help("the most decompressed content (e.g. 64M) to cache for /files/ requests")
+                      .default_value("64M"))
+                 .arg(max_memory_arg()))
+            .subcommand(SubCommand::with_name("load-order")
+                 .about("print the archives and patches directories of an installation, in the order the engine looks files up in them (highest priority first), as its fallout2.cfg (and sfall's ddraw.ini) configure them; or, given paths, which of them each path is loaded from, exiting with 1 if any isn't in any of them")
+                 .arg(Arg::with_name("config")
+                      .long("--config")
+                      .value_name("FALLOUT2_CFG")
+                      .help("the installation's fallout2.cfg, which paths in it are relative to the directory of")
+                      .required(true)
+                      .takes_value(true))
+                 .arg(Arg::with_name("ddraw")
+                      .long("--ddraw")
+                      .value_name("DDRAW_INI")
+                      .help("sfall's ddraw.ini, whose [ExtraPatches] take precedence over the archives fallout2.cfg configures")
+                      .takes_value(true))
+                 .arg(Arg::with_name("paths")
+                      .value_name("PATH")
+                      .help("path of a game file (e.g. art/critters/hapowraa.frm)")
+                      .multiple(true)))
+            .subcommand(SubCommand::with_name("sync")
+                 .about("make a DAT2 archive match a directory: add entries for new files, update those whose files changed and delete those whose files are gone (creating the archive if it doesn't exist); or, with --reverse, make the directory match the archive. Changes are printed as '+ PATH' (added), 'M PATH' (updated) and '- PATH' (deleted)")
+                 .arg(file_arg("DAT2_FILE"))
+                 .arg(Arg::with_name("reverse")
+                      .long("--reverse")
+                      .help("make the directory match the archive, rather than the other way around"))
+                 .arg(Arg::with_name("dry-run")
+                      .short("n")
+                      .long("--dry-run")
+                      .help("only print the changes that would be made"))
+                 .arg(newer_mtime_arg())
+                 .arg(low_priority_arg())
+                 .arg(nice_arg())
+                 .arg(max_memory_arg())
+                 .arg(Arg::with_name("dir")
+                      .value_name("DIR")
+                      .help("the directory")
+                      .required(true)))
+            .subcommand(SubCommand::with_name("patch")
+                 .about("work with patches between versions of a DAT2 archive, which are distributed in place of the new version")
+                 .setting(AppSettings::SubcommandRequiredElseHelp)
+                 .subcommand(SubCommand::with_name("create")
+                      .about("write a patch that turns OLD_DAT2_FILE into NEW_DAT2_FILE: entries whose packed data is unchanged are copied from OLD_DAT2_FILE, entries whose content changed are stored as a binary delta against their old content (where that rebuilds them exactly), and the rest are stored whole; prints how many of each there are")
+                      .arg(Arg::with_name("old")
+                           .value_name("OLD_DAT2_FILE")
+                           .help("the version of the archive that the patch is applied to")
+                           .required(true))
+                      .arg(Arg::with_name("new")
+                           .value_name("NEW_DAT2_FILE")
+                           .help("the version of the archive that applying the patch rebuilds")
+                           .required(true))
+                      .arg(Arg::with_name("output")
+                           .value_name("PATCH_FILE")
+                           .help("the patch to write (e.g. mod-1.1.fo2patch)")
+                           .required(true))
+                      .arg(human_readable_arg())
+                      .arg(low_priority_arg())
+                      .arg(nice_arg()))
+                 .subcommand(SubCommand::with_name("apply")
+                      .about("rebuild the archive a patch was made from, by applying it to the archive it was made against (which is checked first, so that a patch for another version fails without writing anything), and check that the rebuilt archive is identical to the one it was made from")
+                      .arg(Arg::with_name("old")
+                           .value_name("BASE_DAT2_FILE")
+                           .help("the archive that the patch was made against")
+                           .required(true))
+                      .arg(Arg::with_name("patch")
+                           .value_name("PATCH_FILE")
+                           .help("the patch to apply")
+                           .required(true))
+                      .arg(Arg::with_name("output")
+                           .short("o")
+                           .long("--output")
+                           .value_name("DAT2_FILE")
+                           .help("write the rebuilt archive to DAT2_FILE, rather than replacing BASE_DAT2_FILE (which is only replaced once the rebuilt archive is complete and checked)")
+                           .takes_value(true))
+                      .arg(human_readable_arg())))
+            .subcommand(SubCommand::with_name("save-info")
+                 .about("summarize a Fallout 2 savegame (the SAVE.DAT of a save slot, which isn't a DAT2 archive)")
+                 .arg(file_arg("SAVE_DAT_FILE")))
             .get_matches();
 
-        let should_extract = matches.is_present("extract");
-        let should_list = matches.is_present("list");
+        let (action, action_matches) = match matches.subcommand() {
+            ("map-info", Some(sub_matches)) => {
+                let maps = sub_matches.values_of("maps").map(|maps| maps.map(String::from).collect()).unwrap_or_default();
+                (CliAction::MapInfo(maps), sub_matches)
+            },
+            ("pro-dump", Some(sub_matches)) => {
+                let pros = sub_matches.values_of("pros").map(|pros| pros.map(String::from).collect()).unwrap_or_default();
+                (CliAction::ProDump(pros), sub_matches)
+            },
+            ("lst-check", Some(sub_matches)) => (CliAction::LstCheck, sub_matches),
+            ("save-info", Some(sub_matches)) => (CliAction::SaveInfo, sub_matches),
+            ("verify", Some(sub_matches)) => (CliAction::Verify, sub_matches),
+            ("verify-dir", Some(sub_matches)) => (CliAction::VerifyDir, sub_matches),
+            ("diff-dir", Some(sub_matches)) => (CliAction::DiffDir { dir: sub_matches.value_of("dir").unwrap().to_string() }, sub_matches),
+            ("doctor", Some(sub_matches)) => (CliAction::Doctor, sub_matches),
+            ("exists", Some(sub_matches)) => {
+                let paths = sub_matches.values_of("paths").map(|paths| paths.map(String::from).collect()).unwrap_or_default();
+                (CliAction::Exists { paths, case_sensitive: sub_matches.is_present("case-sensitive") }, sub_matches)
+            },
+            ("dev-server", Some(sub_matches)) => {
+                let poll_interval_str = sub_matches.value_of("poll-interval").unwrap();
+                let poll_interval = match poll_interval_str.parse::<u64>() {
+                    Ok(millis) if millis > 0 => Duration::from_millis(millis),
+                    _ => {
+                        let err_msg = format!("{}: invalid poll interval: must be a positive number of milliseconds", poll_interval_str);
+                        return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+                    },
+                };
+                let listen = sub_matches.value_of("listen").unwrap().to_string();
+                let cache_size = parse_size(sub_matches.value_of("cache-size").unwrap())?;
+                (CliAction::DevServer { listen, poll_interval, cache_size }, sub_matches)
+            },
+            ("load-order", Some(sub_matches)) => {
+                let paths = sub_matches.values_of("paths").map(|paths| paths.map(String::from).collect()).unwrap_or_default();
+                (CliAction::LoadOrder { ddraw: sub_matches.value_of("ddraw").map(String::from), paths }, sub_matches)
+            },
+            ("sync", Some(sub_matches)) => {
+                let dir = sub_matches.value_of("dir").unwrap().to_string();
+                (CliAction::Sync { dir, reverse: sub_matches.is_present("reverse"), dry_run: sub_matches.is_present("dry-run") }, sub_matches)
+            },
+            ("patch", Some(patch_matches)) => match patch_matches.subcommand() {
+                ("create", Some(sub_matches)) => {
+                    let new = sub_matches.value_of("new").unwrap().to_string();
+                    (CliAction::PatchCreate { new, output: sub_matches.value_of("output").unwrap().to_string() }, sub_matches)
+                },
+                ("apply", Some(sub_matches)) => {
+                    let patch = sub_matches.value_of("patch").unwrap().to_string();
+                    (CliAction::PatchApply { patch, output: sub_matches.value_of("output").map(String::from) }, sub_matches)
+                },
+                _ => unreachable!("patch requires a subcommand"),
+            },
+            ("count", Some(sub_matches)) => {
+                let histogram = sub_matches.is_present("histogram");
+                (CliAction::Count { filter: glob_filter(sub_matches), sizes: sub_matches.is_present("sizes"), histogram }, sub_matches)
+            },
+            ("estimate", Some(sub_matches)) => {
+                let sample = match sub_matches.value_of("sample") {
+                    Some(n) => match n.parse::<usize>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(Error::new(ErrorKind::InvalidInput, format!("{}: invalid --sample (must be a positive number)", n))),
+                    },
+                    None => None,
+                };
+                (CliAction::Estimate { level: parse_level(sub_matches)?, sample }, sub_matches)
+            },
+            ("recompress", Some(sub_matches)) => {
+                let output = sub_matches.value_of("output").map(String::from);
+                let packing = if sub_matches.is_present("store-all") {
+                    Packing::StoreAll
+                } else if sub_matches.is_present("compress-all") {
+                    Packing::CompressAll
+                } else {
+                    Packing::Auto
+                };
+                let dictionary = sub_matches.is_present("dictionary");
+                (CliAction::Recompress { level: parse_level(sub_matches)?, packing, dictionary, output }, sub_matches)
+            },
+            ("which", Some(sub_matches)) => {
+                let files = sub_matches.values_of("files").map(|files| files.map(String::from).collect()).unwrap_or_default();
+                let algorithm = Algorithm::from_name(sub_matches.value_of("algo").unwrap())?;
+                (CliAction::Which { files, algorithm }, sub_matches)
+            },
+            ("offset", Some(sub_matches)) => {
+                let offsets = sub_matches.values_of("offsets").map(|offsets| offsets.map(parse_offset).collect()).unwrap_or_else(|| Ok(Vec::new()))?;
+                (CliAction::Offset(offsets), sub_matches)
+            },
+            ("export-spec", Some(sub_matches)) => (CliAction::ExportSpec { output: sub_matches.value_of("output").map(String::from) }, sub_matches),
+            ("layout", Some(sub_matches)) => (CliAction::Layout { problems_only: sub_matches.is_present("problems-only") }, sub_matches),
+            ("cat", Some(sub_matches)) => {
+                let paths = sub_matches.values_of("paths").map(|paths| paths.map(String::from).collect()).unwrap_or_default();
+                (CliAction::Cat { paths, filter: glob_filter(sub_matches) }, sub_matches)
+            },
+            ("view", Some(sub_matches)) => {
+                let path = sub_matches.value_of("path").map(String::from);
+                (CliAction::View { path, program: sub_matches.value_of("with").map(String::from) }, sub_matches)
+            },
+            ("checksum", Some(sub_matches)) => {
+                let algorithm = Algorithm::from_name(sub_matches.value_of("algo").unwrap())?;
+                (CliAction::Checksum { algorithm, logical: sub_matches.is_present("logical") }, sub_matches)
+            },
+            ("info", Some(sub_matches)) => (CliAction::Info, sub_matches),
+            ("index", Some(sub_matches)) => (CliAction::Index, sub_matches),
+            ("tree", Some(sub_matches)) => {
+                let dir = sub_matches.value_of("dir").unwrap_or("").to_string();
+                let depth = match sub_matches.value_of("level") {
+                    Some(depth) => match depth.parse::<usize>() {
+                        Ok(depth) if depth > 0 => Some(depth),
+                        _ => {
+                            let err_msg = format!("{}: invalid depth: must be a positive number", depth);
+                            return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+                        },
+                    },
+                    None => None,
+                };
+                (CliAction::Tree { dir, depth }, sub_matches)
+            },
+            ("ls", Some(sub_matches)) => {
+                let dir = sub_matches.value_of("dir").unwrap_or("").to_string();
+                (CliAction::Ls { dir, long: sub_matches.is_present("long") }, sub_matches)
+            },
+            ("stat", Some(sub_matches)) => {
+                let paths = sub_matches.values_of("paths").map(|paths| paths.map(String::from).collect()).unwrap_or_default();
+                (CliAction::Stat(paths), sub_matches)
+            },
+            ("hexdump", Some(sub_matches)) => {
+                let paths = sub_matches.values_of("paths").map(|paths| paths.map(String::from).collect()).unwrap_or_default();
+                (CliAction::Hexdump { paths, raw: sub_matches.is_present("raw") }, sub_matches)
+            },
+            _ => {
+                let should_create = matches.is_present("create");
+                let should_extract = matches.is_present("extract");
+                let should_list = matches.is_present("list");
+                let paths: Vec<String> = matches.values_of("paths").map(|paths| paths.map(String::from).collect()).unwrap_or_default();
+
+                let action = if [should_create, should_extract, should_list].iter().filter(|&&given| given).count() > 1 {
+                    Err(Error::new(ErrorKind::InvalidInput, "you cannot specify more than one '-cxt' option"))
+                } else if should_create {
+                    match matches.value_of("from-spec") {
+                        Some(_) if !paths.is_empty() => {
+                            Err(Error::new(ErrorKind::InvalidInput, "paths cannot be given with --from-spec, which lists the archive's files"))
+                        },
+                        Some(spec) => Ok(CliAction::CreateFromSpec(spec.to_string())),
+                        None if paths.is_empty() => {
+                            Err(Error::new(ErrorKind::InvalidInput, "must provide at least one file or directory to add to the archive"))
+                        },
+                        None => Ok(CliAction::Create(paths)),
+                    }
+                } else if !paths.is_empty() {
+                    Err(Error::new(ErrorKind::InvalidInput, "paths can only be given when creating an archive ('-c'): use --include to select entries"))
+                } else if should_list {
+                    Ok(CliAction::List)
+                } else if should_extract {
+                    Ok(CliAction::Extract)
+                } else {
+                    Err(Error::new(ErrorKind::InvalidInput, "must specify one of '-c', '-t' or '-x'"))
+                }?;
 
-        let action = if should_extract && should_list {
-            Err(Error::new(ErrorKind::InvalidInput, "you cannot specify more than one '-xt' option"))
-        } else if should_list {
-            Ok(CliAction::List)
-        } else if should_extract {
-            Ok(CliAction::Extract)
-        } else {
-            Err(Error::new(ErrorKind::InvalidInput, "must specify either either '-t' or '-x'"))
-        }?;
+                (action, &matches)
+            },
+        };
 
-        let file = match matches.value_of("file").map(String::from) {
-            Some(f) => Ok(f),
+        // load-order's file is the installation's fallout2.cfg, and patch's the old (or base) archive
+        let files = match action_matches.values_of("file") {
+            Some(patterns) => patterns.map(expand_file_pattern).collect::<io::Result<Vec<_>>>()?.concat(),
+            None => action_matches.value_of("config").or_else(|| action_matches.value_of("old")).map(String::from).into_iter().collect(),
+        };
+        let file = match files.first() {
+            Some(f) => Ok(f.clone()),
             None => Err(Error::new(ErrorKind::InvalidInput, "must provide file arg (-f)")),
         }?;
+        if files.len() > 1 {
+            match action {
+                CliAction::List | CliAction::Extract | CliAction::Verify => {},
+                _ => return Err(Error::new(ErrorKind::InvalidInput, "only listing, extracting and verifying take several archives (-f)")),
+            }
+            if matches.is_present("write-manifest") {
+                return Err(Error::new(ErrorKind::InvalidInput, "--write-manifest only takes one archive (-f)"));
+            }
+        }
+
+        let entry_indexes = match action_matches.values_of("entry-index") {
+            Some(index_strs) => index_strs.map(|index_str| {
+                index_str.trim_start_matches('#').parse::<usize>().map_err(|_| {
+                    Error::new(ErrorKind::InvalidInput, format!("{}: invalid entry index", index_str))
+                })
+            }).collect::<io::Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        let occurrence = match action_matches.value_of("occurrence") {
+            Some(occurrence_str) => match occurrence_str.parse::<usize>() {
+                Ok(occurrence) if occurrence > 0 => Some(occurrence),
+                _ => return Err(Error::new(ErrorKind::InvalidInput, format!("{}: invalid --occurrence (must be 1 or more)", occurrence_str))),
+            },
+            None => None,
+        };
 
-        let ch_dir = match matches.value_of("directory") {
+        let show_index = matches.is_present("show-index");
+        let show_crc = matches.is_present("show-crc");
+        let show_method = matches.is_present("show-method");
+        let output_format = match action_matches.value_of("output-format") {
+            Some(template) => Some(Template::parse(template, Field::from_name)?),
+            None => None,
+        };
+        let dirs_only = matches.is_present("dirs-only");
+        let limit = match matches.value_of("limit") {
+            Some(n) => Some(parse_count(n, "--limit")?),
+            None => None,
+        };
+        let largest = match matches.value_of("largest") {
+            Some(n) => Some(parse_count(n, "--largest")?),
+            None => None,
+        };
+
+        let format = match matches.value_of("format") {
+            Some("tactics") => ArchiveFormat::Tactics,
+            _ => ArchiveFormat::Dat2,
+        };
+
+        let ch_dir = match action_matches.value_of("directory") {
             Some(dir) => String::from(dir),
             None => {
                 let cwd = env::current_dir()?;
@@ -92,106 +1330,4159 @@ impl CliArgs {
 
         let verbose = matches.is_present("verbose");
 
-        Ok(CliArgs { action, file, ch_dir, verbose })
+        let dedup = !matches.is_present("no-dedup");
+
+        let sort_entries = match matches.value_of("sort-entries") {
+            Some("none") => EntryOrder::None,
+            Some("size") => EntryOrder::Size,
+            _ => EntryOrder::Path,
+        };
+
+        let strict_names = matches.is_present("strict-names");
+
+        let read_separators = match matches.value_of("read-separators") {
+            Some("any") => Separators::Any,
+            Some("literal") => Separators::Literal,
+            _ => Separators::Backslash,
+        };
+
+        let write_separator = match matches.value_of("write-separator") {
+            Some("slash") => '/',
+            _ => '\\',
+        };
+
+        // as with tar, includes and excludes bind differently unless told otherwise
+        let anchored = toggle(&matches, "anchored", "no-anchored");
+        let wildcards_match_slash = toggle(&matches, "wildcards-match-slash", "no-wildcards-match-slash").unwrap_or(true);
+        let include_options = MatchOptions { anchored: anchored.unwrap_or(true), wildcards_match_slash };
+        let exclude_options = MatchOptions { anchored: anchored.unwrap_or(false), wildcards_match_slash };
+
+        let patterns = |name: &str, options: MatchOptions| -> Vec<Pattern> {
+            match matches.values_of(name) {
+                Some(globs) => globs.map(|glob| Pattern::new(glob, options)).collect(),
+                None => Vec::new(),
+            }
+        };
+        let categories = matches.values_of("only").map(|names| names.map(Category::from_name).collect()).unwrap_or_else(|| Ok(Vec::new()))?;
+        let languages = match matches.values_of("language") {
+            Some(languages) => languages.map(category::language_patterns).collect::<io::Result<Vec<_>>>()?.into_iter().flatten().collect(),
+            None => Vec::new(),
+        };
+        let filter = Filter {
+            includes: patterns("include", include_options),
+            excludes: patterns("exclude", exclude_options),
+            categories: categories.into_iter().flat_map(Category::patterns).collect(),
+            languages,
+        };
+
+        let write_buffer_size = match matches.value_of("write-buffer-size") {
+            Some(size) => parse_size(size)?,
+            None => DEFAULT_WRITE_BUFFER_SIZE,
+        };
+
+        let chunk_size = match matches.value_of("chunk-size") {
+            Some(size) => parse_size(size)?,
+            None => fo2dat::DEFAULT_CHUNK_SIZE,
+        };
+
+        let max_open_files = match matches.value_of("max-open-files") {
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) if n > 0 => Some(n),
+                _ => return Err(Error::new(ErrorKind::InvalidInput, format!("{}: invalid --max-open-files (must be a positive number)", n))),
+            },
+            None => limits::max_open_files().map(|limit| limit.saturating_sub(RESERVED_OPEN_FILES).max(1)),
+        };
+
+        let throttle = match matches.value_of("throttle") {
+            Some(rate_str) => match rate_str.parse::<f64>() {
+                Ok(rate) if rate > 0.0 && rate.is_finite() => Some(rate * 1024.0 * 1024.0),
+                _ => return Err(Error::new(ErrorKind::InvalidInput, format!("{}: invalid --throttle (must be a positive number of MiB per second)", rate_str))),
+            },
+            None => None,
+        };
+
+        // resolved now, as a file it names may be the archive, which creating replaces
+        let newer_mtime = match action_matches.value_of("newer-mtime").or_else(|| matches.value_of("newer-mtime")) {
+            Some(time_str) => Some(parse_mtime(time_str)?),
+            None => None,
+        };
+
+        let max_memory = match action_matches.value_of("max-memory").or_else(|| matches.value_of("max-memory")) {
+            Some(size_str) => Some(parse_size(size_str)?),
+            None => None,
+        };
+
+        let file_mode = match matches.value_of("mode") {
+            Some(mode_str) => Some(parse_mode(mode_str, "--mode")?),
+            None => None,
+        };
+
+        let dir_mode = match matches.value_of("dir-mode") {
+            Some(mode_str) => Some(parse_mode(mode_str, "--dir-mode")?),
+            None => None,
+        };
+
+        let nice = match action_matches.value_of("nice").or_else(|| matches.value_of("nice")) {
+            Some(nice_str) => match nice_str.parse::<u8>() {
+                Ok(nice) if nice > 0 && nice <= priority::MAX_NICE => Some(nice),
+                _ => return Err(Error::new(ErrorKind::InvalidInput, format!("{}: invalid --nice (must be 1-{})", nice_str, priority::MAX_NICE))),
+            },
+            None => None,
+        };
+
+        let checkpoint = match action_matches.value_of("checkpoint").or_else(|| matches.value_of("checkpoint")) {
+            Some(checkpoint_str) => Some(Checkpoint::parse(checkpoint_str)?),
+            None => None,
+        };
+
+        let conversions = match matches.values_of("convert") {
+            Some(conversion_strs) => {
+                // the config file is only read if there's something to convert
+                let external = match converter::config_path() {
+                    Some(config_path) => converter::load(&config_path)?.into_iter().map(Arc::new).collect(),
+                    None => Vec::new(),
+                };
+                conversion_strs.map(|conversion_str| Conversion::parse(conversion_str, &external)).collect::<io::Result<Vec<_>>>()?
+            },
+            None => Vec::new(),
+        };
+
+        let keep_original = matches.is_present("keep-original");
+
+        let flatten = matches.is_present("flatten");
+
+        let on_collision = match matches.value_of("on-collision") {
+            Some("skip") => Collision::Skip,
+            Some("overwrite") => Collision::Overwrite,
+            Some("rename") => Collision::Rename,
+            _ => Collision::Error,
+        };
+
+        let palette = matches.value_of("palette").map(String::from);
+
+        let palette_scaling = match matches.value_of("palette-scaling") {
+            Some("full") => Scaling::Full,
+            _ => Scaling::Engine,
+        };
+
+        let msg_encoding = Encoding::from_name(matches.value_of("msg-encoding").unwrap())?;
+
+        if output_format.is_some() && (format == ArchiveFormat::Tactics || dirs_only) {
+            let err_msg = "--output-format is only supported when listing the entries of DAT2 archives";
+            return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+        }
+
+        if matches.is_present("json") && (format == ArchiveFormat::Tactics || dirs_only) {
+            let err_msg = "--json is only supported when listing the entries of DAT2 archives";
+            return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+        }
+
+        let recursive = matches.is_present("recursive");
+        if format == ArchiveFormat::Tactics && recursive {
+            let err_msg = "--recursive is only supported for DAT2 archives";
+            return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+        }
+
+        if format == ArchiveFormat::Tactics && !conversions.is_empty() {
+            let err_msg = "--convert is not supported for Fallout Tactics archives, whose assets use different formats";
+            return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+        }
+
+        Ok(CliArgs {
+            action,
+            file,
+            files,
+            format,
+            ch_dir,
+            verbose,
+            dedup,
+            dedup_algorithm: Algorithm::from_name(matches.value_of("dedup-algo").unwrap())?,
+            sort_entries,
+            strict_names,
+            strict_flags: matches.is_present("strict-flags"),
+            raw_deflate: matches.is_present("raw-deflate"),
+            absolute_names: matches.is_present("absolute-names"),
+            follow_symlinks: matches.is_present("follow-symlinks"),
+            sandbox: matches.is_present("sandbox"),
+            read_separators,
+            write_separator,
+            crc_table: matches.is_present("crc"),
+            dat64: matches.is_present("dat64"),
+            dictionary: matches.is_present("dictionary"),
+            exclude_vcs: matches.is_present("exclude-vcs"),
+            newer_mtime,
+            dotfiles: matches.value_of("dotfiles") != Some("exclude"),
+            symlinks: match matches.value_of("symlinks") {
+                Some("skip") => Symlinks::Skip,
+                Some("error") => Symlinks::Error,
+                _ => Symlinks::Follow,
+            },
+            empty_dirs: match matches.value_of("empty-dirs") {
+                Some("warn") => EmptyDirs::Warn,
+                Some("error") => EmptyDirs::Error,
+                _ => EmptyDirs::Skip,
+            },
+            tool: if matches.is_present("record-tool") { Some(tool_name()) } else { None },
+            comment: matches.value_of("comment").map(String::from),
+            filter,
+            entry_indexes,
+            occurrence,
+            show_index,
+            show_crc,
+            show_method,
+            human_readable: action_matches.is_present("human-readable"),
+            json: action_matches.is_present("json") || matches.is_present("json"),
+            output_format,
+            dirs_only,
+            recursive,
+            text_eol: matches.value_of("text-eol").map(LineEnding::from_name).transpose()?,
+            text_encoding: matches.value_of("text-encoding").map(Transcoding::from_spec).transpose()?,
+            limit,
+            largest,
+            write_buffer_size,
+            chunk_size,
+            max_open_files,
+            checkpoint,
+            profile: matches.is_present("profile"),
+            throttle,
+            max_memory,
+            low_priority: action_matches.is_present("low-priority") || matches.is_present("low-priority"),
+            nice,
+            atomic: !matches.is_present("no-atomic"),
+            manifest: matches.value_of("write-manifest").map(String::from),
+            manifest_algorithm: Algorithm::from_name(matches.value_of("manifest-algo").unwrap())?,
+            file_mode,
+            dir_mode,
+            conversions,
+            keep_original,
+            flatten,
+            on_collision,
+            palette,
+            palette_scaling,
+            msg_encoding,
+        })
     }
 }
 
-fn main() {
-    match main_internal() {
-        Ok(()) => {},
-        Err(e) => {
-            eprintln!("{}: {}", APP_NAME, e);
-            eprintln!("Try '{} --help' for more information", APP_NAME);
-            std::process::exit(1);
-        },
+/// Returns the `--output-format` argument, which `-t` and `stat` accept.
+fn output_format_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("output-format")
+        .long("--output-format")
+        .value_name("FORMAT")
+        .help(OUTPUT_FORMAT_HELP)
+        .takes_value(true)
+}
+
+/// Returns the `--level` argument, the zlib level that entries are recompressed at.
+fn level_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("level")
+        .long("--level")
+        .value_name("LEVEL")
+        .help("the zlib compression level, from 0 (none) to 9 (best, as archives are created with)")
+        .takes_value(true)
+        .default_value("9")
+}
+
+/// Parses the `--level` argument of a subcommand.
+fn parse_level(sub_matches: &clap::ArgMatches) -> io::Result<u32> {
+    let level_str = sub_matches.value_of("level").unwrap();
+    match level_str.parse::<u32>() {
+        Ok(level) if level <= 9 => Ok(level),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("{}: invalid --level (must be 0-9)", level_str))),
     }
 }
 
-fn main_internal() -> io::Result<()> {
-    let args = CliArgs::parse()?;
+/// Returns the `--entry-index` argument, which selects entries by their index in the tree.
+fn entry_index_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("entry-index")
+        .long("--entry-index")
+        .value_name("INDEX")
+        .help("select the entry at INDEX in the tree (as shown by --show-index), which is unambiguous even for duplicate or undecodable paths")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+}
 
-    match args.action {
-        CliAction::Extract => extract_all_entries(&args),
-        CliAction::List => list_entries(&args.file),
+/// Returns the `--occurrence` argument, which selects one of the entries that share a path.
+fn occurrence_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("occurrence")
+        .long("--occurrence")
+        .value_name("N")
+        .help("of entries that share a path (case-insensitively), select only the Nth in the tree, as tar does; paths with fewer than N entries aren't selected. By default, a path selects its first entry, and extraction writes every entry (so the last one written wins)")
+        .takes_value(true)
+}
+
+/// Tracks how many times each path has been seen so far, to select only the `--occurrence`th
+/// entry with each path.
+struct Occurrences {
+    occurrence: Option<usize>,
+    seen: HashMap<String, usize>,
+}
+
+impl Occurrences {
+    fn new(occurrence: Option<usize>) -> Self {
+        Occurrences { occurrence, seen: HashMap::new() }
+    }
+
+    /// Records the next entry (in tree order) with `path`, returning `true` if it's selected.
+    fn select(&mut self, path: &str) -> bool {
+        let occurrence = match self.occurrence {
+            Some(occurrence) => occurrence,
+            None => return true,
+        };
+
+        let key = path.split(['\\', '/'])
+            .filter(|component| !component.is_empty())
+            .map(str::to_ascii_lowercase)
+            .collect::<Vec<_>>()
+            .join("/");
+        let seen = self.seen.entry(key).or_insert(0);
+        *seen += 1;
+        *seen == occurrence
     }
 }
 
-/// Extract all entries in a DAT file located at `dat_path` to `output_dir`
-fn extract_all_entries(args: &CliArgs) -> io::Result<()> {    
-    let output_dir = PathBuf::from(&args.ch_dir);
+/// Returns a filter that selects the entries matching any of a subcommand's `--glob`s (anchored,
+/// as with `--include`), or every entry if it has none.
+fn glob_filter(sub_matches: &clap::ArgMatches) -> Filter {
+    let options = MatchOptions { anchored: true, wildcards_match_slash: true };
+    Filter {
+        includes: sub_matches.values_of("glob").map(|globs| globs.map(|glob| Pattern::new(glob, options)).collect()).unwrap_or_default(),
+        excludes: Vec::new(),
+        categories: Vec::new(),
+        languages: Vec::new(),
+    }
+}
 
-    if !output_dir.exists() {
-        let err_msg = format!("{}: no such directory", output_dir.to_str().unwrap());
-        return Err(Error::new(ErrorKind::NotFound, err_msg));
-    } else if !output_dir.is_dir() {
-        let err_msg = format!("{}: not a directory", output_dir.to_str().unwrap());
-        return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+/// Returns the value of a pair of `--FLAG`/`--no-FLAG` toggles, if either was given.
+fn toggle(matches: &clap::ArgMatches, flag: &str, no_flag: &str) -> Option<bool> {
+    if matches.is_present(flag) {
+        Some(true)
+    } else if matches.is_present(no_flag) {
+        Some(false)
     } else {
-        extract_all_entries_to_dir(output_dir, mmap(&args.file)?, args)
+        None
     }
 }
 
-fn extract_all_entries_to_dir(output_dir: PathBuf, data: Mmap, args: &CliArgs) -> io::Result<()> {
-
-    let tree_entries: io::Result<Vec<TreeEntry>> = fo2dat::iter_tree(&data)?.collect();
-    let tree_entries = tree_entries?;
-    
-    tree_entries.into_par_iter().try_for_each(|tree_entry| {
-        let output_path = output_dir.join(&tree_entry.path);
-        let entry_data = &data[tree_entry.offset..][..tree_entry.packed_size];
+/// Returns the `-f` argument, which the top-level actions and each subcommand accept.
+fn file_arg<'a, 'b>(value_name: &'b str) -> Arg<'a, 'b> {
+    Arg::with_name("file")
+        .short("f")
+        .long("--file")
+        .value_name(value_name)
+        .help("use file. Listing, extracting and verifying take several archives, each given with its own -f or matched by wildcards in a file name (e.g. -f '*.dat'), and act on each in turn")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+}
 
-        write_entry(&entry_data, &output_path)?;
+/// Returns the files that a `-f` value names: the file at `pattern` or, if there's none and its file
+/// name has wildcards (e.g. `*.dat`, see `fo2dat::glob`), the files in its directory that they
+/// match, ignoring case, in order.
+fn expand_file_pattern(pattern: &str) -> io::Result<Vec<String>> {
+    let path = Path::new(pattern);
+    let name_pattern = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.contains(['*', '?', '[']) && !path.exists() && !remote::is_url(pattern) => name,
+        _ => return Ok(vec![pattern.to_string()]),
+    };
+    let name_pattern = Pattern::new(name_pattern, MatchOptions { anchored: true, wildcards_match_slash: false });
 
-        if args.verbose {
-            println!("{}", output_path.to_str().unwrap());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut files = Vec::new();
+    for dir_entry in std::fs::read_dir(dir).map_err(|e| errors::with_context(e, dir.display()))? {
+        let dir_entry = dir_entry?;
+        let is_match = dir_entry.file_name().to_str().is_some_and(|name| name_pattern.matches(name));
+        if is_match && dir_entry.path().is_file() {
+            files.push(path.with_file_name(dir_entry.file_name()).to_string_lossy().into_owned());
         }
+    }
+    files.sort();
 
-        Ok(())
-    })
+    if files.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("{}: no files match", pattern)));
+    }
+    Ok(files)
 }
 
-fn mmap(dat_path_str: &str) -> io::Result<Mmap> {
-    let dat_path = Path::new(&dat_path_str);
-    if dat_path.exists() {
-        let dat_file = File::open(dat_path)?;
-        unsafe { Mmap::map(&dat_file) }
+/// Returns the `--checkpoint` argument, which makes extraction and `verify` report their progress.
+fn checkpoint_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("checkpoint")
+        .long("--checkpoint")
+        .value_name("N|Ns")
+        .help("when extracting or verifying, report progress, throughput and the estimated time left on stderr every N entries, or every N seconds")
+        .takes_value(true)
+}
+
+/// Returns the `--low-priority` (or `--background`) argument, which makes bulk jobs yield to others.
+fn low_priority_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("low-priority")
+        .long("--low-priority")
+        .alias("background")
+        .help("run at the lowest CPU and IO priority (nice 19 and, on Linux, the idle IO class; background mode on Windows). Also --background")
+        .conflicts_with("nice")
+}
+
+/// Returns the `--nice` argument, which lowers priority less than `--low-priority` does.
+fn nice_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("nice")
+        .long("--nice")
+        .value_name("N")
+        .help("run at niceness N (1-19) and, on Linux, a correspondingly lower IO priority (as ionice does by default), so the job runs behind interactive work without idling")
+        .takes_value(true)
+}
+
+/// Returns the `--newer-mtime` argument, which only packs recently modified files.
+fn newer_mtime_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("newer-mtime")
+        .long("--newer-mtime")
+        .value_name("TIME")
+        .help("when creating or syncing an archive, only pack files modified after TIME: a UTC date and time (e.g. 2024-05-01 or 2024-05-01T18:30:00Z), seconds since the Unix epoch (e.g. @1714588200), or the path of a file (e.g. the archive itself) whose modification time is used. When syncing, files modified before it are kept as they're stored (without being compared), or left out if they aren't")
+        .takes_value(true)
+}
+
+/// Returns the `--max-memory` argument, which bounds the memory that bulk jobs' workers hold.
+fn max_memory_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("max-memory")
+        .long("--max-memory")
+        .value_name("SIZE")
+        .help("hold at most SIZE (e.g. 512M) of decompression and write buffers, entry content and cache at once, across all workers, which then wait for each other rather than exceed it (the archive itself is memory-mapped, which doesn't count)")
+        .takes_value(true)
+}
+
+/// Returns the `-h` argument, which makes subcommands that summarize sizes print them like `1.4M`.
+fn human_readable_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("human-readable")
+        .short("h")
+        .long("--human-readable")
+        .help("print sizes like 1.4M or 320K (powers of 1024, rounded up) rather than in bytes")
+}
+
+/// Returns the `--json` argument, which makes reports machine-readable.
+fn json_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("json")
+        .long("--json")
+        .help("print output as JSON, which declares the version of its schema as its 'schema' member and stays compatible within it; and, with --checkpoint, progress on stderr as one JSON object per line")
+        .conflicts_with("output-format")
+}
+
+/// Returns the name and version that fo2dat records as an archive's creator.
+fn tool_name() -> String {
+    format!("{} {}", APP_NAME, env!("CARGO_PKG_VERSION"))
+}
+
+/// Formats `size`, in bytes, as `-h` prints it: like `1.4M`, `320K` or `12` (bytes), rounding up
+/// as `ls -h` does, so that sizes aren't understated.
+fn human_size(size: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+
+    if size < 1024 {
+        return size.to_string();
+    }
+
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    // one decimal place below 10, unless rounding up carries it to 10
+    let tenths = (value * 10.0).ceil() / 10.0;
+    if tenths < 10.0 {
+        return format!("{:.1}{}", tenths, UNITS[unit]);
+    }
+
+    let value = value.ceil();
+    if value >= 1024.0 && unit < UNITS.len() - 1 {
+        format!("1.0{}", UNITS[unit + 1])
     } else {
-        let err_msg = format!("{}: no such file", dat_path_str);
-        return Err(Error::new(ErrorKind::NotFound, err_msg));
+        format!("{}{}", value, UNITS[unit])
     }
 }
 
-fn write_entry(entry_data: &[u8], output_path: &Path) -> io::Result<()> {
-    if let Some(parent) = output_path.parent() {
-        std::fs::create_dir_all(parent)?;
+/// Formats `size`, in bytes, as a number of bytes or, if `human_readable`, like `1.4M`.
+fn format_size(size: u64, human_readable: bool) -> String {
+    if human_readable { human_size(size) } else { size.to_string() }
+}
+
+/// Formats `duration` to the second, like `45s`, `3m05s` or `1h02m03s`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s),
     }
+}
 
-    let mut output_file = File::create(&output_path)?;
+/// Parses a human-supplied size (e.g. `512`, `64K`, `1M`, `2G`) into a number of bytes.
+fn parse_size(size_str: &str) -> io::Result<usize> {
+    let (digits, multiplier) = match size_str.chars().last() {
+        Some('K') | Some('k') => (&size_str[..size_str.len()-1], 1024),
+        Some('M') | Some('m') => (&size_str[..size_str.len()-1], 1024 * 1024),
+        Some('G') | Some('g') => (&size_str[..size_str.len()-1], 1024 * 1024 * 1024),
+        _ => (size_str, 1),
+    };
 
-    if is_zlib_compressed(&entry_data) {
-        let mut zlib_reader = ZlibDecoder::new(entry_data);
-        std::io::copy(&mut zlib_reader, &mut output_file)?;
-    } else {
-        output_file.write(entry_data)?;
+    match digits.parse::<usize>().ok().filter(|&n| n > 0).and_then(|n| n.checked_mul(multiplier)) {
+        Some(size) => Ok(size),
+        None => {
+            let err_msg = format!("{}: invalid size", size_str);
+            Err(Error::new(ErrorKind::InvalidInput, err_msg))
+        }
     }
+}
 
-    Ok(())
+/// Parses a human-supplied time (see `--newer-mtime`): a UTC date (`2024-05-01`) optionally with
+/// a time (`2024-05-01T18:30`, `2024-05-01 18:30:00Z`), seconds since the epoch (`@1714588200`),
+/// or the path of a file whose modification time is used.
+fn parse_mtime(time_str: &str) -> io::Result<SystemTime> {
+    if let Some(secs) = time_str.strip_prefix('@') {
+        if let Ok(secs) = secs.parse::<u64>() {
+            return Ok(UNIX_EPOCH + Duration::from_secs(secs));
+        }
+    } else if let Some(secs) = parse_utc(time_str) {
+        return Ok(UNIX_EPOCH + Duration::from_secs(secs));
+    } else if Path::new(time_str).exists() {
+        return std::fs::metadata(time_str).and_then(|metadata| metadata.modified()).map_err(|e| errors::with_context(e, time_str));
+    }
+
+    let err_msg = format!("{}: invalid time (must be a date like 2024-05-01, optionally with a time like T18:30:00, @ and seconds since the epoch, or an existing file)", time_str);
+    Err(Error::new(ErrorKind::InvalidInput, err_msg))
 }
 
-/// Returns true if `data` appears to be zlib compressed.
-fn is_zlib_compressed(data: &[u8]) -> bool {
-    const ZLIB_FIRST_MAGIC_BYTE: u8 = 0x78;
-    const ZLIB_SECOND_MAGIC_BYTE: u8 = 0xda;
+/// Parses `YYYY-MM-DD`, optionally followed by `T` (or a space) and `HH:MM` or `HH:MM:SS`, and
+/// optionally `Z`, as a UTC time, returning the seconds since the epoch.
+fn parse_utc(time_str: &str) -> Option<u64> {
+    let time_str = time_str.strip_suffix('Z').unwrap_or(time_str);
+    let (date, time) = match time_str.find(['T', ' ']) {
+        Some(i) => (&time_str[..i], Some(&time_str[i + 1..])),
+        None => (time_str, None),
+    };
+
+    let fields = |s: &str, separator: char, n: usize| -> Option<Vec<u64>> {
+        let fields: Vec<&str> = s.split(separator).collect();
+        let is_valid = fields.len() == n && fields.iter().all(|field| !field.is_empty() && field.bytes().all(|b| b.is_ascii_digit()));
+        if is_valid { fields.iter().map(|field| field.parse().ok()).collect() } else { None }
+    };
+    let date = fields(date, '-', 3)?;
+    let (year, month, day) = (date[0], date[1], date[2]);
+    let time = match time {
+        Some(time) => fields(time, ':', 2).map(|hm| vec![hm[0], hm[1], 0]).or_else(|| fields(time, ':', 3))?,
+        None => vec![0, 0, 0],
+    };
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) || time[0] > 23 || time[1] > 59 || time[2] > 60 {
+        return None;
+    }
 
-    data.len() > 2 && data[0] == ZLIB_FIRST_MAGIC_BYTE && data[1] == ZLIB_SECOND_MAGIC_BYTE
+    // days since the epoch of the civil date, counting years from March, so leap days fall last
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let days = 365 * y + y / 4 - y / 100 + y / 400 + (153 * m + 2) / 5 + day - 1 - 719_468;
+    Some(days * 86_400 + time[0] * 3600 + time[1] * 60 + time[2])
 }
 
-fn list_entries(dat_path: &str) -> io::Result<()> {
-    let data = mmap(dat_path)?;
+/// Returns `true` if the file at `path` was modified after `time`.
+fn is_modified_after(path: &Path, time: SystemTime) -> io::Result<bool> {
+    let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified()).map_err(|e| errors::with_context(e, path.display()))?;
+    Ok(modified > time)
+}
 
-    for tree_entry in fo2dat::iter_tree(&data)? {
-        let tree_entry = tree_entry?;
-        println!("{}", tree_entry.path.to_str().unwrap());
+/// Parses the positive number of entries given with `arg` (e.g. `--limit`).
+fn parse_count(count_str: &str, arg: &str) -> io::Result<usize> {
+    match count_str.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("{}: invalid {} (must be a positive number)", count_str, arg))),
+    }
+}
+
+/// Parses a byte offset, in decimal or `0x`-prefixed hex.
+fn parse_offset(offset_str: &str) -> io::Result<usize> {
+    let parsed = match offset_str.strip_prefix("0x").or_else(|| offset_str.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => offset_str.parse::<usize>(),
+    };
+    parsed.map_err(|_| {
+        Error::new(ErrorKind::InvalidInput, format!("{}: invalid offset (must be a number of bytes, e.g. 4096 or 0x1000)", offset_str))
+    })
+}
+
+/// Parses the octal permissions (e.g. `644`) given with `arg`.
+fn parse_mode(mode_str: &str, arg: &str) -> io::Result<u32> {
+    if !cfg!(unix) {
+        let err_msg = format!("{} is only supported on unix", arg);
+        return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+    }
+
+    match u32::from_str_radix(mode_str, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => {
+            let err_msg = format!("{}: invalid {} (must be octal permissions, e.g. 644)", mode_str, arg);
+            Err(Error::new(ErrorKind::InvalidInput, err_msg))
+        }
+    }
+}
+
+fn main() {
+    match main_internal() {
+        Ok(()) => {},
+        Err(e) => {
+            eprintln!("{}: [{}] {}", APP_NAME, errors::code(&e), e);
+            eprintln!("Try '{} --help' for more information", APP_NAME);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn main_internal() -> io::Result<()> {
+    let args = CliArgs::parse()?;
+
+    // before any worker threads are started, so that they inherit it
+    let lowered = match args.nice {
+        Some(nice) => Some(priority::nice(nice)),
+        None if args.low_priority => Some(priority::lower()),
+        None => None,
+    };
+    if let Some(Err(e)) = lowered {
+        eprintln!("{}: warning: cannot lower priority: {}", APP_NAME, e);
+    }
+
+    if args.profile {
+        profile::enable();
+    }
+    let result = if args.files.len() > 1 { run_each(args) } else { run(&args) };
+
+    // a failed run's profile still shows where its time went before it failed
+    if let Some(report) = profile::report() {
+        eprintln!("{}: {}", APP_NAME, report);
+    }
+    result
+}
+
+/// Runs the action on each of several archives (`args.files`) in turn, printing a header before
+/// each one's output (except JSON, whose documents each name their archive). An archive that fails
+/// doesn't stop the rest, but fails the whole run.
+fn run_each(mut args: CliArgs) -> io::Result<()> {
+    let files = std::mem::take(&mut args.files);
+    let mut failures = Vec::new();
+
+    for (i, file) in files.iter().enumerate() {
+        if !args.json {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            if i > 0 {
+                writeln!(stdout)?;
+            }
+            writeln!(stdout, "==> {} <==", file)?;
+        }
+
+        args.file = file.clone();
+        if let Err(e) = run(&args) {
+            let e = if e.to_string().starts_with(file.as_str()) { e } else { with_path(file, e) };
+            eprintln!("{}: [{}] {}", APP_NAME, errors::code(&e), e);
+            failures.push(e);
+        }
+    }
+
+    // the run fails as its first failure did
+    match failures.first() {
+        Some(e) => {
+            let err_msg = format!("{} of {} archives failed", failures.len(), files.len());
+            Err(errors::new(errors::code(e), e.kind(), err_msg))
+        },
+        None => Ok(()),
+    }
+}
+
+fn run(args: &CliArgs) -> io::Result<()> {
+    match args.action {
+        CliAction::Create(ref paths) => create_archive(args, paths),
+        CliAction::CreateFromSpec(ref spec_path) => create_archive_from_spec(args, spec_path),
+        CliAction::Extract => extract_all_entries(args),
+        CliAction::List => match args.format {
+            ArchiveFormat::Dat2 => list_entries(args),
+            ArchiveFormat::Tactics => list_bos_entries(args),
+        },
+        CliAction::MapInfo(ref maps) => print_map_infos(&args.file, maps),
+        CliAction::ProDump(ref pros) => dump_pros(&args.file, pros),
+        CliAction::LstCheck => check_lsts(&args.file),
+        CliAction::SaveInfo => print_save_info(&args.file),
+        CliAction::Info => print_info(&args.file, args.human_readable, args.json),
+        CliAction::Index => write_index(&args.file),
+        CliAction::Stat(ref paths) => print_stats(&args.file, paths, &args.entry_indexes, args.occurrence, args.human_readable, args.output_format.as_ref(), args.json),
+        CliAction::Hexdump { ref paths, raw } => hexdump_entries(&args.file, paths, &args.entry_indexes, args.occurrence, raw),
+        CliAction::Verify => verify_entries(&args.file, args.checkpoint, args.json),
+        CliAction::VerifyDir => verify_dir(args),
+        CliAction::DiffDir { ref dir } => {
+            if !print_dir_diff(args, Path::new(dir))? {
+                std::process::exit(1);
+            }
+            Ok(())
+        },
+        CliAction::Doctor => print_diagnoses(&args.file, args.json),
+        CliAction::Checksum { algorithm, logical } => print_checksums(&args.file, algorithm, logical),
+        CliAction::Count { ref filter, sizes, histogram } => print_count(&args.file, filter, sizes, histogram, args.human_readable),
+        CliAction::Estimate { level, sample } => print_estimate(&args.file, level, sample, args.human_readable),
+        CliAction::Recompress { level, packing, dictionary, ref output } => recompress(&args.file, level, packing, dictionary, output.as_ref().map(String::as_str), args.human_readable, args.max_memory),
+        CliAction::ExportSpec { ref output } => export_spec(&args.file, output.as_ref().map(String::as_str)),
+        CliAction::Offset(ref offsets) => print_locations(&args.file, offsets),
+        CliAction::Layout { problems_only } => print_layout(&args.file, problems_only, args.human_readable),
+        CliAction::Cat { ref paths, ref filter } => cat_entries(&args.file, paths, &args.entry_indexes, args.occurrence, filter),
+        CliAction::Ls { ref dir, long } => print_dir(&args.file, dir, long, args.human_readable),
+        CliAction::Tree { ref dir, depth } => print_tree(&args.file, dir, depth),
+        CliAction::View { ref path, ref program } => view_entry(&args.file, path.as_ref().map(String::as_str), &args.entry_indexes, args.occurrence, program.as_ref().map(String::as_str)),
+        CliAction::DevServer { ref listen, poll_interval, cache_size } => dev_server(args, listen, poll_interval, cache_size),
+        CliAction::LoadOrder { ref ddraw, ref paths } => {
+            if !print_load_order(&args.file, ddraw.as_ref().map(String::as_str), paths)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        },
+        CliAction::Sync { ref dir, reverse: false, dry_run } => sync_to_archive(args, Path::new(dir), dry_run),
+        CliAction::Sync { ref dir, reverse: true, dry_run } => sync_to_dir(args, Path::new(dir), dry_run),
+        CliAction::PatchCreate { ref new, ref output } => create_patch(&args.file, new, output, args.human_readable),
+        CliAction::PatchApply { ref patch, ref output } => apply_patch(&args.file, patch, output.as_ref().map(String::as_str), args.human_readable),
+        CliAction::Exists { ref paths, case_sensitive } => {
+            if !entries_exist(&args.file, paths, case_sensitive)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        },
+        CliAction::Which { ref files, algorithm } => {
+            if !print_matching_entries(&args.file, files, algorithm)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        },
+    }
+}
+
+/// Create a DAT file at `args.file` containing `paths` (relative to `args.ch_dir`)
+fn create_archive(args: &CliArgs, paths: &[String]) -> io::Result<()> {
+    let input_dir = PathBuf::from(&args.ch_dir);
+
+    // the archive isn't packed into itself if it's under an input path (it can't be if it doesn't
+    // exist yet, as it's only written once every input is collected)
+    let output_path = std::fs::canonicalize(&args.file).unwrap_or_default();
+
+    let mut entries = Vec::new();
+    for path in paths {
+        let mut name_components = Vec::new();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(component) => name_components.push(component.to_string_lossy().into_owned()),
+                Component::ParentDir => {
+                    let err_msg = format!("{}: paths cannot contain '..', because entries can't be outside of the archive", path);
+                    return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+                },
+                _ => {},
+            }
+        }
+
+        let input_path = input_dir.join(path);
+        if !input_path.exists() {
+            let err_msg = format!("{}: no such file or directory", input_path.display());
+            return Err(Error::new(ErrorKind::NotFound, err_msg));
+        }
+        let ignores = Ignores::load(&input_dir, &name_components, args.exclude_vcs)?;
+        collect_new_entries(args, &input_path, &mut name_components, &output_path, &ignores, &mut entries)?;
+    }
+
+    if let Some(newer_mtime) = args.newer_mtime {
+        let mut newer = Vec::with_capacity(entries.len());
+        for entry in entries {
+            if let Source::File(ref path) = entry.source {
+                if !is_modified_after(path, newer_mtime)? {
+                    continue;
+                }
+            }
+            newer.push(entry);
+        }
+        entries = newer;
+    }
+
+    check_names(args, &entries)?;
+    writer::sort_entries(&mut entries, args.sort_entries)?;
+    write_new_archive(args, &entries, args.crc_table, args.dat64)
+}
+
+/// Create a DAT file at `args.file` as described by the spec at `spec_path`, from the files in
+/// `args.ch_dir`. Entries are kept in the spec's order.
+fn create_archive_from_spec(args: &CliArgs, spec_path: &str) -> io::Result<()> {
+    let input_dir = PathBuf::from(&args.ch_dir);
+
+    let spec = std::fs::read_to_string(spec_path).and_then(|text| Spec::parse(&text)).map_err(|e| {
+        errors::with_context(e, spec_path)
+    })?;
+
+    let mut entries = Vec::with_capacity(spec.entries.len());
+    for entry in &spec.entries {
+        let mut input_path = input_dir.clone();
+        for component in entry.file_components() {
+            if component == ".." {
+                let err_msg = format!("{}: files cannot be outside of the directory (-C), so can't contain '..'", entry.path);
+                return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+            }
+            input_path.push(component);
+        }
+
+        if !input_path.is_file() {
+            let err_msg = format!("{}: {}: no such file", entry.path, input_path.display());
+            return Err(Error::new(ErrorKind::NotFound, err_msg));
+        }
+
+        entries.push(NewEntry {
+            name: entry.path.clone(),
+            source: Source::File(input_path),
+            compress: Some(entry.compressed),
+        });
+    }
+
+    check_names(args, &entries)?;
+    write_new_archive(args, &entries, args.crc_table || spec.crc_table, args.dat64 || spec.dat64)
+}
+
+/// Warns about (or, given --strict-names, fails on) entry names the engine may be unable to load.
+fn check_names(args: &CliArgs, entries: &[NewEntry]) -> io::Result<()> {
+    for entry in entries {
+        for problem in writer::check_name(&entry.name) {
+            if args.strict_names {
+                let err_msg = format!("{}: {} (given --strict-names)", entry.name, problem);
+                return Err(errors::new(Code::BadName, ErrorKind::InvalidInput, err_msg));
+            }
+            eprintln!("{}: {}: warning: {}", APP_NAME, entry.name, problem);
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a new archive of `entries`, in order, to `args.file`, which is only replaced once the
+/// new archive is complete (see `replace_file`).
+fn write_new_archive(args: &CliArgs, entries: &[NewEntry], crc_table: bool, dat64: bool) -> io::Result<()> {
+    if dat64 {
+        eprintln!("{}: {}: warning: creating a DAT64 archive, which the game can't load", APP_NAME, args.file);
+    }
+    if args.dictionary {
+        eprintln!("{}: {}: warning: compressing text entries with a preset dictionary, which the game can't load", APP_NAME, args.file);
+    }
+
+    if args.verbose {
+        for entry in entries {
+            println!("{}", entry.name);
+        }
+    }
+
+    let options = WriteOptions {
+        dedup: args.dedup,
+        dedup_algorithm: args.dedup_algorithm,
+        separator: args.write_separator,
+        crc_table,
+        dat64,
+        dictionary: args.dictionary,
+        tool: args.tool.clone(),
+        comment: args.comment.clone(),
+        max_memory: args.max_memory,
+        text_encoding: args.text_encoding.map(Transcoding::reversed),
+        ..WriteOptions::default()
+    };
+    let summary = replace_file(Path::new(&args.file), args.write_buffer_size, |part_file| {
+        writer::write_dat(part_file, entries, &options)
+    }).map_err(|e| errors::with_context(e, &args.file))?;
+
+    if args.verbose && summary.num_deduplicated > 0 {
+        println!("{} entries share the data of an identical entry", summary.num_deduplicated);
+    }
+
+    Ok(())
+}
+
+/// Adds the file at `path`, or the files under it if it's a directory, to `entries`, naming them
+/// after `name_components`. Directories are walked in the order the filesystem lists them, leaving
+/// out what `ignores` (and the `.datignore` files found on the way) say to, and dotfiles, symbolic
+/// links and empty directories as `args` say to.
+fn collect_new_entries(args: &CliArgs, path: &Path, name_components: &mut Vec<String>, output_path: &Path, ignores: &Ignores, entries: &mut Vec<NewEntry>) -> io::Result<()> {
+    if path.is_dir() {
+        let children = std::fs::read_dir(path)?.map(|child| child.map(|child| child.path())).collect::<io::Result<Vec<_>>>()?;
+        let ignores = ignores.enter(path, name_components)?;
+        let num_entries = entries.len();
+
+        for child in children {
+            name_components.push(child.file_name().unwrap().to_string_lossy().into_owned());
+            if !is_left_out(args, &child, name_components, &ignores)? {
+                collect_new_entries(args, &child, name_components, output_path, &ignores, entries)?;
+            }
+            name_components.pop();
+        }
+
+        // the directory being packed (rather than one under it) is left for its caller to check
+        if entries.len() == num_entries && !name_components.is_empty() {
+            match args.empty_dirs {
+                EmptyDirs::Skip => {},
+                EmptyDirs::Warn => eprintln!("{}: {}: warning: skipping a directory that nothing is packed from, as archives can't store directories", APP_NAME, path.display()),
+                EmptyDirs::Error => {
+                    let err_msg = format!("{}: nothing is packed from this directory, and archives can't store directories (given --empty-dirs error)", path.display());
+                    return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+                },
+            }
+        }
+    } else if std::fs::canonicalize(path).map_err(|e| errors::with_context(e, path.display()))? == output_path {
+        eprintln!("{}: {}: skipping the archive itself", APP_NAME, path.display());
+    } else {
+        entries.push(NewEntry {
+            name: name_components.join("\\"),
+            source: Source::File(path.to_path_buf()),
+            compress: None,
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns `true` if `path`, which is under a directory being packed and is named after
+/// `name_components`, is left out of the archive, by `ignores` or by `args`' policies for
+/// dotfiles and symbolic links.
+fn is_left_out(args: &CliArgs, path: &Path, name_components: &[String], ignores: &Ignores) -> io::Result<bool> {
+    if !args.dotfiles && name_components.last().is_some_and(|name| name.starts_with('.')) {
+        return Ok(true);
+    }
+
+    let is_symlink = std::fs::symlink_metadata(path).map_err(|e| errors::with_context(e, path.display()))?.file_type().is_symlink();
+    if is_symlink {
+        match args.symlinks {
+            Symlinks::Skip => return Ok(true),
+            Symlinks::Error => {
+                let err_msg = format!("{}: is a symbolic link (given --symlinks error)", path.display());
+                return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+            },
+            Symlinks::Follow if path.is_dir() => {
+                // a link to a directory that it's in would be walked forever
+                let target = std::fs::canonicalize(path).map_err(|e| errors::with_context(e, path.display()))?;
+                if path.parent().map_or(Ok(PathBuf::new()), std::fs::canonicalize)?.starts_with(&target) {
+                    eprintln!("{}: {}: warning: skipping a symbolic link to a directory that it's in", APP_NAME, path.display());
+                    return Ok(true);
+                }
+            },
+            Symlinks::Follow => {},
+        }
+    }
+
+    Ok(ignores.is_ignored(name_components, path.is_dir()))
+}
+
+fn dev_server(args: &CliArgs, listen: &str, poll_interval: Duration, cache_size: usize) -> io::Result<()> {
+    let source_dir = PathBuf::from(&args.ch_dir);
+    if !source_dir.is_dir() {
+        let err_msg = format!("{}: not a directory", source_dir.display());
+        return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+    }
+    let name = Path::new(&args.file).file_name().map(|name| name.to_string_lossy().into_owned()).ok_or_else(|| {
+        Error::new(ErrorKind::InvalidInput, format!("{}: must be a file name", args.file))
+    })?;
+
+    // the cache takes at most half of the budget, leaving the rest for building snapshots
+    let cache_size = args.max_memory.map_or(cache_size, |max_memory| cache_size.min(max_memory / 2));
+
+    let mut fingerprint = fingerprint_dir(&source_dir)?;
+    let mut generation = 1;
+    let published = Arc::new(Published::new(build_snapshot(args, &source_dir, generation, cache_size)?));
+
+    let listener = TcpListener::bind(listen).map_err(|e| errors::with_context(e, listen))?;
+    eprintln!("{}: serving {} as http://{}/{} (and its entries under /files/)", APP_NAME, source_dir.display(), listener.local_addr()?, name);
+    {
+        let published = published.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = serve::serve(listener, &name, published) {
+                eprintln!("{}: cannot accept connections: {}", APP_NAME, e);
+                std::process::exit(1);
+            }
+        });
+    }
+
+    // a failed rebuild (e.g. of a file that was removed while reading it) leaves the last
+    // snapshot served, and is retried after the next change
+    loop {
+        std::thread::sleep(poll_interval);
+        let latest = match fingerprint_dir(&source_dir) {
+            Ok(latest) => latest,
+            Err(e) => {
+                eprintln!("{}: {}: cannot check for changes: {}", APP_NAME, source_dir.display(), e);
+                continue;
+            },
+        };
+        if latest == fingerprint {
+            continue;
+        }
+        fingerprint = latest;
+
+        let started = Instant::now();
+        match build_snapshot(args, &source_dir, generation + 1, cache_size) {
+            Ok(snapshot) => {
+                generation += 1;
+                published.publish(snapshot);
+                eprintln!("{}: generation {}: rebuilt in {} ms", APP_NAME, generation, started.elapsed().as_millis());
+            },
+            Err(e) => eprintln!("{}: cannot rebuild (still serving generation {}): {}", APP_NAME, generation, e),
+        }
+    }
+}
+
+/// Returns the path, size and modification time of every file under `dir`, sorted, which changes
+/// whenever a file is added, removed or written to.
+fn fingerprint_dir(dir: &Path) -> io::Result<Vec<(PathBuf, u64, Option<std::time::SystemTime>)>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for child in std::fs::read_dir(&dir)? {
+            let child = child?;
+            let metadata = child.metadata()?;
+            if metadata.is_dir() {
+                pending.push(child.path());
+            } else {
+                files.push((child.path(), metadata.len(), metadata.modified().ok()));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Builds an archive of the files in `source_dir`, stored rather than compressed so that it's
+/// quick to rebuild, as the `generation`th snapshot.
+fn build_snapshot(args: &CliArgs, source_dir: &Path, generation: u64, cache_size: usize) -> io::Result<Snapshot> {
+    let mut entries = Vec::new();
+    // no file in the directory is the archive, which is only in memory
+    collect_new_entries(args, source_dir, &mut Vec::new(), Path::new(""), &Ignores::new(args.exclude_vcs), &mut entries)?;
+    writer::sort_entries(&mut entries, EntryOrder::Path)?;
+    check_names(args, &entries)?;
+
+    let options = WriteOptions { compress: false, dedup: false, max_memory: args.max_memory, ..WriteOptions::default() };
+    let mut data = Vec::new();
+    writer::write_dat(&mut data, &entries, &options)?;
+    Snapshot::new(data, generation, cache_size)
+}
+
+/// Extract all entries in a DAT file located at `dat_path` to `output_dir`
+fn extract_all_entries(args: &CliArgs) -> io::Result<()> {    
+    let output_dir = PathBuf::from(&args.ch_dir);
+
+    if !output_dir.exists() {
+        let err_msg = format!("{}: no such directory", output_dir.to_str().unwrap());
+        Err(Error::new(ErrorKind::NotFound, err_msg))
+    } else if !output_dir.is_dir() {
+        let err_msg = format!("{}: not a directory", output_dir.to_str().unwrap());
+        Err(Error::new(ErrorKind::InvalidInput, err_msg))
+    } else {
+        let limits = Limits {
+            open_files: Semaphore::new(args.max_open_files.unwrap_or(usize::MAX)),
+            throttle: Throttle::new(args.throttle),
+            memory: MemoryBudget::new(args.max_memory.unwrap_or(usize::MAX)),
+        };
+        let sandbox = if args.sandbox { Some(Sandbox::open(&output_dir)?) } else { None };
+        let sandbox = sandbox.as_ref();
+        let manifest = args.manifest.as_ref().map(|_| Manifest::new(&output_dir, args.manifest_algorithm));
+        match args.format {
+            ArchiveFormat::Dat2 => {
+                // only the data of the entries that are extracted is fetched, and of the palette
+                // that conversions may need
+                let data = if remote::is_url(&args.file) {
+                    let select = |i: usize, tree_entry: &TreeEntryRef| {
+                        is_selected(args, i, tree_entry.name) || (!args.conversions.is_empty() && names::eq(tree_entry.name, "color.pal"))
+                    };
+                    fetch_remote(&args.file, Fetch::Entries(&select))?
+                } else {
+                    mmap(&args.file)?
+                };
+                extract_all_entries_to_dir(output_dir, data, args, &limits, sandbox, manifest.as_ref())?
+            },
+            ArchiveFormat::Tactics => extract_all_bos_entries_to_dir(output_dir, mmap(&args.file)?, args, &limits, sandbox, manifest.as_ref())?,
+        }
+
+        match (manifest, args.manifest.as_ref()) {
+            (Some(manifest), Some(manifest_path)) => manifest.write(manifest_path, &args.file),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The files that extraction wrote, for `--write-manifest`.
+struct Manifest {
+    output_dir: PathBuf,
+    algorithm: Algorithm,
+    files: Mutex<Vec<ManifestFile>>,
+}
+
+struct ManifestFile {
+    /// The file's path relative to the output directory, with `/` separators.
+    path: String,
+    size: u64,
+    digest: Vec<u8>,
+    /// The path of the entry it was extracted from.
+    entry: String,
+}
+
+impl Manifest {
+    fn new(output_dir: &Path, algorithm: Algorithm) -> Self {
+        Manifest { output_dir: output_dir.to_path_buf(), algorithm, files: Mutex::new(Vec::new()) }
+    }
+
+    /// Records that the file at `output_path` was written from the entry at `entry`.
+    fn record(&self, output_path: &Path, entry: &str, written: Written) {
+        let path = output_path.strip_prefix(&self.output_dir).unwrap_or(output_path);
+        let path = path.components().map(|component| component.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        let digest = written.digest.expect("files are digested when there's a manifest");
+        self.files.lock().unwrap().push(ManifestFile { path, size: written.size, digest, entry: entry.to_string() });
+    }
+
+    /// Writes the manifest, with files sorted by path, to `manifest_path`.
+    fn write(self, manifest_path: &str, archive_path: &str) -> io::Result<()> {
+        let algorithm = self.algorithm;
+        let mut files = self.files.into_inner().unwrap();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let written = File::create(manifest_path).and_then(|manifest_file| {
+            let mut out = BufWriter::new(manifest_file);
+            writeln!(out, "{{")?;
+            writeln!(out, "  \"schema\": {},", json::SCHEMA_VERSION)?;
+            write!(out, "  \"archive\": ")?;
+            json::write_str(&mut out, archive_path)?;
+            writeln!(out, ",")?;
+            writeln!(out, "  \"files\": [")?;
+            for (i, file) in files.iter().enumerate() {
+                write!(out, "    {{\"path\": ")?;
+                json::write_str(&mut out, &file.path)?;
+                write!(out, ", \"size\": {}, \"{}\": \"{}\", \"entry\": ", file.size, algorithm.name(), digest::to_hex(&file.digest))?;
+                json::write_str(&mut out, &file.entry)?;
+                writeln!(out, "}}{}", if i + 1 < files.len() { "," } else { "" })?;
+            }
+            writeln!(out, "  ]")?;
+            writeln!(out, "}}")?;
+            out.flush()
+        });
+
+        written.map_err(|e| errors::with_context(e, manifest_path))
+    }
+}
+
+/// The limits that extraction workers share, and wait for each other to stay within.
+struct Limits {
+    open_files: Semaphore,
+    throttle: Throttle,
+    memory: MemoryBudget,
+}
+
+/// A counting semaphore, which extraction workers use to bound how many output files they have
+/// open at once, so they wait for each other rather than failing once the OS's limit is reached.
+struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+/// A permit acquired from a `Semaphore`, which is released when dropped.
+struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Semaphore { permits: Mutex::new(permits), released: Condvar::new() }
+    }
+
+    /// Blocks until a permit is available, then takes it.
+    fn acquire(&self) -> Permit<'_> {
+        let mut permits = self.permits.lock().unwrap();
+        while *permits == 0 {
+            permits = self.released.wait(permits).unwrap();
+        }
+        *permits -= 1;
+        Permit { semaphore: self }
+    }
+}
+
+impl <'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        *self.semaphore.permits.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+/// A limit on the rate that extraction writes at (`--throttle`), shared by all workers.
+///
+/// Each write reserves the next slot of time the rate allows for it, and waits for the slot to
+/// start. Time that no worker used isn't banked, so a throttled extraction doesn't burst after
+/// a slow entry (e.g. a conversion).
+struct Throttle {
+    bytes_per_sec: Option<f64>,
+    next_slot: Mutex<Instant>,
+}
+
+impl Throttle {
+    fn new(bytes_per_sec: Option<f64>) -> Self {
+        Throttle { bytes_per_sec, next_slot: Mutex::new(Instant::now()) }
+    }
+
+    /// Waits until `len` bytes may be written.
+    fn wait(&self, len: usize) {
+        let bytes_per_sec = match self.bytes_per_sec {
+            Some(bytes_per_sec) => bytes_per_sec,
+            None => return,
+        };
+
+        let slot_start = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot_start = (*next_slot).max(Instant::now());
+            *next_slot = slot_start + Duration::from_secs_f64(len as f64 / bytes_per_sec);
+            slot_start
+        };
+
+        let now = Instant::now();
+        if slot_start > now {
+            std::thread::sleep(slot_start - now);
+        }
+    }
+}
+
+/// A writer that writes through a `Throttle`, in chunks small enough that large entries are
+/// written smoothly rather than in bursts. What's written is counted and, for a manifest,
+/// digested.
+struct ThrottledWriter<'a, W> {
+    inner: W,
+    throttle: &'a Throttle,
+    size: u64,
+    hasher: Option<Hasher>,
+}
+
+/// What `write_output_file` wrote.
+struct Written {
+    size: u64,
+    /// The digest of what was written, if it was digested (for `--write-manifest`).
+    digest: Option<Vec<u8>>,
+}
+
+impl <'a, W: Write> Write for ThrottledWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+        let len = buf.len().min(MAX_CHUNK_SIZE);
+        self.throttle.wait(len);
+        let inner = &mut self.inner;
+        let written = profile::time(Stage::Write, || inner.write(&buf[..len]))?;
+        self.size += written as u64;
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Extraction (or verification) progress, which is reported at each `--checkpoint`.
+struct Progress {
+    checkpoint: Option<Checkpoint>,
+    /// Whether progress is reported as JSON, rather than as text.
+    as_json: bool,
+    num_entries: usize,
+    /// The decompressed size of all the entries.
+    total_bytes: usize,
+    started: Instant,
+    state: Mutex<ProgressState>,
+}
+
+struct ProgressState {
+    num_extracted: usize,
+    bytes_extracted: usize,
+    last_report: Instant,
+}
+
+impl Progress {
+    fn new(checkpoint: Option<Checkpoint>, as_json: bool, num_entries: usize, total_bytes: usize) -> Self {
+        let started = Instant::now();
+        Progress {
+            checkpoint,
+            as_json,
+            num_entries,
+            total_bytes,
+            started,
+            state: Mutex::new(ProgressState { num_extracted: 0, bytes_extracted: 0, last_report: started }),
+        }
+    }
+
+    /// Records that an entry with `size` bytes of (decompressed) content was extracted, reporting
+    /// progress if that reached a checkpoint: with the throughput so far, and how long the rest
+    /// will take at that rate.
+    fn record(&self, size: usize) {
+        let checkpoint = match self.checkpoint {
+            Some(checkpoint) => checkpoint,
+            None => return,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.num_extracted += 1;
+        state.bytes_extracted += size;
+
+        let now = Instant::now();
+        let is_due = match checkpoint {
+            Checkpoint::Entries(n) => state.num_extracted % n == 0,
+            Checkpoint::Interval(interval) => now.duration_since(state.last_report) >= interval,
+        };
+
+        if is_due {
+            state.last_report = now;
+
+            const MIB: f64 = 1024.0 * 1024.0;
+            let elapsed = now.duration_since(self.started).as_secs_f64();
+            let mib_extracted = state.bytes_extracted as f64 / MIB;
+
+            // going by bytes, as entries' sizes vary too much for their count to go by, unless
+            // there are none (e.g. the entries are empty)
+            let done = if self.total_bytes > 0 {
+                state.bytes_extracted as f64 / self.total_bytes as f64
+            } else {
+                state.num_extracted as f64 / self.num_entries.max(1) as f64
+            };
+            let eta = if done > 0.0 { Some(elapsed * (1.0 - done) / done) } else { None };
+
+            if self.as_json {
+                eprintln!("{{\"schema\":{},\"event\":\"checkpoint\",\"entries\":{},\"total_entries\":{},\"bytes\":{},\"total_bytes\":{},\"elapsed_secs\":{:.3},\"eta_secs\":{}}}",
+                          json::SCHEMA_VERSION, state.num_extracted, self.num_entries, state.bytes_extracted, self.total_bytes, elapsed,
+                          eta.map_or_else(|| "null".to_string(), |eta| format!("{:.3}", eta.max(0.0))));
+                return;
+            }
+
+            eprintln!("{}: checkpoint: {}/{} entries, {:.1}/{:.1} MiB in {:.1}s ({:.1} MiB/s, ETA {})",
+                      APP_NAME, state.num_extracted, self.num_entries, mib_extracted, self.total_bytes as f64 / MIB, elapsed,
+                      if elapsed > 0.0 { mib_extracted / elapsed } else { 0.0 },
+                      eta.map_or_else(|| "unknown".to_string(), |eta| format_duration(Duration::from_secs_f64(eta.max(0.0)))));
+        }
+    }
+}
+
+fn extract_all_bos_entries_to_dir(output_dir: PathBuf, data: Mmap, args: &CliArgs, limits: &Limits, sandbox: Option<&Sandbox>, manifest: Option<&Manifest>) -> io::Result<()> {
+    advise::advise(&data, Advice::Sequential);
+
+    let bos = Bos::parse(&data)?;
+
+    let entry_paths = bos.entries().iter().map(|bos_entry| {
+        check_absolute(&bos_entry.name, args.absolute_names)?;
+        if bos_entry.components().any(|component| component == "..") {
+            let err_msg = format!("{}: refusing to extract an entry outside of the output directory", bos_entry);
+            Err(errors::new(Code::BadName, ErrorKind::InvalidData, err_msg))
+        } else {
+            Ok(bos_entry.components().enumerate().filter(|&(i, component)| !(i == 0 && is_drive(component))).map(|(_, component)| component).collect())
+        }
+    }).collect::<io::Result<Vec<PathBuf>>>()?;
+
+    let output_paths = output_paths(&output_dir, entry_paths.into_iter(), args)?;
+    if !args.follow_symlinks {
+        check_symlinks(&output_dir, &output_paths)?;
+    }
+    create_output_dirs(&output_paths, args.dir_mode, sandbox)?;
+
+    let total_bytes = bos.entries().iter().zip(&output_paths).filter(|&(_, output_path)| output_path.is_some()).map(|(bos_entry, _)| bos_entry.decompressed_size).sum();
+    let progress = Progress::new(args.checkpoint, args.json, output_paths.iter().flatten().count(), total_bytes);
+
+    bos.entries().par_iter().zip(output_paths.par_iter()).try_for_each(|(bos_entry, output_path)| {
+        let output_path = match *output_path {
+            Some(ref output_path) => output_path,
+            None => return Ok(()),
+        };
+
+        let _open_file = limits.open_files.acquire();
+        let _buffers = limits.memory.reserve(args.chunk_size + args.write_buffer_size);
+        let mut len = 0;
+        let written = write_output_file(output_path, args, &limits.throttle, sandbox, |output_file| {
+            len = bos_entry.for_each_chunk(args.chunk_size, |chunk| output_file.write_all(chunk))?;
+            output_file.flush()
+        })?;
+        if let Some(manifest) = manifest {
+            manifest.record(output_path, &bos_entry.name, written);
+        }
+
+        if args.verbose {
+            println!("{}", output_path.to_str().unwrap());
+        }
+
+        progress.record(len);
+
+        Ok(())
+    })
+}
+
+fn extract_all_entries_to_dir(output_dir: PathBuf, data: Mmap, args: &CliArgs, limits: &Limits, sandbox: Option<&Sandbox>, manifest: Option<&Manifest>) -> io::Result<()> {
+
+    // number of entries ahead of the current one that a worker prefetches
+    const PREFETCH_LOOKAHEAD: usize = 4;
+
+    advise::for_extraction(&data)?;
+
+    // every entry's data is checked up front, rather than failing part way through extraction
+    let entries = profile::time(Stage::Parse, || fo2dat::entries(&data)?.map(|entry| {
+        let entry = entry?;
+        Ok((entry, entry.packed_data()?))
+    }).collect::<io::Result<Vec<(Entry, &[u8])>>>())?;
+
+    let entry_paths = entries.iter().map(|(entry, _)| {
+        entry_path(entry, args.read_separators, args.absolute_names)
+    }).collect::<io::Result<Vec<PathBuf>>>()?;
+
+    let output_paths = output_paths(&output_dir, entry_paths.into_iter(), args)?;
+    if !args.follow_symlinks {
+        check_symlinks(&output_dir, &output_paths)?;
+    }
+    create_output_dirs(&output_paths, args.dir_mode, sandbox)?;
+
+    // with --recursive, an archive in the archive is extracted as a directory of its entries, in
+    // place of the archive, once the rest are extracted. Its content is inflated to find that it's
+    // an archive, and again to extract it, so that only one is held at a time (within --max-memory)
+    let nested: Vec<usize> = if args.recursive {
+        (0..entries.len())
+            .filter(|&i| output_paths[i].is_some())
+            .filter(|&i| {
+                let _memory = limits.memory.reserve(entries[i].0.decompressed_size);
+                nested_archive(&entries[i].0, entries[i].1).is_some()
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // rayon splits the entries into contiguous runs per worker, so ordering them by offset means
+    // each worker reads forward through the data section, which is what readahead works best with
+    let mut extraction_order: Vec<usize> = (0..entries.len())
+        .filter(|&i| output_paths[i].is_some() && !nested.contains(&i))
+        .collect();
+    extraction_order.sort_by_key(|&entry_index| entries[entry_index].0.offset);
+
+    let context = ConversionContext::new(args, &entries)?;
+    let total_bytes = extraction_order.iter().map(|&entry_index| entries[entry_index].0.decompressed_size).sum();
+    let progress = Progress::new(args.checkpoint, args.json, extraction_order.len(), total_bytes);
+
+    extraction_order.par_iter().enumerate().try_for_each(|(i, &entry_index)| -> io::Result<()> {
+        if let Some(&upcoming_index) = extraction_order.get(i + PREFETCH_LOOKAHEAD) {
+            advise::advise(entries[upcoming_index].1, Advice::WillNeed);
+        }
+
+        let (ref tree_entry, entry_data) = entries[entry_index];
+        let output_path = output_paths[entry_index].as_ref().unwrap();
+
+        let decoding = Decoding::of(tree_entry, entry_data, args.raw_deflate);
+        if let Decoding::Method(method) = decoding {
+            check_flag(tree_entry, method, args.strict_flags)?;
+        }
+
+        let conversion = args.conversions.iter().find(|conversion| {
+            tree_entry.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(conversion.source_extension()))
+        });
+
+        // held while writing both a converted file and the original, which are written one after the other
+        let _open_file = limits.open_files.acquire();
+
+        let _memory = limits.memory.reserve(extraction_memory(tree_entry, conversion.is_some(), args));
+
+        let is_original_extracted = match conversion {
+            Some(conversion) => {
+                let converted_path = output_path.with_extension(conversion.target_extension());
+                let converted_data = read_content(entry_data, decoding).and_then(|content| profile::time(Stage::Convert, || context.convert(conversion, &content))).map_err(|e| {
+                    errors::with_context(e, format!("{}: cannot convert", tree_entry))
+                })?;
+
+                let written = write_output_file(&converted_path, args, &limits.throttle, sandbox, |output_file| output_file.write_all(&converted_data))?;
+                if let Some(manifest) = manifest {
+                    manifest.record(&converted_path, &tree_entry.normalized_path(false), written);
+                }
+
+                if args.verbose {
+                    println!("{}", converted_path.to_str().unwrap());
+                }
+
+                args.keep_original
+            },
+            None => true,
+        };
+
+        if is_original_extracted {
+            let written = write_output_file(output_path, args, &limits.throttle, sandbox, |output_file| {
+                write_extracted(tree_entry, entry_data, decoding, output_file, args)
+            })?;
+            if let Some(manifest) = manifest {
+                manifest.record(output_path, &tree_entry.normalized_path(false), written);
+            }
+
+            if args.verbose {
+                println!("{}", output_path.to_str().unwrap());
+            }
+        }
+
+        progress.record(tree_entry.decompressed_size);
+
+        Ok(())
+    })?;
+
+    for entry_index in nested {
+        let (ref tree_entry, entry_data) = entries[entry_index];
+        let memory = limits.memory.reserve(tree_entry.decompressed_size);
+        if let Some(content) = nested_archive(tree_entry, entry_data) {
+            let nested = NestedArchive { path: tree_entry.normalized_path(false), content, memory };
+            extract_nested(output_paths[entry_index].as_ref().unwrap(), &nested, args, limits, sandbox, manifest)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the memory that extracting `tree_entry` holds: its decompression and write buffers,
+/// and, if it's converted (`is_converted`), its whole content along with what it converts to
+/// (which is taken to be about as large), as for a text asset whose line endings or encoding are
+/// converted.
+fn extraction_memory(tree_entry: &TreeEntryRef, is_converted: bool, args: &CliArgs) -> usize {
+    let is_text = (args.text_eol.is_some() || args.text_encoding.is_some()) && text::has_text_extension(tree_entry.name);
+    let buffers = args.chunk_size + args.write_buffer_size;
+    if is_converted || is_text { buffers + 2 * tree_entry.decompressed_size } else { buffers }
+}
+
+/// An archive nested in the archive being extracted (see `--recursive`).
+struct NestedArchive<'a> {
+    /// The path of its entry, through the archives it's nested in (e.g. `mods/pack.dat`).
+    path: String,
+    content: Vec<u8>,
+    /// The memory that holds its content, within which its entries are extracted.
+    memory: Reservation<'a>,
+}
+
+/// Extracts the entries of `nested` into `output_dir`, descending into archives nested in it in
+/// turn. They're extracted as they're stored, one at a time.
+///
+/// Every entry is extracted: selecting and renaming entries (e.g. `--include`, `--occurrence`,
+/// `--flatten`) and `--convert` only apply to the outer archive's entries.
+fn extract_nested(output_dir: &Path, nested: &NestedArchive, args: &CliArgs, limits: &Limits, sandbox: Option<&Sandbox>, manifest: Option<&Manifest>) -> io::Result<()> {
+    let entry_path = nested.path.as_str();
+    let dat = Dat::parse(&nested.content).map_err(|e| errors::with_context(e, entry_path))?;
+
+    let output_paths = dat.tree_entries().iter().map(|tree_entry| {
+        let path = self::entry_path(tree_entry, args.read_separators, args.absolute_names).map_err(|e| errors::with_context(e, entry_path))?;
+        Ok(Some(output_dir.join(path)))
+    }).collect::<io::Result<Vec<Option<PathBuf>>>>()?;
+    if !args.follow_symlinks {
+        check_symlinks(output_dir, &output_paths)?;
+    }
+    create_output_dirs(&output_paths, args.dir_mode, sandbox)?;
+
+    for (tree_entry, output_path) in dat.tree_entries().iter().zip(output_paths.iter().flatten()) {
+        let entry_data = dat.data(tree_entry)?;
+        let nested_path = format!("{}/{}", entry_path, tree_entry.normalized_path(false));
+
+        let memory = nested.memory.reserve(tree_entry.decompressed_size);
+        if let Some(content) = nested_archive(tree_entry, entry_data) {
+            extract_nested(output_path, &NestedArchive { path: nested_path, content, memory }, args, limits, sandbox, manifest)?;
+            continue;
+        }
+        drop(memory);
+
+        let decoding = Decoding::of(tree_entry, entry_data, false);
+        if let Decoding::Method(method) = decoding {
+            check_flag(tree_entry, method, args.strict_flags).map_err(|e| errors::with_context(e, entry_path))?;
+        }
+        let _open_file = limits.open_files.acquire();
+        let _memory = nested.memory.reserve(extraction_memory(tree_entry, false, args));
+        let written = write_output_file(output_path, args, &limits.throttle, sandbox, |output_file| {
+            write_extracted(tree_entry, entry_data, decoding, output_file, args).map_err(|e| errors::with_context(e, entry_path))
+        })?;
+        if let Some(manifest) = manifest {
+            manifest.record(output_path, &nested_path, written);
+        }
+
+        if args.verbose {
+            println!("{}", output_path.to_str().unwrap());
+        }
+    }
+
+    Ok(())
+}
+
+/// How an entry's data is decoded when it's extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decoding {
+    /// By the method it's stored with.
+    Method(Method),
+    /// As raw deflate data (see `--raw-deflate`).
+    RawDeflate,
+}
+
+impl Decoding {
+    /// Returns how `entry_data`, the data of `tree_entry`, is decoded: by its method (see
+    /// `Method::of_entry`), unless `raw_deflate` and it's raw deflate data in place of zlib's.
+    fn of(tree_entry: &TreeEntryRef, entry_data: &[u8], raw_deflate: bool) -> Self {
+        let method = Method::of_entry(tree_entry, entry_data);
+        // checked only for entries that would otherwise be extracted as stored, as it means inflating them
+        if raw_deflate && tree_entry.is_compressed && method == Method::Stored && fo2dat::is_raw_deflate(entry_data, tree_entry.decompressed_size) {
+            Decoding::RawDeflate
+        } else {
+            Decoding::Method(method)
+        }
+    }
+}
+
+/// Warns about (or, if `strict_flags`, fails on) `tree_entry` if its compressed flag disagrees with
+/// the `method` of its data (see `Method::of_entry`). Either way, entries are extracted by what
+/// their data looks like.
+fn check_flag(tree_entry: &TreeEntryRef, method: Method, strict_flags: bool) -> io::Result<()> {
+    let anomaly = match method {
+        Method::Stored if tree_entry.is_compressed => Anomaly::FlaggedCompressed,
+        Method::Zlib | Method::ZlibDictionary if !tree_entry.is_compressed => Anomaly::FlaggedStored,
+        _ => return Ok(()),
+    };
+
+    if strict_flags {
+        let err_msg = format!("{}: {} (given --strict-flags)", tree_entry, anomaly.description());
+        return Err(errors::new(Code::FlagMismatch, ErrorKind::InvalidData, err_msg));
+    }
+
+    eprintln!("{}: {}: warning: {}, so it's extracted as {} data", APP_NAME, tree_entry, anomaly.description(), method.name());
+    Ok(())
+}
+
+/// Fails on `name`, an entry's stored path, if it's absolute (it starts with a separator or a
+/// drive letter, e.g. `\art` or `C:\art`), unless `absolute_names`.
+fn check_absolute(name: &str, absolute_names: bool) -> io::Result<()> {
+    let is_absolute = name.starts_with(['\\', '/']) || name.split(['\\', '/']).next().is_some_and(is_drive);
+    if is_absolute && !absolute_names {
+        let err_msg = format!("{}: refusing to extract an entry with an absolute path (see --absolute-names)", name);
+        return Err(errors::new(Code::BadName, ErrorKind::InvalidData, err_msg));
+    }
+    Ok(())
+}
+
+/// Returns `true` if `component`, the first component of an entry's path, is a drive (e.g. `C:`).
+fn is_drive(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    bytes.len() == 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// Returns the path (relative to the output directory) that `tree_entry` is extracted to, with its
+/// stored name split into components by `separators`.
+fn entry_path(tree_entry: &TreeEntryRef, separators: Separators, absolute_names: bool) -> io::Result<PathBuf> {
+    check_absolute(tree_entry.name, absolute_names)?;
+
+    let mut path = PathBuf::new();
+
+    for component in tree_entry.components_with(separators).filter(|component| !component.is_empty()) {
+        if is_drive(component) {
+            // only reached with --absolute-names, which extracts such paths relative to the output directory
+            if path.as_os_str().is_empty() {
+                continue;
+            }
+            let err_msg = format!("{}: {:?} is a drive, so it can't be extracted as a directory", tree_entry.name, component);
+            return Err(errors::new(Code::BadName, ErrorKind::InvalidData, err_msg));
+        }
+        if component == ".." {
+            let err_msg = format!("{}: refusing to extract an entry outside of the output directory", tree_entry.name);
+            return Err(errors::new(Code::BadName, ErrorKind::InvalidData, err_msg));
+        }
+        if component.contains(std::path::is_separator) {
+            let err_msg = format!("{}: {:?} contains a path separator, so it can't be extracted as one file or directory (see --read-separators)", tree_entry.name, component);
+            return Err(errors::new(Code::BadName, ErrorKind::InvalidData, err_msg));
+        }
+        path.push(component);
+    }
+
+    Ok(path)
+}
+
+/// Returns `true` if the entry at `index` in the tree with `path` is selected by the `--include`,
+/// `--exclude` and `--entry-index` arguments.
+fn is_selected(args: &CliArgs, index: usize, path: &str) -> bool {
+    (args.entry_indexes.is_empty() || args.entry_indexes.contains(&index)) &&
+        (args.filter.is_empty() || args.filter.is_selected(path))
+}
+
+/// Returns where each entry (given as its path in the archive, in tree order) is extracted to, or
+/// `None` if it's skipped because it isn't selected or because of a `--flatten` collision.
+fn output_paths<I: Iterator<Item = PathBuf>>(output_dir: &Path, entry_paths: I, args: &CliArgs) -> io::Result<Vec<Option<PathBuf>>> {
+    let mut occurrences = Occurrences::new(args.occurrence);
+    let entry_paths = entry_paths.enumerate().map(|(i, entry_path)| {
+        let entry_path_str = entry_path.to_str().unwrap();
+        if is_selected(args, i, entry_path_str) && occurrences.select(entry_path_str) {
+            Some(entry_path)
+        } else {
+            None
+        }
+    });
+
+    if !args.flatten {
+        return Ok(entry_paths.map(|entry_path| entry_path.map(|entry_path| output_dir.join(entry_path))).collect());
+    }
+
+    // entry paths are case-insensitive, and so are some of the filesystems they're extracted to
+    let mut claimed: HashMap<String, usize> = HashMap::new();
+    let mut output_paths: Vec<Option<PathBuf>> = Vec::new();
+
+    for (i, entry_path) in entry_paths.enumerate() {
+        let file_name = match entry_path.as_ref().and_then(|entry_path| entry_path.file_name()) {
+            Some(file_name) => file_name.to_str().unwrap().to_string(),
+            None => {
+                output_paths.push(None);
+                continue;
+            }
+        };
+
+        let key = file_name.to_ascii_lowercase();
+
+        let output_name = match claimed.get(&key).cloned() {
+            None => Some(file_name),
+            Some(claimant) => match args.on_collision {
+                Collision::Error => {
+                    let err_msg = format!("{}: --flatten would extract it onto an earlier entry's {} (see --on-collision)",
+                                          entry_path.unwrap().display(), file_name);
+                    return Err(Error::new(ErrorKind::AlreadyExists, err_msg));
+                },
+                Collision::Skip => None,
+                Collision::Overwrite => {
+                    output_paths[claimant] = None;
+                    Some(file_name)
+                },
+                Collision::Rename => {
+                    let path = Path::new(&file_name);
+                    let stem = path.file_stem().unwrap().to_str().unwrap();
+                    let extension = path.extension().map(|ext| format!(".{}", ext.to_str().unwrap())).unwrap_or_default();
+                    (2..).map(|n| format!("{}~{}{}", stem, n, extension))
+                        .find(|renamed| !claimed.contains_key(&renamed.to_ascii_lowercase()))
+                }
+            },
+        };
+
+        if let Some(ref output_name) = output_name {
+            claimed.insert(output_name.to_ascii_lowercase(), i);
+        }
+        output_paths.push(output_name.map(|output_name| output_dir.join(output_name)));
+    }
+
+    Ok(output_paths)
+}
+
+/// Data shared by all conversions performed during an extraction.
+#[cfg_attr(not(feature = "image"), allow(dead_code))]
+struct ConversionContext {
+    /// The palette used for FRMs. If it was supplied with `--palette`, it's also used instead of
+    /// each RIX's own palette.
+    palette: Option<Palette>,
+    palette_is_override: bool,
+    palette_scaling: Scaling,
+    msg_encoding: Encoding,
+}
+
+impl ConversionContext {
+
+    fn new(args: &CliArgs, entries: &[(Entry, &[u8])]) -> io::Result<Self> {
+        const PALETTE_PATH: &str = "color.pal";
+
+        let palette = if let Some(ref palette_path) = args.palette {
+            let palette_data = std::fs::read(palette_path).map_err(|e| {
+                errors::with_context(e, palette_path)
+            })?;
+            Some(Palette::parse_with_scaling(&palette_data, args.palette_scaling)?)
+        } else if args.conversions.iter().any(|conversion| conversion.needs_palette()) {
+            match entries.iter().find(|(tree_entry, _)| tree_entry.normalized_path(true) == PALETTE_PATH) {
+                Some((palette_entry, palette_data)) => Some(Palette::parse_with_scaling(&decompress_entry(palette_entry, palette_data)?, args.palette_scaling)?),
+                None => {
+                    let err_msg = format!("cannot convert entries: the archive contains no {} (use --palette to supply one)", PALETTE_PATH);
+                    return Err(Error::new(ErrorKind::NotFound, err_msg));
+                },
+            }
+        } else {
+            None
+        };
+
+        Ok(ConversionContext {
+            palette,
+            palette_is_override: args.palette.is_some(),
+            palette_scaling: args.palette_scaling,
+            msg_encoding: args.msg_encoding,
+        })
+    }
+
+    /// Converts `data`, an entry's decompressed content.
+    fn convert(&self, conversion: &Conversion, data: &[u8]) -> io::Result<Vec<u8>> {
+        match *conversion {
+            #[cfg(feature = "image")]
+            Conversion::FrmToPng => {
+                let palette = self.palette.as_ref().expect("palette should be loaded for frm conversions");
+                let mut converted = Vec::new();
+                Frm::parse(data)?.write_png(&mut converted, palette)?;
+                Ok(converted)
+            },
+            #[cfg(feature = "image")]
+            Conversion::RixToPng => {
+                let rix = Rix::parse(data)?;
+                let mut converted = Vec::new();
+                match self.palette {
+                    Some(ref palette) if self.palette_is_override => rix.write_png(&mut converted, palette)?,
+                    _ => rix.write_png(&mut converted, &rix.palette(self.palette_scaling))?,
+                }
+                Ok(converted)
+            },
+            Conversion::MsgToJson | Conversion::MsgToPo | Conversion::MsgToText => {
+                let msg = Msg::parse(&self.msg_encoding.decode(data))?;
+                let mut converted = Vec::new();
+                match *conversion {
+                    Conversion::MsgToJson => msg.write_json(&mut converted)?,
+                    Conversion::MsgToPo => msg.write_po(&mut converted)?,
+                    _ => msg.write_msg(&mut converted)?,
+                }
+                Ok(converted)
+            },
+            Conversion::External(ref converter) => converter.convert(data),
+        }
+    }
+}
+
+fn mmap(dat_path_str: &str) -> io::Result<Mmap> {
+    if remote::is_url(dat_path_str) {
+        return fetch_remote(dat_path_str, Fetch::All);
+    }
+
+    let (file_path, member) = container::split_path(dat_path_str);
+    let dat_path = Path::new(file_path);
+    if !dat_path.exists() {
+        let err_msg = format!("{}: no such file", dat_path_str);
+        return Err(Error::new(ErrorKind::NotFound, err_msg));
+    }
+
+    let dat_file = File::open(dat_path)?;
+    let mmap = unsafe { Mmap::map(&dat_file)? };
+    let unwrapped = match member {
+        Some(member) => {
+            let content = container::zip_member(&mmap, member).map_err(|e| with_path(file_path, e))?;
+            if container::is_gzip_path(member) { container::gunzip(&content) } else { Ok(content) }
+        },
+        None if container::is_gzip_path(file_path) => container::gunzip(&mmap),
+        None => return Ok(mmap),
+    };
+
+    // copied into an anonymous map, so that wrapped archives are read like any other
+    let unwrapped = unwrapped.map_err(|e| with_path(dat_path_str, e))?;
+    if unwrapped.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{}: empty archive", dat_path_str)));
+    }
+    let mut anon = MmapMut::map_anon(unwrapped.len())?;
+    anon.copy_from_slice(&unwrapped);
+    anon.make_read_only()
+}
+
+/// Maps the archive at `dat_path_str` with (at least) its footer and tree, which is all that
+/// reading its entries' metadata needs. Only a remote archive's data is left out.
+fn mmap_metadata(dat_path_str: &str) -> io::Result<Mmap> {
+    if remote::is_url(dat_path_str) {
+        fetch_remote(dat_path_str, Fetch::Tree)
+    } else {
+        mmap(dat_path_str)
+    }
+}
+
+/// Returns the path of the index sidecar (see `fo2dat::index`) of the archive at `dat_path`, unless
+/// it's remote or in a container, which can't have one.
+fn index_path(dat_path: &str) -> Option<PathBuf> {
+    if remote::is_url(dat_path) {
+        return None;
+    }
+    match container::split_path(dat_path) {
+        (file_path, None) => Some(index::sidecar_path(Path::new(file_path))),
+        (_, Some(_)) => None,
+    }
+}
+
+/// Parses `data`, the archive at `dat_path`. If the archive has an index sidecar, its index is read
+/// from that, or, if the sidecar's stale, built and written to it.
+fn parse_dat<'a>(dat_path: &str, data: &'a [u8]) -> io::Result<Dat<'a>> {
+    let index_path = match index_path(dat_path) {
+        Some(index_path) if index_path.is_file() => index_path,
+        _ => return profile::time(Stage::Parse, || Dat::parse(data)),
+    };
+
+    let cached = profile::time(Stage::Parse, || CachedIndex::read(&index_path)?.map_or(Ok(None), |cached| Dat::from_index(data, cached)))?;
+    if let Some(dat) = cached {
+        return Ok(dat);
+    }
+
+    // a sidecar that can't be rewritten (e.g. it's read-only) only costs speed
+    let dat = profile::time(Stage::Parse, || Dat::parse(data))?;
+    if let Err(e) = dat.index().write(&index_path) {
+        eprintln!("{}: warning: cannot update the index sidecar: {}", APP_NAME, e);
+    }
+    Ok(dat)
+}
+
+fn write_index(dat_path: &str) -> io::Result<()> {
+    let index_path = match index_path(dat_path) {
+        Some(index_path) => index_path,
+        None => {
+            let err_msg = format!("{}: only a local archive (not a remote one, or one in a container) can have an index sidecar", dat_path);
+            return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+        },
+    };
+
+    let data = mmap(dat_path)?;
+    Dat::parse(&data)?.index().write(&index_path)
+}
+
+/// What of a remote archive is fetched.
+enum Fetch<'f> {
+    All,
+    /// The footer and tree.
+    Tree,
+    /// The footer, tree and the data of the entries (given by index) that the function selects.
+    Entries(&'f dyn Fn(usize, &TreeEntryRef) -> bool),
+}
+
+/// Fetches the archive at `url` (see `fo2dat::remote`) into an anonymous map. The parts that
+/// aren't fetched are left zeroed, which costs no memory until they're written.
+fn fetch_remote(url: &str, fetch: Fetch) -> io::Result<Mmap> {
+    // the tail of the archive that's fetched first, which has its footer and, usually, its tree
+    const TAIL_LEN: usize = 256 * 1024;
+    // the largest gap between entries' data that's fetched, rather than making another request
+    const MAX_GAP: usize = 64 * 1024;
+
+    let remote = RemoteFile::open(url)?;
+    if remote.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{}: empty archive", url)));
+    }
+    let len = remote.len() as usize;
+    let mut map = MmapMut::map_anon(len)?;
+
+    let fill = |map: &mut MmapMut, range: Range<usize>| -> io::Result<()> {
+        let fetched = remote.read_range(range.start as u64..range.end as u64)?;
+        map[range].copy_from_slice(&fetched);
+        Ok(())
+    };
+
+    let select = match fetch {
+        Fetch::All => {
+            fill(&mut map, 0..len)?;
+            return map.make_read_only();
+        },
+        Fetch::Tree => None,
+        Fetch::Entries(select) => Some(select),
+    };
+
+    let tail_start = len.saturating_sub(TAIL_LEN);
+    fill(&mut map, tail_start..len)?;
+    // the file count may be outside of the tail, but where it is isn't
+    let num_files_start = fo2dat::stats(&map).map_err(|e| with_path(url, e))?.data_range.end;
+    if num_files_start < tail_start {
+        fill(&mut map, num_files_start..tail_start)?;
+    }
+
+    if let Some(select) = select {
+        let mut ranges = Vec::new();
+        for (i, tree_entry) in fo2dat::iter_tree_refs(&map)?.enumerate() {
+            let tree_entry = tree_entry?;
+            // an out-of-bounds range is left for reading the entry to report
+            let end = tree_entry.offset.saturating_add(tree_entry.packed_size);
+            if select(i, &tree_entry) && end <= num_files_start {
+                ranges.push(tree_entry.offset..end);
+            }
+        }
+
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = Vec::new();
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end + MAX_GAP => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        for range in merged {
+            fill(&mut map, range)?;
+        }
+    }
+
+    map.make_read_only()
+}
+
+fn with_path(path: &str, e: Error) -> Error {
+    errors::with_context(e, path)
+}
+
+/// Fails if any of `output_paths`, or any directory that they're in below `output_dir`, is a
+/// symbolic link, which extraction would write through, possibly to outside of `output_dir`.
+fn check_symlinks(output_dir: &Path, output_paths: &[Option<PathBuf>]) -> io::Result<()> {
+    let mut checked: HashSet<&Path> = HashSet::new();
+
+    for output_path in output_paths.iter().flatten() {
+        for path in output_path.ancestors().take_while(|&path| path != output_dir) {
+            // the rest of its ancestors were checked along with it
+            if !checked.insert(path) {
+                break;
+            }
+            match std::fs::symlink_metadata(path) {
+                Ok(ref metadata) if metadata.file_type().is_symlink() => {
+                    let err_msg = format!("{}: refusing to extract through a symbolic link (see --follow-symlinks)", path.display());
+                    return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+                },
+                Ok(_) => {},
+                Err(ref e) if e.kind() == ErrorKind::NotFound => {},
+                Err(e) => return Err(errors::with_context(e, path.display())),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates the directories that `output_paths` are in, so that workers only need to create
+/// files. Doing it up front means each directory is created (or found to exist) once, rather
+/// than once per entry in it.
+///
+/// Directories that are created get `dir_mode`, less the umask. Existing directories (e.g. a
+/// shared game directory being extracted into) keep their permissions.
+fn create_output_dirs(output_paths: &[Option<PathBuf>], dir_mode: Option<u32>, sandbox: Option<&Sandbox>) -> io::Result<()> {
+    let dirs: BTreeSet<&Path> = output_paths.iter().flatten().filter_map(|output_path| output_path.parent()).collect();
+
+    if let Some(sandbox) = sandbox {
+        for dir in dirs {
+            sandbox.create_dir_all(dir, dir_mode.unwrap_or(0o777)).map_err(|e| {
+                errors::with_context(e, dir.display())
+            })?;
+        }
+        return Ok(());
+    }
+
+    let mut dir_builder = std::fs::DirBuilder::new();
+    dir_builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        if let Some(dir_mode) = dir_mode {
+            dir_builder.mode(dir_mode);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = dir_mode;
+
+    for dir in dirs {
+        dir_builder.create(dir).map_err(|e| {
+            errors::with_context(e, dir.display())
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Creates a file at `output_path`, whose directory was created by `create_output_dirs`.
+///
+/// A new file gets `file_mode`, less the umask, as `open(2)` applies it. An existing file is
+/// truncated and keeps its permissions.
+fn create_output_file(output_path: &Path, file_mode: Option<u32>, sandbox: Option<&Sandbox>) -> io::Result<File> {
+    if let Some(sandbox) = sandbox {
+        return sandbox.create_file(output_path, file_mode.unwrap_or(0o666)).map_err(|e| {
+            errors::with_context(e, output_path.display())
+        });
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        if let Some(file_mode) = file_mode {
+            options.mode(file_mode);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = file_mode;
+
+    options.open(output_path).map_err(|e| {
+        errors::with_context(e, output_path.display())
+    })
+}
+
+/// Writes the file at `output_path` with `write`.
+///
+/// If `args.atomic`, the file is written as `<output_path>.part` and only renamed to `output_path`
+/// once it's complete, so an interrupted extraction never leaves a truncated file where the engine
+/// would load it. A failed write removes its `.part` file, but a killed process can't, so
+/// leftover `.part` files are the only trace of an interruption.
+fn write_output_file<F>(output_path: &Path, args: &CliArgs, throttle: &Throttle, sandbox: Option<&Sandbox>, write: F) -> io::Result<Written>
+    where F: FnOnce(&mut ThrottledWriter<File>) -> io::Result<()> {
+
+    let writer = |output_file: File| ThrottledWriter {
+        inner: output_file,
+        throttle,
+        size: 0,
+        hasher: args.manifest.as_ref().map(|_| args.manifest_algorithm.hasher()),
+    };
+    let written = |writer: ThrottledWriter<File>| Written { size: writer.size, digest: writer.hasher.map(Hasher::finish) };
+
+    if !args.atomic {
+        let mut output_file = writer(create_output_file(output_path, args.file_mode, sandbox)?);
+        write(&mut output_file)?;
+        return Ok(written(output_file));
+    }
+
+    let mut part_path = output_path.as_os_str().to_owned();
+    part_path.push(".part");
+    let part_path = PathBuf::from(part_path);
+
+    // the file is closed before it's renamed, which Windows requires
+    let result = create_output_file(&part_path, args.file_mode, sandbox).and_then(|part_file| {
+        let mut part_file = writer(part_file);
+        write(&mut part_file)?;
+        Ok(written(part_file))
+    }).and_then(|written| {
+        match sandbox {
+            Some(sandbox) => sandbox.rename(&part_path, output_path),
+            None => std::fs::rename(&part_path, output_path),
+        }.map_err(|e| {
+            errors::with_context(e, format!("{}: cannot rename to {}", part_path.display(), output_path.display()))
+        })?;
+        Ok(written)
+    });
+
+    if result.is_err() {
+        let _ = match sandbox {
+            Some(sandbox) => sandbox.remove_file(&part_path),
+            None => std::fs::remove_file(&part_path),
+        };
+    }
+
+    result
+}
+
+/// Returns a reader of the content of `entry_data`, which is decoded as `decoding` says.
+fn content_reader(entry_data: &[u8], decoding: Decoding) -> fo2dat::EntryReader<'_> {
+    match decoding {
+        Decoding::Method(method) => fo2dat::method_reader(entry_data, method),
+        Decoding::RawDeflate => fo2dat::raw_deflate_reader(entry_data),
+    }
+}
+
+/// Returns the whole content of `entry_data` (see `content_reader`).
+fn read_content(entry_data: &[u8], decoding: Decoding) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    io::Read::read_to_end(&mut content_reader(entry_data, decoding), &mut content)?;
+    Ok(content)
+}
+
+/// Writes the decompressed content of `tree_entry`, whose data is `entry_data`, to `output_file`,
+/// as it's extracted: with its encoding and line endings converted (whole) if it's a text asset
+/// and `args.text_encoding` or `args.text_eol` are given, otherwise as it is (a chunk at a time).
+fn write_extracted<W: Write>(tree_entry: &TreeEntryRef, entry_data: &[u8], decoding: Decoding, mut output_file: W, args: &CliArgs) -> io::Result<()> {
+    let converts_text = args.text_eol.is_some() || args.text_encoding.is_some();
+    if !converts_text || !text::has_text_extension(tree_entry.name) {
+        return write_entry(entry_data, decoding, output_file, args.write_buffer_size, args.chunk_size).map_err(|e| {
+            errors::with_context(e, tree_entry)
+        });
+    }
+
+    let mut content = read_content(entry_data, decoding).map_err(|e| errors::with_context(e, tree_entry))?;
+    if text::is_text_asset(tree_entry.name, &content) {
+        content = profile::time(Stage::Convert, || -> io::Result<Vec<u8>> {
+            if let Some(transcoding) = args.text_encoding {
+                content = transcoding.transcode(&content).map_err(|e| errors::with_context(e, format!("{}: cannot transcode", tree_entry)))?;
+            }
+            if let Some(line_ending) = args.text_eol {
+                content = text::convert_line_endings(&content, line_ending);
+            }
+            Ok(content)
+        })?;
+    }
+    output_file.write_all(&content)
+}
+
+/// Writes the decompressed content of `entry_data` to `output_file`, a chunk at a time.
+fn write_entry<W: Write>(entry_data: &[u8], decoding: Decoding, output_file: W, write_buffer_size: usize, chunk_size: usize) -> io::Result<()> {
+    if decoding != Decoding::Method(Method::Stored) {
+        // chunks smaller than the buffer would otherwise each become a write syscall
+        let mut output_file = BufWriter::with_capacity(write_buffer_size, output_file);
+        fo2dat::read_chunks(content_reader(entry_data, decoding), chunk_size, |chunk| output_file.write_all(chunk))?;
+        output_file.flush()?;
+    } else {
+        // stored entries are already one contiguous slice of the mapping, so buffering them would
+        // only add a copy
+        let mut output_file = output_file;
+        fo2dat::for_each_chunk_with(entry_data, Method::Stored, chunk_size, |chunk| output_file.write_all(chunk))?;
+    }
+
+    Ok(())
+}
+
+/// Writes `components` joined with the platform's path separator, without building a `PathBuf`.
+fn write_native_path<'a, W: Write, I: Iterator<Item = &'a str>>(out: &mut W, components: I) -> io::Result<()> {
+    for (i, component) in components.enumerate() {
+        if i > 0 {
+            write!(out, "{}", std::path::MAIN_SEPARATOR)?;
+        }
+        out.write_all(component.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn list_entries(args: &CliArgs) -> io::Result<()> {
+    let uses_field = |field: Field| args.output_format.as_ref().is_some_and(|template| template.fields().any(|f| f == field));
+
+    let needs_data = args.show_crc || args.show_method || args.recursive || [Field::Crc, Field::Method, Field::Anomalies].iter().any(|&field| uses_field(field));
+    let data = if needs_data { mmap(&args.file)? } else { mmap_metadata(&args.file)? };
+
+    advise::for_listing(&data)?;
+
+    let crcs = if args.show_crc || uses_field(Field::Crc) {
+        let stats = fo2dat::stats(&data)?;
+        match Extension::parse(&data[stats.data_range])? {
+            Some(extension) => extension.crc_table(stats.num_files)?,
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    // the method is determined from each entry's data, which is in the data section
+    let data_section = if args.show_method || uses_field(Field::Method) || uses_field(Field::Anomalies) {
+        Some(&data[fo2dat::stats(&data)?.data_range])
+    } else {
+        None
+    };
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let mut entry_paths = Vec::new();
+    let mut occurrences = Occurrences::new(args.occurrence);
+    let selected = fo2dat::iter_tree_refs(&data)?.enumerate().filter_map(|(i, tree_entry)| match tree_entry {
+        Ok(ref tree_entry) if !is_selected(args, i, tree_entry.name) || !occurrences.select(tree_entry.name) => None,
+        tree_entry => Some(tree_entry.map(|tree_entry| (i, tree_entry))),
+    });
+
+    if args.json {
+        json::begin_document(&mut stdout)?;
+        write!(stdout, ",\"archive\":")?;
+        json::write_str(&mut stdout, &args.file)?;
+        write!(stdout, ",\"entries\":[")?;
+    }
+
+    for (num_listed, listed) in limit_listing(selected, args, |(_, tree_entry)| tree_entry.decompressed_size)?.enumerate() {
+        let (i, tree_entry) = listed?;
+        if args.dirs_only {
+            entry_paths.push(tree_entry.components_with(args.read_separators).collect());
+            continue;
+        }
+        if args.json {
+            if num_listed > 0 {
+                write!(stdout, ",")?;
+            }
+            let crcs = if args.show_crc { Some(crcs.as_deref()) } else { None };
+            write_entry_json(&mut stdout, i, &tree_entry, args.read_separators, data_section, crcs)?;
+            continue;
+        }
+        if let Some(ref template) = args.output_format {
+            template.write(&mut stdout, |out, field| {
+                write_field(out, field, i, &tree_entry, data_section.unwrap_or(&[]), crcs.as_deref(), args.read_separators)
+            })?;
+            writeln!(stdout)?;
+            continue;
+        }
+        if args.show_index {
+            write!(stdout, "#{}\t", i)?;
+        }
+        if args.show_crc {
+            match crcs.as_ref().and_then(|crcs| crcs.get(i)) {
+                Some(crc) => write!(stdout, "{:08x}\t", crc)?,
+                None => write!(stdout, "--------\t")?,
+            }
+        }
+        if let Some(data_section) = data_section {
+            write_method(&mut stdout, &tree_entry, data_section)?;
+        }
+        write_native_path(&mut stdout, tree_entry.components_with(args.read_separators))?;
+        writeln!(stdout)?;
+
+        if args.recursive {
+            let entry_data = data[fo2dat::stats(&data)?.data_range].get(tree_entry.offset..tree_entry.offset.saturating_add(tree_entry.packed_size));
+            if let Some(content) = entry_data.and_then(|entry_data| nested_archive(&tree_entry, entry_data)) {
+                let prefix: Vec<&str> = tree_entry.components_with(args.read_separators).collect();
+                list_nested(&mut stdout, &prefix, &content, args.read_separators)?;
+            }
+        }
+    }
+
+    if args.dirs_only {
+        write_dirs(&mut stdout, entry_paths)?;
+    }
+    if args.json {
+        writeln!(stdout, "]}}")?;
+    }
+
+    Ok(())
+}
+
+/// Returns the content of `tree_entry`, whose data is `entry_data`, if it's an archive itself (a
+/// `.dat` that parses as a DAT2 archive, rather than, e.g., a savegame), which `--recursive`
+/// descends into.
+///
+/// Its data is inflated no further than its stated size, so that an entry that inflates to far
+/// more than it states (e.g. an archive bomb) is only extracted as a file, a chunk at a time.
+fn nested_archive(tree_entry: &TreeEntryRef, entry_data: &[u8]) -> Option<Vec<u8>> {
+    if !tree_entry.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("dat")) {
+        return None;
+    }
+    let reader = fo2dat::method_reader(entry_data, Method::of_entry(tree_entry, entry_data));
+    let mut content = Vec::new();
+    io::Read::read_to_end(&mut io::Read::take(reader, tree_entry.decompressed_size as u64 + 1), &mut content).ok()?;
+    if content.len() != tree_entry.decompressed_size {
+        return None;
+    }
+    Dat::parse(&content).ok()?;
+    Some(content)
+}
+
+/// Writes the paths of the entries of `content`, an archive nested at `prefix` (its entry's path
+/// components) in the archive being listed, under that path, descending into archives nested in
+/// it in turn.
+fn list_nested<W: Write>(out: &mut W, prefix: &[&str], content: &[u8], separators: Separators) -> io::Result<()> {
+    let dat = Dat::parse(content)?;
+    for tree_entry in dat.tree_entries() {
+        let components = || prefix.iter().cloned().chain(tree_entry.components_with(separators).filter(|component| !component.is_empty()));
+        write_native_path(out, components())?;
+        writeln!(out)?;
+
+        if let Some(content) = nested_archive(tree_entry, dat.data(tree_entry)?) {
+            list_nested(out, &components().collect::<Vec<_>>(), &content, separators)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the entries to list of those `selected`: the `--largest` by `size`, or the first
+/// `--limit`, or all of them.
+fn limit_listing<'a, T, I, F>(selected: I, args: &CliArgs, size: F) -> io::Result<Box<dyn Iterator<Item = io::Result<T>> + 'a>>
+    where T: 'a, I: Iterator<Item = io::Result<T>> + 'a, F: Fn(&T) -> usize {
+
+    match args.largest {
+        Some(n) => Ok(Box::new(largest(selected, n, size)?.into_iter().map(Ok))),
+        None => Ok(Box::new(selected.take(args.limit.unwrap_or(usize::MAX)))),
+    }
+}
+
+/// Returns the `n` of `items` with the largest `size`, largest first, with those of the same size
+/// in the order given. Only about `2 * n` items are held at once, however many there are.
+fn largest<T, I, F>(items: I, n: usize, size: F) -> io::Result<Vec<T>>
+    where I: Iterator<Item = io::Result<T>>, F: Fn(&T) -> usize {
+
+    // the sort is stable, and later items are pushed after the survivors of earlier sorts, so
+    // ties stay in order
+    let mut largest = Vec::new();
+    for item in items {
+        largest.push(item?);
+        if largest.len() >= n.saturating_mul(2) {
+            largest.sort_by_key(|item| Reverse(size(item)));
+            largest.truncate(n);
+        }
+    }
+    largest.sort_by_key(|item| Reverse(size(item)));
+    largest.truncate(n);
+    Ok(largest)
+}
+
+/// Writes `tree_entry`, which is at `index` in the tree, as a JSON object (for `--json`): with
+/// its method and anomalies if `data_section` is given, and its CRC-32 (or `null`, if the archive
+/// has none) if `crcs` is.
+fn write_entry_json<W: Write>(out: &mut W, index: usize, tree_entry: &TreeEntryRef, separators: Separators, data_section: Option<&[u8]>, crcs: Option<Option<&[u32]>>) -> io::Result<()> {
+    let path = tree_entry.components_with(separators).filter(|component| !component.is_empty()).collect::<Vec<_>>().join("/");
+    write!(out, "{{\"index\":{},\"path\":", index)?;
+    json::write_str(out, &path)?;
+    write!(out, ",\"size\":{},\"packed_size\":{},\"offset\":{},\"compressed\":{}",
+           tree_entry.decompressed_size, tree_entry.packed_size, tree_entry.offset, tree_entry.is_compressed)?;
+
+    if let Some(data_section) = data_section {
+        match tree_entry.offset.checked_add(tree_entry.packed_size).and_then(|data_end| data_section.get(tree_entry.offset..data_end)) {
+            Some(entry_data) => {
+                write!(out, ",\"method\":\"{}\",\"anomalies\":", Method::of_entry(tree_entry, entry_data).name())?;
+                json::write_array(out, tree_entry.anomalies(entry_data), |out, anomaly| json::write_str(out, anomaly.name()))?;
+            },
+            None => write!(out, ",\"method\":null,\"anomalies\":[\"out-of-bounds\"]")?,
+        }
+    }
+
+    if let Some(crcs) = crcs {
+        match crcs.and_then(|crcs| crcs.get(index)) {
+            Some(crc) => write!(out, ",\"crc\":\"{:08x}\"", crc)?,
+            None => write!(out, ",\"crc\":null")?,
+        }
+    }
+
+    out.write_all(b"}")
+}
+
+/// Writes the method and anomalies columns of `--show-method` for `tree_entry`.
+fn write_method<W: Write>(out: &mut W, tree_entry: &TreeEntryRef, data_section: &[u8]) -> io::Result<()> {
+    write_field(out, Field::Method, 0, tree_entry, data_section, None, Separators::Backslash)?;
+    out.write_all(b"\t")?;
+    write_field(out, Field::Anomalies, 0, tree_entry, data_section, None, Separators::Backslash)?;
+    out.write_all(b"\t")
+}
+
+/// Writes `field` of `tree_entry`, which is at `index` in the tree, for `--output-format`.
+/// `data_section` and `crcs` are only needed for the fields that come from them.
+fn write_field<W: Write>(out: &mut W, field: Field, index: usize, tree_entry: &TreeEntryRef, data_section: &[u8], crcs: Option<&[u32]>, separators: Separators) -> io::Result<()> {
+    let entry_data = tree_entry.offset.checked_add(tree_entry.packed_size).and_then(|data_end| data_section.get(tree_entry.offset..data_end));
+
+    match field {
+        Field::Path => write_native_path(out, tree_entry.components_with(separators).filter(|component| !component.is_empty())),
+        Field::Index => write!(out, "{}", index),
+        Field::Size => write!(out, "{}", tree_entry.decompressed_size),
+        Field::PackedSize => write!(out, "{}", tree_entry.packed_size),
+        Field::Ratio if tree_entry.decompressed_size == 0 => out.write_all(b"-"),
+        Field::Ratio => write!(out, "{:.2}", tree_entry.packed_size as f64 / tree_entry.decompressed_size as f64),
+        Field::Offset => write!(out, "{}", tree_entry.offset),
+        Field::Compressed => out.write_all(if tree_entry.is_compressed { b"yes" } else { b"no" }),
+        Field::Method => match entry_data {
+            Some(entry_data) => out.write_all(Method::of_entry(tree_entry, entry_data).name().as_bytes()),
+            None => out.write_all(b"?"),
+        },
+        Field::Anomalies => match entry_data {
+            Some(entry_data) => {
+                let anomalies: Vec<&str> = tree_entry.anomalies(entry_data).into_iter().map(Anomaly::name).collect();
+                out.write_all(if anomalies.is_empty() { "-".to_string() } else { anomalies.join(",") }.as_bytes())
+            },
+            None => out.write_all(b"out-of-bounds"),
+        },
+        Field::Crc => match crcs.and_then(|crcs| crcs.get(index)) {
+            Some(crc) => write!(out, "{:08x}", crc),
+            None => out.write_all(b"--------"),
+        },
+    }
+}
+
+/// Writes every directory that contains any of `entry_paths` (each given as its components),
+/// directly or indirectly, prefixed with the number of entries under it.
+fn write_dirs<W: Write>(out: &mut W, entry_paths: Vec<Vec<&str>>) -> io::Result<()> {
+    let mut dirs: BTreeMap<Vec<&str>, usize> = BTreeMap::new();
+
+    for components in entry_paths {
+        let components: Vec<&str> = components.into_iter().filter(|component| !component.is_empty()).collect();
+        for len in 1..components.len() {
+            *dirs.entry(components[..len].to_vec()).or_default() += 1;
+        }
+    }
+
+    for (dir, num_entries) in &dirs {
+        write!(out, "{}\t", num_entries)?;
+        write_native_path(out, dir.iter().cloned())?;
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+fn print_map_infos(dat_path: &str, maps: &[String]) -> io::Result<()> {
+    const SCRIPTS_LST_PATH: &str = "scripts/scripts.lst";
+
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+
+    let map_entries = select_entries(&dat, maps, "map")?;
+
+    // script names are looked up in the same archive, if it has them (master.dat does)
+    let script_names = read_lst(&dat, SCRIPTS_LST_PATH)?;
+    let script_name = |index: usize| -> String {
+        match script_names.get(index) {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => format!("#{}", index),
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    for map_entry in map_entries {
+        let map_info = MapInfo::parse(&decompress_entry(map_entry, dat.data(map_entry)?)?).map_err(|e| {
+            errors::with_context(e, map_entry)
+        })?;
+        let header = &map_info.header;
+
+        writeln!(stdout, "{}", map_entry)?;
+        writeln!(stdout, "  name: {}", header.name)?;
+        writeln!(stdout, "  version: {}", header.version)?;
+        writeln!(stdout, "  elevations: {}", map_info.elevations.len())?;
+        match header.script_lst_index() {
+            Some(index) => writeln!(stdout, "  map script: {}", script_name(index))?,
+            None => writeln!(stdout, "  map script: none")?,
+        }
+        for (type_name, scripts) in SCRIPT_TYPE_NAMES.iter().zip(map_info.scripts.iter()) {
+            write!(stdout, "  {} scripts: {}", type_name, scripts.len())?;
+            for (i, script) in scripts.iter().enumerate() {
+                let separator = if i == 0 { " (" } else { ", " };
+                write!(stdout, "{}{}", separator, script_name(script.script_index.max(0) as usize))?;
+            }
+            writeln!(stdout, "{}", if scripts.is_empty() { "" } else { ")" })?;
+        }
+        for elevation in &map_info.elevations {
+            writeln!(stdout, "  elevation {}: {} floor tiles, {} roof tiles", elevation.elevation, elevation.num_floor_tiles, elevation.num_roof_tiles)?;
+        }
+        writeln!(stdout, "  objects: {}", map_info.num_objects)?;
+    }
+
+    Ok(())
+}
+
+fn dump_pros(dat_path: &str, pros: &[String]) -> io::Result<()> {
+    /// The directory (under `proto/`) and `.lst` file of each prototype type.
+    const PROTO_LSTS: [&str; 6] = ["items", "critters", "scenery", "walls", "tiles", "misc"];
+
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+
+    let pro_entries = select_entries(&dat, pros, "pro")?;
+
+    let lsts = PROTO_LSTS.iter().map(|lst| {
+        read_lst(&dat, &format!("proto/{0}/{0}.lst", lst))
+    }).collect::<io::Result<Vec<_>>>()?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    json::begin_document(&mut stdout)?;
+    write!(stdout, ",\"protos\":")?;
+    json::write_array(&mut stdout, pro_entries, |out, pro_entry| {
+        let pro = Pro::parse(&decompress_entry(pro_entry, dat.data(pro_entry)?)?).map_err(|e| {
+            errors::with_context(e, pro_entry)
+        })?;
+
+        // a prototype's ID is its 1-based line number in its type's lst
+        let lst_name = lsts.get(pro.pro_type() as usize)
+            .and_then(|lst| lst.get((pro.id() as usize).wrapping_sub(1)))
+            .filter(|name| !name.is_empty());
+        let is_listed = match (lst_name, pro_entry.file_name()) {
+            (Some(lst_name), Some(file_name)) => lst_name.eq_ignore_ascii_case(file_name),
+            _ => false,
+        };
+
+        out.write_all(b"{\"path\":")?;
+        json::write_str(out, &pro_entry.to_string())?;
+        out.write_all(b",\"lst_name\":")?;
+        match lst_name {
+            Some(lst_name) => json::write_str(out, lst_name)?,
+            None => out.write_all(b"null")?,
+        }
+        write!(out, ",\"listed\":{},\"proto\":", is_listed)?;
+        pro.write_json(out)?;
+        out.write_all(b"}")
+    })?;
+    writeln!(stdout, "}}")
+}
+
+fn check_lsts(dat_path: &str) -> io::Result<()> {
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+
+    let validation = lst::validate(&dat)?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    for missing in &validation.missing {
+        writeln!(stdout, "missing: {}:{}: {}", missing.lst, missing.line, missing.name)?;
+    }
+    for entry in &validation.unreferenced {
+        writeln!(stdout, "unreferenced: {}", entry)?;
+    }
+    writeln!(stdout, "{} lsts checked, {} missing references, {} unreferenced art entries",
+             validation.num_lsts, validation.missing.len(), validation.unreferenced.len())?;
+
+    if validation.missing.is_empty() {
+        Ok(())
+    } else {
+        let err_msg = format!("{}: {} lst references are missing", dat_path, validation.missing.len());
+        Err(Error::new(ErrorKind::InvalidData, err_msg))
+    }
+}
+
+fn print_save_info(save_path: &str) -> io::Result<()> {
+    let data = mmap(save_path)?;
+    let header = SaveHeader::parse(&data).map_err(|e| {
+        errors::with_context(e, save_path)
+    })?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let (year, month, day) = header.saved_on;
+    let (game_year, game_month, game_day) = header.game_date;
+
+    writeln!(stdout, "version: {}.{}{}", header.version_major, header.version_minor, header.version_release as char)?;
+    writeln!(stdout, "character: {}", header.character_name)?;
+    writeln!(stdout, "description: {}", header.description)?;
+    writeln!(stdout, "saved on: {:04}-{:02}-{:02}", year, month, day)?;
+    writeln!(stdout, "game date: {:04}-{:02}-{:02}", game_year, game_month, game_day)?;
+    writeln!(stdout, "game time: {} ticks", header.game_time)?;
+    writeln!(stdout, "map: {} (#{}, elevation {})", header.map_file_name, header.map_number, header.elevation)?;
+    writeln!(stdout, "thumbnail: {}x{}", save::THUMBNAIL_WIDTH, save::THUMBNAIL_HEIGHT)?;
+
+    Ok(())
+}
+
+/// Prints the load order configured by the `fallout2.cfg` at `cfg_path` (and the `ddraw.ini` at
+/// `ddraw_path`) or, if `paths` are given, the layer each is loaded from, returning `false` if any
+/// isn't in any layer.
+fn print_load_order(cfg_path: &str, ddraw_path: Option<&str>, paths: &[String]) -> io::Result<bool> {
+    let layers = config::load_order(Path::new(cfg_path), ddraw_path.map(Path::new))?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    if paths.is_empty() {
+        for (i, layer) in layers.iter().enumerate() {
+            writeln!(stdout, "{}\t{}", i + 1, layer)?;
+        }
+        return Ok(true);
+    }
+
+    // each archive is only mapped and indexed once, however many paths are looked up
+    let mut maps = Vec::new();
+    for layer in &layers {
+        maps.push(match *layer {
+            Layer::Dat(ref path) => Some(mmap(path.to_str().unwrap())?),
+            Layer::Dir(_) => None,
+        });
+    }
+    let dats = maps.iter().zip(&layers).map(|(map, layer)| match *map {
+        Some(ref data) => Dat::parse(data).map(Some).map_err(|e| with_path(&layer.to_string(), e)),
+        None => Ok(None),
+    }).collect::<io::Result<Vec<_>>>()?;
+
+    let mut all_found = true;
+    for path in paths {
+        let mut found = None;
+        for (layer, dat) in layers.iter().zip(&dats) {
+            let is_in_layer = match (layer, dat) {
+                (_, Some(dat)) => dat.get(path).is_some(),
+                (Layer::Dir(dir), None) => config::find_in_dir(dir, path)?.is_some(),
+                (Layer::Dat(_), None) => false,
+            };
+            if is_in_layer {
+                found = Some(layer);
+                break;
+            }
+        }
+
+        match found {
+            Some(layer) => writeln!(stdout, "{}\t{}", names::to_slashes(path), layer)?,
+            None => {
+                eprintln!("{}: {}: not in any archive or patches directory", APP_NAME, path);
+                all_found = false;
+            },
+        }
+    }
+
+    Ok(all_found)
+}
+
+fn print_dir(dat_path: &str, dir_path: &str, long: bool, human_readable: bool) -> io::Result<()> {
+    let data = mmap_metadata(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+
+    let dir = find_dir(&dat, dir_path)?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let sizes = |size: usize, packed_size: usize| {
+        format!("{:>10} {:>10}  ", format_size(size as u64, human_readable), format_size(packed_size as u64, human_readable))
+    };
+
+    for subdir in dir.children() {
+        let name = stored_dir_name(&subdir);
+        if long {
+            let (size, packed_size) = subdir.entries().fold((0, 0), |(size, packed_size), entry| {
+                (size + entry.decompressed_size, packed_size + entry.packed_size)
+            });
+            write!(stdout, "{}", sizes(size, packed_size))?;
+        }
+        writeln!(stdout, "{}/", name)?;
+    }
+
+    for entry in dir.files() {
+        if long {
+            write!(stdout, "{}", sizes(entry.decompressed_size, entry.packed_size))?;
+        }
+        writeln!(stdout, "{}", entry.file_name().unwrap_or(entry.name))?;
+    }
+
+    Ok(())
+}
+
+fn print_tree(dat_path: &str, dir_path: &str, max_depth: Option<usize>) -> io::Result<()> {
+    let data = mmap_metadata(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+    let dir = find_dir(&dat, dir_path)?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    writeln!(stdout, "{}", if dir.is_root() { "." } else { dir_path })?;
+    let (num_dirs, num_files) = print_subtree(&mut stdout, &dir, "", 1, max_depth)?;
+    writeln!(stdout)?;
+    writeln!(stdout, "{} director{}, {} file{}", num_dirs, if num_dirs == 1 { "y" } else { "ies" }, num_files, if num_files == 1 { "" } else { "s" })?;
+
+    Ok(())
+}
+
+/// Prints the subdirectories and files of `dir` as branches of a tree, each line starting with
+/// `prefix`, and returns the number of directories and files printed.
+fn print_subtree<W: Write>(out: &mut W, dir: &fo2dat::Dir, prefix: &str, depth: usize, max_depth: Option<usize>) -> io::Result<(usize, usize)> {
+    let subdirs: Vec<_> = dir.children().collect();
+    let files: Vec<_> = dir.files().collect();
+    let num_children = subdirs.len() + files.len();
+    let (mut num_dirs, mut num_files) = (subdirs.len(), files.len());
+
+    for (i, subdir) in subdirs.iter().enumerate() {
+        let is_last = i + 1 == num_children;
+        writeln!(out, "{}{}{}/", prefix, if is_last { "└── " } else { "├── " }, stored_dir_name(subdir))?;
+        if max_depth.map_or(true, |max_depth| depth < max_depth) {
+            let subprefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+            let (subdir_dirs, subdir_files) = print_subtree(out, subdir, &subprefix, depth + 1, max_depth)?;
+            num_dirs += subdir_dirs;
+            num_files += subdir_files;
+        }
+    }
+
+    for (i, entry) in files.iter().enumerate() {
+        let is_last = subdirs.len() + i + 1 == num_children;
+        writeln!(out, "{}{}{}", prefix, if is_last { "└── " } else { "├── " }, entry.file_name().unwrap_or(entry.name))?;
+    }
+
+    Ok((num_dirs, num_files))
+}
+
+/// Looks up the directory at `dir_path`, failing if no entry is under it.
+fn find_dir<'d, 'a>(dat: &'d Dat<'a>, dir_path: &str) -> io::Result<fo2dat::Dir<'d, 'a>> {
+    dat.dir(dir_path).ok_or_else(|| {
+        let problem = if dat.get(dir_path).is_some() { "not a directory" } else { "no such directory in the archive" };
+        Error::new(ErrorKind::NotFound, format!("{}: {}", dir_path, problem))
+    })
+}
+
+/// Returns `dir`'s name as the first entry under it has it: directories' paths are normalized,
+/// which lowercases them.
+fn stored_dir_name<'d>(dir: &fo2dat::Dir<'d, '_>) -> &'d str {
+    let depth = names::components(dir.path()).count() - 1;
+    dir.entries().next().and_then(|entry| names::components(entry.name).nth(depth)).unwrap_or_else(|| dir.name())
+}
+
+fn print_info(dat_path: &str, human_readable: bool, as_json: bool) -> io::Result<()> {
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+    let stats = dat.stats();
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    if as_json {
+        let extension = dat.extension();
+        let crc_table = match extension {
+            Ok(Some(ref extension)) => match extension.crc_table(dat.len()) {
+                Ok(Some(_)) => "yes",
+                Ok(None) => "no",
+                Err(_) => "stale",
+            },
+            _ => "no",
+        };
+        let extension_error = extension.as_ref().err().map(|e| e.to_string());
+        let extension = extension.as_ref().ok().and_then(Option::as_ref);
+
+        json::begin_document(&mut stdout)?;
+        write!(stdout, ",\"archive\":")?;
+        json::write_str(&mut stdout, dat_path)?;
+        write!(stdout, ",\"format\":\"{}\",\"entries\":{},\"file_size\":{},\"data_size\":{},\"tree_size\":{},\"crc_table\":\"{}\",\"created_by\":",
+               if stats.is_dat64 { "DAT64" } else { "DAT2" }, dat.len(), data.len(), stats.data_range.len(), stats.tree_range.len(), crc_table)?;
+        json::write_opt_str(&mut stdout, extension.and_then(Extension::tool).as_deref())?;
+        write!(stdout, ",\"comment\":")?;
+        json::write_opt_str(&mut stdout, extension.and_then(Extension::comment).as_deref())?;
+        write!(stdout, ",\"extension_error\":")?;
+        json::write_opt_str(&mut stdout, extension_error.as_deref())?;
+        return writeln!(stdout, "}}");
+    }
+
+    writeln!(stdout, "format: {}", if stats.is_dat64 { "DAT64" } else { "DAT2" })?;
+    writeln!(stdout, "entries: {}", dat.len())?;
+    writeln!(stdout, "file size: {}", format_size(data.len() as u64, human_readable))?;
+    writeln!(stdout, "data size: {}", format_size(stats.data_range.len() as u64, human_readable))?;
+    writeln!(stdout, "tree size: {}", format_size(stats.tree_range.len() as u64, human_readable))?;
+
+    match dat.extension() {
+        Ok(Some(extension)) => {
+            let crc_table = match extension.crc_table(dat.len()) {
+                Ok(Some(_)) => "yes",
+                Ok(None) => "no",
+                Err(_) => "stale",
+            };
+            writeln!(stdout, "crc table: {}", crc_table)?;
+            if let Some(tool) = extension.tool() {
+                writeln!(stdout, "created by: {}", tool)?;
+            }
+            if let Some(comment) = extension.comment() {
+                writeln!(stdout, "comment: {}", comment)?;
+            }
+        },
+        Ok(None) => writeln!(stdout, "crc table: no")?,
+        Err(e) => writeln!(stdout, "extension: malformed ({})", e)?,
+    }
+
+    Ok(())
+}
+
+fn print_stats(dat_path: &str, paths: &[String], entry_indexes: &[usize], occurrence: Option<usize>, human_readable: bool, output_format: Option<&Template<Field>>, as_json: bool) -> io::Result<()> {
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let data_section = &data[dat.stats().data_range.clone()];
+    let crcs = match output_format {
+        Some(template) if template.fields().any(|field| field == Field::Crc) => dat.crc_table()?,
+        _ if as_json => dat.crc_table()?,
+        _ => None,
+    };
+
+    if as_json {
+        let selected = select_entry_indexes(&dat, paths, entry_indexes, occurrence)?;
+        json::begin_document(&mut stdout)?;
+        write!(stdout, ",\"archive\":")?;
+        json::write_str(&mut stdout, dat_path)?;
+        write!(stdout, ",\"entries\":")?;
+        json::write_array(&mut stdout, selected, |out, i| {
+            write_entry_json(out, i, &dat.tree_entries()[i], Separators::Backslash, Some(data_section), Some(crcs.as_deref()))
+        })?;
+        return writeln!(stdout, "}}");
+    }
+
+    for i in select_entry_indexes(&dat, paths, entry_indexes, occurrence)? {
+        let tree_entry = &dat.tree_entries()[i];
+        if let Some(template) = output_format {
+            template.write(&mut stdout, |out, field| {
+                write_field(out, field, i, tree_entry, data_section, crcs.as_deref(), Separators::Backslash)
+            })?;
+            writeln!(stdout)?;
+            continue;
+        }
+        writeln!(stdout, "#{} {}", i, tree_entry)?;
+        writeln!(stdout, "  offset: {}", tree_entry.offset)?;
+        writeln!(stdout, "  packed size: {}", format_size(tree_entry.packed_size as u64, human_readable))?;
+        writeln!(stdout, "  decompressed size: {}", format_size(tree_entry.decompressed_size as u64, human_readable))?;
+        writeln!(stdout, "  compressed: {}", if tree_entry.is_compressed { "yes" } else { "no" })?;
+        match dat.data(tree_entry) {
+            Ok(entry_data) => {
+                let method = Method::of_entry(tree_entry, entry_data);
+                writeln!(stdout, "  method: {}", method.name())?;
+                match sniff::sniff_reader(fo2dat::method_reader(entry_data, method)) {
+                    Ok(sniff::Kind::Text(charset)) => writeln!(stdout, "  content: text ({})", charset.name())?,
+                    Ok(sniff::Kind::Binary(Some(format))) => writeln!(stdout, "  content: binary ({})", format)?,
+                    Ok(sniff::Kind::Binary(None)) => writeln!(stdout, "  content: binary")?,
+                    Err(_) => writeln!(stdout, "  content: unreadable")?,
+                }
+                for anomaly in tree_entry.anomalies(entry_data) {
+                    writeln!(stdout, "  anomaly: {}", anomaly.name())?;
+                }
+            },
+            Err(_) => writeln!(stdout, "  anomaly: out-of-bounds")?,
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_entries(dat_path: &str, checkpoint: Option<Checkpoint>, as_json: bool) -> io::Result<()> {
+    let data = mmap(dat_path)?;
+
+    // entries are read as when extracting (see extract_all_entries_to_dir), which verifying
+    // should be no slower than
+    advise::for_extraction(&data)?;
+
+    let dat = parse_dat(dat_path, &data)?;
+    let crcs = dat.crc_table()?;
+
+    let total_bytes = dat.tree_entries().iter().map(|tree_entry| tree_entry.decompressed_size).sum();
+    let progress = Progress::new(checkpoint, as_json, dat.len(), total_bytes);
+
+    let problems = in_offset_order(&dat, |i, tree_entry| {
+        let mut crc = Crc::new();
+        let checked = dat.data(tree_entry).and_then(|entry_data| {
+            fo2dat::for_each_chunk_with(entry_data, Method::of_entry(tree_entry, entry_data), fo2dat::DEFAULT_CHUNK_SIZE, |chunk| {
+                crc.update(chunk);
+                Ok(())
+            })
+        });
+        progress.record(tree_entry.decompressed_size);
+
+        let len = match checked {
+            Ok(len) => len,
+            Err(e) => return Some((errors::code(&e), e.to_string())),
+        };
+
+        if len != tree_entry.decompressed_size {
+            return Some((Code::SizeMismatch, format!("decompresses to {} bytes, but its stated size is {}", len, tree_entry.decompressed_size)));
+        }
+
+        if let Some(ref crcs) = crcs {
+            if crc.sum() != crcs[i] {
+                return Some((Code::CrcMismatch, format!("CRC-32 mismatch (expected {:08x}, got {:08x})", crcs[i], crc.sum())));
+            }
+        }
+
+        None
+    });
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let corrupt: Vec<(usize, Code, &str)> = problems.iter().enumerate()
+        .filter_map(|(i, problem)| problem.as_ref().map(|&(code, ref problem)| (i, code, problem.as_str())))
+        .collect();
+    let num_corrupt = corrupt.len();
+    let codes: HashSet<Code> = corrupt.iter().map(|&(_, code, _)| code).collect();
+
+    if as_json {
+        json::begin_document(&mut stdout)?;
+        write!(stdout, ",\"archive\":")?;
+        json::write_str(&mut stdout, dat_path)?;
+        write!(stdout, ",\"checked\":{},\"crc_checked\":{},\"corrupt\":", dat.len(), crcs.is_some())?;
+        json::write_array(&mut stdout, &corrupt, |out, &(i, code, problem)| {
+            write!(out, "{{\"index\":{},\"path\":", i)?;
+            json::write_str(out, &dat.tree_entries()[i].to_string())?;
+            write!(out, ",\"code\":\"{}\",\"problem\":", code)?;
+            json::write_str(out, problem)?;
+            out.write_all(b"}")
+        })?;
+        writeln!(stdout, "}}")?;
+    } else {
+        for &(i, _, problem) in &corrupt {
+            writeln!(stdout, "corrupt: {}: {}", dat.tree_entries()[i], problem)?;
+        }
+        writeln!(stdout, "{} entries checked{}, {} corrupt",
+                 dat.len(), if crcs.is_some() { " against their CRC-32s" } else { " (the archive has no CRC table)" }, num_corrupt)?;
+    }
+
+    if num_corrupt == 0 {
+        Ok(())
+    } else {
+        // entries that are all corrupt in the same way are reported as such
+        let code = if codes.len() == 1 { codes.into_iter().next().unwrap() } else { Code::Corrupt };
+        let err_msg = format!("{}: {} entries are corrupt", dat_path, num_corrupt);
+        Err(errors::new(code, ErrorKind::InvalidData, err_msg))
+    }
+}
+
+fn print_diagnoses(dat_path: &str, as_json: bool) -> io::Result<()> {
+    let data = mmap(dat_path)?;
+    let diagnoses = doctor::diagnose(&data, dat_path);
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let num_errors = diagnoses.iter().filter(|diagnosis| diagnosis.severity == Severity::Error).count();
+    let num_warnings = diagnoses.len() - num_errors;
+
+    if as_json {
+        json::begin_document(&mut stdout)?;
+        write!(stdout, ",\"archive\":")?;
+        json::write_str(&mut stdout, dat_path)?;
+        write!(stdout, ",\"diagnoses\":")?;
+        json::write_array(&mut stdout, &diagnoses, |out, diagnosis| {
+            write!(out, "{{\"severity\":\"{}\",\"problem\":", diagnosis.severity)?;
+            json::write_str(out, &diagnosis.problem)?;
+            write!(out, ",\"fix\":")?;
+            json::write_opt_str(out, diagnosis.fix.as_deref())?;
+            out.write_all(b"}")
+        })?;
+        writeln!(stdout, "}}")?;
+    } else {
+        for diagnosis in &diagnoses {
+            writeln!(stdout, "{}: {}", diagnosis.severity, diagnosis.problem)?;
+            if let Some(ref fix) = diagnosis.fix {
+                writeln!(stdout, "  fix: {}", fix)?;
+            }
+        }
+
+        if diagnoses.is_empty() {
+            writeln!(stdout, "{}: no problems found", dat_path)?;
+        } else {
+            writeln!(stdout, "{}: {} errors, {} warnings", dat_path, num_errors, num_warnings)?;
+        }
+    }
+
+    if num_errors == 0 {
+        Ok(())
+    } else {
+        Err(errors::new(Code::Corrupt, ErrorKind::InvalidData, format!("{}: {} problems make it (at least partly) unreadable", dat_path, num_errors)))
+    }
+}
+
+/// Returns `f(i, tree_entry)` for each of `dat`'s entries, in tree order, calling it on the worker
+/// pool in order of the entries' offsets, so that each worker reads forward through the data
+/// section (as extraction does), and with the next entries' data prefetched.
+fn in_offset_order<'a, T, F>(dat: &Dat<'a>, f: F) -> Vec<T>
+    where T: Send, F: Fn(usize, &TreeEntryRef<'a>) -> T + Sync {
+
+    // number of entries ahead of the current one that a worker prefetches
+    const PREFETCH_LOOKAHEAD: usize = 4;
+
+    let tree_entries = dat.tree_entries();
+    let mut order: Vec<usize> = (0..tree_entries.len()).collect();
+    order.sort_by_key(|&i| tree_entries[i].offset);
+
+    let mut results: Vec<(usize, T)> = order.par_iter().enumerate().map(|(position, &i)| {
+        if let Some(upcoming) = order.get(position + PREFETCH_LOOKAHEAD).and_then(|&upcoming| dat.data(&tree_entries[upcoming]).ok()) {
+            advise::advise(upcoming, Advice::WillNeed);
+        }
+        (i, f(i, &tree_entries[i]))
+    }).collect();
+
+    results.sort_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// How a file compares with the archive entry at the same path.
+enum FileComparison {
+    /// The directory has no file at the entry's path.
+    Missing,
+    /// The file's size (the first) differs from the entry's.
+    SizeDiffers(usize),
+    ContentDiffers,
+    Same,
+}
+
+/// How the files under a directory compare with an archive's entries.
+struct DirComparison<'a> {
+    /// Each entry (the first of duplicate paths), the file at its path (if there is one), and how
+    /// they compare.
+    entries: Vec<(&'a TreeEntryRef<'a>, Option<PathBuf>, FileComparison)>,
+    /// The names and paths of the files that no entry is at.
+    extra: Vec<(String, PathBuf)>,
+}
+
+/// Compares the files under `dir` with the entries of `dat`. Which files are compared follows packing's rules (e.g.
+/// `.datignore`), as if `dir` were about to be packed.
+fn compare_dir<'a>(args: &CliArgs, dat: &'a Dat, dir: &Path) -> io::Result<DirComparison<'a>> {
+    if !dir.is_dir() {
+        let err_msg = format!("{}: not a directory", dir.display());
+        return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+    }
+
+    // the archive is skipped if it's in the directory, which a remote one can't be
+    let archive_path = if remote::is_url(&args.file) { PathBuf::new() } else { std::fs::canonicalize(container::split_path(&args.file).0)? };
+    let mut files = Vec::new();
+    collect_new_entries(args, dir, &mut Vec::new(), &archive_path, &Ignores::new(args.exclude_vcs), &mut files)?;
+
+    // entry paths are case-insensitive, and can be extracted onto case-insensitive filesystems
+    let files: HashMap<String, (String, PathBuf)> = files.into_iter().map(|file| {
+        let path = match file.source {
+            Source::File(path) => path,
+            Source::Data(_) | Source::Packed { .. } => unreachable!(),
+        };
+        (names::normalize(&file.name), (names::to_slashes(&file.name), path))
+    }).collect();
+
+    // as with lookups, only the first of duplicate paths counts
+    let mut seen = HashSet::new();
+    let entries: Vec<&TreeEntryRef> = dat.tree_entries().iter()
+        .filter(|tree_entry| seen.insert(tree_entry.normalized_path(true)))
+        .collect();
+
+    let entries = entries.par_iter().map(|&tree_entry| {
+        let file_path = match files.get(&tree_entry.normalized_path(true)) {
+            Some((_, file_path)) => file_path,
+            None => return Ok((tree_entry, None, FileComparison::Missing)),
+        };
+
+        let file_size = std::fs::metadata(file_path)?.len() as usize;
+        if file_size != tree_entry.decompressed_size {
+            return Ok((tree_entry, Some(file_path.clone()), FileComparison::SizeDiffers(file_size)));
+        }
+
+        // compared a chunk at a time, as neither may fit in memory
+        let mut file = io::BufReader::new(File::open(file_path)?);
+        let mut file_chunk = vec![0; fo2dat::DEFAULT_CHUNK_SIZE];
+        let entry_data = dat.data(tree_entry)?;
+        let compared = fo2dat::for_each_chunk_with(entry_data, Method::of_entry(tree_entry, entry_data), fo2dat::DEFAULT_CHUNK_SIZE, |chunk| {
+            io::Read::read_exact(&mut file, &mut file_chunk[..chunk.len()])?;
+            if file_chunk[..chunk.len()] == *chunk {
+                Ok(())
+            } else {
+                Err(Error::new(ErrorKind::InvalidData, "content differs"))
+            }
+        });
+        let comparison = match compared {
+            Ok(_) => FileComparison::Same,
+            Err(ref e) if e.kind() == ErrorKind::InvalidData || e.kind() == ErrorKind::UnexpectedEof => FileComparison::ContentDiffers,
+            Err(e) => return Err(e),
+        };
+
+        Ok((tree_entry, Some(file_path.clone()), comparison))
+    }).collect::<io::Result<Vec<_>>>()?;
+
+    let mut extra: Vec<(String, PathBuf)> = files.into_iter().filter(|(name, _)| !seen.contains(name)).map(|(_, file)| file).collect();
+    extra.sort_by(|a, b| a.1.cmp(&b.1));
+
+    Ok(DirComparison { entries, extra })
+}
+
+fn verify_dir(args: &CliArgs) -> io::Result<()> {
+    let data = mmap(&args.file)?;
+    let dat = parse_dat(&args.file, &data)?;
+    let dir = PathBuf::from(&args.ch_dir);
+    let DirComparison { entries, extra } = compare_dir(args, &dat, &dir)?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let mut num_problems = 0;
+    for (tree_entry, file_path, comparison) in &entries {
+        let problem = match (comparison, file_path) {
+            (FileComparison::Same, _) => continue,
+            (FileComparison::Missing, _) => format!("missing: {}", tree_entry),
+            (&FileComparison::SizeDiffers(file_size), Some(file_path)) => {
+                format!("mismatched: {}: {} bytes, but the entry is {} bytes", file_path.display(), file_size, tree_entry.decompressed_size)
+            },
+            (_, file_path) => format!("mismatched: {}: content differs from the entry's", file_path.as_ref().unwrap().display()),
+        };
+        writeln!(stdout, "{}", problem)?;
+        num_problems += 1;
+    }
+    for (_, path) in &extra {
+        writeln!(stdout, "extra: {}", path.display())?;
+    }
+    writeln!(stdout, "{} entries checked, {} missing or mismatched, {} extra files", entries.len(), num_problems, extra.len())?;
+
+    if num_problems == 0 && extra.is_empty() {
+        Ok(())
+    } else {
+        let err_msg = format!("{}: doesn't match {}", dir.display(), args.file);
+        Err(Error::new(ErrorKind::InvalidData, err_msg))
+    }
+}
+
+/// Prints how the files under `dir` differ from the entries of the archive at `args.file`, as sync
+/// would change the archive to match them: `+ PATH` for a file that only the directory has,
+/// `- PATH` for an entry that only the archive has and `M PATH` for a file whose content differs
+/// from its entry's. Returns `true` if they don't differ.
+fn print_dir_diff(args: &CliArgs, dir: &Path) -> io::Result<bool> {
+    let data = mmap(&args.file)?;
+    let dat = parse_dat(&args.file, &data)?;
+    let DirComparison { entries, extra } = compare_dir(args, &dat, dir)?;
+
+    // printed in path order, as sync prints its changes
+    let mut changes: Vec<(String, char)> = extra.into_iter().map(|(name, _)| (name, '+')).collect();
+    for (tree_entry, _, comparison) in entries {
+        match comparison {
+            FileComparison::Same => {},
+            FileComparison::Missing => changes.push((tree_entry.normalized_path(false), '-')),
+            FileComparison::SizeDiffers(_) | FileComparison::ContentDiffers => changes.push((tree_entry.normalized_path(false), 'M')),
+        }
+    }
+    changes.sort_by_key(|(path, _)| names::normalize(path));
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let count = |marker: char| changes.iter().filter(|&&(_, m)| m == marker).count();
+    for (path, marker) in &changes {
+        writeln!(stdout, "{} {}", marker, path)?;
+    }
+    writeln!(stdout, "{} only in the directory, {} only in the archive, {} differ", count('+'), count('-'), count('M'))?;
+
+    Ok(changes.is_empty())
+}
+
+fn print_checksums(dat_path: &str, algorithm: Algorithm, logical: bool) -> io::Result<()> {
+    let data = mmap(dat_path)?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    writeln!(stdout, "file: {}", digest::to_hex(&algorithm.digest(&data)))?;
+
+    if logical {
+        let dat = parse_dat(dat_path, &data)?;
+
+        // as with lookups, only the first of duplicate paths counts
+        let mut seen = HashSet::new();
+        let entries: Vec<&TreeEntryRef> = dat.tree_entries().iter()
+            .filter(|tree_entry| seen.insert(tree_entry.normalized_path(true)))
+            .collect();
+
+        let mut content_digests = entries.par_iter().map(|tree_entry| {
+            let mut hasher = algorithm.hasher();
+            let entry_data = dat.data(tree_entry)?;
+            fo2dat::for_each_chunk_with(entry_data, Method::of_entry(tree_entry, entry_data), fo2dat::DEFAULT_CHUNK_SIZE, |chunk| {
+                hasher.update(chunk);
+                Ok(())
+            })?;
+            Ok((tree_entry.normalized_path(true), digest::to_hex(&hasher.finish())))
+        }).collect::<io::Result<Vec<(String, String)>>>()?;
+
+        content_digests.sort();
+
+        // the logical form is a sha256sum-style listing: "DIGEST  PATH\n" per entry
+        let mut hasher = algorithm.hasher();
+        for (path, content_digest) in &content_digests {
+            hasher.update(format!("{}  {}\n", content_digest, path).as_bytes());
+        }
+        writeln!(stdout, "logical: {}", digest::to_hex(&hasher.finish()))?;
+    }
+
+    Ok(())
+}
+
+/// The labels of the buckets of `count --histogram`'s compression ratio histogram.
+const RATIO_BUCKETS: [&str; 11] = ["0-10%", "10-20%", "20-30%", "30-40%", "40-50%", "50-60%", "60-70%", "70-80%", "80-90%", "90-100%", ">100%"];
+
+/// The labels of the buckets of `count --histogram`'s size histogram, each 4 times the size of
+/// the last.
+const SIZE_BUCKETS: [&str; 10] = ["<1K", "1K-4K", "4K-16K", "16K-64K", "64K-256K", "256K-1M", "1M-4M", "4M-16M", "16M-64M", ">=64M"];
+
+/// The entries in one bucket of a histogram.
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    num_entries: usize,
+    size: u64,
+    packed_size: u64,
+}
+
+impl Bucket {
+    fn add(&mut self, tree_entry: &TreeEntryRef) {
+        self.num_entries += 1;
+        self.size += tree_entry.decompressed_size as u64;
+        self.packed_size += tree_entry.packed_size as u64;
+    }
+}
+
+fn print_count(dat_path: &str, filter: &Filter, sizes: bool, histogram: bool, human_readable: bool) -> io::Result<()> {
+    let data = mmap(dat_path)?;
+
+    let mut total = Bucket::default();
+    let mut ratio_buckets = [Bucket::default(); RATIO_BUCKETS.len()];
+    let mut size_buckets = [Bucket::default(); SIZE_BUCKETS.len()];
+
+    for tree_entry in fo2dat::iter_tree_refs(&data)? {
+        let tree_entry = tree_entry?;
+        if filter.is_selected(tree_entry.name) {
+            total.add(&tree_entry);
+            if let Some(i) = ratio_bucket(&tree_entry) {
+                ratio_buckets[i].add(&tree_entry);
+            }
+            size_buckets[size_bucket(tree_entry.decompressed_size)].add(&tree_entry);
+        }
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    if sizes {
+        writeln!(stdout, "{}\t{}\t{}", total.num_entries, format_size(total.size, human_readable), format_size(total.packed_size, human_readable))?;
+    } else {
+        writeln!(stdout, "{}", total.num_entries)?;
+    }
+
+    if histogram {
+        writeln!(stdout)?;
+        write_histogram(&mut stdout, "compression ratio", &RATIO_BUCKETS, &ratio_buckets, human_readable)?;
+        writeln!(stdout)?;
+        write_histogram(&mut stdout, "entry size", &SIZE_BUCKETS, &size_buckets, human_readable)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the bucket of `RATIO_BUCKETS` that `tree_entry`'s compression ratio falls in, or `None`
+/// if it's empty (and so has no ratio).
+fn ratio_bucket(tree_entry: &TreeEntryRef) -> Option<usize> {
+    if tree_entry.decompressed_size == 0 {
+        None
+    } else if tree_entry.packed_size > tree_entry.decompressed_size {
+        Some(RATIO_BUCKETS.len() - 1)
+    } else {
+        // stored entries, whose ratio is exactly 100%, fall in the 90-100% bucket
+        let i = tree_entry.packed_size as u64 * 10 / tree_entry.decompressed_size as u64;
+        Some((i as usize).min(RATIO_BUCKETS.len() - 2))
+    }
+}
+
+/// Returns the bucket of `SIZE_BUCKETS` that an entry of (decompressed) `size` falls in.
+fn size_bucket(size: usize) -> usize {
+    let mut limit = 1024;
+    let mut i = 0;
+    while i < SIZE_BUCKETS.len() - 1 && size >= limit {
+        limit *= 4;
+        i += 1;
+    }
+    i
+}
+
+/// Writes a histogram of `buckets` as a table with a text bar of each bucket's packed size,
+/// scaled to the largest's.
+fn write_histogram<W: Write>(out: &mut W, title: &str, labels: &[&str], buckets: &[Bucket], human_readable: bool) -> io::Result<()> {
+    const BAR_WIDTH: u64 = 40;
+
+    let max_packed_size = buckets.iter().map(|bucket| bucket.packed_size).max().unwrap_or(0);
+
+    writeln!(out, "{:>17}  {:>8}  {:>12}  {:>12}", title, "entries", "size", "packed")?;
+    for (label, bucket) in labels.iter().zip(buckets) {
+        // rounded up, so that every bucket with any data has a visible bar
+        let bar_len = match max_packed_size {
+            0 => 0,
+            max_packed_size => (bucket.packed_size * BAR_WIDTH).div_ceil(max_packed_size),
+        };
+        write!(out, "{:>17}  {:>8}  {:>12}  {:>12}", label, bucket.num_entries,
+               format_size(bucket.size, human_readable), format_size(bucket.packed_size, human_readable))?;
+        if bar_len > 0 {
+            write!(out, "  {}", "#".repeat(bar_len as usize))?;
+        }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+fn print_estimate(dat_path: &str, level: u32, sample: Option<usize>, human_readable: bool) -> io::Result<()> {
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+
+    // entries that share data (e.g. deduplicated ones) would still share it once recompressed
+    let mut seen = HashSet::new();
+    let blobs: Vec<&TreeEntryRef> = dat.tree_entries().iter()
+        .filter(|tree_entry| seen.insert((tree_entry.offset, tree_entry.packed_size)))
+        .collect();
+    let total_packed_size: u64 = blobs.iter().map(|tree_entry| tree_entry.packed_size as u64).sum();
+
+    let sampled: Vec<&TreeEntryRef> = match sample {
+        Some(n) if n < blobs.len() => (0..n).map(|i| blobs[i * blobs.len() / n]).collect(),
+        _ => blobs.clone(),
+    };
+
+    let sizes = sampled.par_iter().map(|tree_entry| {
+        let content = decompress_entry(tree_entry, dat.data(tree_entry)?)?;
+        let repacked_size = writer::pack(&content, level)?.map_or(content.len(), |compressed| compressed.len());
+        Ok((tree_entry.packed_size as u64, repacked_size as u64))
+    }).collect::<io::Result<Vec<(u64, u64)>>>()?;
+
+    let sampled_size: u64 = sizes.iter().map(|&(packed_size, _)| packed_size).sum();
+    let sampled_repacked_size: u64 = sizes.iter().map(|&(_, repacked_size)| repacked_size).sum();
+
+    let change = |from: u64, to: u64| -> String {
+        if from == 0 { String::from("n/a") } else { format!("{:+.1}%", (to as f64 - from as f64) / from as f64 * 100.0) }
+    };
+
+    let size = |size: u64| format_size(size, human_readable);
+    let bytes = if human_readable { "" } else { " bytes" };
+
+    println!("sampled: {} of {} distinct entries' data, {} of {}{}", sampled.len(), blobs.len(), size(sampled_size), size(total_packed_size), bytes);
+    println!("sampled at level {}: {} -> {}{} ({})", level, size(sampled_size), size(sampled_repacked_size), bytes, change(sampled_size, sampled_repacked_size));
+
+    // unsampled data is assumed to recompress like the sampled data did
+    let projected_size = if sampled_size == 0 {
+        total_packed_size
+    } else {
+        (total_packed_size as f64 * sampled_repacked_size as f64 / sampled_size as f64).round() as u64
+    };
+    println!("projected: {} -> {}{} ({})", size(total_packed_size), size(projected_size), bytes, change(total_packed_size, projected_size));
+
+    Ok(())
+}
+
+fn recompress(dat_path: &str, level: u32, packing: Packing, dictionary: bool, output: Option<&str>, human_readable: bool, max_memory: Option<usize>) -> io::Result<()> {
+    if output.is_none() && (container::is_wrapped(dat_path) || remote::is_url(dat_path)) {
+        let err_msg = format!("{}: is inside of another file or remote, so can't be rewritten in place (give an output file with -o)", dat_path);
+        return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+    }
+
+    let (entries, had_crc_table, is_dat64, tool, comment, original_size) = {
+        let data = mmap(dat_path)?;
+        let dat = parse_dat(dat_path, &data)?;
+
+        // names are kept as stored, so the separators they were stored with are kept too
+        let entries = dat.tree_entries().iter().map(|tree_entry| {
+            let packed = dat.data(tree_entry)?;
+            Ok(NewEntry {
+                name: tree_entry.name.to_string(),
+                source: Source::Packed { data: packed.to_vec(), method: Method::of_entry(tree_entry, packed), decompressed_size: tree_entry.decompressed_size },
+                compress: None,
+            })
+        }).collect::<io::Result<Vec<_>>>()?;
+
+        // a stale CRC table is dropped, rather than failing the rewrite, but the creator and
+        // comment are kept
+        let extension = dat.extension().ok().and_then(|extension| extension);
+        let tool = extension.as_ref().and_then(Extension::tool).map(String::from);
+        let comment = extension.as_ref().and_then(Extension::comment).map(String::from);
+        (entries, dat.crc_table().ok().and_then(|crcs| crcs).is_some(), dat.stats().is_dat64, tool, comment, data.len())
+    };
+
+    let options = WriteOptions {
+        compress: packing != Packing::StoreAll,
+        compress_all: packing == Packing::CompressAll,
+        level,
+        dictionary,
+        crc_table: had_crc_table,
+        dat64: is_dat64,
+        tool,
+        comment,
+        max_memory,
+        ..WriteOptions::default()
+    };
+
+    let summary = match output {
+        Some(output) => File::create(output).and_then(|output_file| {
+            let mut output_file = BufWriter::with_capacity(DEFAULT_WRITE_BUFFER_SIZE, output_file);
+            let summary = writer::write_dat(&mut output_file, &entries, &options)?;
+            output_file.flush()?;
+            Ok(summary)
+        }).map_err(|e| errors::with_context(e, output))?,
+        None => replace_file(Path::new(dat_path), DEFAULT_WRITE_BUFFER_SIZE, |output_file| {
+            writer::write_dat(output_file, &entries, &options)
+        }).map_err(|e| errors::with_context(e, dat_path))?,
+    };
+
+    let change = (summary.file_size as f64 - original_size as f64) / original_size as f64 * 100.0;
+    println!("{}: {} -> {}{} ({:+.1}%)", output.unwrap_or(dat_path),
+             format_size(original_size as u64, human_readable), format_size(summary.file_size as u64, human_readable),
+             if human_readable { "" } else { " bytes" }, change);
+
+    Ok(())
+}
+
+/// Replaces the file at `path` (e.g. the user's only copy of an archive) with what `write` writes,
+/// such that, however it's interrupted (even by a crash or power loss), it's left either as it was
+/// or replaced whole: it's written as `<path>.part`, which is flushed to disk before it's renamed
+/// over the file, and the rename is flushed to disk too. A failed write removes the `.part` file.
+fn replace_file<T, F>(path: &Path, buffer_size: usize, write: F) -> io::Result<T>
+    where F: FnOnce(&mut BufWriter<Timed<File>>) -> io::Result<T> {
+
+    let mut part_path = path.as_os_str().to_owned();
+    part_path.push(".part");
+    let part_path = PathBuf::from(part_path);
+
+    let written = File::create(&part_path).and_then(|part_file| {
+        let mut part_file = BufWriter::with_capacity(buffer_size, Timed::new(part_file, Stage::Write));
+        let written = write(&mut part_file)?;
+        let part_file = part_file.into_inner().map_err(|e| e.into_error())?.into_inner();
+        profile::time(Stage::Fsync, || part_file.sync_all())?;
+        Ok(written)
+    });
+    let written = match written {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = std::fs::remove_file(&part_path);
+            return Err(e);
+        },
+    };
+
+    std::fs::rename(&part_path, path).map_err(|e| {
+        errors::with_context(e, format!("{}: cannot rename to {}", part_path.display(), path.display()))
+    })?;
+
+    // the rename is an update of the directory, which is flushed separately (and can only be on
+    // Unix; Windows flushes renames itself)
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir).and_then(|dir| profile::time(Stage::Fsync, || dir.sync_all())).map_err(|e| errors::with_context(e, dir.display()))?;
+    }
+
+    Ok(written)
+}
+
+/// Writes a patch that turns the archive at `old_path` into the one at `new_path` to `output`, and
+/// prints how the new archive's blobs are rebuilt and the patch's size.
+fn create_patch(old_path: &str, new_path: &str, output: &str, human_readable: bool) -> io::Result<()> {
+    let old_data = mmap(old_path)?;
+    let new_data = mmap(new_path)?;
+
+    let written = File::create(output).and_then(|output_file| {
+        let mut output_file = BufWriter::with_capacity(DEFAULT_WRITE_BUFFER_SIZE, output_file);
+        let summary = patch::create(&mut output_file, &old_data, &new_data)?;
+        output_file.flush()?;
+        Ok(summary)
+    }).map_err(|e| errors::with_context(e, output));
+
+    let summary = match written {
+        Ok(summary) => summary,
+        Err(e) => {
+            let _ = std::fs::remove_file(output);
+            return Err(e);
+        },
+    };
+
+    let patch_size = std::fs::metadata(output)?.len();
+    println!("{}: {} copied, {} diffed, {} stored", output, summary.num_copied, summary.num_diffed, summary.num_stored);
+    println!("{}: {}{} ({:.1}% of {})", output, format_size(patch_size, human_readable), if human_readable { "" } else { " bytes" },
+             patch_size as f64 / new_data.len() as f64 * 100.0, new_path);
+
+    Ok(())
+}
+
+/// Applies the patch at `patch_path` to the archive at `base_path`, writing the archive it rebuilds
+/// to `output` or, if it isn't given, replacing the base archive once it's rebuilt and checked.
+fn apply_patch(base_path: &str, patch_path: &str, output: Option<&str>, human_readable: bool) -> io::Result<()> {
+    if output.is_none() && (container::is_wrapped(base_path) || remote::is_url(base_path)) {
+        let err_msg = format!("{}: is inside of another file or remote, so can't be patched in place (give an output file with -o)", base_path);
+        return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+    }
+
+    let base_data = mmap(base_path)?;
+    let patch_data = mmap(patch_path)?;
+
+    // nothing is kept if the base doesn't match, or the archive isn't rebuilt exactly
+    let written = match output {
+        Some(output) => replace_file(Path::new(output), DEFAULT_WRITE_BUFFER_SIZE, |output_file| {
+            patch::apply(output_file, &base_data, &patch_data)
+        }).map_err(|e| (e, output)),
+        None => {
+            let patch_data = &patch_data;
+            replace_file(Path::new(base_path), DEFAULT_WRITE_BUFFER_SIZE, move |output_file| {
+                let size = patch::apply(output_file, &base_data, patch_data)?;
+                // the base is unmapped before it's replaced, which Windows requires
+                drop(base_data);
+                Ok(size)
+            }).map_err(|e| (e, base_path))
+        },
+    };
+
+    let size = written.map_err(|(e, output_path)| match errors::code(&e) {
+        Code::BaseMismatch => with_path(base_path, e),
+        Code::Format | Code::Unsupported | Code::ResultMismatch => with_path(patch_path, e),
+        _ => errors::with_context(e, output_path),
+    })?;
+
+    println!("{}: rebuilt from {} and {} ({}{}, checked)", output.unwrap_or(base_path), base_path, patch_path,
+             format_size(size, human_readable), if human_readable { "" } else { " bytes" });
+
+    Ok(())
+}
+
+/// The changes that `sync` made (or, for a dry run, would make).
+#[derive(Default)]
+struct SyncChanges {
+    added: usize,
+    updated: usize,
+    deleted: usize,
+}
+
+impl SyncChanges {
+    fn is_empty(&self) -> bool {
+        self.added + self.updated + self.deleted == 0
+    }
+
+    fn print_summary(&self, dry_run: bool) {
+        let prefix = if dry_run { "would be " } else { "" };
+        println!("{} {}added, {} {}updated, {} {}deleted", self.added, prefix, self.updated, prefix, self.deleted, prefix);
+    }
+}
+
+/// Makes the archive at `args.file` match the files under `dir`: rewrites it with an entry for each
+/// of them, keeping the data of entries whose content is unchanged.
+fn sync_to_archive(args: &CliArgs, dir: &Path, dry_run: bool) -> io::Result<()> {
+    if container::is_wrapped(&args.file) || remote::is_url(&args.file) {
+        let err_msg = format!("{}: is inside of another file or remote, so can't be synced to", args.file);
+        return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+    }
+    if !dir.is_dir() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{}: not a directory", dir.display())));
+    }
+
+    let archive_exists = Path::new(&args.file).exists();
+    let archive_path = if archive_exists { std::fs::canonicalize(&args.file)? } else { PathBuf::new() };
+    let mut files = Vec::new();
+    collect_new_entries(args, dir, &mut Vec::new(), &archive_path, &Ignores::new(args.exclude_vcs), &mut files)?;
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let data = if archive_exists { Some(mmap(&args.file)?) } else { None };
+    let dat = match data {
+        Some(ref data) => Some(parse_dat(&args.file, data)?),
+        None => None,
+    };
+
+    let mut changes = SyncChanges::default();
+    let mut entries = Vec::with_capacity(files.len());
+    let mut synced = HashSet::new();
+    for file in files {
+        let file_path = match file.source {
+            Source::File(ref path) => path.clone(),
+            Source::Data(_) | Source::Packed { .. } => unreachable!(),
+        };
+        synced.insert(names::normalize(&file.name));
+        let is_older = match args.newer_mtime {
+            Some(newer_mtime) => !is_modified_after(&file_path, newer_mtime)?,
+            None => false,
+        };
+
+        let tree_entry = dat.as_ref().and_then(|dat| dat.get(&file.name));
+        let entry = match (dat.as_ref(), tree_entry) {
+            (Some(dat), Some(tree_entry)) => {
+                let packed = dat.data(tree_entry)?;
+                let method = Method::of_entry(tree_entry, packed);
+                let is_unchanged = is_older || (std::fs::metadata(&file_path)?.len() == tree_entry.decompressed_size as u64 &&
+                    std::fs::read(&file_path)? == decompress_with(packed, method)?);
+                if is_unchanged {
+                    // kept as it's stored, so its data isn't recompressed differently
+                    NewEntry {
+                        name: tree_entry.name.to_string(),
+                        source: Source::Packed { data: packed.to_vec(), method, decompressed_size: tree_entry.decompressed_size },
+                        compress: Some(method != Method::Stored),
+                    }
+                } else {
+                    println!("M {}", names::to_slashes(&file.name));
+                    changes.updated += 1;
+                    file
+                }
+            },
+            _ if is_older => continue,
+            _ => {
+                println!("+ {}", names::to_slashes(&file.name));
+                changes.added += 1;
+                file
+            },
+        };
+        entries.push(entry);
+    }
+
+    let mut deleted = HashSet::new();
+    if let Some(ref dat) = dat {
+        for tree_entry in dat.tree_entries() {
+            let path = tree_entry.normalized_path(true);
+            if !synced.contains(&path) && deleted.insert(path) {
+                println!("- {}", tree_entry.normalized_path(false));
+                changes.deleted += 1;
+            }
+        }
+    }
+
+    if changes.is_empty() && archive_exists {
+        println!("{}: up to date", args.file);
+        return Ok(());
+    }
+    changes.print_summary(dry_run);
+    if dry_run {
+        return Ok(());
+    }
+
+    writer::sort_entries(&mut entries, args.sort_entries)?;
+    check_names(args, &entries)?;
+
+    // the archive's settings are kept, and it's only replaced once its successor is complete
+    let extension = dat.as_ref().and_then(|dat| dat.extension().ok().and_then(|extension| extension));
+    let options = WriteOptions {
+        dedup: args.dedup,
+        dedup_algorithm: args.dedup_algorithm,
+        separator: args.write_separator,
+        crc_table: dat.as_ref().is_some_and(|dat| dat.crc_table().ok().and_then(|crcs| crcs).is_some()),
+        dat64: dat.as_ref().is_some_and(|dat| dat.stats().is_dat64),
+        dictionary: args.dictionary,
+        tool: args.tool.clone().or_else(|| extension.as_ref().and_then(Extension::tool).map(String::from)),
+        comment: args.comment.clone().or_else(|| extension.as_ref().and_then(Extension::comment).map(String::from)),
+        max_memory: args.max_memory,
+        ..WriteOptions::default()
+    };
+    drop(dat);
+    drop(data);
+
+    replace_file(Path::new(&args.file), args.write_buffer_size, |part_file| {
+        writer::write_dat(part_file, &entries, &options).map(|_| ())
+    }).map_err(|e| errors::with_context(e, &args.file))
+}
+
+/// Makes the files under `dir` match the archive at `args.file`: writes the entries whose files
+/// are missing or differ, and removes files that aren't in the archive. Of entries that share a
+/// path, the first is synced, as with lookups.
+///
+/// Files are written as extraction writes them, so `--follow-symlinks`, `--sandbox`, `--no-atomic`,
+/// `--mode` and `--dir-mode` apply.
+fn sync_to_dir(args: &CliArgs, dir: &Path, dry_run: bool) -> io::Result<()> {
+    if !dir.is_dir() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{}: not a directory", dir.display())));
+    }
+
+    let data = mmap(&args.file)?;
+    let dat = parse_dat(&args.file, &data)?;
+
+    let entry_paths = dat.tree_entries().iter().map(|tree_entry| {
+        entry_path(tree_entry, args.read_separators, args.absolute_names)
+    }).collect::<io::Result<Vec<PathBuf>>>()?;
+    let mut output_paths = output_paths(dir, entry_paths.into_iter(), args)?;
+
+    let mut synced = HashSet::new();
+    for (tree_entry, output_path) in dat.tree_entries().iter().zip(output_paths.iter_mut()) {
+        if !synced.insert(tree_entry.normalized_path(true)) {
+            *output_path = None;
+        }
+    }
+    // checked before files are compared, too, as comparing reads through them
+    if !args.follow_symlinks {
+        check_symlinks(dir, &output_paths)?;
+    }
+
+    let mut changes = SyncChanges::default();
+    let mut changed_paths: Vec<Option<PathBuf>> = vec![None; output_paths.len()];
+    for (i, tree_entry) in dat.tree_entries().iter().enumerate() {
+        let file_path = match output_paths[i] {
+            Some(ref file_path) => file_path,
+            None => continue,
+        };
+
+        let packed = dat.data(tree_entry)?;
+        let is_present = file_path.is_file();
+        if is_present {
+            let is_unchanged = std::fs::metadata(file_path)?.len() == tree_entry.decompressed_size as u64 &&
+                std::fs::read(file_path)? == read_content(packed, Decoding::of(tree_entry, packed, false))?;
+            if is_unchanged {
+                continue;
+            }
+            println!("M {}", tree_entry.normalized_path(false));
+            changes.updated += 1;
+        } else {
+            println!("+ {}", tree_entry.normalized_path(false));
+            changes.added += 1;
+        }
+        changed_paths[i] = Some(file_path.clone());
+    }
+
+    if !dry_run {
+        let sandbox = if args.sandbox { Some(Sandbox::open(dir)?) } else { None };
+        let sandbox = sandbox.as_ref();
+        let throttle = Throttle::new(args.throttle);
+        create_output_dirs(&changed_paths, args.dir_mode, sandbox)?;
+
+        for (tree_entry, file_path) in dat.tree_entries().iter().zip(changed_paths.iter()) {
+            if let Some(ref file_path) = *file_path {
+                let packed = dat.data(tree_entry)?;
+                write_output_file(file_path, args, &throttle, sandbox, |output_file| {
+                    write_entry(packed, Decoding::of(tree_entry, packed, false), output_file, args.write_buffer_size, args.chunk_size)
+                })?;
+            }
+        }
+    }
+
+    // the archive itself is left alone, if it's in the directory
+    let archive_path = std::fs::canonicalize(container::split_path(&args.file).0).unwrap_or_default();
+    let mut files = Vec::new();
+    collect_new_entries(args, dir, &mut Vec::new(), &archive_path, &Ignores::new(args.exclude_vcs), &mut files)?;
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    for file in files {
+        if synced.contains(&names::normalize(&file.name)) {
+            continue;
+        }
+        println!("- {}", names::to_slashes(&file.name));
+        changes.deleted += 1;
+        if !dry_run {
+            if let Source::File(ref path) = file.source {
+                std::fs::remove_file(path).map_err(|e| errors::with_context(e, path.display()))?;
+            }
+        }
+    }
+
+    if changes.is_empty() {
+        println!("{}: up to date", dir.display());
+    } else {
+        changes.print_summary(dry_run);
+    }
+    Ok(())
+}
+
+fn export_spec(dat_path: &str, output: Option<&str>) -> io::Result<()> {
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+    let spec = Spec::of(&dat)?;
+
+    match output {
+        Some(output) => {
+            let written = File::create(output).and_then(|output_file| {
+                let mut output_file = BufWriter::new(output_file);
+                spec.write_json(&mut output_file)?;
+                output_file.flush()
+            });
+            written.map_err(|e| errors::with_context(e, output))
+        },
+        None => {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            spec.write_json(&mut stdout)?;
+            stdout.flush()
+        },
+    }
+}
+
+fn print_layout(dat_path: &str, problems_only: bool, human_readable: bool) -> io::Result<()> {
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+    let regions = layout::layout(&dat);
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let describe = |entries: &[usize]| -> String {
+        entries.iter().map(|&i| format!("#{} {}", i, dat.tree_entries()[i])).collect::<Vec<_>>().join(", ")
+    };
+
+    for region in regions.iter().filter(|region| !problems_only || region.kind.is_problem()) {
+        write!(stdout, "{}-{}\t{}\t", region.range.start, region.range.end, region.range.len())?;
+        match region.kind {
+            Kind::Entry(i) => writeln!(stdout, "entry\t{}", describe(&[i]))?,
+            Kind::Shared(ref entries) => writeln!(stdout, "shared\t{}", describe(entries))?,
+            Kind::Overlap(ref entries) => writeln!(stdout, "overlap\t{}", describe(entries))?,
+            Kind::Extension => writeln!(stdout, "extension")?,
+            Kind::Gap => writeln!(stdout, "gap")?,
+            Kind::OutOfBounds(ref entries) => writeln!(stdout, "out-of-bounds\t{}", describe(entries))?,
+        }
+    }
+
+    // the region lines keep exact byte counts, as they're offsets and lengths to be acted on
+    let bytes_in = |is_kind: fn(&Kind) -> bool| -> String {
+        format_size(regions.iter().filter(|region| is_kind(&region.kind)).map(|region| region.range.len() as u64).sum(), human_readable)
+    };
+    writeln!(stdout, "{}{} of data section, {} in gaps, {} in overlaps, {} out of bounds",
+             format_size(dat.stats().data_range.len() as u64, human_readable),
+             if human_readable { "" } else { " bytes" },
+             bytes_in(|kind| *kind == Kind::Gap),
+             bytes_in(|kind| matches!(*kind, Kind::Overlap(_))),
+             bytes_in(|kind| matches!(*kind, Kind::OutOfBounds(_))))?;
+
+    Ok(())
+}
+
+fn print_locations(dat_path: &str, offsets: &[usize]) -> io::Result<()> {
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    // each entry is given with how far into its data the offset is
+    let describe = |entries: &[usize], offset: usize| -> String {
+        entries.iter().map(|&i| {
+            let tree_entry = &dat.tree_entries()[i];
+            format!("#{} {} (byte {} of its data)", i, tree_entry, offset - tree_entry.offset)
+        }).collect::<Vec<_>>().join(", ")
+    };
+
+    for &offset in offsets {
+        let location = match layout::locate(&dat, offset) {
+            Some(location) => location,
+            None => {
+                writeln!(stdout, "{}\tpast the end of the archive ({} bytes)", offset, data.len())?;
+                continue;
+            },
+        };
+
+        write!(stdout, "{}\t", offset)?;
+        match location.part {
+            Part::Data => match location.region {
+                Some(ref region) => match region.kind {
+                    Kind::Entry(i) => write!(stdout, "entry\t{}", describe(&[i], offset))?,
+                    Kind::Shared(ref entries) => write!(stdout, "shared\t{}", describe(entries, offset))?,
+                    Kind::Overlap(ref entries) => write!(stdout, "overlap\t{}", describe(entries, offset))?,
+                    Kind::Extension => write!(stdout, "extension")?,
+                    Kind::Gap => write!(stdout, "gap\t{}-{}", region.range.start, region.range.end)?,
+                    Kind::OutOfBounds(_) => unreachable!("out of bounds regions are past the data section"),
+                },
+                None => write!(stdout, "gap")?,
+            },
+            Part::TreeEntry(i, field) => {
+                write!(stdout, "tree entry\t#{} {}: {} (byte {} of its record, {}-{})", i, dat.tree_entries()[i], field.name(),
+                       offset - location.range.start, location.range.start, location.range.end)?
+            },
+            Part::NumFiles => write!(stdout, "num_files field")?,
+            Part::TreeSize => write!(stdout, "tree_size field")?,
+            Part::FileSize => write!(stdout, "file_size field")?,
+            Part::Dat64Magic => write!(stdout, "DAT64 magic number")?,
+        }
+
+        // corrupt entries may claim data past the data section, e.g. in the tree
+        if let Some(Region { kind: Kind::OutOfBounds(ref entries), .. }) = location.region {
+            write!(stdout, "\t(also claimed as data by {})", describe(entries, offset))?;
+        }
+        writeln!(stdout)?;
+    }
+
+    Ok(())
+}
+
+fn cat_entries(dat_path: &str, paths: &[String], entry_indexes: &[usize], occurrence: Option<usize>, filter: &Filter) -> io::Result<()> {
+    if paths.is_empty() && entry_indexes.is_empty() && filter.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "must provide at least one entry path, --glob or --entry-index"));
+    }
+
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+
+    let mut indexes: BTreeSet<usize> = BTreeSet::new();
+    if !paths.is_empty() || !entry_indexes.is_empty() {
+        indexes.extend(select_entry_indexes(&dat, paths, entry_indexes, occurrence)?);
+    }
+    if !filter.is_empty() {
+        indexes.extend(dat.tree_entries().iter().enumerate()
+            .filter(|&(_, tree_entry)| filter.is_selected(tree_entry.name))
+            .map(|(i, _)| i));
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    for i in indexes {
+        let tree_entry = &dat.tree_entries()[i];
+        let entry_data = dat.data(tree_entry)?;
+        match write_entry(entry_data, Decoding::of(tree_entry, entry_data, false), &mut stdout, DEFAULT_WRITE_BUFFER_SIZE, fo2dat::DEFAULT_CHUNK_SIZE) {
+            // e.g. piped into `head`, which has all it wants
+            Err(ref e) if e.kind() == ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        }
+    }
+
+    Ok(())
+}
+
+fn view_entry(dat_path: &str, path: Option<&str>, entry_indexes: &[usize], occurrence: Option<usize>, program: Option<&str>) -> io::Result<()> {
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+
+    let paths: Vec<String> = path.map(String::from).into_iter().collect();
+    let indexes = select_entry_indexes(&dat, &paths, entry_indexes, occurrence)?;
+    if indexes.len() != 1 {
+        return Err(Error::new(ErrorKind::InvalidInput, "can only view one entry at a time"));
+    }
+    let tree_entry = &dat.tree_entries()[indexes[0]];
+
+    // named as the entry is, so that viewers can tell its type by its extension
+    let temp_dir = TempDir::create()?;
+    let temp_name = tree_entry.file_name().filter(|name| !name.is_empty()).unwrap_or("entry");
+    let temp_path = temp_dir.path().join(temp_name);
+    {
+        let mut temp_file = temp_dir.create_file(temp_name)?;
+        let entry_data = dat.data(tree_entry)?;
+        write_entry(entry_data, Decoding::of(tree_entry, entry_data, false), &mut temp_file, DEFAULT_WRITE_BUFFER_SIZE, fo2dat::DEFAULT_CHUNK_SIZE)?;
+    }
+
+    let program = match program {
+        Some(program) => program.to_string(),
+        None => viewer(tree_entry.extension()),
+    };
+    let mut words = program.split_whitespace();
+    let name = words.next().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "the viewer is empty"))?;
+
+    let status = std::process::Command::new(name).args(words).arg(&temp_path).status().map_err(|e| {
+        errors::with_context(e, format!("{}: cannot run", name))
+    })?;
+    if !status.success() {
+        return Err(Error::other(format!("{}: exited with {}", name, status)));
+    }
+
+    Ok(())
+}
+
+/// Returns the program that entries with `extension` are viewed with: the one in
+/// `FO2DAT_VIEWER_<EXT>`, or else the pager.
+fn viewer(extension: Option<&str>) -> String {
+    let configured = extension.and_then(|extension| env::var(format!("FO2DAT_VIEWER_{}", extension.to_ascii_uppercase())).ok());
+    let default_pager = if cfg!(windows) { "more" } else { "less" };
+    configured.or_else(|| env::var("PAGER").ok())
+        .filter(|program| !program.trim().is_empty())
+        .unwrap_or_else(|| default_pager.to_string())
+}
+
+/// Prints the entries of the archive at `dat_path` whose decompressed content is identical to
+/// each of `files`, returning `true` if every file matched at least one.
+fn print_matching_entries(dat_path: &str, files: &[String], algorithm: Algorithm) -> io::Result<bool> {
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    // only entries of the same size as a file can match it, so only they're decompressed and
+    // digested, each at most once
+    let mut entry_digests: HashMap<usize, Vec<u8>> = HashMap::new();
+    let mut all_matched = true;
+
+    for file in files {
+        let content = std::fs::read(file).map_err(|e| errors::with_context(e, file))?;
+        let file_digest = algorithm.digest(&content);
+
+        let candidates: Vec<usize> = dat.tree_entries().iter().enumerate()
+            .filter(|&(i, tree_entry)| tree_entry.decompressed_size == content.len() && !entry_digests.contains_key(&i))
+            .map(|(i, _)| i)
+            .collect();
+        let digests = candidates.par_iter().map(|&i| {
+            let tree_entry = &dat.tree_entries()[i];
+            let mut hasher = algorithm.hasher();
+            let entry_data = dat.data(tree_entry)?;
+            fo2dat::for_each_chunk_with(entry_data, Method::of_entry(tree_entry, entry_data), fo2dat::DEFAULT_CHUNK_SIZE, |chunk| {
+                hasher.update(chunk);
+                Ok(())
+            }).map_err(|e| errors::with_context(e, tree_entry))?;
+            Ok((i, hasher.finish()))
+        }).collect::<io::Result<Vec<_>>>()?;
+        entry_digests.extend(digests);
+
+        let mut matched = false;
+        for (i, tree_entry) in dat.tree_entries().iter().enumerate() {
+            if tree_entry.decompressed_size == content.len() && entry_digests[&i] == file_digest {
+                writeln!(stdout, "{}: #{} {}", file, i, tree_entry)?;
+                matched = true;
+            }
+        }
+
+        if !matched {
+            eprintln!("{}: {}: no entry has identical content", APP_NAME, file);
+            all_matched = false;
+        }
+    }
+
+    Ok(all_matched)
+}
+
+/// Returns `true` if the archive at `dat_path` has entries at all of `paths`.
+fn entries_exist(dat_path: &str, paths: &[String], case_sensitive: bool) -> io::Result<bool> {
+    let data = mmap_metadata(dat_path)?;
+
+    let normalize = |path: &str| if case_sensitive { names::to_slashes(path) } else { names::normalize(path) };
+    let mut missing: HashSet<String> = paths.iter().map(|path| normalize(path)).collect();
+
+    // a single pass over the tree, without indexing it, is all that one lookup needs
+    for tree_entry in fo2dat::iter_tree_refs(&data)? {
+        missing.remove(&tree_entry?.normalized_path(!case_sensitive));
+        if missing.is_empty() {
+            return Ok(true);
+        }
+    }
+
+    Ok(missing.is_empty())
+}
+
+fn hexdump_entries(dat_path: &str, paths: &[String], entry_indexes: &[usize], occurrence: Option<usize>, raw: bool) -> io::Result<()> {
+    const BYTES_PER_LINE: usize = 16;
+
+    let data = mmap(dat_path)?;
+    let dat = parse_dat(dat_path, &data)?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    for i in select_entry_indexes(&dat, paths, entry_indexes, occurrence)? {
+        let tree_entry = &dat.tree_entries()[i];
+        let raw_data = dat.data(tree_entry)?;
+        let content = if raw { raw_data.to_vec() } else { decompress_entry(tree_entry, raw_data)? };
+
+        writeln!(stdout, "#{} {}", i, tree_entry)?;
+        for (line, chunk) in content.chunks(BYTES_PER_LINE).enumerate() {
+            write!(stdout, "{:08x} ", line * BYTES_PER_LINE)?;
+            for column in 0..BYTES_PER_LINE {
+                if column % 8 == 0 {
+                    write!(stdout, " ")?;
+                }
+                match chunk.get(column) {
+                    Some(b) => write!(stdout, "{:02x} ", b)?,
+                    None => write!(stdout, "   ")?,
+                }
+            }
+            let printable: String = chunk.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+            writeln!(stdout, " |{}|", printable)?;
+        }
+        writeln!(stdout, "{:08x}", content.len())?;
+    }
+
+    Ok(())
+}
+
+/// Returns the tree indexes of the entries at `paths` (the `occurrence`th entry with each, or the
+/// first), followed by `entry_indexes`, checking that at least one entry is given and that each
+/// exists.
+fn select_entry_indexes(dat: &Dat, paths: &[String], entry_indexes: &[usize], occurrence: Option<usize>) -> io::Result<Vec<usize>> {
+    if paths.is_empty() && entry_indexes.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "must provide at least one entry path or --entry-index"));
+    }
+
+    let mut indexes = paths.iter().map(|path| {
+        let occurrence = occurrence.unwrap_or(1);
+        dat.indexes_of(path).nth(occurrence - 1).ok_or_else(|| {
+            match dat.indexes_of(path).count() {
+                0 => Error::new(ErrorKind::NotFound, format!("{}: no such entry", path)),
+                count => Error::new(ErrorKind::NotFound, format!("{}: no occurrence {} (the archive has {} entries with this path)", path, occurrence, count)),
+            }
+        })
+    }).collect::<io::Result<Vec<_>>>()?;
+
+    for &i in entry_indexes {
+        if i >= dat.len() {
+            let err_msg = format!("#{}: no such entry (the archive has {} entries)", i, dat.len());
+            return Err(Error::new(ErrorKind::NotFound, err_msg));
+        }
+        indexes.push(i);
+    }
+
+    Ok(indexes)
+}
+
+/// Returns the entries at `paths`, or all entries with `extension` if `paths` is empty.
+fn select_entries<'d, 'a>(dat: &'d Dat<'a>, paths: &[String], extension: &str) -> io::Result<Vec<&'d fo2dat::TreeEntryRef<'a>>> {
+    if paths.is_empty() {
+        Ok(dat.tree_entries().iter()
+            .filter(|tree_entry| tree_entry.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(extension)))
+            .collect())
+    } else {
+        paths.iter().map(|path| {
+            dat.get(path).ok_or_else(|| {
+                Error::new(ErrorKind::NotFound, format!("{}: no such entry", path))
+            })
+        }).collect()
+    }
+}
+
+/// Returns the name referenced by each line of the `.lst` entry at `path`, or no names if the
+/// archive doesn't contain it.
+fn read_lst(dat: &Dat, path: &str) -> io::Result<Vec<String>> {
+    match dat.get(path) {
+        Some(lst_entry) => {
+            let lst_content = decompress_entry(lst_entry, dat.data(lst_entry)?)?;
+            Ok(lst::names(&String::from_utf8_lossy(&lst_content)).map(String::from).collect())
+        },
+        None => Ok(Vec::new()),
+    }
+}
+
+fn list_bos_entries(args: &CliArgs) -> io::Result<()> {
+    let data = mmap(&args.file)?;
+    let bos = Bos::parse(&data)?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let mut entry_paths = Vec::new();
+    let mut occurrences = Occurrences::new(args.occurrence);
+    let selected = bos.entries().iter().enumerate()
+        .filter(|&(i, bos_entry)| is_selected(args, i, &bos_entry.name) && occurrences.select(&bos_entry.name))
+        .map(Ok);
+
+    for listed in limit_listing(selected, args, |(_, bos_entry)| bos_entry.decompressed_size)? {
+        let (i, bos_entry) = listed?;
+        if args.dirs_only {
+            entry_paths.push(bos_entry.components().collect());
+            continue;
+        }
+        if args.show_index {
+            write!(stdout, "#{}\t", i)?;
+        }
+        write_native_path(&mut stdout, bos_entry.components())?;
+        writeln!(stdout)?;
+    }
+
+    if args.dirs_only {
+        write_dirs(&mut stdout, entry_paths)?;
     }
 
     Ok(())
@@ -0,0 +1,216 @@
+//! Parsing of Fallout MAP files, far enough to summarize them.
+//!
+//! All multi-byte numbers are big-endian. A MAP contains, in order:
+//!
+//! - A 236-byte header
+//! - The map's global variables, then its local variables (4 bytes each)
+//! - 100x100 tiles (4 bytes each) for each elevation the map has
+//! - Five lists of scripts (system, spatial, timer, item, critter), stored in blocks of 16
+//! - The map's objects, starting with the total number of objects
+//!
+//! Objects are variable-length (their size depends on their prototype), so they're counted
+//! rather than parsed.
+
+use std::io;
+use std::io::ErrorKind;
+use byteorder::{BigEndian, ByteOrder};
+
+use errors;
+use errors::Code;
+
+/// The maximum number of elevations a map can have.
+pub const NUM_ELEVATIONS: usize = 3;
+
+/// The number of tiles on each elevation.
+pub const TILES_PER_ELEVATION: usize = 100 * 100;
+
+/// The names of the script types, in the order their lists appear in a MAP.
+pub const SCRIPT_TYPE_NAMES: [&str; NUM_SCRIPT_TYPES] = ["system", "spatial", "timer", "item", "critter"];
+
+const NUM_SCRIPT_TYPES: usize = 5;
+const NAME_SIZE: usize = 16;
+const HEADER_SIZE: usize = 236;
+const SCRIPTS_PER_BLOCK: usize = 16;
+const SCRIPT_TYPE_SPATIAL: u32 = 1;
+const SCRIPT_TYPE_TIMER: u32 = 2;
+
+/// Flags that, when set in the header, mean the map lacks the corresponding elevation.
+const ELEVATION_ABSENT_FLAGS: [i32; NUM_ELEVATIONS] = [0x2, 0x4, 0x8];
+
+/// The tile ID the engine uses for "no tile".
+const EMPTY_TILE: u16 = 1;
+
+/// A summary of a MAP file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapInfo {
+    pub header: MapHeader,
+    /// The elevations the map has.
+    pub elevations: Vec<ElevationInfo>,
+    /// The scripts in each list, indexed in the same order as `SCRIPT_TYPE_NAMES`.
+    pub scripts: [Vec<MapScript>; NUM_SCRIPT_TYPES],
+    pub num_objects: usize,
+}
+
+/// The header of a MAP file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapHeader {
+    /// `19` for Fallout 1 maps, `20` for Fallout 2 maps.
+    pub version: u32,
+    pub name: String,
+    pub entering_tile: i32,
+    pub entering_elevation: i32,
+    pub entering_rotation: i32,
+    pub num_local_vars: usize,
+    /// 1-based index of the map's script in `scripts.lst`, or `<= 0` if it has none.
+    pub script_index: i32,
+    pub flags: i32,
+    pub darkness: i32,
+    pub num_global_vars: usize,
+    pub map_index: i32,
+    pub last_visit_time: u32,
+}
+
+/// Tile usage of one elevation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElevationInfo {
+    pub elevation: usize,
+    pub num_floor_tiles: usize,
+    pub num_roof_tiles: usize,
+}
+
+/// A script attached to a map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapScript {
+    pub sid: u32,
+    /// 0-based index of the script in `scripts.lst`.
+    pub script_index: i32,
+}
+
+impl MapHeader {
+    /// Returns the 0-based index of the map's script in `scripts.lst`, if it has one.
+    pub fn script_lst_index(&self) -> Option<usize> {
+        if self.script_index > 0 {
+            Some(self.script_index as usize - 1)
+        } else {
+            None
+        }
+    }
+
+    fn parse(data: &[u8]) -> Self {
+        let name_field = &data[4..4 + NAME_SIZE];
+        let name_len = name_field.iter().position(|&b| b == 0).unwrap_or(NAME_SIZE);
+        let i32_at = |offset: usize| BigEndian::read_i32(&data[offset..]);
+
+        MapHeader {
+            version: BigEndian::read_u32(data),
+            name: String::from_utf8_lossy(&name_field[..name_len]).into_owned(),
+            entering_tile: i32_at(0x14),
+            entering_elevation: i32_at(0x18),
+            entering_rotation: i32_at(0x1c),
+            num_local_vars: i32_at(0x20).max(0) as usize,
+            script_index: i32_at(0x24),
+            flags: i32_at(0x28),
+            darkness: i32_at(0x2c),
+            num_global_vars: i32_at(0x30).max(0) as usize,
+            map_index: i32_at(0x34),
+            last_visit_time: BigEndian::read_u32(&data[0x38..]),
+        }
+    }
+}
+
+impl MapInfo {
+
+    /// Attempts to parse `data` as a MAP file.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader { data, offset: 0 };
+
+        let header = MapHeader::parse(reader.take(HEADER_SIZE, "header")?);
+
+        reader.take(4 * header.num_global_vars, "global variables")?;
+        reader.take(4 * header.num_local_vars, "local variables")?;
+
+        let mut elevations = Vec::new();
+        for (elevation, &absent_flag) in ELEVATION_ABSENT_FLAGS.iter().enumerate() {
+            if header.flags & absent_flag != 0 {
+                continue;
+            }
+
+            let tiles = reader.take(4 * TILES_PER_ELEVATION, "tiles")?;
+            let mut info = ElevationInfo { elevation, num_floor_tiles: 0, num_roof_tiles: 0 };
+            for tile in tiles.chunks(4) {
+                if BigEndian::read_u16(&tile[0..2]) != EMPTY_TILE {
+                    info.num_roof_tiles += 1;
+                }
+                if BigEndian::read_u16(&tile[2..4]) != EMPTY_TILE {
+                    info.num_floor_tiles += 1;
+                }
+            }
+            elevations.push(info);
+        }
+
+        let mut scripts: [Vec<MapScript>; NUM_SCRIPT_TYPES] = Default::default();
+        for list in scripts.iter_mut() {
+            let num_scripts = reader.read_u32("script count")? as usize;
+            let num_blocks = num_scripts.div_ceil(SCRIPTS_PER_BLOCK);
+
+            for block in 0..num_blocks {
+                for slot in 0..SCRIPTS_PER_BLOCK {
+                    let script = reader.read_script()?;
+                    if block * SCRIPTS_PER_BLOCK + slot < num_scripts {
+                        list.push(script);
+                    }
+                }
+                // the block's length and a (runtime-only) pointer to the next block
+                reader.take(8, "script block footer")?;
+            }
+        }
+
+        let num_objects = reader.read_u32("object count")? as usize;
+
+        Ok(MapInfo { header, elevations, scripts, num_objects })
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl <'a> Reader<'a> {
+
+    fn take(&mut self, len: usize, what: &str) -> io::Result<&'a [u8]> {
+        match self.data.get(self.offset..self.offset + len) {
+            Some(taken) => {
+                self.offset += len;
+                Ok(taken)
+            },
+            None => {
+                let err_msg = format!("MAP data ends before its {} (at offset {})", what, self.offset);
+                Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg))
+            }
+        }
+    }
+
+    fn read_u32(&mut self, what: &str) -> io::Result<u32> {
+        self.take(4, what).map(BigEndian::read_u32)
+    }
+
+    fn read_script(&mut self) -> io::Result<MapScript> {
+        // fields common to all scripts, after the type-specific ones
+        const NUM_COMMON_FIELDS: usize = 14;
+
+        let sid = self.read_u32("script")?;
+        self.take(4, "script")?;
+
+        match sid >> 24 {
+            SCRIPT_TYPE_SPATIAL => { self.take(8, "script")?; },
+            SCRIPT_TYPE_TIMER => { self.take(4, "script")?; },
+            _ => {},
+        }
+
+        let common_fields = self.take(4 * NUM_COMMON_FIELDS, "script")?;
+        let script_index = BigEndian::read_i32(&common_fields[4..]);
+
+        Ok(MapScript { sid, script_index })
+    }
+}
@@ -0,0 +1,197 @@
+//! Parsing of Fallout MSG files (dialog and other game text).
+//!
+//! A MSG is a sequence of messages, each of which is three brace-delimited fields:
+//!
+//! ```text
+//! {100}{sound}{The message's text}
+//! ```
+//!
+//! `sound` is usually empty. Anything outside of the braces is ignored by the engine and, by
+//! convention, is used for comments (e.g. lines starting with `#`).
+
+use std::io;
+use std::io::ErrorKind;
+use std::io::Write;
+
+use errors;
+use errors::Code;
+use json;
+
+/// A parsed MSG file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Msg {
+    pub messages: Vec<Message>,
+    /// Comments after the last message.
+    pub trailing_comments: Vec<String>,
+}
+
+/// A single message of a MSG file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub index: i32,
+    pub sound: String,
+    pub text: String,
+    /// Non-blank lines of text found between the previous message and this one.
+    pub comments: Vec<String>,
+}
+
+impl Msg {
+
+    /// Attempts to parse `content` (the already-decoded text of a MSG file).
+    pub fn parse(content: &str) -> io::Result<Self> {
+        const FIELDS_PER_MESSAGE: usize = 3;
+
+        let mut messages = Vec::new();
+        let mut fields: Vec<&str> = Vec::with_capacity(FIELDS_PER_MESSAGE);
+        let mut comments = Vec::new();
+        let mut remaining = content;
+
+        while let Some(field_start) = remaining.find('{') {
+            if fields.is_empty() {
+                comments.extend(comment_lines(&remaining[..field_start]));
+            }
+
+            let field_end = match remaining[field_start..].find('}') {
+                Some(len) => field_start + len,
+                None => {
+                    let err_msg = format!("unterminated field after message {}", messages.len());
+                    return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+                }
+            };
+
+            fields.push(&remaining[field_start+1..field_end]);
+            remaining = &remaining[field_end+1..];
+
+            if fields.len() == FIELDS_PER_MESSAGE {
+                let index = match fields[0].trim().parse() {
+                    Ok(index) => index,
+                    Err(_) => {
+                        let err_msg = format!("{}: message index is not a number", fields[0]);
+                        return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+                    }
+                };
+
+                messages.push(Message {
+                    index,
+                    sound: fields[1].to_string(),
+                    text: fields[2].to_string(),
+                    comments: comments.split_off(0),
+                });
+                fields.clear();
+            }
+        }
+
+        if !fields.is_empty() {
+            let err_msg = format!("incomplete message after message {}: expected {} fields", messages.len(), FIELDS_PER_MESSAGE);
+            return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+        }
+
+        comments.extend(comment_lines(remaining));
+
+        Ok(Msg { messages, trailing_comments: comments })
+    }
+
+    /// Returns the text of the first message with `index`.
+    pub fn get(&self, index: i32) -> Option<&str> {
+        self.messages.iter().find(|message| message.index == index).map(|message| message.text.as_str())
+    }
+
+    /// Writes the MSG back out in its own format, with comments on their own lines before the
+    /// message they preceded.
+    pub fn write_msg<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for message in &self.messages {
+            for comment in &message.comments {
+                writeln!(out, "{}", comment)?;
+            }
+            writeln!(out, "{{{}}}{{{}}}{{{}}}", message.index, message.sound, message.text)?;
+        }
+        for comment in &self.trailing_comments {
+            writeln!(out, "{}", comment)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the MSG as a JSON object containing a `messages` array and `trailing_comments`.
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(b"{\"messages\":")?;
+        json::write_array(out, &self.messages, |out, message| {
+            write!(out, "{{\"index\":{},\"sound\":", message.index)?;
+            json::write_str(out, &message.sound)?;
+            out.write_all(b",\"text\":")?;
+            json::write_str(out, &message.text)?;
+            out.write_all(b",\"comments\":")?;
+            json::write_array(out, &message.comments, |out, comment| json::write_str(out, comment))?;
+            out.write_all(b"}")
+        })?;
+        out.write_all(b",\"trailing_comments\":")?;
+        json::write_array(out, &self.trailing_comments, |out, comment| json::write_str(out, comment))?;
+        out.write_all(b"}\n")
+    }
+
+    /// Writes the MSG as a gettext PO file. Each message's index is its `msgctxt` (so duplicate
+    /// texts stay distinct), and comments become extracted comments (`#.`). Comments after the last
+    /// message, which have no message to belong to, end the file (where PO readers skip them).
+    pub fn write_po<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "msgid \"\"")?;
+        writeln!(out, "msgstr \"Content-Type: text/plain; charset=UTF-8\\n\"")?;
+
+        for message in &self.messages {
+            writeln!(out)?;
+            for comment in &message.comments {
+                writeln!(out, "#. {}", comment)?;
+            }
+            if !message.sound.is_empty() {
+                writeln!(out, "#. sound: {}", message.sound)?;
+            }
+            writeln!(out, "msgctxt \"{}\"", message.index)?;
+            write!(out, "msgid ")?;
+            write_po_str(out, &message.text)?;
+            writeln!(out)?;
+            writeln!(out, "msgstr \"\"")?;
+        }
+
+        if !self.trailing_comments.is_empty() {
+            writeln!(out)?;
+            for comment in &self.trailing_comments {
+                writeln!(out, "#. {}", comment)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn write_po_str<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => {},
+            '\t' => out.write_all(b"\\t")?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+fn comment_lines(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.lines().map(str::trim).filter(|line| !line.is_empty()).map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn po_keeps_every_comment() {
+        let msg = Msg::parse("# greetings\n{100}{}{Hello}\n# end of greetings\n").unwrap();
+        let mut po = Vec::new();
+        msg.write_po(&mut po).unwrap();
+        let po = String::from_utf8(po).unwrap();
+
+        assert!(po.contains("#. # greetings\nmsgctxt \"100\"\nmsgid \"Hello\"\n"), "{}", po);
+        assert!(po.ends_with("msgstr \"\"\n\n#. # end of greetings\n"), "{}", po);
+    }
+}
@@ -0,0 +1,66 @@
+//! Entry names, and when two of them name the same entry.
+//!
+//! Names are stored `\`-separated (e.g. `ART\CRITTERS\HAPOWRAA.FRM`), and the game looks them up
+//! as Windows does. Two names are equivalent if they're equal once normalized, which:
+//!
+//! - treats `\` and `/` as the same separator;
+//! - ignores ASCII case (other characters are compared exactly);
+//! - ignores empty components, so leading, trailing and repeated separators don't count.
+//!
+//! `.` and `..` components aren't resolved: they're compared like any other. Lookups, filters,
+//! dedup and comparisons against directories all use these rules.
+
+/// The separator of components in stored names.
+pub const STORED_SEPARATOR: char = '\\';
+
+/// Returns an iterator over the non-empty components of `name`, split at `\` and `/`.
+pub fn components(name: &str) -> impl Iterator<Item = &str> {
+    name.split(['\\', '/']).filter(|component| !component.is_empty())
+}
+
+/// Returns `name` normalized: lowercase and `/`-separated, without empty components (e.g.
+/// `art/critters/hapowraa.frm`). Equivalent names normalize to the same string.
+pub fn normalize(name: &str) -> String {
+    join(components(name), '/', true)
+}
+
+/// Returns `name` `/`-separated, without empty components, but with its case kept (e.g.
+/// `ART/CRITTERS/HAPOWRAA.FRM`), for display.
+pub fn to_slashes(name: &str) -> String {
+    join(components(name), '/', false)
+}
+
+/// Returns `name` as it's stored: `\`-separated, without empty components, and with its case
+/// kept (e.g. `ART\CRITTERS\HAPOWRAA.FRM`).
+pub fn to_stored(name: &str) -> String {
+    join(components(name), STORED_SEPARATOR, false)
+}
+
+/// Returns `true` if `a` and `b` name the same entry.
+pub fn eq(a: &str, b: &str) -> bool {
+    let mut a = components(a);
+    let mut b = components(b);
+    loop {
+        match (a.next(), b.next()) {
+            (Some(a), Some(b)) if a.eq_ignore_ascii_case(b) => {},
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Joins the non-empty `components` with `separator`, optionally lowercasing them.
+pub(crate) fn join<'a, I: Iterator<Item = &'a str>>(components: I, separator: char, lowercase: bool) -> String {
+    let mut joined = String::new();
+    for component in components.filter(|c| !c.is_empty()) {
+        if !joined.is_empty() {
+            joined.push(separator);
+        }
+        if lowercase {
+            joined.push_str(&component.to_ascii_lowercase());
+        } else {
+            joined.push_str(component);
+        }
+    }
+    joined
+}
@@ -0,0 +1,77 @@
+//! Parsing of Fallout PAL palettes (e.g. `color.pal`).
+//!
+//! A PAL starts with 256 RGB triplets. Each channel is a 6-bit VGA DAC value (`0..=63`), which
+//! the engine scales up to 8 bits by multiplying it by 4. That multiplier is a quirk worth
+//! knowing about: it means the engine never displays a channel brighter than 252, so faithful
+//! exports look slightly darker than a "full range" `0..=255` stretch would.
+//!
+//! Channels above 63 aren't colors at all. The engine treats them as unmapped (they're typically
+//! placeholders for the palette-cycled animation colors), so they're rendered as black.
+
+use std::io;
+use std::io::ErrorKind;
+
+use errors;
+use errors::Code;
+
+/// The number of colors in a palette.
+pub const NUM_COLORS: usize = 256;
+
+/// The size, in bytes, of the colors held by a PAL.
+pub const COLORS_SIZE: usize = NUM_COLORS * 3;
+
+const MAX_CHANNEL_VALUE: u8 = 63;
+
+/// How 6-bit palette channels are scaled up to 8 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaling {
+    /// Multiply each channel by 4, exactly as the engine does (`63` becomes `252`).
+    Engine,
+    /// Stretch `0..=63` over the full `0..=255` range (`63` becomes `255`).
+    Full,
+}
+
+impl Scaling {
+    fn scale(self, value: u8) -> u8 {
+        if value > MAX_CHANNEL_VALUE {
+            return 0;
+        }
+
+        match self {
+            Scaling::Engine => value * 4,
+            Scaling::Full => ((value as u32 * 255 + 31) / 63) as u8,
+        }
+    }
+}
+
+/// A 256-color palette of 8-bit RGB colors.
+#[derive(Clone)]
+pub struct Palette {
+    pub colors: [[u8; 3]; NUM_COLORS],
+}
+
+impl Palette {
+
+    /// Attempts to parse the colors at the start of `data`, as found in `color.pal` and other PAL
+    /// files, scaling them the same way as the engine.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        Palette::parse_with_scaling(data, Scaling::Engine)
+    }
+
+    /// Attempts to parse the colors at the start of `data`, scaling them with `scaling`.
+    pub fn parse_with_scaling(data: &[u8], scaling: Scaling) -> io::Result<Self> {
+        if data.len() < COLORS_SIZE {
+            let err_msg = format!("palette data ({} bytes) is too small to fit {} colors", data.len(), NUM_COLORS);
+            return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+        }
+
+        let mut colors = [[0; 3]; NUM_COLORS];
+        for (color, rgb) in colors.iter_mut().zip(data[..COLORS_SIZE].chunks(3)) {
+            for (channel, &value) in color.iter_mut().zip(rgb) {
+                *channel = scaling.scale(value);
+            }
+        }
+
+        Ok(Palette { colors })
+    }
+}
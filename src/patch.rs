@@ -0,0 +1,606 @@
+//! Patches between two versions of an archive (`.fo2patch` files), so that an update to a mod can
+//! be distributed as what changed rather than as the whole new archive.
+//!
+//! A patch is entry-aware: it rebuilds the new archive's data section blob by blob (a blob being
+//! the packed data of one or more entries), each of which is either copied from the old archive
+//! (when an entry's packed data is unchanged, whatever it's named now), rebuilt from the content
+//! of the old entry with the same name and a delta (see `delta`), or stored whole. The new
+//! archive's tree, and any bytes of its data section that no entry's data covers (e.g. its
+//! extension), are stored whole too.
+//!
+//! ```text
+//! patch   := "FO2PATCH" | version (u32) | old_size (u64) | old_sha256 (32 bytes) | new_size (u64) | new_sha256 (32 bytes) | body
+//! body    := (zlib compressed) data_size (u64) | tail_size (u64) | tail | num_segments (u64) | segment*
+//! segment := op (u8) | len (u64) | ...
+//!   op 0 (copy):   offset (u64), of the `len` bytes in the old archive
+//!   op 1 (diff):   offset (u64) | old_len (u64) | packing | delta_len (u64) | delta
+//!   op 2 (stored): the `len` bytes
+//! packing := 0 (stored) | 1 | level (u8) | zlib header (2 bytes)
+//! ```
+//!
+//! The segments, one after the other, make the new data section (of `data_size` bytes), and the
+//! tail is everything after it (`num_files`, the tree and the footer). A diff segment's blob is
+//! the old blob at `offset` (of `old_len` bytes), decompressed, with the delta applied and, if
+//! it's packed with zlib, compressed at `level` with the given header. The sizes and SHA-256
+//! digests of the old and new archive files check that a patch is applied to the right archive
+//! (which, as the digest is cryptographic, can't be one crafted to pass for it), and that applying
+//! it rebuilt the new one exactly. Multi-byte numbers are little-endian.
+//!
+//! A changed blob is only diffed if recompressing its content reproduces it exactly, which it
+//! does for blobs fo2dat wrote (at its default level), but mightn't for those that other tools
+//! wrote; other changed blobs are stored whole.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::ops::Range;
+use byteorder::{LittleEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use rayon::prelude::*;
+
+use delta;
+use digest::{self, Sha256};
+use errors;
+use errors::Code;
+use writer;
+use {decompress, entries, is_zlib_compressed, stats};
+
+/// The magic number that starts a patch.
+pub const MAGIC: &[u8; 8] = b"FO2PATCH";
+
+/// The version of the patch format that's written, and the only one that's read. (Version 1
+/// patches identified archives by CRC-32, rather than SHA-256.)
+pub const VERSION: u32 = 2;
+
+/// The largest content (old or new) that's diffed. Finding a delta takes many times the old
+/// content's size in memory (see `delta::diff`), per worker, so larger blobs are stored whole.
+pub const MAX_DIFF_SIZE: usize = 8 * 1024 * 1024;
+
+/// The zlib levels that a changed blob's content is recompressed at to try to reproduce it: the
+/// level fo2dat writes at, and zlib's default.
+const REPRODUCIBLE_LEVELS: &[u32] = &[writer::BEST_LEVEL, 6];
+
+/// The zlib level that deltas are compressed at to judge whether they're smaller than the blobs
+/// they rebuild.
+const DELTA_LEVEL: u32 = 6;
+
+const OP_COPY: u8 = 0;
+const OP_DIFF: u8 = 1;
+const OP_STORED: u8 = 2;
+
+/// The number of blobs per worker thread that `create` encodes at once.
+const ENCODED_PER_THREAD: usize = 2;
+
+/// The size of a patch's header.
+const HEADER_SIZE: usize = 8 + 4 + 8 + 32 + 8 + 32;
+
+/// The header of a patch, which identifies the archives it's between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatchHeader {
+    pub version: u32,
+    /// The size and SHA-256 digest of the archive that the patch is applied to.
+    pub old_size: u64,
+    pub old_sha256: [u8; 32],
+    /// The size and SHA-256 digest of the archive that applying the patch rebuilds.
+    pub new_size: u64,
+    pub new_sha256: [u8; 32],
+}
+
+impl PatchHeader {
+
+    /// Parses the header at the start of `patch`, failing if it isn't a patch, or is of a version
+    /// this can't read.
+    pub fn parse(patch: &[u8]) -> io::Result<Self> {
+        if patch.len() < 12 || !patch.starts_with(MAGIC) {
+            return Err(errors::new(Code::Format, ErrorKind::InvalidData, "not a patch: doesn't start with FO2PATCH"));
+        }
+
+        let version = LittleEndian::read_u32(&patch[8..]);
+        if version != VERSION {
+            let err_msg = if version > VERSION {
+                format!("patch is of version {}, newer than the version {} this reads", version, VERSION)
+            } else {
+                format!("patch is of version {}, which identifies archives by CRC-32 and is no longer read: create it again", version)
+            };
+            return Err(errors::new(Code::Unsupported, ErrorKind::InvalidData, err_msg));
+        }
+        if patch.len() < HEADER_SIZE {
+            return Err(malformed("its header is truncated"));
+        }
+
+        let digest_at = |offset: usize| {
+            let mut digest = [0; 32];
+            digest.copy_from_slice(&patch[offset..offset + 32]);
+            digest
+        };
+        Ok(PatchHeader {
+            version,
+            old_size: LittleEndian::read_u64(&patch[12..]),
+            old_sha256: digest_at(20),
+            new_size: LittleEndian::read_u64(&patch[52..]),
+            new_sha256: digest_at(60),
+        })
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_u32::<LittleEndian>(self.version)?;
+        out.write_u64::<LittleEndian>(self.old_size)?;
+        out.write_all(&self.old_sha256)?;
+        out.write_u64::<LittleEndian>(self.new_size)?;
+        out.write_all(&self.new_sha256)
+    }
+}
+
+/// A summary of a created patch, by how the new archive's blobs are rebuilt.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PatchSummary {
+    /// The number of blobs copied from the old archive.
+    pub num_copied: usize,
+    /// The number of blobs rebuilt from an old entry's content and a delta.
+    pub num_diffed: usize,
+    /// The number of blobs stored whole.
+    pub num_stored: usize,
+}
+
+/// How a blob's content is packed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Packing {
+    Stored,
+    Zlib { level: u32, header: [u8; 2] },
+}
+
+/// A run of the new data section, and how it's rebuilt.
+enum Segment<'a> {
+    Copy { offset: usize, len: usize },
+    Diff { offset: usize, old_len: usize, packing: Packing, len: usize, delta: Vec<u8> },
+    Stored(&'a [u8]),
+}
+
+/// A run of the new data section to encode: an entry's blob, or bytes that no entry's data covers.
+enum Run<'a> {
+    Blob { range: Range<usize>, name: String },
+    Gap(&'a [u8]),
+}
+
+/// The old archive's blobs, by the name of (the first) entry they're the data of, and by size.
+struct OldBlobs<'a> {
+    data: &'a [u8],
+    by_name: HashMap<String, Range<usize>>,
+    by_len: HashMap<usize, Vec<usize>>,
+}
+
+impl <'a> OldBlobs<'a> {
+    fn new(old_data: &'a [u8]) -> io::Result<Self> {
+        let mut blobs = OldBlobs { data: old_data, by_name: HashMap::new(), by_len: HashMap::new() };
+
+        // the data section starts the archive, so entries' offsets are offsets into it; entries
+        // whose data is out of bounds are left out, as if they weren't there
+        for entry in entries(old_data)? {
+            let entry = entry?;
+            if entry.packed_data().is_err() {
+                continue;
+            }
+            let range = entry.offset..entry.offset + entry.packed_size;
+            let offsets = blobs.by_len.entry(range.len()).or_default();
+            if !offsets.contains(&range.start) {
+                offsets.push(range.start);
+            }
+            blobs.by_name.entry(entry.normalized_path(true)).or_insert(range);
+        }
+
+        Ok(blobs)
+    }
+
+    /// Returns the offset of an old blob identical to `packed`, if there is one, preferring the
+    /// blob of the entry with the same name.
+    fn find_identical(&self, packed: &[u8], name: &str) -> Option<usize> {
+        if let Some(range) = self.by_name.get(name) {
+            if &self.data[range.clone()] == packed {
+                return Some(range.start);
+            }
+        }
+
+        self.by_len.get(&packed.len())?.iter().cloned().find(|&offset| &self.data[offset..offset + packed.len()] == packed)
+    }
+}
+
+/// Writes a patch that turns the archive `old_data` into the archive `new_data` to `out`, for
+/// `apply` to apply.
+pub fn create<W: Write>(out: &mut W, old_data: &[u8], new_data: &[u8]) -> io::Result<PatchSummary> {
+    let old_blobs = OldBlobs::new(old_data)?;
+    let new_stats = stats(new_data)?;
+    let data_section = &new_data[new_stats.data_range.clone()];
+
+    // blobs shared between entries (or overlapping) are rebuilt once, in the order they're in
+    let mut blob_ranges = Vec::new();
+    for entry in entries(new_data)? {
+        let entry = entry?;
+        if entry.packed_size > 0 && entry.packed_data().is_ok() {
+            blob_ranges.push((entry.offset..entry.offset + entry.packed_size, entry.normalized_path(true)));
+        }
+    }
+    blob_ranges.sort_by_key(|(range, _)| (range.start, !range.end));
+
+    let mut runs = Vec::new();
+    let mut covered_to = 0;
+    for (range, name) in blob_ranges {
+        if range.start >= covered_to {
+            if range.start > covered_to {
+                runs.push(Run::Gap(&data_section[covered_to..range.start]));
+            }
+            covered_to = range.end;
+            runs.push(Run::Blob { range, name });
+        } else if range.end > covered_to {
+            runs.push(Run::Gap(&data_section[covered_to..range.end]));
+            covered_to = range.end;
+        }
+    }
+    if covered_to < data_section.len() {
+        runs.push(Run::Gap(&data_section[covered_to..]));
+    }
+
+    let header = PatchHeader {
+        version: VERSION,
+        old_size: old_data.len() as u64,
+        old_sha256: sha256(old_data),
+        new_size: new_data.len() as u64,
+        new_sha256: sha256(new_data),
+    };
+    header.write(out)?;
+
+    let mut body = ZlibEncoder::new(out, Compression::best());
+    let tail = &new_data[new_stats.data_range.end..];
+    body.write_u64::<LittleEndian>(data_section.len() as u64)?;
+    body.write_u64::<LittleEndian>(tail.len() as u64)?;
+    body.write_all(tail)?;
+    body.write_u64::<LittleEndian>(runs.len() as u64)?;
+
+    // runs are encoded on the worker pool a window at a time, then written in order, so at most
+    // a window's deltas are held in memory at once
+    let mut summary = PatchSummary::default();
+    let window_len = rayon::current_num_threads() * ENCODED_PER_THREAD;
+    for window in runs.chunks(window_len) {
+        let segments = window.par_iter().map(|run| match *run {
+            Run::Blob { ref range, ref name } => encode_blob(&old_blobs, &data_section[range.clone()], name),
+            Run::Gap(bytes) => Ok(Segment::Stored(bytes)),
+        }).collect::<io::Result<Vec<_>>>()?;
+
+        for (run, segment) in window.iter().zip(segments) {
+            if let Run::Blob { .. } = *run {
+                match segment {
+                    Segment::Copy { .. } => summary.num_copied += 1,
+                    Segment::Diff { .. } => summary.num_diffed += 1,
+                    Segment::Stored(_) => summary.num_stored += 1,
+                }
+            }
+            write_segment(&mut body, &segment)?;
+        }
+    }
+
+    body.finish()?.flush()?;
+    Ok(summary)
+}
+
+/// Returns how the new archive's blob `packed`, the data of the entry (normalized) `name`, is
+/// rebuilt: by copying an identical old blob, by diffing the old content of the same name, or
+/// else by storing it whole.
+fn encode_blob<'a>(old_blobs: &OldBlobs, packed: &'a [u8], name: &str) -> io::Result<Segment<'a>> {
+    if let Some(offset) = old_blobs.find_identical(packed, name) {
+        return Ok(Segment::Copy { offset, len: packed.len() });
+    }
+
+    let old_range = match old_blobs.by_name.get(name) {
+        Some(old_range) => old_range.clone(),
+        None => return Ok(Segment::Stored(packed)),
+    };
+
+    // content that can't be reproduced (or read) is stored whole, rather than failing the patch
+    let (packing, content) = match reproducible_content(packed) {
+        Some(reproducible) => reproducible,
+        None => return Ok(Segment::Stored(packed)),
+    };
+    let old_content = match decompress(&old_blobs.data[old_range.clone()]) {
+        Ok(old_content) if old_content.len() <= MAX_DIFF_SIZE => old_content,
+        _ => return Ok(Segment::Stored(packed)),
+    };
+
+    // the delta is mostly zeros, so what it'll cost is judged by its compressed size
+    let delta = delta::diff(&old_content, &content);
+    if writer::compress(&delta, DELTA_LEVEL)?.len() >= packed.len() {
+        return Ok(Segment::Stored(packed));
+    }
+
+    Ok(Segment::Diff { offset: old_range.start, old_len: old_range.len(), packing, len: packed.len(), delta })
+}
+
+/// Returns the content of the blob `packed` and how it's packed, if packing the content again
+/// reproduces the blob exactly and it isn't too large to diff.
+fn reproducible_content(packed: &[u8]) -> Option<(Packing, Vec<u8>)> {
+    if !is_zlib_compressed(packed) {
+        return if packed.len() <= MAX_DIFF_SIZE { Some((Packing::Stored, packed.to_vec())) } else { None };
+    }
+
+    let content = decompress(packed).ok().filter(|content| content.len() <= MAX_DIFF_SIZE)?;
+    let header = [packed[0], packed[1]];
+    for &level in REPRODUCIBLE_LEVELS {
+        let compressed = writer::compress(&content, level).ok()?;
+        if compressed[2..] == packed[2..] {
+            return Some((Packing::Zlib { level, header }, content));
+        }
+    }
+    None
+}
+
+/// Writes the archive that applying `patch` to the archive `old_data` rebuilds to `out`, returning
+/// its size.
+///
+/// Fails with `Code::BaseMismatch`, having written nothing, if `old_data` isn't the archive that
+/// the patch was made against, and with `Code::ResultMismatch` if the archive written isn't the
+/// one it was made from (which shouldn't be kept).
+pub fn apply<W: Write>(out: &mut W, old_data: &[u8], patch: &[u8]) -> io::Result<u64> {
+    let header = PatchHeader::parse(patch)?;
+
+    let old_sha256 = sha256(old_data);
+    if old_data.len() as u64 != header.old_size || old_sha256 != header.old_sha256 {
+        let err_msg = format!("isn't the archive the patch was made against: it's {} bytes with SHA-256 {}, but the patch is for {} bytes with SHA-256 {}",
+                              old_data.len(), digest::to_hex(&old_sha256), header.old_size, digest::to_hex(&header.old_sha256));
+        return Err(errors::new(Code::BaseMismatch, ErrorKind::InvalidInput, err_msg));
+    }
+
+    let mut out = Sha256Writer { inner: out, sha256: Sha256::new() };
+    let size = rebuild(Body(ZlibDecoder::new(&patch[HEADER_SIZE..])), &mut out, old_data).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => malformed("ends early (is it truncated?)"),
+        _ => e,
+    })?;
+
+    let new_sha256 = out.sha256.finish();
+    if size != header.new_size || new_sha256 != header.new_sha256 {
+        let err_msg = format!("the rebuilt archive is {} bytes with SHA-256 {}, but the patch was made from {} bytes with SHA-256 {}",
+                              size, digest::to_hex(&new_sha256), header.new_size, digest::to_hex(&header.new_sha256));
+        return Err(errors::new(Code::ResultMismatch, ErrorKind::InvalidData, err_msg));
+    }
+
+    Ok(size)
+}
+
+/// Writes the archive that the patch's `body` rebuilds from `old_data` to `out`, returning its size.
+fn rebuild<R: Read, W: Write>(mut body: R, out: &mut W, old_data: &[u8]) -> io::Result<u64> {
+    let data_size = body.read_u64::<LittleEndian>()?;
+    let tail_size = body.read_u64::<LittleEndian>()?;
+    let tail = read_run(&mut body, tail_size)?;
+    let num_segments = body.read_u64::<LittleEndian>()?;
+
+    let old_blob = |offset: u64, len: u64| {
+        let end = offset.checked_add(len).filter(|&end| end <= old_data.len() as u64);
+        match end {
+            Some(end) => Ok(&old_data[offset as usize..end as usize]),
+            None => Err(malformed(&format!("copies {} bytes at {}, out of bounds of the base archive", len, offset))),
+        }
+    };
+
+    let mut written = 0u64;
+    for _ in 0..num_segments {
+        let op = body.read_u8()?;
+        let len = body.read_u64::<LittleEndian>()?;
+
+        match op {
+            OP_COPY => {
+                let offset = body.read_u64::<LittleEndian>()?;
+                out.write_all(old_blob(offset, len)?)?;
+            },
+            OP_DIFF => {
+                let offset = body.read_u64::<LittleEndian>()?;
+                let old_len = body.read_u64::<LittleEndian>()?;
+                let packing = match body.read_u8()? {
+                    0 => Packing::Stored,
+                    1 => {
+                        let level = body.read_u8()? as u32;
+                        let mut header = [0; 2];
+                        body.read_exact(&mut header)?;
+                        Packing::Zlib { level: level.min(writer::BEST_LEVEL), header }
+                    },
+                    packing => return Err(malformed(&format!("unknown packing ({})", packing))),
+                };
+                let delta_len = body.read_u64::<LittleEndian>()?;
+                let delta = read_run(&mut body, delta_len)?;
+
+                let content = delta::apply(&decompress(old_blob(offset, old_len)?)?, &delta)?;
+                let packed = match packing {
+                    Packing::Stored => content,
+                    Packing::Zlib { level, header } => {
+                        let mut compressed = writer::compress(&content, level)?;
+                        compressed[..2].copy_from_slice(&header);
+                        compressed
+                    },
+                };
+
+                // zlib implementations (or versions of one) may compress the same content differently
+                if packed.len() as u64 != len {
+                    let err_msg = format!("a rebuilt blob is {} bytes, rather than {} (was the patch made by a build of fo2dat that compresses differently?)", packed.len(), len);
+                    return Err(errors::new(Code::ResultMismatch, ErrorKind::InvalidData, err_msg));
+                }
+                out.write_all(&packed)?;
+            },
+            OP_STORED => {
+                if io::copy(&mut (&mut body).take(len), out)? != len {
+                    return Err(io::Error::from(ErrorKind::UnexpectedEof));
+                }
+            },
+            op => return Err(malformed(&format!("unknown segment ({})", op))),
+        }
+
+        written += len;
+    }
+
+    if written != data_size {
+        return Err(malformed(&format!("its segments rebuild {} bytes of data, rather than {}", written, data_size)));
+    }
+    out.write_all(&tail)?;
+
+    Ok(written + tail_size)
+}
+
+/// A reader of a patch's body, whose errors (e.g. of decompressing it) are the patch's.
+struct Body<R>(R);
+
+impl <R: Read> Read for Body<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|e| malformed(&e.to_string()))
+    }
+}
+
+/// Reads `len` bytes from `reader`, which may not have them (so they're read, rather than
+/// allocated up front).
+fn read_run<R: Read>(reader: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut run = Vec::new();
+    if reader.take(len).read_to_end(&mut run)? as u64 != len {
+        return Err(io::Error::from(ErrorKind::UnexpectedEof));
+    }
+    Ok(run)
+}
+
+fn malformed(problem: &str) -> io::Error {
+    errors::new(Code::Format, ErrorKind::InvalidData, format!("malformed patch: {}", problem))
+}
+
+fn write_segment<W: Write>(out: &mut W, segment: &Segment) -> io::Result<()> {
+    match *segment {
+        Segment::Copy { offset, len } => {
+            out.write_u8(OP_COPY)?;
+            out.write_u64::<LittleEndian>(len as u64)?;
+            out.write_u64::<LittleEndian>(offset as u64)
+        },
+        Segment::Diff { offset, old_len, packing, len, ref delta } => {
+            out.write_u8(OP_DIFF)?;
+            out.write_u64::<LittleEndian>(len as u64)?;
+            out.write_u64::<LittleEndian>(offset as u64)?;
+            out.write_u64::<LittleEndian>(old_len as u64)?;
+            match packing {
+                Packing::Stored => out.write_u8(0)?,
+                Packing::Zlib { level, header } => {
+                    out.write_u8(1)?;
+                    out.write_u8(level as u8)?;
+                    out.write_all(&header)?;
+                },
+            }
+            out.write_u64::<LittleEndian>(delta.len() as u64)?;
+            out.write_all(delta)
+        },
+        Segment::Stored(bytes) => {
+            out.write_u8(OP_STORED)?;
+            out.write_u64::<LittleEndian>(bytes.len() as u64)?;
+            out.write_all(bytes)
+        },
+    }
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut sha256 = Sha256::new();
+    sha256.update(data);
+    sha256.finish()
+}
+
+/// A writer that computes the SHA-256 digest of what's written through it.
+struct Sha256Writer<W> {
+    inner: W,
+    sha256: Sha256,
+}
+
+impl <W: Write> Write for Sha256Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.sha256.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil;
+    use writer::WriteOptions;
+
+    /// Returns incompressible content (so it's stored, and a small change to it is diffed).
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        let mut noise: Vec<u8> = (0..len).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        }).collect();
+        // stored content mustn't look compressed
+        noise[0] = 0;
+        noise
+    }
+
+    /// Returns an old and a new archive: with an unchanged entry, an entry changed a little, and
+    /// (only in the new one) a new entry.
+    fn archives() -> (Vec<u8>, Vec<u8>) {
+        let unchanged = testutil::entry("text/readme.txt", &noise(2048, 1));
+        let old_changed = noise(4096, 2);
+        let mut new_changed = old_changed.clone();
+        new_changed[100..110].copy_from_slice(b"0123456789");
+
+        let options = WriteOptions::default();
+        let old = testutil::build(&[unchanged.clone(), testutil::entry("data/changed.dat", &old_changed)], &options).unwrap();
+        let new = testutil::build(&[
+            unchanged,
+            testutil::entry("data/changed.dat", &new_changed),
+            testutil::entry("data/new.dat", &noise(1024, 3)),
+        ], &options).unwrap();
+        (old, new)
+    }
+
+    #[test]
+    fn blobs_are_copied_diffed_or_stored() {
+        let (old, new) = archives();
+        let mut patch = Vec::new();
+        let summary = create(&mut patch, &old, &new).unwrap();
+        assert_eq!(summary, PatchSummary { num_copied: 1, num_diffed: 1, num_stored: 1 });
+
+        let header = PatchHeader::parse(&patch).unwrap();
+        assert_eq!(header.old_size, old.len() as u64);
+        assert_eq!(header.new_size, new.len() as u64);
+    }
+
+    #[test]
+    fn applying_a_patch_rebuilds_the_new_archive() {
+        let (old, new) = archives();
+        let mut patch = Vec::new();
+        create(&mut patch, &old, &new).unwrap();
+
+        let mut rebuilt = Vec::new();
+        assert_eq!(apply(&mut rebuilt, &old, &patch).unwrap(), new.len() as u64);
+        assert_eq!(rebuilt, new);
+    }
+
+    #[test]
+    fn patches_are_only_applied_to_their_base() {
+        let (old, new) = archives();
+        let mut patch = Vec::new();
+        create(&mut patch, &old, &new).unwrap();
+
+        let mut rebuilt = Vec::new();
+        let err = apply(&mut rebuilt, &new, &patch).unwrap_err();
+        assert_eq!(errors::code(&err), Code::BaseMismatch);
+        assert!(rebuilt.is_empty());
+    }
+
+    #[test]
+    fn truncated_patches_are_malformed() {
+        let (old, new) = archives();
+        let mut patch = Vec::new();
+        create(&mut patch, &old, &new).unwrap();
+
+        for &len in &[HEADER_SIZE / 2, HEADER_SIZE + (patch.len() - HEADER_SIZE) / 2] {
+            let err = apply(&mut Vec::new(), &old, &patch[..len]).unwrap_err();
+            assert_eq!(errors::code(&err), Code::Format);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! A minimal PNG encoder for paletted images, which is all that's needed to export Fallout's
+//! 8-bit graphics.
+
+use std::io;
+use std::io::{ErrorKind, Write};
+use byteorder::{BigEndian, WriteBytesExt};
+use flate2::Compression;
+use flate2::Crc;
+use flate2::write::ZlibEncoder;
+
+use errors;
+use errors::Code;
+use pal::Palette;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const BIT_DEPTH: u8 = 8;
+const COLOR_TYPE_INDEXED: u8 = 3;
+const FILTER_NONE: u8 = 0;
+
+/// Writes `pixels` (row-major palette indices, `width * height` long) as a paletted PNG.
+/// `transparent_index`, if supplied, is made fully transparent. Fails if the image is empty (of
+/// zero width or height), which PNGs can't be.
+pub fn write_indexed<W: Write>(out: &mut W, width: usize, height: usize, pixels: &[u8], palette: &Palette, transparent_index: Option<u8>) -> io::Result<()> {
+    assert_eq!(pixels.len(), width * height);
+    if width == 0 || height == 0 {
+        let err_msg = format!("a {}x{} image can't be written as a PNG", width, height);
+        return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+    }
+
+    out.write_all(&SIGNATURE)?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.write_u32::<BigEndian>(width as u32)?;
+    ihdr.write_u32::<BigEndian>(height as u32)?;
+    // bit depth, color type, compression method, filter method, interlace method
+    ihdr.extend_from_slice(&[BIT_DEPTH, COLOR_TYPE_INDEXED, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &ihdr)?;
+
+    let plte: Vec<u8> = palette.colors.iter().flat_map(|rgb| rgb.iter().cloned()).collect();
+    write_chunk(out, b"PLTE", &plte)?;
+
+    if let Some(transparent_index) = transparent_index {
+        let trns: Vec<u8> = (0..=transparent_index).map(|i| if i == transparent_index { 0 } else { 0xff }).collect();
+        write_chunk(out, b"tRNS", &trns)?;
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    if width > 0 {
+        for row in pixels.chunks(width) {
+            encoder.write_all(&[FILTER_NONE])?;
+            encoder.write_all(row)?;
+        }
+    }
+    write_chunk(out, b"IDAT", &encoder.finish()?)?;
+
+    write_chunk(out, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(out: &mut W, chunk_type: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_u32::<BigEndian>(data.len() as u32)?;
+    out.write_all(chunk_type)?;
+    out.write_all(data)?;
+
+    let mut crc = Crc::new();
+    crc.update(chunk_type);
+    crc.update(data);
+    out.write_u32::<BigEndian>(crc.sum())
+}
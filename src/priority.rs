@@ -0,0 +1,142 @@
+//! Lowering of the process's scheduling priority, so that long-running work (e.g. extracting a
+//! whole archive in the background) yields CPU time and disk bandwidth to other programs.
+//!
+//! Priorities are inherited by threads created afterwards, so this should be called before any
+//! worker threads are started.
+
+use std::io;
+#[cfg(unix)]
+use libc;
+
+/// The most that priority can be lowered by: niceness 19, as with `nice`.
+pub const MAX_NICE: u8 = 19;
+
+/// Lowers the CPU and (where supported) IO priority of the calling thread, and of threads it
+/// creates afterwards, to the lowest available.
+pub fn lower() -> io::Result<()> {
+    sys::lower()
+}
+
+/// Lowers the CPU and (where supported) IO priority of the calling thread, and of threads it
+/// creates afterwards, to niceness `nice` (1 to `MAX_NICE`), as `nice -n` does. The IO priority
+/// is lowered in proportion, within the normal (best-effort) class, as `ionice` does by default.
+/// Priority that's already lower is left as it is.
+pub fn nice(nice: u8) -> io::Result<()> {
+    if nice == 0 || nice > MAX_NICE {
+        let err_msg = format!("{}: invalid niceness (must be 1-{})", nice, MAX_NICE);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
+    }
+    sys::nice(nice)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use libc;
+
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_BE: libc::c_int = 2;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+    pub fn lower() -> io::Result<()> {
+        // with a `who` of 0, both apply to the calling thread, which new threads inherit from
+        // (`which`'s type differs between libcs)
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, 19) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // the idle class only gets disk time when no other process wants it
+        let ioprio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    pub fn nice(nice: u8) -> io::Result<()> {
+        super::unix_nice(nice)?;
+
+        // best-effort levels go from 0 (highest) to 7, and niceness maps onto them as the kernel
+        // maps it when no IO priority is set
+        let ioprio = (IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT) | ((nice as libc::c_int + 20) / 5);
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+/// Sets the calling thread's niceness to `nice`, unless it's already nicer.
+#[cfg(unix)]
+fn unix_nice(nice: u8) -> io::Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice as libc::c_int) } != 0 {
+        let e = io::Error::last_os_error();
+        // only privileged processes may raise their priority, which is what lowering their
+        // niceness would be
+        if e.kind() != io::ErrorKind::PermissionDenied {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+mod sys {
+    use std::io;
+    use libc;
+
+    // IO priority isn't portably settable, but most schedulers weigh it by CPU priority anyway
+    pub fn lower() -> io::Result<()> {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, 19) } != 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn nice(nice: u8) -> io::Result<()> {
+        super::unix_nice(nice)
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::io;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, SetPriorityClass};
+    use winapi::um::winbase::{BELOW_NORMAL_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, PROCESS_MODE_BACKGROUND_BEGIN};
+
+    // background mode lowers CPU, IO and memory priority together
+    pub fn lower() -> io::Result<()> {
+        if unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) } == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    // Windows has only two priority classes below normal, which niceness is split between
+    pub fn nice(nice: u8) -> io::Result<()> {
+        let class = if nice < 10 { BELOW_NORMAL_PRIORITY_CLASS } else { IDLE_PRIORITY_CLASS };
+        if unsafe { SetPriorityClass(GetCurrentProcess(), class) } == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use std::io;
+
+    pub fn lower() -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Other, "lowering priority isn't supported on this platform"))
+    }
+
+    pub fn nice(_nice: u8) -> io::Result<()> {
+        lower()
+    }
+}
@@ -0,0 +1,397 @@
+//! Parsing of Fallout PRO prototypes (the static definitions of items, critters, scenery, etc.).
+//!
+//! All numbers are big-endian. Every prototype starts with the same 24-byte header:
+//!
+//! ```text
+//! pid | text id | fid | light radius | light intensity | flags
+//! ```
+//!
+//! The top byte of the pid is the prototype's type, which decides what follows. The rest is its
+//! ID, which is its (1-based) line number in the type's `.lst` file (e.g. `proto/items/items.lst`).
+//! Only items and critters are parsed beyond the header.
+
+use std::io;
+use std::io::ErrorKind;
+use std::io::Write;
+use byteorder::{BigEndian, ByteOrder};
+
+use errors;
+use errors::Code;
+use json;
+
+/// The names of the prototype types, indexed by type.
+pub const TYPE_NAMES: [&str; 6] = ["item", "critter", "scenery", "wall", "tile", "misc"];
+
+/// The names of the item types, indexed by type.
+pub const ITEM_TYPE_NAMES: [&str; 7] = ["armor", "container", "drug", "weapon", "ammo", "misc", "key"];
+
+/// The names of the damage types, in the order damage thresholds and resistances are stored.
+pub const DAMAGE_TYPE_NAMES: [&str; 7] = ["normal", "laser", "fire", "plasma", "electrical", "emp", "explosion"];
+
+/// The names of a critter's stats, in the order they're stored.
+pub const STAT_NAMES: [&str; NUM_STATS] = [
+    "strength", "perception", "endurance", "charisma", "intelligence", "agility", "luck",
+    "max_hit_points", "max_action_points", "armor_class", "unarmed_damage", "melee_damage",
+    "carry_weight", "sequence", "healing_rate", "critical_chance", "better_criticals",
+    "dt_normal", "dt_laser", "dt_fire", "dt_plasma", "dt_electrical", "dt_emp", "dt_explosion",
+    "dr_normal", "dr_laser", "dr_fire", "dr_plasma", "dr_electrical", "dr_emp", "dr_explosion",
+    "radiation_resistance", "poison_resistance", "age", "gender",
+];
+
+/// The names of a critter's skills, in the order they're stored.
+pub const SKILL_NAMES: [&str; NUM_SKILLS] = [
+    "small_guns", "big_guns", "energy_weapons", "unarmed", "melee_weapons", "throwing",
+    "first_aid", "doctor", "sneak", "lockpick", "steal", "traps", "science", "repair",
+    "speech", "barter", "gambling", "outdoorsman",
+];
+
+const NUM_STATS: usize = 35;
+const NUM_SKILLS: usize = 18;
+const NUM_DAMAGE_TYPES: usize = 7;
+const HEADER_SIZE: usize = 0x18;
+const ITEM_HEADER_SIZE: usize = 0x39;
+const ARMOR_SIZE: usize = 0x81;
+const WEAPON_SIZE: usize = 0x7a;
+/// Fallout 1 critters lack the trailing damage type.
+const CRITTER_SIZE_WITHOUT_DAMAGE_TYPE: usize = 0x19c;
+
+const TYPE_ITEM: u32 = 0;
+const TYPE_CRITTER: u32 = 1;
+const ITEM_TYPE_ARMOR: i32 = 0;
+const ITEM_TYPE_WEAPON: i32 = 3;
+
+/// A parsed prototype.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pro {
+    pub pid: u32,
+    /// The index of the prototype's name in its type's MSG file (e.g. `pro_item.msg`). Its
+    /// description is at `text_id + 1`.
+    pub text_id: i32,
+    pub fid: i32,
+    pub light_radius: i32,
+    pub light_intensity: i32,
+    pub flags: u32,
+    pub kind: ProKind,
+}
+
+/// The type-specific part of a prototype.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProKind {
+    Item(ItemPro),
+    Critter(Box<CritterPro>),
+    /// A type whose data isn't parsed.
+    Other,
+}
+
+/// The item-specific part of a prototype.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemPro {
+    pub flags_ext: u32,
+    pub script_id: i32,
+    pub item_type: i32,
+    pub material: i32,
+    pub size: i32,
+    pub weight: i32,
+    pub cost: i32,
+    pub inventory_fid: i32,
+    pub sound_id: u8,
+    pub details: ItemDetails,
+}
+
+/// The part of an item prototype that depends on its item type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItemDetails {
+    Armor(Armor),
+    Weapon(Weapon),
+    /// An item type whose data isn't parsed.
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Armor {
+    pub armor_class: i32,
+    /// Indexed in the same order as `DAMAGE_TYPE_NAMES`.
+    pub damage_resistance: [i32; NUM_DAMAGE_TYPES],
+    /// Indexed in the same order as `DAMAGE_TYPE_NAMES`.
+    pub damage_threshold: [i32; NUM_DAMAGE_TYPES],
+    pub perk: i32,
+    pub male_fid: i32,
+    pub female_fid: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Weapon {
+    pub animation_code: i32,
+    pub min_damage: i32,
+    pub max_damage: i32,
+    pub damage_type: i32,
+    pub max_range_primary: i32,
+    pub max_range_secondary: i32,
+    pub projectile_pid: i32,
+    pub min_strength: i32,
+    pub ap_cost_primary: i32,
+    pub ap_cost_secondary: i32,
+    pub critical_failure_table: i32,
+    pub perk: i32,
+    pub burst_rounds: i32,
+    pub caliber: i32,
+    pub ammo_pid: i32,
+    pub max_ammo: i32,
+    pub sound_id: u8,
+}
+
+/// The critter-specific part of a prototype.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CritterPro {
+    pub flags_ext: u32,
+    pub script_id: i32,
+    pub head_fid: i32,
+    pub ai_packet: i32,
+    pub team: i32,
+    pub critter_flags: u32,
+    /// Indexed in the same order as `STAT_NAMES`.
+    pub base_stats: [i32; NUM_STATS],
+    /// Indexed in the same order as `STAT_NAMES`.
+    pub bonus_stats: [i32; NUM_STATS],
+    /// Indexed in the same order as `SKILL_NAMES`.
+    pub skills: [i32; NUM_SKILLS],
+    pub body_type: i32,
+    pub experience: i32,
+    pub kill_type: i32,
+    /// The damage type of the critter's unarmed attacks. Absent in Fallout 1 prototypes.
+    pub damage_type: Option<i32>,
+}
+
+impl Pro {
+
+    /// Attempts to parse `data` as a PRO.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        require_size(data, HEADER_SIZE, "prototype")?;
+
+        let pid = BigEndian::read_u32(data);
+
+        let kind = match pid >> 24 {
+            TYPE_ITEM => ProKind::Item(ItemPro::parse(data)?),
+            TYPE_CRITTER => ProKind::Critter(Box::new(CritterPro::parse(data)?)),
+            _ => ProKind::Other,
+        };
+
+        Ok(Pro {
+            pid,
+            text_id: read_i32(data, 0x04),
+            fid: read_i32(data, 0x08),
+            light_radius: read_i32(data, 0x0c),
+            light_intensity: read_i32(data, 0x10),
+            flags: BigEndian::read_u32(&data[0x14..]),
+            kind,
+        })
+    }
+
+    /// Returns the prototype's type (the index of its name in `TYPE_NAMES`).
+    pub fn pro_type(&self) -> u32 {
+        self.pid >> 24
+    }
+
+    /// Returns the prototype's ID, which is its line number in its type's `.lst` file.
+    pub fn id(&self) -> u32 {
+        self.pid & 0x00ff_ffff
+    }
+
+    /// Returns the name of the prototype's type, if it's a known type.
+    pub fn type_name(&self) -> Option<&'static str> {
+        TYPE_NAMES.get(self.pro_type() as usize).cloned()
+    }
+
+    /// Writes the prototype as a JSON object. Stats, skills and damage types are keyed by name.
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "{{\"pid\":{},\"type\":", self.pid)?;
+        write_name_json(out, self.type_name())?;
+        write!(out, ",\"id\":{},\"text_id\":{},\"fid\":{},\"light_radius\":{},\"light_intensity\":{},\"flags\":{}",
+               self.id(), self.text_id, self.fid, self.light_radius, self.light_intensity, self.flags)?;
+
+        match self.kind {
+            ProKind::Item(ref item) => {
+                out.write_all(b",\"item\":")?;
+                item.write_json(out)?;
+            },
+            ProKind::Critter(ref critter) => {
+                out.write_all(b",\"critter\":")?;
+                critter.write_json(out)?;
+            },
+            ProKind::Other => {},
+        }
+
+        out.write_all(b"}")
+    }
+}
+
+impl ItemPro {
+    fn parse(data: &[u8]) -> io::Result<Self> {
+        require_size(data, ITEM_HEADER_SIZE, "item prototype")?;
+
+        let item_type = read_i32(data, 0x20);
+
+        let details = match item_type {
+            ITEM_TYPE_ARMOR => {
+                require_size(data, ARMOR_SIZE, "armor prototype")?;
+                ItemDetails::Armor(Armor {
+                    armor_class: read_i32(data, 0x39),
+                    damage_resistance: read_i32s(data, 0x3d),
+                    damage_threshold: read_i32s(data, 0x59),
+                    perk: read_i32(data, 0x75),
+                    male_fid: read_i32(data, 0x79),
+                    female_fid: read_i32(data, 0x7d),
+                })
+            },
+            ITEM_TYPE_WEAPON => {
+                require_size(data, WEAPON_SIZE, "weapon prototype")?;
+                ItemDetails::Weapon(Weapon {
+                    animation_code: read_i32(data, 0x39),
+                    min_damage: read_i32(data, 0x3d),
+                    max_damage: read_i32(data, 0x41),
+                    damage_type: read_i32(data, 0x45),
+                    max_range_primary: read_i32(data, 0x49),
+                    max_range_secondary: read_i32(data, 0x4d),
+                    projectile_pid: read_i32(data, 0x51),
+                    min_strength: read_i32(data, 0x55),
+                    ap_cost_primary: read_i32(data, 0x59),
+                    ap_cost_secondary: read_i32(data, 0x5d),
+                    critical_failure_table: read_i32(data, 0x61),
+                    perk: read_i32(data, 0x65),
+                    burst_rounds: read_i32(data, 0x69),
+                    caliber: read_i32(data, 0x6d),
+                    ammo_pid: read_i32(data, 0x71),
+                    max_ammo: read_i32(data, 0x75),
+                    sound_id: data[0x79],
+                })
+            },
+            _ => ItemDetails::Other,
+        };
+
+        Ok(ItemPro {
+            flags_ext: BigEndian::read_u32(&data[0x18..]),
+            script_id: read_i32(data, 0x1c),
+            item_type,
+            material: read_i32(data, 0x24),
+            size: read_i32(data, 0x28),
+            weight: read_i32(data, 0x2c),
+            cost: read_i32(data, 0x30),
+            inventory_fid: read_i32(data, 0x34),
+            sound_id: data[0x38],
+            details,
+        })
+    }
+
+    fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(b"{\"item_type\":")?;
+        write_name_json(out, ITEM_TYPE_NAMES.get(self.item_type as usize).cloned())?;
+        write!(out, ",\"flags_ext\":{},\"script_id\":{},\"material\":{},\"size\":{},\"weight\":{},\"cost\":{},\"inventory_fid\":{},\"sound_id\":{}",
+               self.flags_ext, self.script_id, self.material, self.size, self.weight, self.cost, self.inventory_fid, self.sound_id)?;
+
+        match self.details {
+            ItemDetails::Armor(ref armor) => {
+                write!(out, ",\"armor\":{{\"armor_class\":{},\"damage_resistance\":", armor.armor_class)?;
+                write_named_values_json(out, &DAMAGE_TYPE_NAMES, &armor.damage_resistance)?;
+                out.write_all(b",\"damage_threshold\":")?;
+                write_named_values_json(out, &DAMAGE_TYPE_NAMES, &armor.damage_threshold)?;
+                write!(out, ",\"perk\":{},\"male_fid\":{},\"female_fid\":{}}}", armor.perk, armor.male_fid, armor.female_fid)?;
+            },
+            ItemDetails::Weapon(ref weapon) => {
+                write!(out, ",\"weapon\":{{\"animation_code\":{},\"min_damage\":{},\"max_damage\":{},\"damage_type\":",
+                       weapon.animation_code, weapon.min_damage, weapon.max_damage)?;
+                write_name_json(out, DAMAGE_TYPE_NAMES.get(weapon.damage_type as usize).cloned())?;
+                write!(out, ",\"max_range_primary\":{},\"max_range_secondary\":{},\"projectile_pid\":{},\"min_strength\":{},\"ap_cost_primary\":{},\"ap_cost_secondary\":{},\"critical_failure_table\":{},\"perk\":{},\"burst_rounds\":{},\"caliber\":{},\"ammo_pid\":{},\"max_ammo\":{},\"sound_id\":{}}}",
+                       weapon.max_range_primary, weapon.max_range_secondary, weapon.projectile_pid, weapon.min_strength,
+                       weapon.ap_cost_primary, weapon.ap_cost_secondary, weapon.critical_failure_table, weapon.perk,
+                       weapon.burst_rounds, weapon.caliber, weapon.ammo_pid, weapon.max_ammo, weapon.sound_id)?;
+            },
+            ItemDetails::Other => {},
+        }
+
+        out.write_all(b"}")
+    }
+}
+
+impl CritterPro {
+    fn parse(data: &[u8]) -> io::Result<Self> {
+        require_size(data, CRITTER_SIZE_WITHOUT_DAMAGE_TYPE, "critter prototype")?;
+
+        let damage_type = if data.len() >= CRITTER_SIZE_WITHOUT_DAMAGE_TYPE + 4 {
+            Some(read_i32(data, CRITTER_SIZE_WITHOUT_DAMAGE_TYPE))
+        } else {
+            None
+        };
+
+        Ok(CritterPro {
+            flags_ext: BigEndian::read_u32(&data[0x18..]),
+            script_id: read_i32(data, 0x1c),
+            head_fid: read_i32(data, 0x20),
+            ai_packet: read_i32(data, 0x24),
+            team: read_i32(data, 0x28),
+            critter_flags: BigEndian::read_u32(&data[0x2c..]),
+            base_stats: read_i32s(data, 0x30),
+            bonus_stats: read_i32s(data, 0xbc),
+            skills: read_i32s(data, 0x148),
+            body_type: read_i32(data, 0x190),
+            experience: read_i32(data, 0x194),
+            kill_type: read_i32(data, 0x198),
+            damage_type,
+        })
+    }
+
+    fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "{{\"flags_ext\":{},\"script_id\":{},\"head_fid\":{},\"ai_packet\":{},\"team\":{},\"critter_flags\":{},\"base_stats\":",
+               self.flags_ext, self.script_id, self.head_fid, self.ai_packet, self.team, self.critter_flags)?;
+        write_named_values_json(out, &STAT_NAMES, &self.base_stats)?;
+        out.write_all(b",\"bonus_stats\":")?;
+        write_named_values_json(out, &STAT_NAMES, &self.bonus_stats)?;
+        out.write_all(b",\"skills\":")?;
+        write_named_values_json(out, &SKILL_NAMES, &self.skills)?;
+        write!(out, ",\"body_type\":{},\"experience\":{},\"kill_type\":{},\"damage_type\":", self.body_type, self.experience, self.kill_type)?;
+        match self.damage_type {
+            Some(damage_type) => write_name_json(out, DAMAGE_TYPE_NAMES.get(damage_type as usize).cloned())?,
+            None => out.write_all(b"null")?,
+        }
+        out.write_all(b"}")
+    }
+}
+
+fn require_size(data: &[u8], size: usize, what: &str) -> io::Result<()> {
+    if data.len() < size {
+        let err_msg = format!("data is too small to be a {} ({} bytes, expected {})", what, data.len(), size);
+        Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg))
+    } else {
+        Ok(())
+    }
+}
+
+fn read_i32(data: &[u8], offset: usize) -> i32 {
+    BigEndian::read_i32(&data[offset..])
+}
+
+fn read_i32s<const N: usize>(data: &[u8], offset: usize) -> [i32; N] {
+    let mut values = [0; N];
+    BigEndian::read_i32_into(&data[offset..][..4 * N], &mut values);
+    values
+}
+
+/// Writes `name` as a JSON string, or `null` if it's unknown.
+fn write_name_json<W: Write>(out: &mut W, name: Option<&str>) -> io::Result<()> {
+    match name {
+        Some(name) => json::write_str(out, name),
+        None => out.write_all(b"null"),
+    }
+}
+
+/// Writes `values` as a JSON object, keyed by the corresponding entry of `names`.
+fn write_named_values_json<W: Write>(out: &mut W, names: &[&str], values: &[i32]) -> io::Result<()> {
+    out.write_all(b"{")?;
+    for (i, (name, value)) in names.iter().zip(values).enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        json::write_str(out, name)?;
+        write!(out, ":{}", value)?;
+    }
+    out.write_all(b"}")
+}
@@ -0,0 +1,242 @@
+//! Profiling of an operation (`--profile`): how long it spent in each stage (e.g. parsing the tree,
+//! decompressing, writing), and how busy each thread was, so that a slow run can be told to be
+//! CPU-bound (and would gain from more threads) or IO-bound (and wouldn't).
+//!
+//! Profiling is process-wide, as the stages are timed wherever they happen (e.g. in `writer` and
+//! `entry_reader`), and off until `enable` is called. While it's off, timing a stage costs one
+//! check of a flag.
+//!
+//! Stages are timed on whichever thread runs them, so across threads they add up to more than the
+//! operation's wall time. A thread's busy time is the time it spent in any stage: a thread that
+//! was busy for much less than the operation took waited (e.g. for `--throttle`, or for other
+//! threads), or ran code that isn't in a stage.
+
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A stage of an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Parsing an archive's tree (or reading its index sidecar).
+    Parse,
+    /// Reading files that are packed.
+    Read,
+    /// Decompressing entries.
+    Decompress,
+    /// Converting entries (e.g. `--convert` and `--text-encoding`).
+    Convert,
+    /// Compressing entries.
+    Compress,
+    /// Writing files.
+    Write,
+    /// Flushing written files to disk.
+    Fsync,
+}
+
+const STAGES: [Stage; 7] = [Stage::Parse, Stage::Read, Stage::Decompress, Stage::Convert, Stage::Compress, Stage::Write, Stage::Fsync];
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Parse => "parse",
+            Stage::Read => "read",
+            Stage::Decompress => "decompress",
+            Stage::Convert => "convert",
+            Stage::Compress => "compress",
+            Stage::Write => "write",
+            Stage::Fsync => "fsync",
+        }
+    }
+
+    /// Returns true if the stage waits on storage, rather than the CPU.
+    pub fn is_io(self) -> bool {
+        match self {
+            Stage::Read | Stage::Write | Stage::Fsync => true,
+            Stage::Parse | Stage::Decompress | Stage::Convert | Stage::Compress => false,
+        }
+    }
+
+    fn index(self) -> usize {
+        STAGES.iter().position(|&stage| stage == self).unwrap()
+    }
+}
+
+struct Profiler {
+    started: Instant,
+    /// Each stage's time, in nanoseconds.
+    stages: [AtomicU64; STAGES.len()],
+    threads: Mutex<Vec<ThreadTime>>,
+}
+
+/// A thread's busy time, in nanoseconds, which only the thread adds to.
+struct ThreadTime {
+    /// The thread's number among the workers (the threads other than the main one, numbered in the
+    /// order they first ran a stage), if it's one of them.
+    worker: Option<usize>,
+    busy: Arc<AtomicU64>,
+}
+
+static PROFILER: OnceLock<Profiler> = OnceLock::new();
+
+thread_local! {
+    static BUSY: Arc<AtomicU64> = register_thread();
+}
+
+fn register_thread() -> Arc<AtomicU64> {
+    let busy = Arc::new(AtomicU64::new(0));
+    if let Some(profiler) = PROFILER.get() {
+        let mut threads = profiler.threads.lock().unwrap();
+        let worker = if thread::current().name() == Some("main") {
+            None
+        } else {
+            Some(threads.iter().filter(|thread| thread.worker.is_some()).count())
+        };
+        threads.push(ThreadTime { worker, busy: busy.clone() });
+    }
+    busy
+}
+
+/// Turns profiling on, starting the operation's clock.
+pub fn enable() {
+    let _ = PROFILER.set(Profiler {
+        started: Instant::now(),
+        stages: Default::default(),
+        threads: Mutex::new(Vec::new()),
+    });
+}
+
+/// Runs `f` as part of `stage`, adding the time it takes to the stage's if profiling is on.
+pub fn time<T, F: FnOnce() -> T>(stage: Stage, f: F) -> T {
+    let profiler = match PROFILER.get() {
+        Some(profiler) => profiler,
+        None => return f(),
+    };
+
+    let started = Instant::now();
+    let result = f();
+    let elapsed = started.elapsed().as_nanos() as u64;
+    profiler.stages[stage.index()].fetch_add(elapsed, Ordering::Relaxed);
+    BUSY.with(|busy| busy.fetch_add(elapsed, Ordering::Relaxed));
+    result
+}
+
+/// A reader or writer whose reads or writes are timed as a stage.
+pub struct Timed<T> {
+    inner: T,
+    stage: Stage,
+}
+
+impl <T> Timed<T> {
+    pub fn new(inner: T, stage: Stage) -> Self {
+        Timed { inner, stage }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl <R: Read> Read for Timed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        time(self.stage, || inner.read(buf))
+    }
+}
+
+impl <W: Write> Write for Timed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        time(self.stage, || inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let inner = &mut self.inner;
+        time(self.stage, || inner.flush())
+    }
+}
+
+/// What a profiled operation spent its time on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// The time since profiling was turned on.
+    pub wall: Duration,
+    /// The stages the operation spent any time in, with their times, in `Stage` order.
+    pub stages: Vec<(Stage, Duration)>,
+    /// Each thread that ran a stage, with its busy time: the main thread (`None`), then the
+    /// workers by number.
+    pub threads: Vec<(Option<usize>, Duration)>,
+}
+
+/// Returns what the operation has spent its time on so far, if profiling is on.
+pub fn report() -> Option<Report> {
+    let profiler = PROFILER.get()?;
+
+    let stages = STAGES.iter()
+        .map(|&stage| (stage, Duration::from_nanos(profiler.stages[stage.index()].load(Ordering::Relaxed))))
+        .filter(|&(_, time)| time > Duration::from_secs(0))
+        .collect();
+
+    let mut threads: Vec<(Option<usize>, Duration)> = profiler.threads.lock().unwrap().iter()
+        .map(|thread| (thread.worker, Duration::from_nanos(thread.busy.load(Ordering::Relaxed))))
+        .collect();
+    threads.sort_by_key(|&(worker, _)| worker);
+
+    Some(Report { wall: profiler.started.elapsed(), stages, threads })
+}
+
+impl Report {
+
+    /// Returns the share of the stages' time (across threads) that was spent on IO, from 0 to 1.
+    pub fn io_share(&self) -> f64 {
+        let total: f64 = self.stages.iter().map(|&(_, time)| time.as_secs_f64()).sum();
+        let io: f64 = self.stages.iter().filter(|&&(stage, _)| stage.is_io()).map(|&(_, time)| time.as_secs_f64()).sum();
+        if total > 0.0 { io / total } else { 0.0 }
+    }
+
+    /// Returns the workers' mean busy time, as a share of the wall time, from 0 to 1. The main
+    /// thread, which mostly waits for the workers when there are any, only counts if there aren't.
+    pub fn utilization(&self) -> f64 {
+        let wall = self.wall.as_secs_f64();
+        let has_workers = self.threads.iter().any(|&(worker, _)| worker.is_some());
+        let busy: Vec<f64> = self.threads.iter()
+            .filter(|&&(worker, _)| worker.is_some() || !has_workers)
+            .map(|&(_, busy)| busy.as_secs_f64())
+            .collect();
+        if busy.is_empty() || wall <= 0.0 {
+            return 0.0;
+        }
+        busy.iter().sum::<f64>() / busy.len() as f64 / wall
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let wall = self.wall.as_secs_f64();
+        let total: f64 = self.stages.iter().map(|&(_, time)| time.as_secs_f64()).sum();
+        let percent = |part: f64, whole: f64| if whole > 0.0 { 100.0 * part / whole } else { 0.0 };
+
+        writeln!(f, "profile: {:.3}s wall", wall)?;
+        writeln!(f, "  {:<12} {:>10} {:>7}", "stage", "time", "share")?;
+        for &(stage, time) in &self.stages {
+            writeln!(f, "  {:<12} {:>9.3}s {:>6.1}%", stage.name(), time.as_secs_f64(), percent(time.as_secs_f64(), total))?;
+        }
+
+        writeln!(f, "  {:<12} {:>10} {:>7}", "thread", "busy", "util")?;
+        for &(worker, busy) in &self.threads {
+            let name = worker.map_or_else(|| "main".to_string(), |worker| format!("worker {}", worker));
+            writeln!(f, "  {:<12} {:>9.3}s {:>6.1}%", name, busy.as_secs_f64(), percent(busy.as_secs_f64(), wall))?;
+        }
+
+        if self.stages.is_empty() {
+            return write!(f, "  no stage was timed");
+        }
+        let io_share = self.io_share();
+        let (bound, share) = if io_share > 0.5 { ("IO", io_share) } else { ("CPU", 1.0 - io_share) };
+        write!(f, "  mostly {}-bound: {:.0}% of the stages' time, with threads busy {:.0}% of the time on average", bound, 100.0 * share, 100.0 * self.utilization())
+    }
+}
@@ -0,0 +1,309 @@
+//! Archives read over HTTP, a range of bytes at a time.
+//!
+//! A `RemoteFile` fetches ranges of a file with HTTP/1.1 range requests, so that e.g. an archive's
+//! tree can be read, and a few entries' data, without downloading the whole archive. The server
+//! must support range requests (answering them with `206 Partial Content`). Only `http://` URLs
+//! are supported: fetching over HTTPS needs TLS, which fo2dat doesn't implement.
+
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::io::Error;
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::ops::Range;
+use std::time::Duration;
+
+use errors;
+use errors::Code;
+
+/// The most redirects that are followed when opening a file.
+const MAX_REDIRECTS: usize = 5;
+
+/// How long connecting, or waiting for the server, takes before giving up.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Returns `true` if `path` is a URL (`http://` or `https://`), rather than a file's path.
+pub fn is_url(path: &str) -> bool {
+    let lower = path.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// A file on an HTTP server.
+#[derive(Debug, Clone)]
+pub struct RemoteFile {
+    url: Url,
+    len: u64,
+}
+
+impl RemoteFile {
+
+    /// Opens the file at `url`, following redirects, and finds its length.
+    pub fn open(url: &str) -> io::Result<Self> {
+        let mut url = Url::parse(url)?;
+
+        for _ in 0..MAX_REDIRECTS + 1 {
+            let response = request(&url, 0..1)?;
+            match response.status {
+                206 => {
+                    check_range(&url, &response, 0..1)?;
+                    let len = response.total_len.ok_or_else(|| invalid(&url, "the response has no Content-Range length"))?;
+                    return Ok(RemoteFile { url, len });
+                },
+                200 => return Err(invalid(&url, "the server doesn't support range requests")),
+                // there's no byte 0 to fetch, so the file's empty
+                416 => return Ok(RemoteFile { url, len: 0 }),
+                301 | 302 | 303 | 307 | 308 => {
+                    let location = response.location.ok_or_else(|| invalid(&url, "a redirect has no Location"))?;
+                    url = url.join(&location)?;
+                },
+                status => return Err(errors::new(Code::Http, ErrorKind::Other, format!("{}: the server answered with status {}", url, status))),
+            }
+        }
+
+        Err(invalid(&url, "too many redirects"))
+    }
+
+    /// Returns the file's length, in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Fetches the bytes of the file in `range`.
+    pub fn read_range(&self, range: Range<u64>) -> io::Result<Vec<u8>> {
+        if range.end > self.len {
+            let err_msg = format!("{}: range {}-{} is past the end of the file ({} bytes)", self.url, range.start, range.end, self.len);
+            return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+        }
+        if range.start >= range.end {
+            return Ok(Vec::new());
+        }
+
+        let response = request(&self.url, range.clone())?;
+        if response.status != 206 {
+            let err_msg = format!("{}: the server answered a range request with status {}", self.url, response.status);
+            return Err(errors::new(Code::Http, ErrorKind::Other, err_msg));
+        }
+        check_range(&self.url, &response, range.clone())?;
+        let len = (range.end - range.start) as usize;
+        if response.body.len() != len {
+            let err_msg = format!("{}: the server sent {} bytes of range {}-{}, rather than {}", self.url, response.body.len(), range.start, range.end, len);
+            return Err(errors::new(Code::Http, ErrorKind::UnexpectedEof, err_msg));
+        }
+
+        Ok(response.body)
+    }
+}
+
+/// A parsed `http://` URL.
+#[derive(Debug, Clone)]
+struct Url {
+    host: String,
+    port: u16,
+    /// The path and query, starting with `/`.
+    target: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> io::Result<Self> {
+        let rest = match url.find("://") {
+            Some(i) if url[..i].eq_ignore_ascii_case("http") => &url[i + 3..],
+            Some(i) if url[..i].eq_ignore_ascii_case("https") => {
+                let err_msg = format!("{}: HTTPS isn't supported (fo2dat doesn't implement TLS): use an http:// URL", url);
+                return Err(Error::new(ErrorKind::Unsupported, err_msg));
+            },
+            _ => return Err(Error::new(ErrorKind::InvalidInput, format!("{}: not an http:// URL", url))),
+        };
+
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(i) if rest.as_bytes()[i] == b'/' => (&rest[..i], rest[i..].to_string()),
+            Some(i) => (&rest[..i], format!("/{}", &rest[i..])),
+            None => (rest, "/".to_string()),
+        };
+        // a fragment isn't sent to the server
+        let target = target.split('#').next().unwrap().to_string();
+
+        let (host, port) = match authority.rfind(':') {
+            Some(i) if !authority.ends_with(']') => {
+                let port = authority[i + 1..].parse().map_err(|_| {
+                    Error::new(ErrorKind::InvalidInput, format!("{}: invalid port", url))
+                })?;
+                (&authority[..i], port)
+            },
+            _ => (authority, 80),
+        };
+        if host.is_empty() || host.contains('@') {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{}: invalid host", url)));
+        }
+
+        Ok(Url { host: host.to_string(), port, target })
+    }
+
+    /// Resolves `location`, a redirect's target, against the URL.
+    fn join(&self, location: &str) -> io::Result<Url> {
+        if location.contains("://") {
+            Url::parse(location)
+        } else if location.starts_with('/') {
+            Ok(Url { target: location.to_string(), ..self.clone() })
+        } else {
+            let dir_end = self.target.split('?').next().unwrap().rfind('/').unwrap_or(0);
+            Ok(Url { target: format!("{}/{}", &self.target[..dir_end], location), ..self.clone() })
+        }
+    }
+
+    /// Returns the host to connect to: the URL's, without the brackets of an IPv6 address (e.g.
+    /// `::1` for `[::1]`).
+    fn connect_host(&self) -> &str {
+        if self.host.starts_with('[') && self.host.ends_with(']') {
+            &self.host[1..self.host.len() - 1]
+        } else {
+            &self.host
+        }
+    }
+
+    fn host_header(&self) -> String {
+        if self.port == 80 { self.host.clone() } else { format!("{}:{}", self.host, self.port) }
+    }
+}
+
+impl ::std::fmt::Display for Url {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "http://{}{}", self.host_header(), self.target)
+    }
+}
+
+struct Response {
+    status: u16,
+    /// The range of the file that the body is, from `Content-Range`.
+    range: Option<Range<u64>>,
+    /// The length of the whole file, from `Content-Range`.
+    total_len: Option<u64>,
+    location: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Requests the bytes of the file at `url` in `range`, over a new connection.
+fn request(url: &Url, range: Range<u64>) -> io::Result<Response> {
+    let with_url = |e: Error| errors::with_context(e, url);
+
+    let mut stream = TcpStream::connect((url.connect_host(), url.port)).map_err(with_url)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\nRange: bytes={}-{}\r\nUser-Agent: fo2dat/{}\r\nAccept-Encoding: identity\r\nConnection: close\r\n\r\n",
+           url.target, url.host_header(), range.start, range.end - 1, env!("CARGO_PKG_VERSION")).map_err(with_url)?;
+    stream.flush().map_err(with_url)?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(with_url)?;
+    let status = line.split_whitespace().nth(1).and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid(url, "the response has no status line"))?;
+
+    let mut content_len = None;
+    let mut content_range = None;
+    let mut total_len = None;
+    let mut location = None;
+    let mut is_chunked = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(with_url)? == 0 {
+            return Err(invalid(url, "the response ended in its headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = match header.find(':') {
+            Some(i) => (header[..i].trim().to_ascii_lowercase(), header[i + 1..].trim()),
+            None => continue,
+        };
+        match name.as_str() {
+            "content-length" => content_len = value.parse::<u64>().ok(),
+            "content-range" => {
+                let (range, total) = parse_content_range(value);
+                content_range = range;
+                total_len = total;
+            },
+            "location" => location = Some(value.to_string()),
+            "transfer-encoding" => is_chunked = value.to_ascii_lowercase().contains("chunked"),
+            _ => {},
+        }
+    }
+
+    let body = if status != 206 {
+        // only partial content is wanted, and a whole file would be too much to read
+        Vec::new()
+    } else if is_chunked {
+        read_chunked(&mut reader).map_err(with_url)?
+    } else {
+        let mut body = Vec::new();
+        match content_len {
+            Some(content_len) => reader.take(content_len).read_to_end(&mut body),
+            None => reader.read_to_end(&mut body),
+        }.map_err(with_url)?;
+        body
+    };
+
+    Ok(Response { status, range: content_range, total_len, location, body })
+}
+
+/// Parses the value of a `Content-Range` header (e.g. `bytes 0-0/1234`), returning the range that
+/// it says the body is, and the length of the whole file, if they're given.
+fn parse_content_range(value: &str) -> (Option<Range<u64>>, Option<u64>) {
+    let value = value.trim_start_matches("bytes").trim_start();
+    let (range, total) = match value.find('/') {
+        Some(i) => (&value[..i], &value[i + 1..]),
+        None => (value, ""),
+    };
+    let range = range.find('-').and_then(|i| {
+        let start = range[..i].trim().parse::<u64>().ok()?;
+        let end = range[i + 1..].trim().parse::<u64>().ok()?;
+        end.checked_add(1).filter(|&end| end > start).map(|end| start..end)
+    });
+    (range, total.trim().parse::<u64>().ok())
+}
+
+/// Fails unless `response`, to a request for `range`, says (with `Content-Range`) that its body is
+/// exactly that range, so that the wrong bytes are never taken for it.
+fn check_range(url: &Url, response: &Response, range: Range<u64>) -> io::Result<()> {
+    match response.range {
+        Some(ref sent) if *sent == range => Ok(()),
+        Some(ref sent) => {
+            let err_msg = format!("{}: the server sent range {}-{} for a request of range {}-{}", url, sent.start, sent.end - 1, range.start, range.end - 1);
+            Err(errors::new(Code::Http, ErrorKind::InvalidData, err_msg))
+        },
+        None => Err(invalid(url, "the response has no Content-Range")),
+    }
+}
+
+/// Reads a body sent with chunked transfer encoding.
+fn read_chunked<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let size_str = line.trim().split(';').next().unwrap();
+        let size = u64::from_str_radix(size_str, 16).map_err(|_| {
+            Error::new(ErrorKind::InvalidData, format!("invalid chunk size {:?}", size_str))
+        })?;
+        if size == 0 {
+            return Ok(body);
+        }
+        let read = reader.by_ref().take(size).read_to_end(&mut body)?;
+        if read as u64 != size {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "the response ended in a chunk"));
+        }
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+}
+
+fn invalid(url: &Url, msg: &str) -> Error {
+    errors::new(Code::Http, ErrorKind::InvalidData, format!("{}: {}", url, msg))
+}
@@ -0,0 +1,78 @@
+//! Parsing of Fallout RIX images (e.g. splash screens).
+//!
+//! A RIX is a single 8-bit paletted image that carries its own palette:
+//!
+//! ```text
+//! "RIX3" | width (u16) | height (u16) | palette type (u8) | storage type (u8) | palette (768) | pixels
+//! ```
+//!
+//! Multi-byte numbers are little-endian. Only uncompressed storage is used by the game.
+
+use std::io;
+use std::io::ErrorKind;
+use byteorder::{LittleEndian, ByteOrder};
+
+use errors;
+use errors::Code;
+use pal;
+use pal::Palette;
+
+const MAGIC: &[u8; 4] = b"RIX3";
+const HEADER_SIZE: usize = 10;
+const STORAGE_UNCOMPRESSED: u8 = 0;
+
+/// A parsed RIX, borrowing its pixel data from the underlying RIX data.
+pub struct Rix<'a> {
+    pub width: usize,
+    pub height: usize,
+    /// The RIX's own palette, as raw (6-bit) PAL data.
+    pub palette_data: &'a [u8],
+    /// Row-major palette indices, `width * height` long.
+    pub pixels: &'a [u8],
+}
+
+impl <'a> Rix<'a> {
+
+    /// Attempts to parse `data` as a RIX.
+    pub fn parse(data: &'a [u8]) -> io::Result<Self> {
+        if data.len() < HEADER_SIZE + pal::COLORS_SIZE || &data[..4] != MAGIC {
+            let err_msg = "data is not a RIX3 image";
+            return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+        }
+
+        let width = LittleEndian::read_u16(&data[4..]) as usize;
+        let height = LittleEndian::read_u16(&data[6..]) as usize;
+        let storage_type = data[9];
+
+        if storage_type != STORAGE_UNCOMPRESSED {
+            let err_msg = format!("unsupported RIX storage type ({})", storage_type);
+            return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+        }
+
+        let palette_data = &data[HEADER_SIZE..][..pal::COLORS_SIZE];
+        let pixels_start = HEADER_SIZE + pal::COLORS_SIZE;
+
+        match data.get(pixels_start..pixels_start + width * height) {
+            Some(pixels) => Ok(Rix { width, height, palette_data, pixels }),
+            None => {
+                let err_msg = format!("RIX data is too small to fit a {}x{} image", width, height);
+                Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg))
+            }
+        }
+    }
+
+    /// Returns the RIX's own palette, scaled with `scaling`.
+    pub fn palette(&self, scaling: pal::Scaling) -> Palette {
+        // always large enough, because that's checked while parsing
+        Palette::parse_with_scaling(self.palette_data, scaling).unwrap()
+    }
+}
+
+#[cfg(feature = "image")]
+impl <'a> Rix<'a> {
+
+    /// Writes the RIX as a PNG, coloring it with `palette`.
+    pub fn write_png<W: ::std::io::Write>(&self, out: &mut W, palette: &Palette) -> io::Result<()> {
+        ::png::write_indexed(out, self.width, self.height, self.pixels, palette, None)
+    }
+}
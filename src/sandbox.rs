@@ -0,0 +1,246 @@
+//! Extraction confined to an output directory, however entries' paths resolve.
+//!
+//! A `Sandbox` holds its directory open and resolves every path it's given relative to it with
+//! Linux's `openat2` and `RESOLVE_BENEATH`, which fails on any path that would resolve to outside
+//! of the directory: through `..`, an absolute path, or a symbolic link, including one swapped in
+//! while extracting. Other platforms don't support it.
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use errors;
+use errors::Code;
+
+/// A directory that files and directories are only ever created beneath.
+///
+/// Paths are given as the directory's path (as it was opened) joined with a path relative to it,
+/// e.g. `out/art/a.frm` for a sandbox opened with `out`.
+pub struct Sandbox {
+    dir: PathBuf,
+    inner: sys::Sandbox,
+}
+
+impl Sandbox {
+
+    /// Opens `dir` as a sandbox. Fails if the platform doesn't support sandboxes (they need Linux
+    /// 5.6 or later).
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        Ok(Sandbox { dir: dir.to_path_buf(), inner: sys::Sandbox::open(dir)? })
+    }
+
+    /// Creates the directory at `path`, and any missing parents, with `mode` (which the umask
+    /// applies to, and which is ignored if they exist).
+    pub fn create_dir_all(&self, path: &Path, mode: u32) -> io::Result<()> {
+        self.inner.create_dir_all(self.relative(path)?, mode)
+    }
+
+    /// Opens the file at `path` for writing, truncating it, or creating it with `mode` (which the
+    /// umask applies to).
+    pub fn create_file(&self, path: &Path, mode: u32) -> io::Result<File> {
+        self.inner.create_file(self.relative(path)?, mode)
+    }
+
+    /// Renames the file at `from` to `to`, which must be in the same directory.
+    pub fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(self.relative(from)?, self.relative(to)?)
+    }
+
+    /// Removes the file at `path`.
+    pub fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(self.relative(path)?)
+    }
+
+    /// Returns `path` relative to the sandbox's directory.
+    fn relative<'p>(&self, path: &'p Path) -> io::Result<&'p Path> {
+        path.strip_prefix(&self.dir).map_err(|_| {
+            let err_msg = format!("{}: not in {}", path.display(), self.dir.display());
+            errors::new(Code::Sandbox, io::ErrorKind::InvalidInput, err_msg)
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::{CString, OsStr};
+    use std::fs::File;
+    use std::io;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+    use std::path::{Component, Path};
+    use libc;
+
+    // newer than the libc that's depended on
+    const SYS_OPENAT2: libc::c_long = 437;
+    const RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+    const RESOLVE_BENEATH: u64 = 0x08;
+
+    /// How many times opening a path is tried while concurrent renames keep affecting its
+    /// resolution.
+    const MAX_ATTEMPTS: usize = 16;
+
+    #[repr(C)]
+    struct OpenHow {
+        flags: u64,
+        mode: u64,
+        resolve: u64,
+    }
+
+    pub struct Sandbox {
+        dir: File,
+    }
+
+    impl Sandbox {
+        pub fn open(dir: &Path) -> io::Result<Self> {
+            let sandbox = Sandbox { dir: File::open(dir)? };
+
+            // checked up front, rather than failing on the first entry
+            sandbox.open_dir(Path::new(".")).map_err(|e| {
+                if e.raw_os_error() == Some(libc::ENOSYS) {
+                    ::errors::new(::errors::Code::Unsupported, io::ErrorKind::Unsupported, "sandboxes need openat2 (Linux 5.6 or later)")
+                } else {
+                    e
+                }
+            })?;
+
+            Ok(sandbox)
+        }
+
+        pub fn create_dir_all(&self, path: &Path, mode: u32) -> io::Result<()> {
+            let mut parent = self.open_dir(Path::new("."))?;
+
+            for component in path.components() {
+                let name = match component {
+                    Component::Normal(name) => name,
+                    Component::CurDir => continue,
+                    _ => return Err(not_beneath()),
+                };
+
+                // an existing symbolic link to outside of the sandbox isn't followed by mkdirat,
+                // and fails to open below
+                let c_name = c_string(name)?;
+                if unsafe { libc::mkdirat(parent.as_raw_fd(), c_name.as_ptr(), mode as libc::mode_t) } != 0 {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::AlreadyExists {
+                        return Err(e);
+                    }
+                }
+
+                parent = openat2(parent.as_raw_fd(), Path::new(name), libc::O_PATH | libc::O_DIRECTORY, 0)?;
+            }
+
+            Ok(())
+        }
+
+        pub fn create_file(&self, path: &Path, mode: u32) -> io::Result<File> {
+            openat2(self.dir.as_raw_fd(), path, libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC, mode)
+        }
+
+        pub fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            if from.parent() != to.parent() {
+                let err_msg = format!("{}: can only be renamed within its directory", from.display());
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
+            }
+
+            // only the file names are resolved relative to the parent, so they can't escape it
+            let parent = self.open_parent(from)?;
+            let from_name = c_string(from.file_name().ok_or_else(not_beneath)?)?;
+            let to_name = c_string(to.file_name().ok_or_else(not_beneath)?)?;
+            if unsafe { libc::renameat(parent.as_raw_fd(), from_name.as_ptr(), parent.as_raw_fd(), to_name.as_ptr()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        }
+
+        pub fn remove_file(&self, path: &Path) -> io::Result<()> {
+            let parent = self.open_parent(path)?;
+            let name = c_string(path.file_name().ok_or_else(not_beneath)?)?;
+            if unsafe { libc::unlinkat(parent.as_raw_fd(), name.as_ptr(), 0) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        }
+
+        fn open_dir(&self, path: &Path) -> io::Result<File> {
+            openat2(self.dir.as_raw_fd(), path, libc::O_PATH | libc::O_DIRECTORY, 0)
+        }
+
+        fn open_parent(&self, path: &Path) -> io::Result<File> {
+            match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => self.open_dir(parent),
+                _ => self.open_dir(Path::new(".")),
+            }
+        }
+    }
+
+    /// Opens `path` relative to the directory `dir`, failing if it resolves to outside of it.
+    fn openat2(dir: RawFd, path: &Path, flags: libc::c_int, mode: u32) -> io::Result<File> {
+        let c_path = c_string(path.as_os_str())?;
+        let how = OpenHow {
+            flags: (flags | libc::O_CLOEXEC) as u64,
+            mode: u64::from(mode),
+            resolve: RESOLVE_BENEATH | RESOLVE_NO_MAGICLINKS,
+        };
+
+        let mut attempts = 0;
+        loop {
+            let fd = unsafe { libc::syscall(SYS_OPENAT2, dir, c_path.as_ptr(), &how as *const OpenHow, mem::size_of::<OpenHow>()) };
+            if fd >= 0 {
+                return Ok(unsafe { File::from_raw_fd(fd as RawFd) });
+            }
+
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                // a concurrent rename may have affected resolution, so it's retried, but not forever
+                Some(libc::EAGAIN) if attempts + 1 < MAX_ATTEMPTS => attempts += 1,
+                Some(libc::EXDEV) => return Err(not_beneath()),
+                _ => return Err(e),
+            }
+        }
+    }
+
+    fn c_string(s: &OsStr) -> io::Result<CString> {
+        CString::new(s.as_bytes()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{:?}: contains a NUL byte", s))
+        })
+    }
+
+    fn not_beneath() -> io::Error {
+        ::errors::new(::errors::Code::Sandbox, io::ErrorKind::PermissionDenied, "resolves to outside of the sandbox")
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::fs::File;
+    use std::io;
+    use std::path::Path;
+
+    pub enum Sandbox {}
+
+    impl Sandbox {
+        pub fn open(_dir: &Path) -> io::Result<Self> {
+            Err(::errors::new(::errors::Code::Unsupported, io::ErrorKind::Unsupported, "sandboxes aren't supported on this platform (they need Linux's openat2)"))
+        }
+
+        pub fn create_dir_all(&self, _path: &Path, _mode: u32) -> io::Result<()> {
+            match *self {}
+        }
+
+        pub fn create_file(&self, _path: &Path, _mode: u32) -> io::Result<File> {
+            match *self {}
+        }
+
+        pub fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+            match *self {}
+        }
+
+        pub fn remove_file(&self, _path: &Path) -> io::Result<()> {
+            match *self {}
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Parsing of the header of Fallout 2 savegames (`SAVE.DAT`).
+//!
+//! Despite its name, a `SAVE.DAT` isn't a DAT archive: it's a flat record of the game's state,
+//! starting with a header that the load/save screen reads. The rest of a save slot's directory
+//! holds the savegame's other files (e.g. the visited maps, as gzipped `.SAV` files), which are
+//! ordinary files rather than archive entries.
+//!
+//! All numbers are big-endian. The header is:
+//!
+//! ```text
+//! "FALLOUT SAVE FILE" (24) | version (u16 x2, u8) | character name (32) | description (30) |
+//! real date (u16 x3) | real time (u32) | game date (u16 x3) | game time (u32) |
+//! elevation (u16) | map number (u16) | map file name (16) | thumbnail (224x133)
+//! ```
+
+use std::io;
+use std::io::ErrorKind;
+use byteorder::{BigEndian, ByteOrder};
+
+use errors;
+use errors::Code;
+
+/// The signature every savegame starts with.
+pub const SIGNATURE: &[u8] = b"FALLOUT SAVE FILE";
+
+/// The dimensions of the thumbnail stored in the header.
+pub const THUMBNAIL_WIDTH: usize = 224;
+pub const THUMBNAIL_HEIGHT: usize = 133;
+
+const SIGNATURE_SIZE: usize = 24;
+const CHARACTER_NAME_SIZE: usize = 32;
+const DESCRIPTION_SIZE: usize = 30;
+const MAP_FILE_NAME_SIZE: usize = 16;
+const THUMBNAIL_OFFSET: usize = 0x83;
+const HEADER_SIZE: usize = THUMBNAIL_OFFSET + THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT;
+
+/// The header of a savegame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveHeader<'a> {
+    pub version_minor: u16,
+    pub version_major: u16,
+    pub version_release: u8,
+    pub character_name: String,
+    pub description: String,
+    /// The real-world date the game was saved on, as (year, month, day).
+    pub saved_on: (u16, u16, u16),
+    /// The in-game date the game was saved on, as (year, month, day).
+    pub game_date: (u16, u16, u16),
+    /// The in-game time, in ticks (tenths of a second) since the game started.
+    pub game_time: u32,
+    pub elevation: u16,
+    pub map_number: u16,
+    pub map_file_name: String,
+    /// 8-bit palette indices (in the game's `color.pal`), `THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT` long.
+    pub thumbnail: &'a [u8],
+}
+
+/// Returns true if `data` starts with a savegame's signature.
+pub fn is_save(data: &[u8]) -> bool {
+    data.starts_with(SIGNATURE)
+}
+
+impl <'a> SaveHeader<'a> {
+
+    /// Attempts to parse the header at the start of `data` (the content of a `SAVE.DAT`).
+    pub fn parse(data: &'a [u8]) -> io::Result<Self> {
+        if !is_save(data) {
+            let err_msg = "data is not a Fallout 2 savegame: it doesn't start with the savegame signature";
+            return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+        }
+
+        if data.len() < HEADER_SIZE {
+            let err_msg = format!("savegame data ({} bytes) is too small to fit a header ({} bytes)", data.len(), HEADER_SIZE);
+            return Err(errors::new(Code::Format, ErrorKind::InvalidData, err_msg));
+        }
+
+        let u16_at = |offset: usize| BigEndian::read_u16(&data[offset..]);
+
+        Ok(SaveHeader {
+            version_minor: u16_at(SIGNATURE_SIZE),
+            version_major: u16_at(SIGNATURE_SIZE + 2),
+            version_release: data[SIGNATURE_SIZE + 4],
+            character_name: read_str(&data[0x1d..][..CHARACTER_NAME_SIZE]),
+            description: read_str(&data[0x3d..][..DESCRIPTION_SIZE]),
+            saved_on: (u16_at(0x5f), u16_at(0x5d), u16_at(0x5b)),
+            game_date: (u16_at(0x69), u16_at(0x65), u16_at(0x67)),
+            game_time: BigEndian::read_u32(&data[0x6b..]),
+            elevation: u16_at(0x6f),
+            map_number: u16_at(0x71),
+            map_file_name: read_str(&data[0x73..][..MAP_FILE_NAME_SIZE]),
+            thumbnail: &data[THUMBNAIL_OFFSET..HEADER_SIZE],
+        })
+    }
+}
+
+/// Reads a NUL-padded string field.
+fn read_str(field: &[u8]) -> String {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Dat;
+
+    /// Returns a save's header, built field by field as the game lays it out (real saves are the
+    /// game's data, so can't be included), of a game saved in Arroyo on 14 Oct 2026, followed by
+    /// some of the game's state.
+    fn save_fixture() -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE + 1024];
+        let mut put = |offset: usize, bytes: &[u8]| data[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        put(0, b"FALLOUT SAVE FILE");
+        put(0x18, &[0, 1, 0, 2, b'R']);
+        put(0x1d, b"Chosen One");
+        put(0x3d, b"Arroyo, before the temple");
+        // the real date is day, month, year; the game date, month, day, year
+        put(0x5b, &[0, 14, 0, 10, 0x07, 0xea]);
+        put(0x61, &[0, 0, 0x1c, 0x20]);
+        put(0x65, &[0, 7, 0, 25, 0x08, 0xc1]);
+        put(0x6b, &[0, 0, 0x03, 0xe8]);
+        put(0x6f, &[0, 1]);
+        put(0x71, &[0, 2]);
+        put(0x73, b"ARTEMPLE.SAV");
+        for (i, b) in data[THUMBNAIL_OFFSET..HEADER_SIZE].iter_mut().enumerate() {
+            *b = (i % 229) as u8;
+        }
+        data
+    }
+
+    #[test]
+    fn parses_header() {
+        let data = save_fixture();
+        let header = SaveHeader::parse(&data).unwrap();
+
+        assert_eq!((header.version_minor, header.version_major, header.version_release), (1, 2, b'R'));
+        assert_eq!(header.character_name, "Chosen One");
+        assert_eq!(header.description, "Arroyo, before the temple");
+        assert_eq!(header.saved_on, (2026, 10, 14));
+        assert_eq!(header.game_date, (2241, 7, 25));
+        assert_eq!(header.game_time, 1000);
+        assert_eq!((header.elevation, header.map_number), (1, 2));
+        assert_eq!(header.map_file_name, "ARTEMPLE.SAV");
+        assert_eq!(header.thumbnail.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+        assert_eq!(header.thumbnail[230], 1);
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let data = save_fixture();
+        let e = SaveHeader::parse(&data[..HEADER_SIZE - 1]).unwrap_err();
+        assert_eq!(errors::code(&e), Code::Format);
+        assert!(SaveHeader::parse(&data[..HEADER_SIZE]).is_ok());
+    }
+
+    #[test]
+    fn rejects_other_data() {
+        let mut data = save_fixture();
+        data[0] = b'f';
+        assert_eq!(errors::code(&SaveHeader::parse(&data).unwrap_err()), Code::Format);
+    }
+
+    #[test]
+    fn saves_are_not_parsed_as_archives() {
+        let data = save_fixture();
+        assert_eq!(errors::code(&::stats(&data).unwrap_err()), Code::Savegame);
+        assert_eq!(errors::code(&Dat::parse(&data).err().unwrap()), Code::Savegame);
+    }
+}
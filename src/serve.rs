@@ -0,0 +1,330 @@
+//! Serving an archive over HTTP, for tools (and engine forks) that read assets from a server.
+//!
+//! The archive that's served is a `Snapshot`, which can be swapped for a newer one while serving
+//! (e.g. when the files it was built from change), with each request answered from the snapshot
+//! that was current when it arrived. A server answers `GET` and `HEAD` requests for:
+//!
+//! - `/<name>` (e.g. `/mod.dat`): the archive itself, with range requests supported, so that it
+//!   can be read remotely (see `remote`);
+//! - `/files/<path>`: the decompressed content of the entry at `path` (compared as entry names
+//!   are: see `names`) or, if it's a directory, its subdirectories (with a trailing `/`) and files,
+//!   one per line.
+//!
+//! Every response has an `X-Fo2dat-Generation` header, the generation of the snapshot it's from,
+//! so clients can tell when the archive has changed.
+//!
+//! At most `MAX_CONNECTIONS` connections are handled at once (others wait to be accepted), and a
+//! request's head (its request line and headers) may be at most `MAX_HEAD_SIZE` bytes, so that
+//! clients can't have the server spawn threads, or buffer headers, without bound.
+
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use cache::ContentCache;
+use names;
+use {Dat, SharedDat};
+
+/// How long a connection may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a connection may take to receive each part of its response.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The most connections that are handled at once.
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// The most bytes a request's head (its request line and headers) may be.
+pub const MAX_HEAD_SIZE: u64 = 8 * 1024;
+
+/// An archive as it was at one point in time.
+pub struct Snapshot {
+    dat: SharedDat,
+    generation: u64,
+    cache: ContentCache,
+}
+
+impl Snapshot {
+
+    /// Creates a snapshot of the archive `data`, the `generation`th, whose decompressed entries
+    /// are cached up to `cache_capacity` bytes. Fails if `data` isn't a valid archive.
+    pub fn new(data: Vec<u8>, generation: u64, cache_capacity: usize) -> io::Result<Self> {
+        Ok(Snapshot { dat: SharedDat::parse(data)?, generation, cache: ContentCache::new(cache_capacity) })
+    }
+
+    /// Returns the archive's data.
+    pub fn data(&self) -> &[u8] {
+        self.dat.data()
+    }
+
+    /// Returns the archive, as parsed when the snapshot was created.
+    pub fn dat(&self) -> &Dat<'_> {
+        self.dat.dat()
+    }
+
+    /// Returns the snapshot's generation.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+/// The snapshot that's currently served.
+pub struct Published {
+    current: RwLock<Arc<Snapshot>>,
+}
+
+impl Published {
+
+    /// Publishes `snapshot`.
+    pub fn new(snapshot: Snapshot) -> Self {
+        Published { current: RwLock::new(Arc::new(snapshot)) }
+    }
+
+    /// Replaces the published snapshot with `snapshot`. Requests that are being answered from the
+    /// old one carry on with it.
+    pub fn publish(&self, snapshot: Snapshot) {
+        *self.current.write().unwrap() = Arc::new(snapshot);
+    }
+
+    /// Returns the published snapshot.
+    pub fn current(&self) -> Arc<Snapshot> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+/// Serves `published` as `/<name>` (and its entries under `/files/`) to connections on
+/// `listener`, each on its own thread (up to `MAX_CONNECTIONS` of them), until accepting a
+/// connection fails.
+pub fn serve(listener: TcpListener, name: &str, published: Arc<Published>) -> io::Result<()> {
+    let name = Arc::new(name.to_string());
+    let slots = Arc::new(Slots { used: Mutex::new(0), freed: Condvar::new() });
+    loop {
+        // connections that arrive while every slot's used wait in the listener's backlog
+        let slot = Slots::take(&slots);
+        let (stream, _) = listener.accept()?;
+        let name = name.clone();
+        let published = published.clone();
+        thread::spawn(move || {
+            let _slot = slot;
+            // a client that goes away only affects its own connection
+            let _ = handle(stream, &name, &published);
+        });
+    }
+}
+
+/// The number of connections being handled, of `MAX_CONNECTIONS`.
+struct Slots {
+    used: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl Slots {
+
+    /// Waits for a slot to be free, and takes it until the returned `Slot` is dropped.
+    fn take(slots: &Arc<Slots>) -> Slot {
+        let mut used = slots.used.lock().unwrap();
+        while *used >= MAX_CONNECTIONS {
+            used = slots.freed.wait(used).unwrap();
+        }
+        *used += 1;
+        Slot(slots.clone())
+    }
+}
+
+struct Slot(Arc<Slots>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        *self.0.used.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+struct Response {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Body,
+}
+
+enum Body {
+    Empty,
+    Owned(Vec<u8>),
+    Shared(Arc<Vec<u8>>),
+    /// A range of the snapshot's archive data.
+    Archive(Arc<Snapshot>, usize, usize),
+}
+
+impl Body {
+    fn bytes(&self) -> &[u8] {
+        match *self {
+            Body::Empty => &[],
+            Body::Owned(ref data) => data,
+            Body::Shared(ref data) => data,
+            Body::Archive(ref snapshot, start, end) => &snapshot.data()[start..end],
+        }
+    }
+}
+
+fn handle(stream: TcpStream, name: &str, published: &Published) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?.take(MAX_HEAD_SIZE));
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.ends_with('\n') && reader.get_ref().limit() == 0 {
+        return respond(stream, false, text("431 Request Header Fields Too Large", "the request's head is too large\n"));
+    }
+    let mut request_line = line.split_whitespace();
+    let (method, target) = match (request_line.next(), request_line.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return respond(stream, false, text("400 Bad Request", "malformed request\n")),
+    };
+
+    let mut range = None;
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        if !line.ends_with('\n') && reader.get_ref().limit() == 0 {
+            return respond(stream, false, text("431 Request Header Fields Too Large", "the request's head is too large\n"));
+        }
+        if line.trim_end().is_empty() {
+            break;
+        }
+        if let Some(i) = line.find(':') {
+            if line[..i].trim().eq_ignore_ascii_case("range") {
+                range = Some(line[i + 1..].trim().to_string());
+            }
+        }
+    }
+
+    let is_head = method == "HEAD";
+    if method != "GET" && !is_head {
+        return respond(stream, false, text("405 Method Not Allowed", "only GET and HEAD are supported\n"));
+    }
+
+    let snapshot = published.current();
+    let mut response = match percent_decode(target.split('?').next().unwrap()) {
+        Some(path) => answer(&snapshot, name, &path, range.as_deref()),
+        None => text("400 Bad Request", "malformed path\n"),
+    };
+    response.headers.push(("X-Fo2dat-Generation", snapshot.generation.to_string()));
+    respond(stream, is_head, response)
+}
+
+fn answer(snapshot: &Arc<Snapshot>, name: &str, path: &str, range: Option<&str>) -> Response {
+    if path.trim_start_matches('/') == name {
+        return archive_range(snapshot, range);
+    }
+
+    let entry_path = match path.strip_prefix("/files/") {
+        Some(entry_path) => entry_path,
+        None if path == "/files" => "",
+        None => return text("404 Not Found", "no such path: try /files/ or the archive's name\n"),
+    };
+
+    let dat = snapshot.dat();
+    if dat.get(entry_path).is_some() {
+        return match snapshot.cache.content(dat, entry_path) {
+            Ok(content) => Response {
+                status: "200 OK",
+                headers: vec![("Content-Type", "application/octet-stream".to_string())],
+                body: Body::Shared(content),
+            },
+            Err(e) => text("500 Internal Server Error", &format!("{}: {}\n", entry_path, e)),
+        };
+    }
+
+    match dat.dir(entry_path) {
+        Some(dir) => {
+            let mut listing = String::new();
+            for subdir in dir.children() {
+                listing.push_str(subdir.name());
+                listing.push_str("/\n");
+            }
+            for entry in dir.files() {
+                listing.push_str(entry.file_name().unwrap_or(entry.name));
+                listing.push('\n');
+            }
+            text("200 OK", &listing)
+        },
+        None => text("404 Not Found", &format!("{}: no such entry\n", names::to_slashes(entry_path))),
+    }
+}
+
+/// Answers a request for the archive, or the range of it in `range` (a `Range` header's value,
+/// of which only a single `bytes=` range is supported).
+fn archive_range(snapshot: &Arc<Snapshot>, range: Option<&str>) -> Response {
+    let len = snapshot.data().len();
+    let mut headers = vec![("Content-Type", "application/octet-stream".to_string()), ("Accept-Ranges", "bytes".to_string())];
+
+    let range = match range.and_then(|range| range.strip_prefix("bytes=")) {
+        Some(range) => range,
+        None => return Response { status: "200 OK", headers, body: Body::Archive(snapshot.clone(), 0, len) },
+    };
+
+    let bounds = range.find('-').and_then(|i| {
+        let (first, last) = (&range[..i], &range[i + 1..]);
+        match (first.parse::<usize>().ok(), last.parse::<usize>().ok()) {
+            (Some(first), Some(last)) if first <= last => Some((first, last.min(len.saturating_sub(1)))),
+            (Some(first), None) if last.is_empty() => Some((first, len.saturating_sub(1))),
+            // the last `suffix` bytes
+            (None, Some(suffix)) if first.is_empty() && suffix > 0 => Some((len.saturating_sub(suffix), len.saturating_sub(1))),
+            _ => None,
+        }
+    });
+
+    match bounds {
+        Some((first, last)) if first < len => {
+            headers.push(("Content-Range", format!("bytes {}-{}/{}", first, last, len)));
+            Response { status: "206 Partial Content", headers, body: Body::Archive(snapshot.clone(), first, last + 1) }
+        },
+        _ => {
+            headers.push(("Content-Range", format!("bytes */{}", len)));
+            Response { status: "416 Range Not Satisfiable", headers, body: Body::Empty }
+        },
+    }
+}
+
+fn text(status: &'static str, body: &str) -> Response {
+    Response {
+        status,
+        headers: vec![("Content-Type", "text/plain; charset=utf-8".to_string())],
+        body: Body::Owned(body.as_bytes().to_vec()),
+    }
+}
+
+fn respond(mut stream: TcpStream, is_head: bool, response: Response) -> io::Result<()> {
+    let body = response.body.bytes();
+    write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, body.len())?;
+    for (name, value) in &response.headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+    write!(stream, "\r\n")?;
+    if !is_head {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
+/// Decodes the `%XX` escapes of a URL's path, returning `None` if they're malformed or don't
+/// decode to UTF-8.
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = path.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
@@ -0,0 +1,141 @@
+//! Telling text content from binary content, by looking at its first bytes.
+//!
+//! Content is binary if it starts with a known format's magic (e.g. a PNG's, or an FRM's version)
+//! or has NUL bytes or more than a few other control characters; otherwise, it's text. Text is
+//! further told apart by its character set: ASCII, UTF-8, or an 8-bit one, of which cp866 (which
+//! the Russian releases' text uses) is told apart from the others by where its letters are.
+
+use std::io;
+use std::io::Read;
+
+use is_zlib_compressed;
+
+/// The number of bytes of content that are looked at.
+pub const SAMPLE_SIZE: usize = 8 * 1024;
+
+/// The magics of formats that are recognized, with their names, which `Kind::Binary` gives.
+const MAGICS: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (b"GIF87a", "gif"),
+    (b"GIF89a", "gif"),
+    (b"\xff\xd8\xff", "jpeg"),
+    (b"RIFF", "riff"),
+    (b"OggS", "ogg"),
+    (b"PK\x03\x04", "zip"),
+    (b"\x1f\x8b", "gzip"),
+    (b"\x7fELF", "elf"),
+    (b"Interplay MVE File\x1a", "mve"),
+    // ACM's signature, 0x01032897, little-endian
+    (b"\x97\x28\x03\x01", "acm"),
+    (b"AAFF", "aaf"),
+    // an FRM's version (4), big-endian, which is all it starts with
+    (b"\x00\x00\x00\x04", "frm"),
+];
+
+/// The most control characters (other than whitespace) that text may have, per this many bytes.
+const MAX_CONTROLS_PER: usize = 100;
+
+/// The character set of text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// 7-bit ASCII, which is also valid in every other character set.
+    Ascii,
+    Utf8,
+    /// DOS Cyrillic, which the Russian releases use.
+    Cp866,
+    /// Any other 8-bit character set, e.g. Windows-1252.
+    Other8Bit,
+}
+
+impl Charset {
+    /// Returns the character set's name (e.g. `utf-8`).
+    pub fn name(self) -> &'static str {
+        match self {
+            Charset::Ascii => "ascii",
+            Charset::Utf8 => "utf-8",
+            Charset::Cp866 => "cp866",
+            Charset::Other8Bit => "8-bit",
+        }
+    }
+}
+
+/// What content looks like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Text, in a character set. Empty content is ASCII text.
+    Text(Charset),
+    /// Binary content, with the name of its format (e.g. `png`), if it's known.
+    Binary(Option<&'static str>),
+}
+
+impl Kind {
+    pub fn is_text(self) -> bool {
+        match self {
+            Kind::Text(_) => true,
+            Kind::Binary(_) => false,
+        }
+    }
+}
+
+/// Returns what `content` looks like, going by its first `SAMPLE_SIZE` bytes.
+pub fn sniff(content: &[u8]) -> Kind {
+    if let Some(format) = magic(content) {
+        return Kind::Binary(Some(format));
+    }
+    if is_zlib_compressed(content) {
+        return Kind::Binary(Some("zlib"));
+    }
+
+    let is_truncated = content.len() > SAMPLE_SIZE;
+    let sample = &content[..content.len().min(SAMPLE_SIZE)];
+
+    let is_control = |b: u8| (b < 0x20 && !b"\t\n\r\x0c\x1a".contains(&b)) || b == 0x7f;
+    let num_controls = sample.iter().filter(|&&b| is_control(b)).count();
+    if sample.contains(&0) || num_controls * MAX_CONTROLS_PER > sample.len() {
+        return Kind::Binary(None);
+    }
+
+    if sample.is_ascii() {
+        return Kind::Text(Charset::Ascii);
+    }
+
+    // a sample that was cut short may end part way through a character
+    match std::str::from_utf8(sample) {
+        Ok(_) => return Kind::Text(Charset::Utf8),
+        Err(ref e) if is_truncated && e.error_len().is_none() => return Kind::Text(Charset::Utf8),
+        Err(_) => {},
+    }
+
+    // cp866's letters are at 0x80-0xaf and 0xe0-0xf1, and its box drawing characters between. Other
+    // 8-bit character sets (e.g. Windows-1252 and Windows-1251) have their letters at 0xc0-0xff,
+    // and little but punctuation and symbols at 0x80-0xaf, where Russian text in cp866 has about
+    // half of its letters (including its commonest, о, е and а)
+    let (mut num_high, mut num_first_letters, mut num_letters) = (0, 0, 0);
+    for &b in sample.iter().filter(|&&b| b >= 0x80) {
+        num_high += 1;
+        if b <= 0xaf {
+            num_first_letters += 1;
+            num_letters += 1;
+        } else if (0xe0..=0xf1).contains(&b) {
+            num_letters += 1;
+        }
+    }
+    if num_first_letters * 3 > num_high && num_letters * 10 >= num_high * 9 {
+        Kind::Text(Charset::Cp866)
+    } else {
+        Kind::Text(Charset::Other8Bit)
+    }
+}
+
+/// Returns what the content read from `reader` looks like, reading no more of it than needed.
+pub fn sniff_reader<R: Read>(reader: R) -> io::Result<Kind> {
+    // a byte more than is looked at tells whether the sample was cut short
+    let mut sample = Vec::with_capacity(SAMPLE_SIZE + 1);
+    reader.take(SAMPLE_SIZE as u64 + 1).read_to_end(&mut sample)?;
+    Ok(sniff(&sample))
+}
+
+/// Returns the name of the known format (e.g. `png`) whose magic `content` starts with, if any.
+pub fn magic(content: &[u8]) -> Option<&'static str> {
+    MAGICS.iter().find(|&&(magic, _)| content.starts_with(magic)).map(|&(_, format)| format)
+}
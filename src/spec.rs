@@ -0,0 +1,192 @@
+//! Archive specs: a DAT2 archive's tree without its data, as JSON.
+//!
+//! A spec lists every entry's stored path and compressed flag in tree order, with the options the
+//! archive was written with, so that an archive can be rebuilt from its spec and a directory of
+//! its files with the same tree, and its definition kept in source control. For example:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "dat64": false,
+//!   "crc_table": false,
+//!   "entries": [
+//!     {"path": "art\\intrface\\iface.frm", "compressed": true},
+//!     {"path": "text\\english\\game\\misc.msg", "compressed": false, "file": "misc.msg"}
+//!   ]
+//! }
+//! ```
+//!
+//! An entry's content is read from its `file`, relative to the directory the archive is rebuilt
+//! from, or else from its path (split at `\` and `/`).
+
+use std::io;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Write;
+
+use errors;
+use errors::Code;
+use json;
+use json::Value;
+use Dat;
+
+/// The version of the spec format that's written, and the newest that's read.
+pub const VERSION: u32 = 1;
+
+/// An archive's spec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spec {
+    /// Whether the archive is a DAT64 archive (see `DAT64_MAGIC`).
+    pub dat64: bool,
+    /// Whether the archive has a CRC table (see `extension`).
+    pub crc_table: bool,
+    pub entries: Vec<SpecEntry>,
+}
+
+/// An entry of a `Spec`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecEntry {
+    /// The entry's path, as stored in the tree.
+    pub path: String,
+    /// Whether the entry's data is compressed.
+    pub compressed: bool,
+    /// The file that the entry's content is read from, if it isn't at the entry's path.
+    pub file: Option<String>,
+}
+
+impl Spec {
+
+    /// Returns the spec of `dat`. Entries are flagged as compressed by their data, rather than
+    /// their (unreliable) flags.
+    pub fn of(dat: &Dat) -> io::Result<Self> {
+        let entries = dat.tree_entries().iter().map(|tree_entry| {
+            Ok(SpecEntry {
+                path: tree_entry.name.to_string(),
+                compressed: ::Method::of_entry(tree_entry, dat.data(tree_entry)?) != ::Method::Stored,
+                file: None,
+            })
+        }).collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Spec {
+            dat64: dat.stats().is_dat64,
+            crc_table: dat.crc_table().ok().and_then(|crcs| crcs).is_some(),
+            entries,
+        })
+    }
+
+    /// Parses a spec from its JSON.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let root = json::parse(text)?;
+
+        match root.get("version").and_then(Value::as_f64) {
+            Some(version) if version >= 1.0 && version <= VERSION as f64 => {},
+            Some(version) => return Err(invalid(&format!("unsupported version {} (at most {} is supported)", version, VERSION))),
+            None => return Err(invalid("missing \"version\"")),
+        }
+
+        let flag = |name: &str| -> io::Result<bool> {
+            match root.get(name) {
+                Some(value) => value.as_bool().ok_or_else(|| invalid(&format!("\"{}\" must be true or false", name))),
+                None => Ok(false),
+            }
+        };
+
+        let entries = root.get("entries").and_then(Value::as_array).ok_or_else(|| invalid("missing \"entries\" array"))?;
+        let entries = entries.iter().enumerate().map(|(i, entry)| {
+            let path = entry.get("path").and_then(Value::as_str).ok_or_else(|| {
+                invalid(&format!("entry {}: missing \"path\"", i))
+            })?;
+            let compressed = match entry.get("compressed") {
+                Some(compressed) => compressed.as_bool().ok_or_else(|| {
+                    invalid(&format!("{}: \"compressed\" must be true or false", path))
+                })?,
+                None => true,
+            };
+            let file = match entry.get("file") {
+                Some(file) => Some(file.as_str().ok_or_else(|| invalid(&format!("{}: \"file\" must be a string", path)))?.to_string()),
+                None => None,
+            };
+            Ok(SpecEntry { path: path.to_string(), compressed, file })
+        }).collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Spec { dat64: flag("dat64")?, crc_table: flag("crc_table")?, entries })
+    }
+
+    /// Writes the spec as JSON, with one entry per line (so that changes diff well).
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"version\": {},", VERSION)?;
+        writeln!(out, "  \"dat64\": {},", self.dat64)?;
+        writeln!(out, "  \"crc_table\": {},", self.crc_table)?;
+        writeln!(out, "  \"entries\": [")?;
+        for (i, entry) in self.entries.iter().enumerate() {
+            write!(out, "    {{\"path\": ")?;
+            json::write_str(out, &entry.path)?;
+            write!(out, ", \"compressed\": {}", entry.compressed)?;
+            if let Some(ref file) = entry.file {
+                write!(out, ", \"file\": ")?;
+                json::write_str(out, file)?;
+            }
+            writeln!(out, "}}{}", if i + 1 < self.entries.len() { "," } else { "" })?;
+        }
+        writeln!(out, "  ]")?;
+        writeln!(out, "}}")
+    }
+}
+
+impl SpecEntry {
+    /// Returns the components of the path, relative to the directory the archive is rebuilt
+    /// from, of the file that the entry's content is read from.
+    pub fn file_components(&self) -> impl Iterator<Item = &str> {
+        self.file.as_ref().unwrap_or(&self.path).split(['\\', '/']).filter(|component| !component.is_empty())
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    errors::new(Code::Format, ErrorKind::InvalidData, format!("invalid spec: {}", msg))
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::*;
+    use testutil;
+    use writer::{NewEntry, Source, WriteOptions};
+
+    #[test]
+    fn archives_are_rebuilt_from_their_spec() {
+        let mut entries = testutil::sample_entries(20, 11);
+        // some entries that would compress are stored, which the spec has to keep
+        for entry in entries.iter_mut().step_by(3) {
+            entry.compress = Some(false);
+        }
+        let options = WriteOptions { crc_table: true, ..WriteOptions::default() };
+        let data = testutil::build(&entries, &options).unwrap();
+        let dat = Dat::parse(&data).unwrap();
+
+        let spec = Spec::of(&dat).unwrap();
+        assert!(spec.crc_table);
+        assert!(spec.entries.iter().any(|entry| entry.compressed) && spec.entries.iter().any(|entry| !entry.compressed));
+        let mut json = Vec::new();
+        spec.write_json(&mut json).unwrap();
+        let parsed = Spec::parse(&String::from_utf8(json).unwrap()).unwrap();
+        assert_eq!(parsed, spec);
+
+        // as `create --from-spec` rebuilds it, with each entry's content at its path
+        let rebuilt_entries: Vec<NewEntry> = parsed.entries.iter().zip(dat.tree_entries()).map(|(entry, tree_entry)| NewEntry {
+            name: entry.path.clone(),
+            source: Source::Data(::decompress_entry(tree_entry, dat.data(tree_entry).unwrap()).unwrap()),
+            compress: Some(entry.compressed),
+        }).collect();
+        let rebuilt_options = WriteOptions { crc_table: parsed.crc_table, dat64: parsed.dat64, ..WriteOptions::default() };
+        let rebuilt_data = testutil::build(&rebuilt_entries, &rebuilt_options).unwrap();
+        let rebuilt = Dat::parse(&rebuilt_data).unwrap();
+
+        assert_eq!(Spec::of(&rebuilt).unwrap(), spec);
+        for (tree_entry, rebuilt_entry) in dat.tree_entries().iter().zip(rebuilt.tree_entries()) {
+            assert_eq!(rebuilt_entry.name, tree_entry.name);
+            assert_eq!(rebuilt_entry.is_compressed, tree_entry.is_compressed);
+            assert_eq!(::decompress_entry(rebuilt_entry, rebuilt.data(rebuilt_entry).unwrap()).unwrap(),
+                       ::decompress_entry(tree_entry, dat.data(tree_entry).unwrap()).unwrap());
+        }
+    }
+}
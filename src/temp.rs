@@ -0,0 +1,73 @@
+//! Temporary files, for commands that take files rather than pipes (e.g. viewers and external
+//! converters). They're created in a directory of their own, in the system's temporary directory,
+//! that only the user can access, so that other users can neither read them nor plant files (or
+//! symbolic links) at their paths for fo2dat to write through or read back.
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use errors;
+
+/// Numbers temporary directories, which are also named after the process, so that the process's
+/// threads don't contend for names.
+static NEXT_TEMP_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory, in the system's temporary directory, that only the user can access, and which is
+/// removed (with its contents) when it's dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn create() -> io::Result<Self> {
+        let mut dir_builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            dir_builder.mode(0o700);
+        }
+
+        // creating it fails if the name's taken, so it can't be one that someone else prepared
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|since| since.subsec_nanos()).unwrap_or(0);
+        for _ in 0..100 {
+            let n = NEXT_TEMP_DIR.fetch_add(1, Ordering::Relaxed);
+            let path = env::temp_dir().join(format!("fo2dat-{}-{}-{}", std::process::id(), nanos, n));
+            match dir_builder.create(&path) {
+                Ok(()) => return Ok(TempDir { path }),
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(errors::with_context(e, path.display())),
+            }
+        }
+
+        Err(Error::new(ErrorKind::AlreadyExists, "cannot create a temporary directory: too many exist"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Creates a file named `name` in the directory, which only the user can access, failing if
+    /// one exists.
+    pub fn create_file(&self, name: &str) -> io::Result<File> {
+        let path = self.path.join(name);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(&path).map_err(|e| errors::with_context(e, path.display()))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
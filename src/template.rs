@@ -0,0 +1,102 @@
+//! Templates for custom output, e.g. `{path}\t{size}`.
+//!
+//! A template is literal text with `{NAME}` placeholders, each replaced with the named field of
+//! whatever is being written. `{{` and `}}` are literal braces, and `\t`, `\n` and `\\` are tab,
+//! newline and backslash, which are awkward to pass on a command line otherwise.
+
+use std::io;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Write;
+
+/// A parsed template, whose placeholders are fields of type `F`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template<F> {
+    segments: Vec<Segment<F>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment<F> {
+    Literal(String),
+    Field(F),
+}
+
+impl <F: Copy> Template<F> {
+
+    /// Parses `template`, looking up the field of each placeholder by name with `field`.
+    pub fn parse<L: Fn(&str) -> Option<F>>(template: &str, field: L) -> io::Result<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                },
+                '{' => {
+                    let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    match field(&name) {
+                        Some(field) => {
+                            if !literal.is_empty() {
+                                segments.push(Segment::Literal(literal.split_off(0)));
+                            }
+                            segments.push(Segment::Field(field));
+                        },
+                        None => {
+                            let err_msg = format!("{}: unknown placeholder {{{}}}", template, name);
+                            return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+                        },
+                    }
+                },
+                '}' => {
+                    let err_msg = format!("{}: unmatched '}}' (use '}}}}' for a literal one)", template);
+                    return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+                },
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('\\') => literal.push('\\'),
+                    Some(c) => {
+                        literal.push('\\');
+                        literal.push(c);
+                    },
+                    None => literal.push('\\'),
+                },
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template { segments })
+    }
+
+    /// Returns the fields of the template's placeholders, in order.
+    pub fn fields<'t>(&'t self) -> impl Iterator<Item = F> + 't {
+        self.segments.iter().filter_map(|segment| match *segment {
+            Segment::Field(field) => Some(field),
+            Segment::Literal(_) => None,
+        })
+    }
+
+    /// Writes the template to `out`, using `write_field` to write each placeholder's field.
+    pub fn write<W, G>(&self, out: &mut W, mut write_field: G) -> io::Result<()>
+        where W: Write, G: FnMut(&mut W, F) -> io::Result<()>
+    {
+        for segment in &self.segments {
+            match *segment {
+                Segment::Literal(ref literal) => out.write_all(literal.as_bytes())?,
+                Segment::Field(field) => write_field(out, field)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+//! Building of archives for tests and fuzzers (the `testutil` feature): valid ones with given (or
+//! generated) entries, and ones with a given defect, so that projects using fo2dat can make
+//! fixtures without shipping the game's data.
+//!
+//! Archives are written by `writer`, so a valid one is as fo2dat would create it. A malformed one
+//! is a valid one with fields or data overwritten afterwards (see `Defect`).
+
+use std::io;
+use std::io::Error;
+use std::io::ErrorKind;
+use byteorder::{ByteOrder, LittleEndian};
+
+use writer;
+use writer::{NewEntry, Source, WriteOptions};
+use DatStats;
+
+/// A way for an archive to be malformed. Defects of an entry give its index in the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Defect {
+    /// The archive is cut short to its first `n` bytes (e.g. fewer than its footer's).
+    Truncated(usize),
+    /// The `file_size` field is one more than the archive's size.
+    FileSizeMismatch,
+    /// The `tree_size` field states a tree larger than the archive.
+    TreeTooLarge,
+    /// The `num_files` field states one more entry than the tree has.
+    TooManyFiles,
+    /// The entry's name length runs past the end of the tree.
+    NameTooLong(usize),
+    /// The entry's data starts past the end of the data section.
+    DataOutOfBounds(usize),
+    /// The entry's data is overwritten after its first two bytes (which, if it's compressed, are
+    /// its zlib header, so that it still looks compressed but fails to decompress). Entries that
+    /// share its data (see `WriteOptions::dedup`) are corrupted too.
+    CorruptData(usize),
+    /// The entry states a decompressed size one more than its content's.
+    SizeMismatch(usize),
+    /// The entry is flagged as stored if it's compressed, or as compressed if it's stored.
+    FlagMismatch(usize),
+}
+
+/// Returns an entry named `name` (with `\` or `/` separators), with `content`.
+pub fn entry(name: &str, content: &[u8]) -> NewEntry {
+    NewEntry { name: name.replace('/', "\\"), source: Source::Data(content.to_vec()), compress: None }
+}
+
+/// Returns `count` entries with pseudo-random paths, sizes and content (some text, which
+/// compresses, and some random bytes, which doesn't), which are the same for the same `seed`.
+pub fn sample_entries(count: usize, seed: u64) -> Vec<NewEntry> {
+    const DIRS: &[(&str, &str, bool)] = &[
+        ("art\\critters", "frm", false),
+        ("art\\items", "frm", false),
+        ("text\\english\\game", "msg", true),
+        ("text\\english\\dialog", "msg", true),
+        ("proto\\items", "pro", false),
+        ("scripts", "int", false),
+        ("sound\\sfx", "acm", false),
+        ("data", "lst", true),
+    ];
+    const WORDS: &[&str] = &["vault", "dweller", "wasteland", "brahmin", "caps", "radscorpion", "pip-boy", "geck", "arroyo", "klamath"];
+
+    let mut rng = Rng::new(seed);
+    (0..count).map(|i| {
+        let (dir, extension, is_text) = DIRS[rng.below(DIRS.len())];
+        // mostly small entries, as the game's are, with a few large ones
+        let max_size = if rng.below(8) == 0 { 64 * 1024 } else { 2048 };
+        let size = rng.below(max_size);
+
+        let content = if is_text {
+            let mut text = String::with_capacity(size + 32);
+            let mut line = 100;
+            while text.len() < size {
+                text.push_str(&format!("{{{}}}{{}}{{{} {}}}\r\n", line, WORDS[rng.below(WORDS.len())], WORDS[rng.below(WORDS.len())]));
+                line += 1;
+            }
+            text.into_bytes()
+        } else {
+            (0..size).map(|_| rng.next() as u8).collect()
+        };
+
+        NewEntry { name: format!("{}\\{:05}.{}", dir, i, extension), source: Source::Data(content), compress: None }
+    }).collect()
+}
+
+/// Returns an archive of `entries`, written with `options`.
+pub fn build(entries: &[NewEntry], options: &WriteOptions) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    writer::write_dat(&mut data, entries, options)?;
+    Ok(data)
+}
+
+/// Returns an archive of `entries`, written with `options`, with `defect`.
+pub fn build_malformed(entries: &[NewEntry], options: &WriteOptions, defect: Defect) -> io::Result<Vec<u8>> {
+    let mut data = build(entries, options)?;
+    let stats = ::stats(&data)?;
+    let width = if stats.is_dat64 { 8 } else { 4 };
+    let tree_size_offset = stats.tree_range.end;
+    let file_size_offset = tree_size_offset + width;
+
+    match defect {
+        Defect::Truncated(n) => data.truncate(n),
+        Defect::FileSizeMismatch => add_to_field(&mut data, file_size_offset, width, 1),
+        Defect::TreeTooLarge => add_to_field(&mut data, tree_size_offset, width, stats.file_size as u64),
+        Defect::TooManyFiles => add_to_field(&mut data, stats.data_range.end, width, 1),
+        Defect::NameTooLong(i) => {
+            let offset = entry_offset(&data, &stats, i)?;
+            LittleEndian::write_u32(&mut data[offset..], stats.tree_range.len() as u32);
+        },
+        Defect::DataOutOfBounds(i) => {
+            let fields = flag_offset(&data, &stats, i)? + 1;
+            write_field(&mut data, fields + 2 * width, width, stats.data_range.end as u64 + 1);
+        },
+        Defect::CorruptData(i) => {
+            let fields = flag_offset(&data, &stats, i)? + 1;
+            let packed_size = read_field(&data, fields + width, width) as usize;
+            let offset = read_field(&data, fields + 2 * width, width) as usize;
+            for b in data[offset..offset + packed_size].iter_mut().skip(2) {
+                *b = 0xff;
+            }
+        },
+        Defect::SizeMismatch(i) => {
+            let fields = flag_offset(&data, &stats, i)? + 1;
+            add_to_field(&mut data, fields, width, 1);
+        },
+        Defect::FlagMismatch(i) => {
+            let flag = flag_offset(&data, &stats, i)?;
+            data[flag] ^= 1;
+        },
+    }
+
+    Ok(data)
+}
+
+/// Returns the offset of the tree entry at `index` in `data`.
+fn entry_offset(data: &[u8], stats: &DatStats, index: usize) -> io::Result<usize> {
+    if index >= stats.num_files {
+        let err_msg = format!("no entry {} (the archive has {})", index, stats.num_files);
+        return Err(Error::new(ErrorKind::InvalidInput, err_msg));
+    }
+
+    let width = if stats.is_dat64 { 8 } else { 4 };
+    let mut offset = stats.tree_range.start;
+    for _ in 0..index {
+        offset += 4 + name_len(data, offset) + 1 + 3 * width;
+    }
+    Ok(offset)
+}
+
+/// Returns the offset of the compressed flag of the tree entry at `index` in `data`, which its
+/// size and offset fields follow.
+fn flag_offset(data: &[u8], stats: &DatStats, index: usize) -> io::Result<usize> {
+    let offset = entry_offset(data, stats, index)?;
+    Ok(offset + 4 + name_len(data, offset))
+}
+
+fn name_len(data: &[u8], entry_offset: usize) -> usize {
+    LittleEndian::read_u32(&data[entry_offset..]) as usize
+}
+
+fn read_field(data: &[u8], offset: usize, width: usize) -> u64 {
+    if width == 8 { LittleEndian::read_u64(&data[offset..]) } else { LittleEndian::read_u32(&data[offset..]) as u64 }
+}
+
+fn write_field(data: &mut [u8], offset: usize, width: usize, value: u64) {
+    if width == 8 {
+        LittleEndian::write_u64(&mut data[offset..], value);
+    } else {
+        LittleEndian::write_u32(&mut data[offset..], value as u32);
+    }
+}
+
+fn add_to_field(data: &mut [u8], offset: usize, width: usize, n: u64) {
+    let value = read_field(data, offset, width);
+    write_field(data, offset, width, value + n);
+}
+
+/// A xorshift64* generator, which is all that generating entries needs.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // a state of 0 would stay 0, so the one seed that gives it gets another
+        let state = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        Rng { state: if state == 0 { 1 } else { state } }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use doctor;
+    use doctor::Severity;
+    use writer::Source;
+    use {for_each_chunk_with, Dat, Method, DEFAULT_CHUNK_SIZE};
+
+    /// How far along checking an archive its defect is found.
+    #[derive(Debug, PartialEq, Eq)]
+    enum Caught {
+        /// `Dat::parse` rejects the archive.
+        Parse,
+        /// Reading an entry's content, as `verify` does, fails or gives the wrong size.
+        Verify,
+        /// The archive reads fine, and only `doctor` finds something wrong with it, of (at worst)
+        /// the given severity.
+        Doctor(Severity),
+    }
+
+    fn content(dat: &Dat, index: usize) -> io::Result<Vec<u8>> {
+        let tree_entry = &dat.tree_entries()[index];
+        let entry_data = dat.data(tree_entry)?;
+        let mut content = Vec::new();
+        for_each_chunk_with(entry_data, Method::of_entry(tree_entry, entry_data), DEFAULT_CHUNK_SIZE, |chunk| {
+            content.extend_from_slice(chunk);
+            Ok(())
+        })?;
+        Ok(content)
+    }
+
+    /// Returns where the defect in `data` is first caught, after checking that `doctor` finds it.
+    fn caught(data: &[u8]) -> Caught {
+        let diagnoses = doctor::diagnose(data, "broken.dat");
+        assert!(!diagnoses.is_empty(), "doctor found nothing wrong");
+
+        let dat = match Dat::parse(data) {
+            Ok(dat) => dat,
+            Err(_) => return Caught::Parse,
+        };
+        for (i, tree_entry) in dat.tree_entries().iter().enumerate() {
+            match content(&dat, i) {
+                Ok(ref content) if content.len() == tree_entry.decompressed_size => {},
+                _ => return Caught::Verify,
+            }
+        }
+        Caught::Doctor(diagnoses.iter().map(|diagnosis| diagnosis.severity).min().unwrap())
+    }
+
+    fn malformed(defect: Defect) -> Vec<u8> {
+        let entries = vec![
+            entry("art/critters/hmjmpsaa.frm", &[0x42; 4096]),
+            entry("text/english/game/misc.msg", b"{100}{}{Vault 13}\r\n{101}{}{Vault City}\r\n"),
+            entry("data/vault13.gam", b"GAME_GLOBAL_VARS:\r\n"),
+        ];
+        build_malformed(&entries, &WriteOptions::default(), defect).unwrap()
+    }
+
+    #[test]
+    fn sample_entries_round_trip() {
+        let entries = sample_entries(200, 7);
+        for options in &[WriteOptions::default(), WriteOptions { dat64: true, ..WriteOptions::default() }] {
+            let data = build(&entries, options).unwrap();
+            let dat = Dat::parse(&data).unwrap();
+            assert_eq!(dat.len(), entries.len());
+            for new_entry in &entries {
+                let expected = match new_entry.source {
+                    Source::Data(ref expected) => expected,
+                    _ => unreachable!(),
+                };
+                let index = dat.index_of(&new_entry.name).unwrap_or_else(|| panic!("{}: missing", new_entry.name));
+                assert_eq!(&content(&dat, index).unwrap(), expected, "{}", new_entry.name);
+            }
+            assert!(doctor::diagnose(&data, "sample.dat").is_empty());
+        }
+    }
+
+    #[test]
+    fn sample_entries_depend_only_on_the_seed() {
+        let names = |entries: Vec<NewEntry>| entries.into_iter().map(|entry| entry.name).collect::<Vec<_>>();
+        assert_eq!(names(sample_entries(50, 1)), names(sample_entries(50, 1)));
+        assert_ne!(names(sample_entries(50, 1)), names(sample_entries(50, 2)));
+    }
+
+    #[test]
+    fn truncated_is_rejected_by_parse() {
+        assert_eq!(caught(&malformed(Defect::Truncated(8))), Caught::Parse);
+        let len = malformed(Defect::FileSizeMismatch).len();
+        assert_eq!(caught(&malformed(Defect::Truncated(len - 1))), Caught::Parse);
+    }
+
+    #[test]
+    fn file_size_mismatch_is_rejected_by_parse() {
+        assert_eq!(caught(&malformed(Defect::FileSizeMismatch)), Caught::Parse);
+    }
+
+    #[test]
+    fn tree_too_large_is_rejected_by_parse() {
+        assert_eq!(caught(&malformed(Defect::TreeTooLarge)), Caught::Parse);
+    }
+
+    #[test]
+    fn too_many_files_is_an_error_to_doctor() {
+        // the tree's entries are all readable, but the engine would look for one more
+        assert_eq!(caught(&malformed(Defect::TooManyFiles)), Caught::Doctor(Severity::Error));
+    }
+
+    #[test]
+    fn name_too_long_is_rejected_by_parse() {
+        assert_eq!(caught(&malformed(Defect::NameTooLong(1))), Caught::Parse);
+    }
+
+    #[test]
+    fn data_out_of_bounds_is_rejected_by_verify() {
+        assert_eq!(caught(&malformed(Defect::DataOutOfBounds(0))), Caught::Verify);
+    }
+
+    #[test]
+    fn corrupt_data_is_rejected_by_verify() {
+        assert_eq!(caught(&malformed(Defect::CorruptData(0))), Caught::Verify);
+    }
+
+    #[test]
+    fn size_mismatch_is_rejected_by_verify() {
+        assert_eq!(caught(&malformed(Defect::SizeMismatch(1))), Caught::Verify);
+    }
+
+    #[test]
+    fn flag_mismatch_is_a_warning_to_doctor() {
+        assert_eq!(caught(&malformed(Defect::FlagMismatch(0))), Caught::Doctor(Severity::Warning));
+    }
+
+    #[test]
+    fn defects_of_missing_entries_are_errors() {
+        assert_eq!(build_malformed(&[entry("a.txt", b"a")], &WriteOptions::default(), Defect::CorruptData(1)).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
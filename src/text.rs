@@ -0,0 +1,113 @@
+//! Conversions of the game's text assets (e.g. MSG files and SSL source) as they're extracted, for
+//! working on them with tools that expect other conventions than the game's.
+//!
+//! The game's text files have CRLF line endings, which tools on other platforms (e.g. diff and
+//! version control, as translation teams use) see as a change on every line when they're mixed
+//! with LF ones. And localizations' text is in the 8-bit code page of their release (e.g. cp866
+//! for Russian ones), which modern editors don't open as such, so it can be transcoded to UTF-8 as
+//! it's extracted, and back as it's packed.
+
+use std::io;
+use std::io::Error;
+use std::io::ErrorKind;
+
+use encoding::Encoding;
+use sniff;
+
+/// The extensions of the text assets that are converted: message files, SSL scripts (and their
+/// headers), and the game's lists, configuration and global variable files.
+pub const TEXT_EXTENSIONS: &[&str] = &["msg", "txt", "ssl", "h", "lst", "gam", "cfg", "ini"];
+
+/// A line ending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`, as on Unix.
+    Lf,
+    /// `\r\n`, as the game's files have.
+    Crlf,
+}
+
+impl LineEnding {
+
+    /// Parses a line ending's name (`lf` or `crlf`).
+    pub fn from_name(name: &str) -> io::Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            _ => {
+                let err_msg = format!("{}: unsupported line ending (supported: lf, crlf)", name);
+                Err(Error::new(ErrorKind::InvalidInput, err_msg))
+            }
+        }
+    }
+}
+
+/// A conversion of text from one encoding to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transcoding {
+    pub from: Encoding,
+    pub to: Encoding,
+}
+
+impl Transcoding {
+
+    /// Parses a transcoding, given as `FROM:TO` (e.g. `cp866:utf-8`).
+    pub fn from_spec(spec: &str) -> io::Result<Self> {
+        match spec.split_once(':') {
+            Some((from, to)) => Ok(Transcoding { from: Encoding::from_name(from)?, to: Encoding::from_name(to)? }),
+            None => {
+                let err_msg = format!("{}: expected FROM:TO (e.g. cp866:utf-8)", spec);
+                Err(Error::new(ErrorKind::InvalidInput, err_msg))
+            }
+        }
+    }
+
+    /// Returns the opposite conversion, which text is packed with after being extracted with
+    /// this one.
+    pub fn reversed(self) -> Self {
+        Transcoding { from: self.to, to: self.from }
+    }
+
+    /// Returns `content` converted from `from` to `to`. Fails if it isn't valid in `from`, or has
+    /// characters that `to` doesn't, rather than lose them.
+    pub fn transcode(self, content: &[u8]) -> io::Result<Vec<u8>> {
+        if self.from == self.to {
+            return Ok(content.to_vec());
+        }
+        self.to.encode(&self.from.decode_exact(content)?)
+    }
+}
+
+/// Returns true if the entry named `name` has one of `TEXT_EXTENSIONS`, so may be a text asset.
+pub fn has_text_extension(name: &str) -> bool {
+    let extension = name.rsplit(['\\', '/']).next().and_then(|file_name| file_name.rsplit_once('.')).map(|(_, extension)| extension);
+    extension.is_some_and(|extension| TEXT_EXTENSIONS.iter().any(|text| text.eq_ignore_ascii_case(extension)))
+}
+
+/// Returns true if the entry named `name` is a text asset: if it has one of `TEXT_EXTENSIONS`, and
+/// its `content` looks like text (so that, e.g., a binary file that happens to be named `.txt`
+/// isn't mangled).
+pub fn is_text_asset(name: &str, content: &[u8]) -> bool {
+    has_text_extension(name) && sniff::sniff(content).is_text()
+}
+
+/// Returns `content` with its line endings converted to `line_ending`. A lone `\r` (which isn't a
+/// line ending the game's files use) is kept as it is.
+pub fn convert_line_endings(content: &[u8], line_ending: LineEnding) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(content.len() + content.len() / 32);
+    let mut bytes = content.iter().peekable();
+
+    while let Some(&byte) = bytes.next() {
+        match (byte, line_ending) {
+            (b'\r', LineEnding::Lf) if bytes.peek() == Some(&&b'\n') => {},
+            (b'\r', LineEnding::Crlf) if bytes.peek() == Some(&&b'\n') => {
+                converted.extend_from_slice(b"\r\n");
+                bytes.next();
+            },
+            (b'\n', LineEnding::Crlf) => converted.extend_from_slice(b"\r\n"),
+            _ => converted.push(byte),
+        }
+    }
+
+    converted
+}
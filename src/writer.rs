@@ -0,0 +1,558 @@
+//! Writing of DAT2 archives.
+//!
+//! The archive is written in one forward pass: each entry's (possibly compressed) data is written
+//! as it's read, followed by the tree, so the output doesn't need to be seekable and entries
+//! don't all need to be held in memory at once.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::path::PathBuf;
+use byteorder::{LittleEndian, WriteBytesExt};
+use flate2::Compression;
+use flate2::Crc;
+use flate2::write::ZlibEncoder;
+use rayon::prelude::*;
+
+use digest::Algorithm;
+use errors;
+use errors::Code;
+use extension;
+use extension::Block;
+use dictionary;
+use profile;
+use profile::Stage;
+use text;
+use text::Transcoding;
+use {decompress_with, Method, DAT64_MAGIC};
+
+/// An entry to write into an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewEntry {
+    /// The entry's path as stored in the tree (i.e. `\`-separated).
+    pub name: String,
+    pub source: Source,
+    /// Whether to compress the entry (even if that makes it larger) or store it, rather than as
+    /// the `WriteOptions` say.
+    pub compress: Option<bool>,
+}
+
+/// Where the content of a `NewEntry` comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A file, which is read when the entry is written.
+    File(PathBuf),
+    Data(Vec<u8>),
+    /// Another archive's raw entry data, stored with `method` (see `Method::of_entry`), which is
+    /// decompressed when the entry is written (so an archive can be rewritten while only holding
+    /// its packed data in memory).
+    Packed { data: Vec<u8>, method: Method, decompressed_size: usize },
+}
+
+/// An order to write entries in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryOrder {
+    /// By path, case-insensitively and component-wise (so a directory's entries are contiguous),
+    /// which gives the same tree for the same files however they were gathered.
+    Path,
+    /// In the order given.
+    None,
+    /// By decompressed size, smallest first, with entries of the same size ordered by path.
+    Size,
+}
+
+/// Options controlling how an archive is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Whether to zlib compress entries. An entry is stored uncompressed anyway if compressing it
+    /// wouldn't make it smaller.
+    pub compress: bool,
+    /// Whether to zlib compress every entry, even those that compressing makes larger (if
+    /// `compress`).
+    pub compress_all: bool,
+    /// The zlib level (0-9) that entries are compressed at.
+    pub level: u32,
+    /// Whether small text entries are compressed with fo2dat's preset dictionary (see
+    /// `dictionary`) where that makes them smaller, which the engine can't read them with.
+    pub dictionary: bool,
+    /// Whether entries with identical content share one copy of the data.
+    pub dedup: bool,
+    /// The digest that identical content is found by when deduplicating. Content with the same
+    /// digest is compared to confirm it's identical, unless the digest is collision resistant.
+    pub dedup_algorithm: Algorithm,
+    /// The separator stored between the components of entries' names. The engine expects `\`,
+    /// but some third-party tools expect `/`.
+    pub separator: char,
+    /// Whether to store each entry's CRC-32 in the archive's extension (see `extension`), which
+    /// the engine ignores.
+    pub crc_table: bool,
+    /// Whether to write a DAT64 archive (see `DAT64_MAGIC`), which may be larger than 4 GiB, but
+    /// which the engine can't load.
+    pub dat64: bool,
+    /// The tool (and its version) recorded as the archive's creator in its extension, if any.
+    pub tool: Option<String>,
+    /// A freetext comment recorded in the archive's extension, if any.
+    pub comment: Option<String>,
+    /// The most memory (in bytes) that entries' content may take while they're being prepared,
+    /// if it's bounded. Fewer entries are prepared at once to stay within it.
+    pub max_memory: Option<usize>,
+    /// The conversion of the content of text assets read from files (see `text::is_text_asset`),
+    /// if they're transcoded as they're packed.
+    pub text_encoding: Option<Transcoding>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions { compress: true, compress_all: false, level: BEST_LEVEL, dictionary: false, dedup: true, dedup_algorithm: Algorithm::Crc32, separator: '\\', crc_table: false, dat64: false, tool: None, comment: None, max_memory: None, text_encoding: None }
+    }
+}
+
+/// A summary of a written archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteSummary {
+    pub num_entries: usize,
+    /// The number of entries that point at an earlier entry's data, rather than their own.
+    pub num_deduplicated: usize,
+    /// The total size of the written archive.
+    pub file_size: usize,
+}
+
+/// The longest entry path the engine can open: its path buffers are `MAX_PATH` (260) bytes,
+/// including the terminating NUL.
+pub const MAX_NAME_LEN: usize = 259;
+
+/// The most components an entry path should have. The game's own archives are at most four deep,
+/// and the engine's path handling isn't known to cope with much more.
+pub const MAX_NAME_COMPONENTS: usize = 8;
+
+/// A reason the engine may be unable to load an entry with a given path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameProblem {
+    /// The path is longer than `MAX_NAME_LEN` bytes.
+    TooLong(usize),
+    /// The path has more than `MAX_NAME_COMPONENTS` components.
+    TooDeep(usize),
+    /// The path contains characters outside of ASCII, which the engine interprets using the
+    /// system's code page.
+    NonAscii,
+    /// The path contains a character that Windows doesn't allow in file names.
+    ForbiddenChar(char),
+    /// The path has an empty component (e.g. `art\\a.frm`).
+    EmptyComponent,
+}
+
+impl fmt::Display for NameProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NameProblem::TooLong(len) => write!(f, "path is {} bytes long, longer than the {} bytes the engine can open", len, MAX_NAME_LEN),
+            NameProblem::TooDeep(num_components) => write!(f, "path has {} components, more than the {} the engine is known to handle", num_components, MAX_NAME_COMPONENTS),
+            NameProblem::NonAscii => f.write_str("path contains non-ASCII characters, which the engine reads in the system's code page"),
+            NameProblem::ForbiddenChar(c) => write!(f, "path contains {:?}, which isn't allowed in file names", c),
+            NameProblem::EmptyComponent => f.write_str("path has an empty component"),
+        }
+    }
+}
+
+/// Returns the reasons the engine may be unable to load an entry named `name` (a `\`-separated
+/// path), if any.
+pub fn check_name(name: &str) -> Vec<NameProblem> {
+    let mut problems = Vec::new();
+
+    if name.len() > MAX_NAME_LEN {
+        problems.push(NameProblem::TooLong(name.len()));
+    }
+
+    let num_components = name.split('\\').count();
+    if num_components > MAX_NAME_COMPONENTS {
+        problems.push(NameProblem::TooDeep(num_components));
+    }
+
+    if !name.is_ascii() {
+        problems.push(NameProblem::NonAscii);
+    }
+
+    if let Some(c) = name.chars().find(|&c| c.is_ascii_control() || "<>:\"/|?*".contains(c)) {
+        problems.push(NameProblem::ForbiddenChar(c));
+    }
+
+    if name.split('\\').any(str::is_empty) {
+        problems.push(NameProblem::EmptyComponent);
+    }
+
+    problems
+}
+
+/// The location and encoding of data already written to the data section.
+#[derive(Clone, Copy)]
+struct Blob {
+    is_compressed: bool,
+    decompressed_size: usize,
+    packed_size: usize,
+    offset: usize,
+}
+
+impl Source {
+    fn read(&self) -> io::Result<Vec<u8>> {
+        match *self {
+            Source::File(ref path) => profile::time(Stage::Read, || fs::read(path)).map_err(|e| errors::with_context(e, path.display())),
+            Source::Data(ref data) => Ok(data.clone()),
+            Source::Packed { ref data, method, .. } => decompress_with(data, method),
+        }
+    }
+
+    /// Returns the size of the content, without reading it.
+    fn len(&self) -> io::Result<u64> {
+        match *self {
+            Source::File(ref path) => fs::metadata(path).map(|metadata| metadata.len()).map_err(|e| errors::with_context(e, path.display())),
+            Source::Data(ref data) => Ok(data.len() as u64),
+            Source::Packed { decompressed_size, .. } => Ok(decompressed_size as u64),
+        }
+    }
+}
+
+/// Sorts `entries` into `order`. The sort is stable, so entries with the same path stay in the
+/// order given.
+pub fn sort_entries(entries: &mut Vec<NewEntry>, order: EntryOrder) -> io::Result<()> {
+    match order {
+        EntryOrder::Path => entries.sort_by(|a, b| cmp_names(&a.name, &b.name)),
+        EntryOrder::None => {},
+        EntryOrder::Size => {
+            let mut sized = entries.drain(..).map(|entry| Ok((entry.source.len()?, entry))).collect::<io::Result<Vec<_>>>()?;
+            sized.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| cmp_names(&a.1.name, &b.1.name)));
+            entries.extend(sized.into_iter().map(|(_, entry)| entry));
+        },
+    }
+
+    Ok(())
+}
+
+fn cmp_names(a: &str, b: &str) -> Ordering {
+    let components = |name: &str| name.split('\\').map(str::to_ascii_lowercase).collect::<Vec<_>>();
+    components(a).cmp(&components(b))
+}
+
+/// The number of entries per worker thread that `write_dat` prepares at once.
+const PREPARED_PER_THREAD: usize = 2;
+
+/// Returns the end of the window of entries, starting at `start`, that `write_dat` prepares at
+/// once: at most `window_len` entries, and (if `max_memory` is set) at most as many as fit in it,
+/// but always at least one.
+fn window_end(entries: &[NewEntry], start: usize, window_len: usize, max_memory: Option<usize>) -> io::Result<usize> {
+    let max_end = entries.len().min(start + window_len);
+    let max_memory = match max_memory {
+        Some(max_memory) => max_memory,
+        None => return Ok(max_end),
+    };
+
+    // an entry being prepared holds its content and, at worst, compressed data about as large
+    let mut held = 0usize;
+    let mut end = start;
+    while end < max_end {
+        held = held.saturating_add((entries[end].source.len()? as usize).saturating_mul(2));
+        if end > start && held > max_memory {
+            break;
+        }
+        end += 1;
+    }
+    Ok(end)
+}
+
+/// An entry's content, digested and, if it's to be stored compressed, compressed.
+struct Prepared {
+    content: Vec<u8>,
+    crc: u32,
+    /// The content's size and digest, which identical content shares, if deduplicating.
+    key: Option<(usize, Vec<u8>)>,
+    compressed: Option<Vec<u8>>,
+}
+
+/// Reads `entry`'s content, as it's packed: transcoded, if it's a text asset read from a file and
+/// `options.text_encoding` is given.
+fn read_content(entry: &NewEntry, options: &WriteOptions) -> io::Result<Vec<u8>> {
+    let content = entry.source.read()?;
+    match (options.text_encoding, &entry.source) {
+        (Some(transcoding), Source::File(path)) if text::is_text_asset(&entry.name, &content) => {
+            transcoding.transcode(&content).map_err(|e| errors::with_context(e, format!("{}: cannot transcode", path.display())))
+        },
+        _ => Ok(content),
+    }
+}
+
+fn prepare(entry: &NewEntry, options: &WriteOptions) -> io::Result<Prepared> {
+    let content = read_content(entry, options)?;
+
+    let mut crc = Crc::new();
+    crc.update(&content);
+
+    let key = if !options.dedup {
+        None
+    } else if options.dedup_algorithm == Algorithm::Crc32 {
+        Some((content.len(), crc.sum().to_be_bytes().to_vec()))
+    } else {
+        Some((content.len(), options.dedup_algorithm.digest(&content)))
+    };
+
+    // stored data that happens to start with a zlib header would be mistaken for compressed
+    // data, so it's compressed whatever the options
+    let compress_always = entry.compress.unwrap_or(options.compress && options.compress_all);
+    let mut compressed = if compress_always || Method::of(&content) != Method::Stored {
+        Some(compress(&content, options.level)?)
+    } else if entry.compress.unwrap_or(options.compress) {
+        pack(&content, options.level)?
+    } else {
+        None
+    };
+
+    if options.dictionary && entry.compress.unwrap_or(options.compress) && dictionary::suits(&content) {
+        let with_dictionary = profile::time(Stage::Compress, || dictionary::compress(&content, options.level))?;
+        if with_dictionary.len() < compressed.as_ref().map_or(content.len(), Vec::len) {
+            compressed = Some(with_dictionary);
+        }
+    }
+
+    Ok(Prepared { content, crc: crc.sum(), key, compressed })
+}
+
+/// Writes `entries` to `out` as a DAT2 archive, in the order given.
+pub fn write_dat<W: Write>(out: &mut W, entries: &[NewEntry], options: &WriteOptions) -> io::Result<WriteSummary> {
+    let mut blobs: Vec<Blob> = Vec::with_capacity(entries.len());
+    let mut crcs: Vec<u32> = Vec::with_capacity(entries.len());
+    // the entries that wrote each distinct blob, keyed by their content's (size, digest)
+    let mut written: HashMap<(usize, Vec<u8>), Vec<usize>> = HashMap::new();
+    let mut num_deduplicated = 0;
+    let mut data_size = 0;
+
+    // entries are read, digested and compressed on the worker pool a window at a time, then
+    // written in order, so at most a window's content is held in memory at once
+    let window_len = rayon::current_num_threads() * PREPARED_PER_THREAD;
+    let mut start = 0;
+    while start < entries.len() {
+        let end = window_end(entries, start, window_len, options.max_memory)?;
+        let prepared = entries[start..end].par_iter().map(|entry| prepare(entry, options)).collect::<io::Result<Vec<_>>>()?;
+
+        for (i, prepared) in (start..).zip(prepared) {
+            crcs.push(prepared.crc);
+
+            // a match of a weak digest is only a candidate: the earlier content is re-read to
+            // confirm it (the duplicate's compression, done in advance, goes unused)
+            if let Some(ref key) = prepared.key {
+                let mut duplicate_of = None;
+                for &candidate in written.get(key).map(|candidates| candidates.as_slice()).unwrap_or(&[]) {
+                    if options.dedup_algorithm.is_collision_resistant() || read_content(&entries[candidate], options)? == prepared.content {
+                        duplicate_of = Some(candidate);
+                        break;
+                    }
+                }
+
+                if let Some(original) = duplicate_of {
+                    blobs.push(blobs[original]);
+                    num_deduplicated += 1;
+                    continue;
+                }
+            }
+
+            let blob = match prepared.compressed {
+                Some(ref compressed) => {
+                    out.write_all(compressed)?;
+                    Blob { is_compressed: true, decompressed_size: prepared.content.len(), packed_size: compressed.len(), offset: data_size }
+                },
+                _ => {
+                    out.write_all(&prepared.content)?;
+                    Blob { is_compressed: false, decompressed_size: prepared.content.len(), packed_size: prepared.content.len(), offset: data_size }
+                },
+            };
+
+            data_size += blob.packed_size;
+            check_size(blob.decompressed_size, "entry", options)?;
+            check_size(data_size, "data section", options)?;
+            blobs.push(blob);
+
+            if let Some(key) = prepared.key {
+                written.entry(key).or_default().push(i);
+            }
+        }
+
+        start = end;
+    }
+
+    let crc_table_payload = if options.crc_table { Some(extension::crc_table_payload(&crcs)) } else { None };
+    let mut blocks = Vec::new();
+    if let Some(ref payload) = crc_table_payload {
+        blocks.push(Block { tag: extension::CRC_TABLE_TAG, payload });
+    }
+    if let Some(ref tool) = options.tool {
+        blocks.push(Block { tag: extension::TOOL_TAG, payload: tool.as_bytes() });
+    }
+    if let Some(ref comment) = options.comment {
+        blocks.push(Block { tag: extension::COMMENT_TAG, payload: comment.as_bytes() });
+    }
+    if !blocks.is_empty() {
+        data_size += extension::write(out, &blocks)?;
+        check_size(data_size, "data section", options)?;
+    }
+
+    let mut tree = Vec::new();
+    for (entry, blob) in entries.iter().zip(&blobs) {
+        let name = entry.name.replace('\\', options.separator.encode_utf8(&mut [0; 4]));
+        tree.write_u32::<LittleEndian>(name.len() as u32)?;
+        tree.write_all(name.as_bytes())?;
+        tree.write_u8(blob.is_compressed as u8)?;
+        write_field(&mut tree, blob.decompressed_size, options)?;
+        write_field(&mut tree, blob.packed_size, options)?;
+        write_field(&mut tree, blob.offset, options)?;
+    }
+
+    // the tree_size field counts itself, but not num_files or file_size
+    let field_bytes = if options.dat64 { 8 } else { 4 };
+    let magic_bytes = if options.dat64 { DAT64_MAGIC.len() } else { 0 };
+    let tree_size = tree.len() + field_bytes;
+    let file_size = data_size + field_bytes + tree.len() + field_bytes + field_bytes + magic_bytes;
+    check_size(file_size, "archive", options)?;
+
+    write_field(out, entries.len(), options)?;
+    out.write_all(&tree)?;
+    write_field(out, tree_size, options)?;
+    write_field(out, file_size, options)?;
+    if options.dat64 {
+        out.write_all(DAT64_MAGIC)?;
+    }
+
+    Ok(WriteSummary { num_entries: entries.len(), num_deduplicated, file_size })
+}
+
+/// The zlib level that entries are compressed at.
+pub const BEST_LEVEL: u32 = 9;
+
+/// Returns `content` compressed at zlib `level` (0-9), or `None` if it's stored as it is because
+/// compressing it wouldn't make it smaller, as entries are when written.
+pub fn pack(content: &[u8], level: u32) -> io::Result<Option<Vec<u8>>> {
+    let compressed = compress(content, level)?;
+
+    // stored data that happens to start with a zlib header would be mistaken for compressed data
+    if compressed.len() < content.len() || Method::of(content) != Method::Stored {
+        Ok(Some(compressed))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Compresses `content` at `level`, with the `0x78da` header that readers (including this one)
+/// use to recognize compressed entries.
+pub fn compress(content: &[u8], level: u32) -> io::Result<Vec<u8>> {
+    // the second header byte only records the compression level (and a checksum), which decoders
+    // ignore, but some encoders don't set it to "best" even when compressing at the best level
+    const ZLIB_BEST_COMPRESSION_FLAGS: u8 = 0xda;
+
+    let mut compressed = profile::time(Stage::Compress, || {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+        encoder.write_all(content)?;
+        encoder.finish()
+    })?;
+    compressed[1] = ZLIB_BEST_COMPRESSION_FLAGS;
+    Ok(compressed)
+}
+
+/// Writes a u32 field of DAT2, which is a u64 in DAT64.
+fn write_field<W: Write>(out: &mut W, value: usize, options: &WriteOptions) -> io::Result<()> {
+    if options.dat64 {
+        out.write_u64::<LittleEndian>(value as u64)
+    } else {
+        out.write_u32::<LittleEndian>(value as u32)
+    }
+}
+
+fn check_size(size: usize, what: &str, options: &WriteOptions) -> io::Result<()> {
+    if !options.dat64 && size > u32::MAX as usize {
+        let err_msg = format!("the {} would be larger than the 4 GiB a DAT2 archive can address (a DAT64 archive can be larger, but the engine can't load it)", what);
+        Err(errors::new(Code::TooLarge, ErrorKind::InvalidInput, err_msg))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil;
+    use Dat;
+
+    fn write(entries: &[NewEntry], options: &WriteOptions) -> (Vec<u8>, WriteSummary) {
+        let mut data = Vec::new();
+        let summary = write_dat(&mut data, entries, options).unwrap();
+        (data, summary)
+    }
+
+    #[test]
+    fn identical_entries_share_their_data() {
+        let entries = [
+            testutil::entry("a.txt", b"the same content"),
+            testutil::entry("b.txt", b"other content"),
+            testutil::entry("c.txt", b"the same content"),
+            testutil::entry("d.txt", b"the same content"),
+        ];
+        let (data, summary) = write(&entries, &WriteOptions::default());
+        assert_eq!(summary.num_entries, 4);
+        assert_eq!(summary.num_deduplicated, 2);
+
+        let dat = Dat::parse(&data).unwrap();
+        let offsets: Vec<usize> = dat.tree_entries().iter().map(|tree_entry| tree_entry.offset).collect();
+        assert_eq!(offsets[0], offsets[2]);
+        assert_eq!(offsets[0], offsets[3]);
+        assert_ne!(offsets[0], offsets[1]);
+        for (tree_entry, entry) in dat.tree_entries().iter().zip(&entries) {
+            assert_eq!(::decompress_entry(tree_entry, dat.data(tree_entry).unwrap()).unwrap(), entry.source.read().unwrap());
+        }
+    }
+
+    #[test]
+    fn identical_entries_are_kept_apart_without_dedup() {
+        let entries = [testutil::entry("a.txt", b"the same content"), testutil::entry("b.txt", b"the same content")];
+        let (data, summary) = write(&entries, &WriteOptions { dedup: false, ..WriteOptions::default() });
+        assert_eq!(summary.num_deduplicated, 0);
+
+        let dat = Dat::parse(&data).unwrap();
+        assert_ne!(dat.tree_entries()[0].offset, dat.tree_entries()[1].offset);
+    }
+
+    #[test]
+    fn crc32_collisions_are_not_merged() {
+        // the same size and CRC-32 (0x4ddb0c25), but different content
+        let entries = [testutil::entry("a.txt", b"plumless"), testutil::entry("b.txt", b"buckeroo")];
+        let (data, summary) = write(&entries, &WriteOptions::default());
+        assert_eq!(summary.num_deduplicated, 0);
+
+        let dat = Dat::parse(&data).unwrap();
+        let tree_entries = dat.tree_entries();
+        assert_ne!(tree_entries[0].offset, tree_entries[1].offset);
+        assert_eq!(::decompress_entry(&tree_entries[0], dat.data(&tree_entries[0]).unwrap()).unwrap(), b"plumless");
+        assert_eq!(::decompress_entry(&tree_entries[1], dat.data(&tree_entries[1]).unwrap()).unwrap(), b"buckeroo");
+    }
+
+    #[test]
+    fn dat64_archives_round_trip() {
+        let entries = testutil::sample_entries(20, 5);
+        let (data, _) = write(&entries, &WriteOptions { dat64: true, ..WriteOptions::default() });
+
+        let dat = Dat::parse(&data).unwrap();
+        assert!(dat.stats().is_dat64);
+        assert_eq!(dat.tree_entries().len(), entries.len());
+        for (tree_entry, entry) in dat.tree_entries().iter().zip(&entries) {
+            assert_eq!(tree_entry.name, entry.name);
+            assert_eq!(::decompress_entry(tree_entry, dat.data(tree_entry).unwrap()).unwrap(), entry.source.read().unwrap());
+        }
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn dat2_archives_are_limited_to_4_gib() {
+        let size = u32::MAX as usize + 1;
+        let err = check_size(size, "archive", &WriteOptions::default()).unwrap_err();
+        assert_eq!(errors::code(&err), Code::TooLarge);
+        assert!(check_size(size - 1, "archive", &WriteOptions::default()).is_ok());
+        assert!(check_size(size, "archive", &WriteOptions { dat64: true, ..WriteOptions::default() }).is_ok());
+    }
+}